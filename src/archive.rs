@@ -0,0 +1,235 @@
+//! `diff --archive`: the directory `--destination` would produce, packed into
+//! a single `.tar.gz` or `.zip` file to be attached to a ticket. `current
+//! --archive` packs the packages straight from the sources Cargo unpacked,
+//! entry by entry, without copying them first.
+//!
+//! The entries are sorted and have no timestamps nor owners, so the same
+//! sources always give the very same archive.
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use tar::{EntryType, Header};
+use zip::{write::SimpleFileOptions, CompressionMethod, DateTime, ZipWriter};
+
+use {tree, Result};
+
+/// The permissions of the entries, which are otherwise those of the
+/// temporary copies.
+const DIR_MODE: u32 = 0o755;
+const FILE_MODE: u32 = 0o644;
+const EXECUTABLE_MODE: u32 = 0o755;
+
+enum Format {
+    TarGz,
+    Zip,
+}
+
+enum Kind {
+    Dir,
+    File { executable: bool },
+    Symlink(PathBuf),
+}
+
+/// Fails if there's no archive format for the extension of `path`, before
+/// anything is downloaded.
+pub(crate) fn check_format(path: &Path) -> Result<()> {
+    format(path).map(|_| ())
+}
+
+fn format(path: &Path) -> Result<Format> {
+    let name = path.to_string_lossy();
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Ok(Format::TarGz)
+    } else if name.ends_with(".zip") {
+        Ok(Format::Zip)
+    } else {
+        bail!(
+            "unsupported archive {}; expected a .tar.gz, .tgz or .zip file",
+            path.display()
+        )
+    }
+}
+
+/// Packs the contents of `dir` into the archive at `path`. The format comes
+/// from the extension of `path`.
+pub(crate) fn write(path: &Path, dir: &Path) -> Result<()> {
+    let mut writer = Writer::create(path)?;
+    let mut entries = Vec::new();
+    list_entries(dir, Path::new(""), &mut entries)?;
+    for (entry, kind) in entries {
+        match kind {
+            Kind::Dir => writer.add_dir(&entry)?,
+            Kind::File { executable } => {
+                writer.add_file(&entry, &fs::read(dir.join(&entry))?, executable)?
+            }
+            Kind::Symlink(target) => writer.add_symlink(&entry, &target)?,
+        }
+    }
+    // Only `current` has a `--quiet`.
+    writer.finish(false)
+}
+
+/// An archive being written, one entry after the other.
+pub(crate) struct Writer {
+    path: PathBuf,
+    inner: Inner,
+}
+
+enum Inner {
+    TarGz(tar::Builder<GzEncoder<File>>),
+    Zip(Box<ZipWriter<File>>, SimpleFileOptions),
+}
+
+impl Writer {
+    /// The archive at `path`, in the format of its extension.
+    pub(crate) fn create(path: &Path) -> Result<Writer> {
+        let format = format(path)?;
+        let file = File::create(path)
+            .map_err(|err| format_err!("can't create {}: {}", path.display(), err))?;
+        let inner = match format {
+            // The gzip header has no timestamp nor file name either.
+            Format::TarGz => Inner::TarGz(tar::Builder::new(GzEncoder::new(
+                file,
+                Compression::default(),
+            ))),
+            // The earliest time zip files can have.
+            Format::Zip => Inner::Zip(
+                Box::new(ZipWriter::new(file)),
+                SimpleFileOptions::default()
+                    .compression_method(CompressionMethod::Deflated)
+                    .last_modified_time(DateTime::default()),
+            ),
+        };
+        Ok(Writer {
+            path: path.to_path_buf(),
+            inner,
+        })
+    }
+
+    pub(crate) fn add_dir(&mut self, path: &Path) -> Result<()> {
+        match &mut self.inner {
+            Inner::TarGz(builder) => {
+                let mut header = header(EntryType::Directory, DIR_MODE, 0);
+                builder.append_data(&mut header, entry_name(path) + "/", io::empty())?;
+            }
+            Inner::Zip(zip, options) => {
+                zip.add_directory(entry_name(path), options.unix_permissions(DIR_MODE))?
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn add_file(
+        &mut self,
+        path: &Path,
+        contents: &[u8],
+        executable: bool,
+    ) -> Result<()> {
+        let mode = if executable {
+            EXECUTABLE_MODE
+        } else {
+            FILE_MODE
+        };
+        match &mut self.inner {
+            Inner::TarGz(builder) => {
+                let mut header = header(EntryType::Regular, mode, contents.len() as u64);
+                builder.append_data(&mut header, entry_name(path), contents)?;
+            }
+            Inner::Zip(zip, options) => {
+                zip.start_file(entry_name(path), options.unix_permissions(mode))?;
+                zip.write_all(contents)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn add_symlink(&mut self, path: &Path, target: &Path) -> Result<()> {
+        match &mut self.inner {
+            Inner::TarGz(builder) => {
+                let mut header = header(EntryType::Symlink, FILE_MODE, 0);
+                builder.append_link(&mut header, entry_name(path), target)?;
+            }
+            Inner::Zip(zip, options) => zip.add_symlink(
+                entry_name(path),
+                entry_name(target),
+                options.unix_permissions(FILE_MODE),
+            )?,
+        }
+        Ok(())
+    }
+
+    /// Writes what's left of the archive, and tells where it is unless
+    /// `quiet`.
+    pub(crate) fn finish(self, quiet: bool) -> Result<()> {
+        match self.inner {
+            Inner::TarGz(builder) => builder.into_inner()?.finish()?.flush()?,
+            Inner::Zip(zip, _) => zip.finish()?.flush()?,
+        }
+        note!(quiet, "Wrote the archive to {}", self.path.display());
+        Ok(())
+    }
+}
+
+/// A tar header without a timestamp nor an owner.
+fn header(entry_type: EntryType, mode: u32, size: u64) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_entry_type(entry_type);
+    header.set_mode(mode);
+    header.set_size(size);
+    header
+}
+
+/// The entries of `root`, sorted, with the directories before their contents.
+fn list_entries(root: &Path, dir: &Path, res: &mut Vec<(PathBuf, Kind)>) -> Result<()> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(root.join(dir))? {
+        names.push(entry?.file_name());
+    }
+    names.sort();
+    for name in names {
+        if tree::is_git_dir(&name) {
+            continue;
+        }
+        let path = dir.join(&name);
+        let metadata = fs::symlink_metadata(root.join(&path))?;
+        if metadata.file_type().is_symlink() {
+            let target = fs::read_link(root.join(&path))?;
+            res.push((path, Kind::Symlink(target)));
+        } else if metadata.is_dir() {
+            res.push((path.clone(), Kind::Dir));
+            list_entries(root, &path, res)?;
+        } else {
+            let executable = is_executable(&metadata);
+            res.push((path, Kind::File { executable }));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_executable(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+/// Archive paths always use `/`.
+fn entry_name(path: &Path) -> String {
+    let components: Vec<_> = path
+        .components()
+        .map(|it| it.as_os_str().to_string_lossy())
+        .collect();
+    components.join("/")
+}
@@ -0,0 +1,962 @@
+//! A recursive unified diff of two directories, for when there's no `diff`
+//! command around, and a summary of it for `--stat`.
+//!
+//! With `ignore_whitespace`, lines are compared without any whitespace, like
+//! with `diff -w`. Files which only differ in whitespace are then reported as
+//! formatting-only changes instead of being diffed.
+//!
+//! With `color_words`, the changed lines are shown as a single text with the
+//! deleted and the inserted words marked, like `git diff --color-words`.
+//! Words are identifiers and numbers, runs of whitespace and single
+//! punctuation characters, so a renamed identifier is a single change.
+//!
+//! With `collapse_generated`, the diff of a generated text file is a line
+//! with its numbers of changed lines, and `--stat` tells how many of the
+//! changes are in generated files.
+//!
+//! Like `diff`, the printing functions return whether the trees differ, and
+//! formatting-only changes don't count when whitespace is ignored.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt, fs,
+    io::{self, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    str,
+};
+
+use glob::Pattern;
+use similar::{capture_diff_slices, group_diff_ops, Algorithm, ChangeTag, DiffOp, DiffTag};
+
+use truncate::{Limited, Truncation};
+use {color, generated, jobs, tree, Result};
+
+/// How the lines of text files are compared and shown.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DiffOptions<'a> {
+    /// Lines of context around the changes.
+    pub(crate) context: usize,
+    /// Compare lines without their whitespace.
+    pub(crate) ignore_whitespace: bool,
+    /// Highlight the changed words of the changed lines.
+    pub(crate) color_words: bool,
+    /// Pair up the removed and the added files which have at least this
+    /// percentage of their lines in common as renames, see `find_renames`.
+    pub(crate) rename_threshold: Option<u8>,
+    /// Show a summary line instead of the hunks of the generated files, see
+    /// `generated`.
+    pub(crate) collapse_generated: bool,
+    /// The patterns of the `--generated` globs.
+    pub(crate) generated: &'a [Pattern],
+    /// How many threads diff the files, see `jobs`.
+    pub(crate) jobs: usize,
+    /// Where the diffs printed to stdout stop.
+    pub(crate) truncation: &'a Truncation,
+}
+
+/// A file which differs between the two trees. `None` means that there's no
+/// such file on that side.
+#[derive(Debug)]
+pub(crate) struct ChangedFile {
+    /// The path relative to the roots of the trees.
+    pub(crate) path: PathBuf,
+    pub(crate) old: Option<Vec<u8>>,
+    pub(crate) new: Option<Vec<u8>>,
+    /// Where the file was on the old side, if it was renamed.
+    pub(crate) renamed: Option<Rename>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Rename {
+    pub(crate) from: PathBuf,
+    /// The percentage of lines the two versions have in common.
+    pub(crate) similarity: u8,
+}
+
+/// Files which differ between the `a` and `b` directories, sorted by path,
+/// read on `jobs` threads.
+pub(crate) fn changed_files(a: &Path, b: &Path, jobs: usize) -> Result<Vec<ChangedFile>> {
+    let mut paths = BTreeSet::new();
+    list_files(a, Path::new(""), &mut paths)?;
+    list_files(b, Path::new(""), &mut paths)?;
+    let paths: Vec<PathBuf> = paths.into_iter().collect();
+    let mut res = Vec::new();
+    for (file, path) in jobs::map(jobs, &paths, |path| -> Result<_> {
+        let old = read_file(&a.join(path))?;
+        let new = read_file(&b.join(path))?;
+        Ok((old, new))
+    })
+    .into_iter()
+    .zip(paths)
+    {
+        let (old, new) = file?;
+        if old != new {
+            res.push(ChangedFile {
+                path,
+                old,
+                new,
+                renamed: None,
+            });
+        }
+    }
+    Ok(res)
+}
+
+/// More pairs of removed and added files than this and renames aren't looked
+/// for, like with `diff.renameLimit` of git.
+const MAX_RENAME_PAIRS: usize = 1_000_000;
+
+/// Pairs up the files of `changed` which were removed with the ones which were
+/// added with at least `threshold` percent of the same lines, the most similar
+/// first, and turns each pair into a renamed file at its new path. The files
+/// stay sorted by path.
+pub(crate) fn find_renames(
+    changed: Vec<ChangedFile>,
+    threshold: u8,
+    jobs: usize,
+) -> Vec<ChangedFile> {
+    let removed: Vec<usize> = (0..changed.len())
+        .filter(|&idx| changed[idx].new.is_none())
+        .collect();
+    let added: Vec<usize> = (0..changed.len())
+        .filter(|&idx| changed[idx].old.is_none())
+        .collect();
+    if removed.is_empty() || added.is_empty() {
+        return changed;
+    }
+    if removed.len() * added.len() > MAX_RENAME_PAIRS {
+        eprintln!(
+            "{} too many removed and added files to look for renames",
+            color::warning_label()
+        );
+        return changed;
+    }
+    let removed_lines: Vec<Lines> = removed
+        .iter()
+        .map(|&it| Lines::new(changed[it].old.as_deref().unwrap_or_default()))
+        .collect();
+    let mut pairs: Vec<(u8, usize, usize)> = jobs::map(jobs, &added, |&added| {
+        let new = Lines::new(changed[added].new.as_deref().unwrap_or_default());
+        let candidates = removed.iter().zip(removed_lines.iter());
+        candidates
+            .filter_map(|(&removed, old)| {
+                let similarity = old.similarity(&new, threshold)?;
+                Some((similarity, added, removed))
+            })
+            .collect::<Vec<_>>()
+    })
+    .into_iter()
+    .flatten()
+    .collect();
+    // The most similar first, then in the order of the paths, so that the
+    // same files are paired up every time.
+    pairs.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+
+    let mut files: Vec<Option<ChangedFile>> = changed.into_iter().map(Some).collect();
+    for (similarity, added, removed) in pairs {
+        let paired = files[added].as_ref().is_none_or(|it| it.renamed.is_some());
+        if paired || files[removed].is_none() {
+            continue;
+        }
+        let removed = files[removed].take().expect("checked above");
+        let added = files[added].as_mut().expect("checked above");
+        added.old = removed.old;
+        added.renamed = Some(Rename {
+            from: removed.path,
+            similarity,
+        });
+    }
+    files.into_iter().flatten().collect()
+}
+
+/// The lines of a file, counted, to compare files without diffing them. A
+/// binary file is a single line.
+struct Lines<'a> {
+    counts: HashMap<&'a [u8], usize>,
+    total: usize,
+}
+
+impl<'a> Lines<'a> {
+    fn new(contents: &'a [u8]) -> Lines<'a> {
+        let mut counts = HashMap::new();
+        let mut total = 0;
+        let lines: Box<dyn Iterator<Item = &[u8]>> = match as_text(contents) {
+            Some(_) => Box::new(contents.split_inclusive(|&b| b == b'\n')),
+            None => Box::new(std::iter::once(contents)),
+        };
+        for line in lines {
+            *counts.entry(line).or_insert(0) += 1;
+            total += 1;
+        }
+        Lines { counts, total }
+    }
+
+    /// The percentage of lines which are in both files, in any order, if it
+    /// is at least `threshold`.
+    fn similarity(&self, other: &Lines, threshold: u8) -> Option<u8> {
+        let total = self.total + other.total;
+        if total == 0 {
+            return Some(100);
+        }
+        // Even if all the lines of the shorter file are in the other one.
+        let most = 200 * self.total.min(other.total) / total;
+        if most < usize::from(threshold) {
+            return None;
+        }
+        let (small, big) = if self.counts.len() <= other.counts.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let common: usize = small
+            .counts
+            .iter()
+            .map(|(line, count)| (*count).min(big.counts.get(line).copied().unwrap_or(0)))
+            .sum();
+        let similarity = 200 * common / total;
+        if similarity < usize::from(threshold) {
+            return None;
+        }
+        Some(similarity as u8)
+    }
+}
+
+/// The lines of two versions of a text file, with the operations turning the
+/// old lines into the new ones.
+pub(crate) struct LineDiff<'a> {
+    /// The lines with their line breaks.
+    pub(crate) old: Vec<&'a str>,
+    pub(crate) new: Vec<&'a str>,
+    pub(crate) ops: Vec<DiffOp>,
+}
+
+pub(crate) fn line_diff<'a>(old: &'a str, new: &'a str, ignore_whitespace: bool) -> LineDiff<'a> {
+    let old: Vec<&str> = old.split_inclusive('\n').collect();
+    let new: Vec<&str> = new.split_inclusive('\n').collect();
+    let ops = if ignore_whitespace {
+        let old: Vec<String> = old.iter().map(|it| without_whitespace(it)).collect();
+        let new: Vec<String> = new.iter().map(|it| without_whitespace(it)).collect();
+        capture_diff_slices(Algorithm::Myers, &old, &new)
+    } else {
+        capture_diff_slices(Algorithm::Myers, &old, &new)
+    };
+    LineDiff { old, new, ops }
+}
+
+/// Whether the two versions of `file` are text which only differs in
+/// whitespace.
+pub(crate) fn is_formatting_only(file: &ChangedFile) -> bool {
+    let old = file.old.as_deref().and_then(as_text);
+    let new = file.new.as_deref().and_then(as_text);
+    match (old, new) {
+        (Some(old), Some(new)) => old
+            .split_inclusive('\n')
+            .map(without_whitespace)
+            .eq(new.split_inclusive('\n').map(without_whitespace)),
+        _ => false,
+    }
+}
+
+fn without_whitespace(line: &str) -> String {
+    line.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+/// The contents of a text file, `None` if the file looks binary.
+pub(crate) fn as_text(bytes: &[u8]) -> Option<&str> {
+    if bytes.contains(&0) {
+        return None;
+    }
+    str::from_utf8(bytes).ok()
+}
+
+/// Prints the unified diff of the `a` and `b` directories to stdout. File
+/// names are prefixed with the labels instead of the full paths of the
+/// directories.
+pub(crate) fn print_diff(
+    a: &Path,
+    a_label: &str,
+    b: &Path,
+    b_label: &str,
+    options: DiffOptions,
+) -> Result<bool> {
+    let color = color::stdout();
+    let stdout = io::stdout();
+    let mut out = Limited {
+        out: stdout.lock(),
+        truncation: options.truncation,
+    };
+    let files = diffed_files(a, b, options)?;
+    write_files(&mut out, color, &files, a_label, b_label, options, true)
+}
+
+/// Writes the unified diff of the `a` and `b` directories to `out`, colored if
+/// `color` is set.
+pub(crate) fn write_diff(
+    out: &mut impl Write,
+    color: bool,
+    a: &Path,
+    a_label: &str,
+    b: &Path,
+    b_label: &str,
+    options: DiffOptions,
+) -> Result<bool> {
+    let files = diffed_files(a, b, options)?;
+    write_files(out, color, &files, a_label, b_label, options, false)
+}
+
+/// The changed files of the `a` and `b` directories, with the renames.
+fn diffed_files(a: &Path, b: &Path, options: DiffOptions) -> Result<Vec<ChangedFile>> {
+    let files = changed_files(a, b, options.jobs)?;
+    Ok(match options.rename_threshold {
+        Some(threshold) => find_renames(files, threshold, options.jobs),
+        None => files,
+    })
+}
+
+/// Writes the diffs of `files` to `out`. With `limited`, the files which
+/// come after the output is full, see `truncate`, are left out.
+fn write_files(
+    out: &mut impl Write,
+    color: bool,
+    files: &[ChangedFile],
+    a_label: &str,
+    b_label: &str,
+    options: DiffOptions,
+    limited: bool,
+) -> Result<bool> {
+    let mut differ = false;
+    // The diffs are written batch by batch, so that the first ones show up
+    // while the others are still being diffed.
+    let batch_size = jobs::threads(options.jobs) * 4;
+    let truncation = options.truncation;
+    for (idx, batch) in files.chunks(batch_size).enumerate() {
+        if limited && truncation.is_full() {
+            let rest = &files[idx * batch_size..];
+            truncation.hide(rest.len());
+            differ |= rest.iter().any(|file| differs(file, options));
+            break;
+        }
+        for (file, diff) in batch
+            .iter()
+            .zip(jobs::map(options.jobs, batch, |file| -> Result<_> {
+                let mut diff: Vec<u8> = Vec::new();
+                let differ = write_file_diff(&mut diff, color, file, a_label, b_label, options)?;
+                Ok((diff, differ))
+            }))
+        {
+            let (diff, file_differ) = diff?;
+            if limited && truncation.is_full() {
+                truncation.hide(1);
+                differ |= differs(file, options);
+                continue;
+            }
+            out.write_all(&diff)?;
+            differ |= file_differ;
+        }
+    }
+    Ok(differ)
+}
+
+/// Whether the diff of `file` would say that it differs, as
+/// `write_file_diff` does.
+pub(crate) fn differs(file: &ChangedFile, options: DiffOptions) -> bool {
+    file.renamed.is_some() || !(options.ignore_whitespace && is_formatting_only(file))
+}
+
+/// Writes the unified diff of a single changed file to `out`. Returns whether
+/// it differs, which formatting-only changes don't when whitespace is
+/// ignored, unless the file was renamed too.
+pub(crate) fn write_file_diff(
+    out: &mut impl Write,
+    color: bool,
+    file: &ChangedFile,
+    a_label: &str,
+    b_label: &str,
+    options: DiffOptions,
+) -> Result<bool> {
+    let old_path = file.renamed.as_ref().map_or(&file.path, |it| &it.from);
+    let name = |label: &str, path: &Path, contents: &Option<Vec<u8>>| match contents {
+        Some(_) => format!("{}/{}", label, path.display()),
+        None => "/dev/null".to_string(),
+    };
+    let old_name = name(a_label, old_path, &file.old);
+    let new_name = name(b_label, &file.path, &file.new);
+    if let Some(rename) = &file.renamed {
+        paint(out, color, BOLD, &rename_line(file, rename))?;
+        if file.old == file.new {
+            return Ok(true);
+        }
+    }
+    if options.ignore_whitespace && is_formatting_only(file) {
+        writeln!(
+            out,
+            "Formatting-only changes in {} and {}",
+            old_name, new_name
+        )?;
+        return Ok(file.renamed.is_some());
+    }
+    let old = file.old.as_deref().unwrap_or_default();
+    let new = file.new.as_deref().unwrap_or_default();
+    match (as_text(old), as_text(new)) {
+        (Some(_), Some(_))
+            if options.collapse_generated && generated::is_generated(file, options.generated) =>
+        {
+            let name = if file.new.is_some() {
+                new_name
+            } else {
+                old_name
+            };
+            let summary = generated::summary(file, &name, options.ignore_whitespace);
+            paint(out, color, BOLD, &summary)?;
+        }
+        (Some(old), Some(new)) => {
+            paint(out, color, BOLD, &format!("--- {}", old_name))?;
+            paint(out, color, BOLD, &format!("+++ {}", new_name))?;
+            let diff = line_diff(old, new, options.ignore_whitespace);
+            print_hunks(out, color, &diff, options)?;
+        }
+        _ => writeln!(out, "Binary files {} and {} differ", old_name, new_name)?,
+    }
+    Ok(true)
+}
+
+/// `R old -> new (93% similar)`.
+fn rename_line(file: &ChangedFile, rename: &Rename) -> String {
+    format!(
+        "R {} -> {} ({}% similar)",
+        rename.from.display(),
+        file.path.display(),
+        rename.similarity
+    )
+}
+
+/// The file at `path` of the `a` and `b` directories, which differs.
+pub(crate) fn changed_file(a: &Path, b: &Path, path: &Path) -> Result<ChangedFile> {
+    Ok(ChangedFile {
+        path: path.to_path_buf(),
+        old: read_file(&a.join(path))?,
+        new: read_file(&b.join(path))?,
+        renamed: None,
+    })
+}
+
+/// The files which are the same in both trees of `changed_paths`.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Unchanged {
+    pub(crate) files: usize,
+    pub(crate) bytes: u64,
+}
+
+/// The paths of the files which differ between the `a` and `b` directories,
+/// sorted, and the files which don't. Files of different sizes are never
+/// read, so this is cheap when most of the files stay the same.
+pub(crate) fn changed_paths(a: &Path, b: &Path, jobs: usize) -> Result<(Vec<PathBuf>, Unchanged)> {
+    let mut paths = BTreeSet::new();
+    list_files(a, Path::new(""), &mut paths)?;
+    list_files(b, Path::new(""), &mut paths)?;
+    let paths: Vec<PathBuf> = paths.into_iter().collect();
+    let mut changed = Vec::new();
+    let mut unchanged = Unchanged::default();
+    let same = jobs::map(jobs, &paths, |path| same_file(&a.join(path), &b.join(path)));
+    for (path, same) in paths.into_iter().zip(same) {
+        match same? {
+            Some(bytes) => {
+                unchanged.files += 1;
+                unchanged.bytes += bytes;
+            }
+            None => changed.push(path),
+        }
+    }
+    Ok((changed, unchanged))
+}
+
+/// The size of the files at `a` and `b` if they are the same.
+fn same_file(a: &Path, b: &Path) -> Result<Option<u64>> {
+    let (a_meta, b_meta) = match (fs::symlink_metadata(a), fs::symlink_metadata(b)) {
+        (Ok(a_meta), Ok(b_meta)) => (a_meta, b_meta),
+        _ => return Ok(None),
+    };
+    if a_meta.is_dir() || b_meta.is_dir() {
+        return Ok(None);
+    }
+    let symlinks = a_meta.file_type().is_symlink() || b_meta.file_type().is_symlink();
+    if !symlinks && a_meta.len() != b_meta.len() {
+        return Ok(None);
+    }
+    let res = if read_file(a)? == read_file(b)? {
+        Some(a_meta.len())
+    } else {
+        None
+    };
+    Ok(res)
+}
+
+/// Numbers of inserted and deleted lines between two versions of a text
+/// file.
+pub(crate) fn count_lines(old: &str, new: &str, ignore_whitespace: bool) -> (usize, usize) {
+    let diff = line_diff(old, new, ignore_whitespace);
+    diff.ops
+        .iter()
+        .fold((0, 0), |(insertions, deletions), op| match op.tag() {
+            DiffTag::Equal => (insertions, deletions),
+            _ => (
+                insertions + op.new_range().len(),
+                deletions + op.old_range().len(),
+            ),
+        })
+}
+
+/// Prints the paths of the files which differ between the `a` and `b`
+/// directories, with `A` for added, `D` for deleted, `M` for modified and `R`
+/// for renamed files. Only the bytes are compared, the lines aren't diffed.
+pub(crate) fn print_brief(a: &Path, b: &Path, options: DiffOptions) -> Result<bool> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut files = changed_files(a, b, options.jobs)?;
+    if let Some(threshold) = options.rename_threshold {
+        files = find_renames(files, threshold, options.jobs);
+    }
+    for file in files.iter() {
+        if let Some(rename) = &file.renamed {
+            writeln!(out, "{}", rename_line(file, rename))?;
+            continue;
+        }
+        let status = match (&file.old, &file.new) {
+            (None, _) => 'A',
+            (_, None) => 'D',
+            _ => 'M',
+        };
+        writeln!(out, "{} {}", status, file.path.display())?;
+    }
+    Ok(!files.is_empty())
+}
+
+/// Prints a summary of the changes between the `a` and `b` directories, like
+/// `git diff --stat` does. Formatting-only changes are listed separately if
+/// whitespace is ignored.
+pub(crate) fn print_stat(a: &Path, b: &Path, options: DiffOptions) -> Result<bool> {
+    /// The widest bar of `+` and `-`.
+    const MAX_BAR: usize = 40;
+
+    let color = color::stdout();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let ignore_whitespace = options.ignore_whitespace;
+    let (formatting_only, mut files): (Vec<ChangedFile>, Vec<ChangedFile>) =
+        changed_files(a, b, options.jobs)?
+            .into_iter()
+            .partition(|file| ignore_whitespace && is_formatting_only(file));
+    if let Some(threshold) = options.rename_threshold {
+        files = find_renames(files, threshold, options.jobs);
+    }
+    let rows = jobs::map(options.jobs, &files, |file| {
+        let mut name = file.path.display().to_string();
+        match (&file.old, &file.new, &file.renamed) {
+            (_, _, Some(rename)) => name = format!("{} -> {}", rename.from.display(), name),
+            (None, _, _) => name.push_str(" (added)"),
+            (_, None, _) => name.push_str(" (deleted)"),
+            _ => (),
+        }
+        let generated =
+            options.collapse_generated && generated::is_generated(file, options.generated);
+        if generated {
+            name.push_str(" (generated)");
+        }
+        let old = file.old.as_deref().unwrap_or_default();
+        let new = file.new.as_deref().unwrap_or_default();
+        let counts = match (as_text(old), as_text(new)) {
+            (Some(old), Some(new)) => Ok(count_lines(old, new, ignore_whitespace)),
+            _ => Err(format!("Bin {} -> {} bytes", old.len(), new.len())),
+        };
+        (name, counts, generated)
+    });
+    let sum = |generated_only: bool| {
+        rows.iter()
+            .filter(|(_, _, generated)| *generated || !generated_only)
+            .filter_map(|(_, counts, _)| counts.as_ref().ok())
+            .fold((0, 0), |(insertions, deletions), (ins, del)| {
+                (insertions + ins, deletions + del)
+            })
+    };
+    let (total_insertions, total_deletions) = sum(false);
+
+    let name_width = rows
+        .iter()
+        .map(|(name, _, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let max_changes = rows
+        .iter()
+        .filter_map(|(_, counts, _)| counts.as_ref().ok())
+        .map(|(insertions, deletions)| insertions + deletions)
+        .max()
+        .unwrap_or(0);
+    let count_width = max_changes.to_string().len();
+    // Counts are scaled down to fit, but any change gets at least one sign.
+    let scale = |n: usize| match n {
+        0 => 0,
+        _ if max_changes <= MAX_BAR => n,
+        _ => (n * MAX_BAR / max_changes).max(1),
+    };
+    let (insertions, deletions) = sum(true);
+    let generated = Totals {
+        files: rows.iter().filter(|(_, _, generated)| *generated).count(),
+        insertions,
+        deletions,
+    };
+    for (name, counts, _) in rows {
+        write!(out, " {:<width$} | ", name, width = name_width)?;
+        match counts {
+            Ok((insertions, deletions)) => {
+                write!(
+                    out,
+                    "{:>width$} ",
+                    insertions + deletions,
+                    width = count_width
+                )?;
+                let plus = "+".repeat(scale(insertions));
+                let minus = "-".repeat(scale(deletions));
+                if color {
+                    writeln!(out, "\x1b[{}m{}\x1b[{}m{}\x1b[0m", GREEN, plus, RED, minus)?;
+                } else {
+                    writeln!(out, "{}{}", plus, minus)?;
+                }
+            }
+            Err(binary) => writeln!(out, "{}", binary)?,
+        }
+    }
+    let totals = Totals {
+        files: files.len(),
+        insertions: total_insertions,
+        deletions: total_deletions,
+    };
+    writeln!(out, " {}", totals)?;
+    if generated.files > 0 {
+        writeln!(out, " Of which generated: {}", generated)?;
+    }
+    if !formatting_only.is_empty() {
+        writeln!(out, " Formatting-only changes:")?;
+        for file in formatting_only {
+            writeln!(out, "  {}", file.path.display())?;
+        }
+    }
+    Ok(!files.is_empty())
+}
+
+/// The numbers of changed files and lines, as in the last line of `--stat`.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Totals {
+    pub(crate) files: usize,
+    pub(crate) insertions: usize,
+    pub(crate) deletions: usize,
+}
+
+impl fmt::Display for Totals {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        write!(
+            fmt,
+            "{} file{} changed, {} insertion{}(+), {} deletion{}(-)",
+            self.files,
+            plural(self.files),
+            self.insertions,
+            plural(self.insertions),
+            self.deletions,
+            plural(self.deletions)
+        )
+    }
+}
+
+/// Counts the changes between the `a` and `b` directories, on `jobs`
+/// threads. Formatting-only changes don't count when whitespace is ignored.
+pub(crate) fn totals(a: &Path, b: &Path, ignore_whitespace: bool, jobs: usize) -> Result<Totals> {
+    let mut res = Totals::default();
+    let files = changed_files(a, b, jobs)?;
+    for counts in jobs::map(jobs, &files, |file| {
+        if ignore_whitespace && is_formatting_only(file) {
+            return None;
+        }
+        let old = file.old.as_deref().unwrap_or_default();
+        let new = file.new.as_deref().unwrap_or_default();
+        match (as_text(old), as_text(new)) {
+            (Some(old), Some(new)) => Some(count_lines(old, new, ignore_whitespace)),
+            _ => Some((0, 0)),
+        }
+    })
+    .into_iter()
+    .flatten()
+    {
+        res.files += 1;
+        res.insertions += counts.0;
+        res.deletions += counts.1;
+    }
+    Ok(res)
+}
+
+pub(crate) const BOLD: &str = "1";
+const RED: &str = "31";
+const GREEN: &str = "32";
+const CYAN: &str = "36";
+
+fn print_hunks(
+    out: &mut impl Write,
+    color: bool,
+    diff: &LineDiff,
+    options: DiffOptions,
+) -> Result<()> {
+    for group in group_diff_ops(diff.ops.clone(), options.context) {
+        print_hunk(out, color, diff, &group, options)?;
+    }
+    Ok(())
+}
+
+/// Prints a hunk of `diff`, with the operations of one of the groups of
+/// `group_diff_ops`.
+pub(crate) fn print_hunk(
+    out: &mut impl Write,
+    color: bool,
+    diff: &LineDiff,
+    group: &[DiffOp],
+    options: DiffOptions,
+) -> Result<()> {
+    let (first, last) = (&group[0], &group[group.len() - 1]);
+    let header = format!(
+        "@@ -{} +{} @@",
+        hunk_range(first.old_range().start, last.old_range().end),
+        hunk_range(first.new_range().start, last.new_range().end)
+    );
+    paint(out, color, CYAN, &header)?;
+    for op in group.iter() {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            // Like `diff -w`, the old version of the lines is shown.
+            print_lines(out, color, ' ', "", &diff.old[old_range])?;
+            continue;
+        }
+        if options.color_words {
+            let old = diff.old[old_range].concat();
+            let new = diff.new[new_range].concat();
+            print_words(out, color, &old, &new, options.ignore_whitespace)?;
+            continue;
+        }
+        print_lines(out, color, '-', RED, &diff.old[old_range])?;
+        print_lines(out, color, '+', GREEN, &diff.new[new_range])?;
+    }
+    Ok(())
+}
+
+/// Prints the changed lines `old` and `new` as one text, with the deleted
+/// words in red and the inserted ones in green, or between `[-` `-]` and
+/// `{+` `+}` without colors. The lines get a `~` instead of a sign.
+fn print_words(
+    out: &mut impl Write,
+    color: bool,
+    old: &str,
+    new: &str,
+    ignore_whitespace: bool,
+) -> Result<()> {
+    let mut line = String::new();
+    for (tag, text) in word_diff(old, new, ignore_whitespace) {
+        // The marks are closed at the ends of the lines, so that each line
+        // reads on its own.
+        for part in text.split_inclusive('\n') {
+            let (part, newline) = match part.strip_suffix('\n') {
+                Some(part) => (part, true),
+                None => (part, false),
+            };
+            if !part.is_empty() {
+                match (tag, color) {
+                    (ChangeTag::Equal, _) => line.push_str(part),
+                    (ChangeTag::Delete, true) => {
+                        line.push_str(&format!("\x1b[{}m{}\x1b[0m", RED, part))
+                    }
+                    (ChangeTag::Insert, true) => {
+                        line.push_str(&format!("\x1b[{}m{}\x1b[0m", GREEN, part))
+                    }
+                    (ChangeTag::Delete, false) => line.push_str(&format!("[-{}-]", part)),
+                    (ChangeTag::Insert, false) => line.push_str(&format!("{{+{}+}}", part)),
+                }
+            }
+            if newline {
+                writeln!(out, "~{}", line)?;
+                line.clear();
+            }
+        }
+    }
+    if !line.is_empty() {
+        writeln!(out, "~{}", line)?;
+        writeln!(out, "\\ No newline at end of file")?;
+    }
+    Ok(())
+}
+
+/// The word diff of two texts: the unchanged, deleted and inserted pieces of
+/// them, in order. Runs of words with the same change are a single piece.
+pub(crate) fn word_diff<'a>(
+    old: &'a str,
+    new: &'a str,
+    ignore_whitespace: bool,
+) -> Vec<(ChangeTag, &'a str)> {
+    let old_words = words(old);
+    let new_words = words(new);
+    let key = |text: &'a str, range: &Range<usize>| {
+        let word = &text[range.clone()];
+        if ignore_whitespace && word.chars().all(|it| it.is_whitespace() && it != '\n') {
+            " "
+        } else {
+            word
+        }
+    };
+    let old_keys: Vec<&str> = old_words.iter().map(|it| key(old, it)).collect();
+    let new_keys: Vec<&str> = new_words.iter().map(|it| key(new, it)).collect();
+    let mut pieces: Vec<(ChangeTag, Range<usize>)> = Vec::new();
+    let mut push = |tag: ChangeTag, words: &[Range<usize>]| {
+        for word in words {
+            match pieces.last_mut() {
+                Some((last_tag, last)) if *last_tag == tag && last.end == word.start => {
+                    last.end = word.end
+                }
+                _ => pieces.push((tag, word.clone())),
+            }
+        }
+    };
+    for op in capture_diff_slices(Algorithm::Myers, &old_keys, &new_keys) {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            push(ChangeTag::Equal, &old_words[old_range]);
+        } else {
+            push(ChangeTag::Delete, &old_words[old_range]);
+            push(ChangeTag::Insert, &new_words[new_range]);
+        }
+    }
+    pieces
+        .into_iter()
+        .map(|(tag, range)| match tag {
+            ChangeTag::Insert => (tag, &new[range]),
+            _ => (tag, &old[range]),
+        })
+        .collect()
+}
+
+/// The ranges of the words of `text`: identifiers and numbers, runs of
+/// whitespace, line breaks, and any other character on its own.
+fn words(text: &str) -> Vec<Range<usize>> {
+    #[derive(PartialEq)]
+    enum Class {
+        Word,
+        Space,
+        Other,
+    }
+    let class = |c: char| {
+        if c == '_' || c.is_alphanumeric() {
+            Class::Word
+        } else if c.is_whitespace() && c != '\n' {
+            Class::Space
+        } else {
+            Class::Other
+        }
+    };
+    let mut res: Vec<Range<usize>> = Vec::new();
+    let mut last = None;
+    for (idx, c) in text.char_indices() {
+        let current = class(c);
+        let end = idx + c.len_utf8();
+        match res.last_mut() {
+            Some(range) if current != Class::Other && last.as_ref() == Some(&current) => {
+                range.end = end
+            }
+            _ => res.push(idx..end),
+        }
+        last = Some(current);
+    }
+    res
+}
+
+fn print_lines(
+    out: &mut impl Write,
+    color: bool,
+    sign: char,
+    style: &str,
+    lines: &[&str],
+) -> Result<()> {
+    for line in lines {
+        paint(
+            out,
+            color,
+            style,
+            &format!("{}{}", sign, line.trim_end_matches('\n')),
+        )?;
+        if !line.ends_with('\n') {
+            writeln!(out, "\\ No newline at end of file")?;
+        }
+    }
+    Ok(())
+}
+
+/// The range of lines of a hunk header, like `3,2` for two lines starting at
+/// the third one. A single line has no count, and an empty range starts at
+/// the line before it.
+fn hunk_range(start: usize, end: usize) -> String {
+    match end - start {
+        1 => (start + 1).to_string(),
+        0 => format!("{},0", start),
+        len => format!("{},{}", start + 1, len),
+    }
+}
+
+/// The hunks of the unified diff of a text file, without the file names.
+pub(crate) fn unified_hunks(old: &str, new: &str, options: DiffOptions) -> String {
+    let mut res = Vec::new();
+    let diff = line_diff(old, new, options.ignore_whitespace);
+    let options = DiffOptions {
+        color_words: false,
+        ..options
+    };
+    print_hunks(&mut res, false, &diff, options).expect("writing to a Vec can't fail");
+    String::from_utf8(res).expect("diff of text is text")
+}
+
+/// Writes a line, colored with an ANSI `style` if `color` is set.
+pub(crate) fn paint(out: &mut impl Write, color: bool, style: &str, line: &str) -> Result<()> {
+    if color && !style.is_empty() {
+        writeln!(out, "\x1b[{}m{}\x1b[0m", style, line)?;
+    } else {
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+fn list_files(root: &Path, dir: &Path, res: &mut BTreeSet<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(root.join(dir))? {
+        let entry = entry?;
+        if tree::is_git_dir(&entry.file_name()) {
+            continue;
+        }
+        let path = dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            list_files(root, &path, res)?;
+        } else {
+            res.insert(path);
+        }
+    }
+    Ok(())
+}
+
+/// Reads a file, or the target of a symlink. `None` if there's no such file,
+/// or if it is a directory on this side.
+fn read_file(path: &Path) -> Result<Option<Vec<u8>>> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(it) => it,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path)?;
+        return Ok(Some(target.to_string_lossy().into_owned().into_bytes()));
+    }
+    if metadata.is_dir() {
+        return Ok(None);
+    }
+    Ok(Some(fs::read(path)?))
+}
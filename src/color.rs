@@ -0,0 +1,72 @@
+//! When the output is colored, as chosen with `--color` for the whole run.
+//!
+//! `auto` colors what goes to a terminal, directly or through the pager,
+//! unless `$NO_COLOR` is set to anything but the empty string. `always` and
+//! `never` ignore both the terminal and `$NO_COLOR`.
+
+use std::{
+    env,
+    io::{self, IsTerminal},
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use pager;
+
+/// The choice of `--color`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+static CHOICE: AtomicU8 = AtomicU8::new(ColorChoice::Auto as u8);
+
+/// Sets when the output of the whole process is colored.
+pub fn set_color(choice: ColorChoice) {
+    CHOICE.store(choice as u8, Ordering::SeqCst);
+}
+
+fn choice() -> ColorChoice {
+    match CHOICE.load(Ordering::SeqCst) {
+        x if x == ColorChoice::Always as u8 => ColorChoice::Always,
+        x if x == ColorChoice::Never as u8 => ColorChoice::Never,
+        _ => ColorChoice::Auto,
+    }
+}
+
+fn colored(is_terminal: bool) -> bool {
+    match choice() {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => is_terminal && env::var_os("NO_COLOR").is_none_or(|it| it.is_empty()),
+    }
+}
+
+/// Whether stdout gets colors.
+pub(crate) fn stdout() -> bool {
+    colored(pager::is_terminal())
+}
+
+/// Whether stderr gets colors.
+pub(crate) fn stderr() -> bool {
+    colored(io::stderr().is_terminal())
+}
+
+/// `warning:`, in bold yellow if stderr gets colors.
+pub fn warning_label() -> &'static str {
+    if stderr() {
+        "\x1b[1;33mwarning\x1b[0m:"
+    } else {
+        "warning:"
+    }
+}
+
+/// `error:`, in bold red if stderr gets colors.
+pub fn error_label() -> &'static str {
+    if stderr() {
+        "\x1b[1;31merror\x1b[0m:"
+    } else {
+        "error:"
+    }
+}
@@ -0,0 +1,111 @@
+//! `current --since`: what changed since an earlier snapshot, from its
+//! `manifest.json`. The packages it has in the same version are referenced by
+//! the new manifest instead of being copied again, and `delta.md` lists the
+//! packages added, removed and upgraded, by name.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use snapshot::Entry;
+use {packages_word, Result};
+
+pub(crate) const DELTA_FILE: &str = "delta.md";
+
+#[derive(Debug, Default)]
+pub(crate) struct Delta {
+    /// The names, with the versions.
+    added: Vec<(String, Vec<String>)>,
+    removed: Vec<(String, Vec<String>)>,
+    /// The names, with the versions which are gone and the new ones.
+    upgraded: Vec<(String, Vec<String>, Vec<String>)>,
+}
+
+/// The versions of the packages of `entries`, by name.
+fn versions(entries: &[Entry]) -> BTreeMap<&str, BTreeSet<&str>> {
+    let mut res: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for entry in entries {
+        res.entry(&entry.name).or_default().insert(&entry.version);
+    }
+    res
+}
+
+pub(crate) fn compare(old: &[Entry], new: &[Entry]) -> Delta {
+    let (old, new) = (versions(old), versions(new));
+    let empty = BTreeSet::new();
+    let names: BTreeSet<&str> = old.keys().chain(new.keys()).cloned().collect();
+    let mut res = Delta::default();
+    for name in names {
+        let before = old.get(name).unwrap_or(&empty);
+        let after = new.get(name).unwrap_or(&empty);
+        let gone: Vec<String> = before.difference(after).map(|it| it.to_string()).collect();
+        let came: Vec<String> = after.difference(before).map(|it| it.to_string()).collect();
+        match (gone.is_empty(), came.is_empty()) {
+            (true, true) => (),
+            (true, false) => res.added.push((name.to_string(), came)),
+            (false, true) => res.removed.push((name.to_string(), gone)),
+            (false, false) => res.upgraded.push((name.to_string(), gone, came)),
+        }
+    }
+    res
+}
+
+impl Delta {
+    /// Like `2 packages added, 0 removed, 1 upgraded`.
+    pub(crate) fn counts(&self) -> String {
+        format!(
+            "{} added, {} removed, {} upgraded",
+            packages_word(self.added.len()),
+            self.removed.len(),
+            self.upgraded.len()
+        )
+    }
+
+    /// `delta.md`, since the snapshot in `since`.
+    pub(crate) fn render(&self, since: &str) -> String {
+        let mut res = format!("# Delta since {}\n\n{}.\n", since, self.counts());
+        let sections = [("Added", &self.added), ("Removed", &self.removed)];
+        for (title, packages) in sections.iter() {
+            if packages.is_empty() {
+                continue;
+            }
+            res += &format!("\n## {}\n\n", title);
+            for (name, versions) in packages.iter() {
+                res += &format!("- `{}` {}\n", name, versions.join(", "));
+            }
+        }
+        if !self.upgraded.is_empty() {
+            res += "\n## Upgraded\n\n";
+            for (name, gone, came) in self.upgraded.iter() {
+                res += &format!("- `{}` {} -> {}\n", name, gone.join(", "), came.join(", "));
+            }
+        }
+        res
+    }
+
+    pub(crate) fn write(&self, dest: &Path, since: &str) -> Result<()> {
+        fs::write(dest.join(DELTA_FILE), self.render(since))?;
+        Ok(())
+    }
+}
+
+/// The `path` relative to the directory `base`, both canonical, with `/`s.
+pub(crate) fn relative(path: &Path, base: &Path) -> String {
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    let common = path
+        .iter()
+        .zip(base.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut res = PathBuf::new();
+    for _ in common..base.len() {
+        res.push("..");
+    }
+    for component in &path[common..] {
+        res.push(component);
+    }
+    res.to_string_lossy().replace('\\', "/")
+}
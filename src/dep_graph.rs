@@ -0,0 +1,432 @@
+//! The dependencies a package pulls in, all the way down, as Cargo resolves
+//! them when the package is a dependency: without its dev-dependencies, and
+//! with the features it is fetched with. Bumping a crate matters less than
+//! the crates the new version brings along.
+//!
+//! Dependencies are matched by name. The proc-macros and the
+//! build-dependencies, which run at build time, are flagged.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+
+use cargo_metadata::{self, DependencyKind};
+use semver::Version;
+
+use Result;
+
+/// The transitive dependencies of a package.
+#[derive(Debug, Default)]
+pub(crate) struct Graph {
+    packages: BTreeMap<String, Dependency>,
+}
+
+#[derive(Debug, Default)]
+struct Dependency {
+    /// From the oldest, there may be several semver incompatible versions.
+    versions: Vec<String>,
+    proc_macro: bool,
+    /// Some package depends on it in its `[build-dependencies]`.
+    build: bool,
+}
+
+/// The dependencies of the package `root_id` in `metadata`.
+pub(crate) fn graph(metadata: &cargo_metadata::Metadata, root_id: &str) -> Graph {
+    let packages: HashMap<&str, &cargo_metadata::Package> = metadata
+        .packages
+        .iter()
+        .map(|it| (it.id.repr.as_str(), it))
+        .collect();
+    let nodes: HashMap<&str, &cargo_metadata::Node> = match &metadata.resolve {
+        Some(resolve) => resolve
+            .nodes
+            .iter()
+            .map(|it| (it.id.repr.as_str(), it))
+            .collect(),
+        None => HashMap::new(),
+    };
+    let mut res = Graph::default();
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut queue: VecDeque<&str> = VecDeque::new();
+    seen.insert(root_id);
+    queue.push_back(root_id);
+    while let Some(id) = queue.pop_front() {
+        let node = match nodes.get(id) {
+            Some(it) => it,
+            None => continue,
+        };
+        for dep in node.deps.iter() {
+            let dep_id = &dep.pkg;
+            let package = match packages.get(dep_id.repr.as_str()) {
+                Some(it) => it,
+                None => continue,
+            };
+            let dependency = res.packages.entry(package.name.clone()).or_default();
+            dependency.build |= dep
+                .dep_kinds
+                .iter()
+                .any(|it| it.kind == DependencyKind::Build);
+            if !seen.insert(&dep_id.repr) {
+                continue;
+            }
+            queue.push_back(&dep_id.repr);
+            dependency.versions.push(package.version.to_string());
+            dependency
+                .versions
+                .sort_by_key(|it| Version::parse(it).ok());
+            dependency.proc_macro |= package
+                .targets
+                .iter()
+                .any(|it| it.kind.iter().any(|kind| kind == "proc-macro"));
+        }
+    }
+    res
+}
+
+/// The ids of the packages the members of the workspace of `metadata` pull
+/// in, with the members, without going through the dependencies of the
+/// `excluded` kinds. A package which some members only need for their tests,
+/// but others build with, is in.
+pub(crate) fn reachable<'a>(
+    metadata: &'a cargo_metadata::Metadata,
+    excluded: &[DependencyKind],
+) -> Result<HashSet<&'a str>> {
+    let members: Vec<&str> = metadata
+        .workspace_members
+        .iter()
+        .map(|it| it.repr.as_str())
+        .collect();
+    reachable_from(metadata, &members, excluded)
+}
+
+/// The ids of the packages the packages `roots` pull in, with them, without
+/// going through the dependencies of the `excluded` kinds.
+pub(crate) fn reachable_from<'a>(
+    metadata: &'a cargo_metadata::Metadata,
+    roots: &[&'a str],
+    excluded: &[DependencyKind],
+) -> Result<HashSet<&'a str>> {
+    let resolve = metadata
+        .resolve
+        .as_ref()
+        .ok_or_else(|| format_err!("Cargo didn't resolve the dependencies of the workspace"))?;
+    let nodes: HashMap<&str, &cargo_metadata::Node> = resolve
+        .nodes
+        .iter()
+        .map(|it| (it.id.repr.as_str(), it))
+        .collect();
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut queue: VecDeque<&str> = VecDeque::new();
+    for root in roots {
+        if seen.insert(root) {
+            queue.push_back(root);
+        }
+    }
+    while let Some(id) = queue.pop_front() {
+        let node = match nodes.get(id) {
+            Some(it) => it,
+            None => continue,
+        };
+        for dep in node.deps.iter() {
+            // The same crate may be several kinds of dependency at once. Cargo
+            // older than 1.41 doesn't tell, it's followed then.
+            let followed = dep.dep_kinds.is_empty()
+                || dep.dep_kinds.iter().any(|it| !excluded.contains(&it.kind));
+            if followed && seen.insert(&dep.pkg.repr) {
+                queue.push_back(&dep.pkg.repr);
+            }
+        }
+    }
+    Ok(seen)
+}
+
+/// The ids of the direct dependencies of the members of the workspace of
+/// `metadata` which pull in the package `id`, walking the resolve graph
+/// backwards: `id` itself if a member depends on it.
+pub(crate) fn pulled_in_by<'a>(
+    metadata: &'a cargo_metadata::Metadata,
+    id: &'a str,
+) -> Result<BTreeSet<&'a str>> {
+    let resolve = metadata
+        .resolve
+        .as_ref()
+        .ok_or_else(|| format_err!("Cargo didn't resolve the dependencies of the workspace"))?;
+    let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+    for node in resolve.nodes.iter() {
+        for dep_id in node.dependencies.iter() {
+            dependents
+                .entry(dep_id.repr.as_str())
+                .or_default()
+                .push(&node.id.repr);
+        }
+    }
+    let is_member = |id: &str| metadata.workspace_members.iter().any(|it| it.repr == id);
+    let mut res = BTreeSet::new();
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut queue: VecDeque<&str> = VecDeque::new();
+    seen.insert(id);
+    queue.push_back(id);
+    while let Some(id) = queue.pop_front() {
+        let parents = dependents.get(id).map(Vec::as_slice).unwrap_or_default();
+        if parents.iter().any(|it| is_member(it)) && !is_member(id) {
+            res.insert(id);
+        }
+        for parent in parents {
+            if !is_member(parent) && seen.insert(parent) {
+                queue.push_back(parent);
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// The shortest chain of dependencies from a member of the workspace of
+/// `metadata` to the package `id`, as ids, from the member to `id`. Empty if
+/// nothing pulls it in.
+pub(crate) fn chain<'a>(metadata: &'a cargo_metadata::Metadata, id: &str) -> Result<Vec<&'a str>> {
+    let resolve = metadata
+        .resolve
+        .as_ref()
+        .ok_or_else(|| format_err!("Cargo didn't resolve the dependencies of the workspace"))?;
+    let nodes: HashMap<&str, &cargo_metadata::Node> = resolve
+        .nodes
+        .iter()
+        .map(|it| (it.id.repr.as_str(), it))
+        .collect();
+    // The package each one was first reached from.
+    let mut parents: HashMap<&str, Option<&str>> = HashMap::new();
+    let mut queue: VecDeque<&str> = VecDeque::new();
+    for member in metadata.workspace_members.iter() {
+        if parents.insert(&member.repr, None).is_none() {
+            queue.push_back(&member.repr);
+        }
+    }
+    while let Some(current) = queue.pop_front() {
+        if current == id {
+            let mut res = vec![current];
+            while let Some(Some(parent)) = parents.get(res[res.len() - 1]) {
+                res.push(parent);
+            }
+            res.reverse();
+            return Ok(res);
+        }
+        for dep_id in nodes
+            .get(current)
+            .into_iter()
+            .flat_map(|it| &it.dependencies)
+        {
+            if !parents.contains_key(dep_id.repr.as_str()) {
+                parents.insert(&dep_id.repr, Some(current));
+                queue.push_back(&dep_id.repr);
+            }
+        }
+    }
+    Ok(Vec::new())
+}
+
+/// The chains of dependencies which pull each package of `metadata` into the
+/// workspace, like `app -> rand -> rand_core`, by name: the shortest one
+/// through each of the packages which depend on it, at most `max` of them,
+/// the shortest first. The members of the workspace have none.
+pub(crate) fn provenance<'a>(
+    metadata: &'a cargo_metadata::Metadata,
+    max: usize,
+) -> Result<HashMap<&'a str, Vec<String>>> {
+    let resolve = metadata
+        .resolve
+        .as_ref()
+        .ok_or_else(|| format_err!("Cargo didn't resolve the dependencies of the workspace"))?;
+    let names: HashMap<&str, &str> = metadata
+        .packages
+        .iter()
+        .map(|it| (it.id.repr.as_str(), it.name.as_str()))
+        .collect();
+    let nodes: HashMap<&str, &cargo_metadata::Node> = resolve
+        .nodes
+        .iter()
+        .map(|it| (it.id.repr.as_str(), it))
+        .collect();
+    // The package each one was first reached from, and the order they were.
+    let mut parents: HashMap<&str, Option<&str>> = HashMap::new();
+    let mut order = Vec::new();
+    let mut queue: VecDeque<&str> = VecDeque::new();
+    for member in metadata.workspace_members.iter() {
+        if parents.insert(&member.repr, None).is_none() {
+            queue.push_back(&member.repr);
+        }
+    }
+    let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+    while let Some(current) = queue.pop_front() {
+        order.push(current);
+        for dep_id in nodes
+            .get(current)
+            .into_iter()
+            .flat_map(|it| &it.dependencies)
+        {
+            dependents.entry(&dep_id.repr).or_default().push(current);
+            if !parents.contains_key(dep_id.repr.as_str()) {
+                parents.insert(&dep_id.repr, Some(current));
+                queue.push_back(&dep_id.repr);
+            }
+        }
+    }
+    let shortest = |id: &'a str| -> Vec<&'a str> {
+        let mut res = vec![id];
+        while let Some(Some(parent)) = parents.get(res[res.len() - 1]) {
+            res.push(parent);
+        }
+        res.reverse();
+        res
+    };
+    let mut res = HashMap::new();
+    for id in order {
+        if parents.get(id) == Some(&None) {
+            continue;
+        }
+        let mut chains: Vec<Vec<&str>> = dependents[id]
+            .iter()
+            .map(|parent| shortest(parent))
+            // Through the package itself, with the dev-dependencies.
+            .filter(|it| !it.contains(&id))
+            .collect();
+        chains.sort_by_key(Vec::len);
+        let mut lines: Vec<String> = Vec::new();
+        for chain in chains {
+            let line = chain
+                .iter()
+                .chain(Some(&id))
+                .map(|it| names.get(it).cloned().unwrap_or(it))
+                .collect::<Vec<&str>>()
+                .join(" -> ");
+            if lines.len() < max && !lines.contains(&line) {
+                lines.push(line);
+            }
+        }
+        res.insert(id, lines);
+    }
+    Ok(res)
+}
+
+/// The differences between the dependencies of two packages.
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct Changes {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    added: Vec<Change>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    removed: Vec<Change>,
+    /// The dependencies with other versions.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    changed: Vec<Change>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Change {
+    name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    old: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    new: Vec<String>,
+    proc_macro: bool,
+    build: bool,
+}
+
+pub(crate) fn compare(old: &Graph, new: &Graph) -> Changes {
+    let mut res = Changes::default();
+    let empty = Dependency::default();
+    let names: BTreeSet<&String> = old.packages.keys().chain(new.packages.keys()).collect();
+    for name in names {
+        let (before, after) = match (old.packages.get(name), new.packages.get(name)) {
+            (Some(before), Some(after)) if before.versions == after.versions => continue,
+            (before, after) => (before.unwrap_or(&empty), after.unwrap_or(&empty)),
+        };
+        // The flags of the new version, unless it's gone.
+        let flags = if after.versions.is_empty() {
+            before
+        } else {
+            after
+        };
+        let change = Change {
+            name: name.clone(),
+            old: before.versions.clone(),
+            new: after.versions.clone(),
+            proc_macro: flags.proc_macro,
+            build: flags.build,
+        };
+        match (change.old.is_empty(), change.new.is_empty()) {
+            (true, _) => res.added.push(change),
+            (_, true) => res.removed.push(change),
+            _ => res.changed.push(change),
+        }
+    }
+    res
+}
+
+impl Changes {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// The names of the added dependencies.
+    pub(crate) fn added(&self) -> Vec<String> {
+        self.added.iter().map(|it| it.name.clone()).collect()
+    }
+
+    /// Prints the changes to stdout, before the diff.
+    pub(crate) fn print(&self) {
+        if self.is_empty() {
+            return;
+        }
+        println!("Changes in the dependency graph:");
+        for line in self.lines() {
+            println!(" {}", line);
+        }
+        println!();
+    }
+
+    /// The changes, as printed.
+    fn lines(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        for change in self.added.iter() {
+            res.push(format!(
+                "added {} {}{}",
+                change.name,
+                change.new.join(", "),
+                change.flags()
+            ));
+        }
+        for change in self.removed.iter() {
+            res.push(format!(
+                "removed {} {}{}",
+                change.name,
+                change.old.join(", "),
+                change.flags()
+            ));
+        }
+        for change in self.changed.iter() {
+            res.push(format!(
+                "changed {} {} -> {}{}",
+                change.name,
+                change.old.join(", "),
+                change.new.join(", "),
+                change.flags()
+            ));
+        }
+        res
+    }
+}
+
+impl Change {
+    /// ` (proc-macro)`, ` (build-dependency)`, both or nothing.
+    fn flags(&self) -> String {
+        let mut flags = Vec::new();
+        if self.proc_macro {
+            flags.push("proc-macro");
+        }
+        if self.build {
+            flags.push("build-dependency");
+        }
+        if flags.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", flags.join(", "))
+        }
+    }
+}
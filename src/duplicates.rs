@@ -0,0 +1,130 @@
+//! The crates `current` checks out in several versions, as each of them is
+//! built, shipped and reviewed as many times: with the direct dependencies of
+//! the workspace which pull in each version, to tell what to bump to get rid
+//! of one, and the lines of Rust of the versions but the largest one, which
+//! are the duplicated ones. `--deny-duplicates` fails on the named crates.
+
+use std::collections::BTreeMap;
+
+use cargo_metadata;
+
+use {dep_graph, enumerate, same_name, Result};
+
+/// A crate checked out in several versions.
+pub(crate) struct Duplicate<'a> {
+    name: &'a str,
+    /// From the oldest.
+    versions: Vec<Version<'a>>,
+}
+
+struct Version<'a> {
+    pkg: &'a cargo_metadata::Package,
+    /// The direct dependencies of the workspace which pull it in, as
+    /// `name version`.
+    pulled_in_by: Vec<String>,
+    loc: usize,
+}
+
+impl<'a> Duplicate<'a> {
+    /// The lines of Rust of the versions but the largest one.
+    fn extra_loc(&self) -> usize {
+        let locs = self.versions.iter().map(|it| it.loc);
+        locs.clone().sum::<usize>() - locs.max().unwrap_or(0)
+    }
+
+    fn versions(&self) -> Vec<String> {
+        self.versions
+            .iter()
+            .map(|it| it.pkg.version.to_string())
+            .collect()
+    }
+}
+
+/// The crates of `packages`, the dependencies of `meta` with their `locs`,
+/// which are in several versions, by name.
+pub(crate) fn find<'a>(
+    meta: &'a cargo_metadata::Metadata,
+    packages: &[&'a cargo_metadata::Package],
+    locs: &[usize],
+) -> Result<Vec<Duplicate<'a>>> {
+    let mut by_name: BTreeMap<&str, Vec<(&cargo_metadata::Package, usize)>> = BTreeMap::new();
+    for (pkg, loc) in packages.iter().zip(locs) {
+        by_name.entry(&pkg.name).or_default().push((pkg, *loc));
+    }
+    let packages: BTreeMap<&str, &cargo_metadata::Package> = meta
+        .packages
+        .iter()
+        .map(|it| (it.id.repr.as_str(), it))
+        .collect();
+    let mut res = Vec::new();
+    for (name, mut found) in by_name {
+        if found.len() < 2 {
+            continue;
+        }
+        found.sort_by(|a, b| a.0.version.cmp(&b.0.version));
+        let mut versions = Vec::new();
+        for (pkg, loc) in found {
+            let pulled_in_by = dep_graph::pulled_in_by(meta, &pkg.id.repr)?
+                .into_iter()
+                .filter_map(|id| packages.get(id))
+                .map(|it| format!("{} {}", it.name, it.version))
+                .collect();
+            versions.push(Version {
+                pkg,
+                pulled_in_by,
+                loc,
+            });
+        }
+        res.push(Duplicate { name, versions });
+    }
+    Ok(res)
+}
+
+pub(crate) fn print(duplicates: &[Duplicate], quiet: bool) {
+    if duplicates.is_empty() {
+        return;
+    }
+    let extra_loc: usize = duplicates.iter().map(Duplicate::extra_loc).sum();
+    let plural = if duplicates.len() == 1 { "" } else { "s" };
+    note!(
+        quiet,
+        "Duplicates ({} crate{} in several versions, {} duplicated lines of Rust):",
+        duplicates.len(),
+        plural,
+        extra_loc
+    );
+    for duplicate in duplicates {
+        note!(
+            quiet,
+            "  {} {}",
+            duplicate.name,
+            duplicate.versions().join(", ")
+        );
+        for version in duplicate.versions.iter() {
+            if !version.pulled_in_by.is_empty() {
+                note!(
+                    quiet,
+                    "    {}, pulled in by {}",
+                    version.pkg.version,
+                    version.pulled_in_by.join(", ")
+                );
+            }
+        }
+    }
+}
+
+/// Fails if one of the `denied` crates is in `duplicates`.
+pub(crate) fn deny(duplicates: &[Duplicate], denied: &[String]) -> Result<()> {
+    let found: Vec<String> = duplicates
+        .iter()
+        .filter(|it| denied.iter().any(|name| same_name(name, it.name)))
+        .map(|it| format!("`{}` ({})", it.name, enumerate(&it.versions())))
+        .collect();
+    if !found.is_empty() {
+        bail!(
+            "--deny-duplicates denies {}, checked out in several versions",
+            enumerate(&found)
+        );
+    }
+    Ok(())
+}
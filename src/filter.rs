@@ -0,0 +1,536 @@
+//! Filtering the files of the compared trees with `--include` and `--exclude`
+//! globs.
+//!
+//! Patterns are relative to the crate root. A pattern without a `/` matches
+//! the names of files and directories anywhere, like `*.snap`; other patterns
+//! match the whole path, like `tests/**`. Excluding a directory excludes
+//! everything in it.
+//!
+//! Includes are applied first, then excludes. Unless the includes are strict,
+//! the files which decide what the crate does at build time, Cargo.toml, the
+//! build script and the entry point of a proc-macro, are always included:
+//! hiding them from a review would be dangerous.
+//!
+//! `--no-tests` adds excludes for the tests, the benches, the examples and the
+//! modules which are only compiled for tests.
+//!
+//! `current` has its own `--exclude`, and `--max-file-size`, see `Omit`, and
+//! leaves out of the local packages what `cargo package` would, see
+//! `Packaged`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use glob::{MatchOptions, Pattern};
+use toml;
+
+use snapshot::SkippedFile;
+use tree;
+use unsafe_report::tokenize;
+use Result;
+
+/// The directories of a package `--no-tests` leaves out.
+const TEST_DIRS: &[&str] = &["tests", "benches", "examples"];
+
+#[derive(Debug, Default)]
+pub(crate) struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    /// Don't include the build related files implicitly.
+    strict: bool,
+}
+
+impl Filter {
+    pub(crate) fn new(include: &[String], exclude: &[String], strict: bool) -> Result<Filter> {
+        Ok(Filter {
+            include: patterns("--include", include)?,
+            exclude: patterns("--exclude", exclude)?,
+            strict,
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Whether `path`, or one of the directories it is in, is excluded.
+    pub(crate) fn excludes(&self, path: &Path) -> bool {
+        path.ancestors()
+            .filter(|it| !it.as_os_str().is_empty())
+            .any(|it| matches_any(&self.exclude, it))
+    }
+
+    /// Copies the files of `src` which pass the filter to `dst`. Returns the
+    /// number of files left out.
+    pub(crate) fn copy(&self, src: &Path, dst: &Path) -> Result<usize> {
+        let always_included = if self.strict {
+            Vec::new()
+        } else {
+            build_files(src).into_iter().map(|(path, _)| path).collect()
+        };
+        fs::create_dir_all(dst)?;
+        self.copy_dir(src, dst, Path::new(""), &always_included)
+    }
+
+    fn copy_dir(
+        &self,
+        src: &Path,
+        dst: &Path,
+        dir: &Path,
+        always_included: &[PathBuf],
+    ) -> Result<usize> {
+        let mut skipped = 0;
+        for entry in fs::read_dir(src.join(dir))? {
+            let entry = entry?;
+            let path = dir.join(entry.file_name());
+            let is_dir = entry.file_type()?.is_dir();
+            if matches_any(&self.exclude, &path) {
+                skipped += if is_dir {
+                    count_files(&entry.path())?
+                } else {
+                    1
+                };
+            } else if is_dir {
+                skipped += self.copy_dir(src, dst, &path, always_included)?;
+            } else if self.include.is_empty()
+                || matches_any(&self.include, &path)
+                || always_included.contains(&path)
+            {
+                // Directories are created on demand, so that the ones without
+                // included files don't show up in the diff.
+                fs::create_dir_all(dst.join(dir))?;
+                tree::copy_file(&entry.path(), &dst.join(&path))?;
+            } else {
+                skipped += 1;
+            }
+        }
+        Ok(skipped)
+    }
+}
+
+/// The files `current` leaves out of the packages it checks out: those
+/// matching `--exclude`, but the build related files, and those larger than
+/// `--max-file-size`, but the build related files and the ones in `src`.
+/// Each of them is recorded in the manifest, with why.
+#[derive(Debug, Default)]
+pub(crate) struct Omit {
+    exclude: Vec<Pattern>,
+    max_file_size: Option<u64>,
+}
+
+impl Omit {
+    pub(crate) fn new(exclude: &[String], max_file_size: Option<u64>) -> Result<Omit> {
+        Ok(Omit {
+            exclude: patterns("--exclude", exclude)?,
+            max_file_size,
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.exclude.is_empty() && self.max_file_size.is_none()
+    }
+
+    /// Why the file at `path`, of `size` bytes, in a package with the `build`
+    /// files, is left out, if it is.
+    pub(crate) fn reason(&self, path: &Path, size: u64, build: &[PathBuf]) -> Option<String> {
+        if build.iter().any(|it| it == path) {
+            return None;
+        }
+        if let Some(pattern) = self.excluding(path) {
+            return Some(format!("excluded by `{}`", pattern.as_str()));
+        }
+        match self.max_file_size {
+            Some(max) if size > max && !path.starts_with("src") => {
+                Some(format!("larger than {} bytes", max))
+            }
+            _ => None,
+        }
+    }
+
+    /// The `--exclude` pattern matching `path`, or a directory it's in.
+    fn excluding(&self, path: &Path) -> Option<&Pattern> {
+        path.ancestors()
+            .filter(|it| !it.as_os_str().is_empty())
+            .find_map(|it| {
+                self.exclude
+                    .iter()
+                    .find(|pattern| matches_any(std::slice::from_ref(*pattern), it))
+            })
+    }
+
+    /// Removes the files left out of the package checked out to `dir`, and
+    /// the excluded directories they leave empty.
+    pub(crate) fn remove(&self, dir: &Path) -> Result<Vec<SkippedFile>> {
+        let mut res = Vec::new();
+        if !self.is_empty() {
+            let build: Vec<PathBuf> = build_files(dir).into_iter().map(|(it, _)| it).collect();
+            self.remove_in(dir, Path::new(""), &build, &mut res)?;
+        }
+        Ok(res)
+    }
+
+    fn remove_in(
+        &self,
+        root: &Path,
+        dir: &Path,
+        build: &[PathBuf],
+        res: &mut Vec<SkippedFile>,
+    ) -> Result<()> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(root.join(dir))? {
+            names.push(entry?.file_name());
+        }
+        names.sort();
+        for name in names {
+            let path = dir.join(name);
+            let metadata = fs::symlink_metadata(root.join(&path))?;
+            let size = if metadata.file_type().is_symlink() {
+                0
+            } else {
+                metadata.len()
+            };
+            if metadata.is_dir() {
+                self.remove_in(root, &path, build, res)?;
+                let is_empty = fs::read_dir(root.join(&path))?.next().is_none();
+                if is_empty && self.excluding(&path).is_some() {
+                    fs::remove_dir(root.join(&path))?;
+                }
+            } else if let Some(reason) = self.reason(&path, size, build) {
+                fs::remove_file(root.join(&path))?;
+                res.push(SkippedFile::new(&path, size, reason));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The files `cargo package` would pack of the local package in a
+/// directory, near enough: those its `package.include` names, or else all but
+/// those its `package.exclude` names, never the ones of the packages nested
+/// in it, and always `Cargo.toml`. The globs are read as in a `.gitignore`,
+/// anchored to the root if they have a `/`, without the negated ones.
+pub(crate) struct Packaged {
+    root: PathBuf,
+    include: Vec<(Pattern, bool)>,
+    exclude: Vec<(Pattern, bool)>,
+}
+
+impl Packaged {
+    pub(crate) fn read(root: &Path) -> Packaged {
+        let manifest = fs::read_to_string(root.join("Cargo.toml"))
+            .ok()
+            .and_then(|it| toml::from_str::<toml::Value>(&it).ok());
+        let rules = |key: &str| -> Vec<(Pattern, bool)> {
+            let globs = manifest
+                .as_ref()
+                .and_then(|it| it.get("package")?.get(key)?.as_array().cloned());
+            globs
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|it| it.as_str())
+                .filter(|it| !it.starts_with('!'))
+                .filter_map(|it| {
+                    let glob = it.trim_end_matches('/');
+                    let anchored = glob.contains('/');
+                    let pattern = Pattern::new(glob.trim_start_matches('/')).ok()?;
+                    Some((pattern, anchored))
+                })
+                .collect()
+        };
+        Packaged {
+            root: root.to_path_buf(),
+            include: rules("include"),
+            exclude: rules("exclude"),
+        }
+    }
+
+    /// Whether `path`, relative to the root, is left out.
+    pub(crate) fn excludes(&self, path: &Path) -> bool {
+        if path == Path::new("Cargo.toml") {
+            return false;
+        }
+        let mut ancestors = path.ancestors().filter(|it| !it.as_os_str().is_empty());
+        if ancestors.any(|it| self.root.join(it).join("Cargo.toml").is_file()) {
+            return true;
+        }
+        if self.include.is_empty() {
+            gitignore_matches(&self.exclude, path)
+        } else {
+            !gitignore_matches(&self.include, path)
+        }
+    }
+
+    /// Removes the files left out from the root, and the directories they
+    /// leave empty.
+    pub(crate) fn remove(&self) -> Result<()> {
+        self.remove_in(Path::new("")).map(drop)
+    }
+
+    /// Returns whether anything was removed from `dir`.
+    fn remove_in(&self, dir: &Path) -> Result<bool> {
+        let mut removed = false;
+        for entry in fs::read_dir(self.root.join(dir))? {
+            let entry = entry?;
+            let path = dir.join(entry.file_name());
+            let full_path = self.root.join(&path);
+            let whole_dir = full_path.join("Cargo.toml").is_file()
+                || (self.include.is_empty() && gitignore_matches(&self.exclude, &path));
+            if entry.file_type()?.is_dir() && whole_dir {
+                fs::remove_dir_all(full_path)?;
+                removed = true;
+            } else if entry.file_type()?.is_dir() {
+                if self.remove_in(&path)? && fs::read_dir(&full_path)?.next().is_none() {
+                    fs::remove_dir(full_path)?;
+                    removed = true;
+                }
+            } else if self.excludes(&path) {
+                fs::remove_file(full_path)?;
+                removed = true;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Whether one of the `rules`, anchored or not, matches `path` or a
+/// directory it's in.
+fn gitignore_matches(rules: &[(Pattern, bool)], path: &Path) -> bool {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    let name_matches = |pattern: &Pattern, path: &Path| {
+        let name = path.file_name().unwrap_or_default();
+        pattern.matches_path_with(Path::new(name), options)
+    };
+    path.ancestors()
+        .filter(|it| !it.as_os_str().is_empty())
+        .any(|it| {
+            rules.iter().any(|(pattern, anchored)| {
+                if *anchored {
+                    pattern.matches_path_with(it, options)
+                } else {
+                    name_matches(pattern, it)
+                }
+            })
+        })
+}
+
+fn patterns(flag: &str, globs: &[String]) -> Result<Vec<Pattern>> {
+    globs
+        .iter()
+        .map(|it| {
+            Pattern::new(it)
+                .map_err(|err| format_err!("invalid {} pattern `{}`: {}", flag, it, err))
+        })
+        .collect()
+}
+
+fn matches_any(patterns: &[Pattern], path: &Path) -> bool {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    let name = PathBuf::from(path.file_name().unwrap_or_default());
+    patterns.iter().any(|pattern| {
+        let target = if pattern.as_str().contains('/') {
+            path
+        } else {
+            &name
+        };
+        pattern.matches_path_with(target, options)
+    })
+}
+
+/// Cargo.toml, the build script and the entry point of a proc-macro of the
+/// package in `root`, with what they are. They are guessed from the default
+/// locations if the manifest can't be read.
+pub(crate) fn build_files(root: &Path) -> Vec<(PathBuf, &'static str)> {
+    let mut res = vec![
+        (PathBuf::from("Cargo.toml"), "manifest"),
+        (PathBuf::from("build.rs"), "build script"),
+    ];
+    let manifest = fs::read_to_string(root.join("Cargo.toml"))
+        .ok()
+        .and_then(|it| toml::from_str::<toml::Value>(&it).ok());
+    let manifest = match manifest {
+        Some(it) => it,
+        None => return res,
+    };
+    let relative = |path: &str| PathBuf::from(path.trim_start_matches("./"));
+    if let Some(build) = manifest
+        .get("package")
+        .and_then(|it| it.get("build"))
+        .and_then(|it| it.as_str())
+    {
+        res.push((relative(build), "build script"));
+    }
+    if let Some(lib) = manifest.get("lib") {
+        let proc_macro = lib
+            .get("proc-macro")
+            .or_else(|| lib.get("proc_macro"))
+            .and_then(|it| it.as_bool())
+            .unwrap_or(false);
+        if proc_macro {
+            let path = lib.get("path").and_then(|it| it.as_str());
+            res.push((relative(path.unwrap_or("src/lib.rs")), "proc-macro"));
+        }
+    }
+    res
+}
+
+/// The globs `--no-tests` excludes in the packages in `roots`: the
+/// `TEST_DIRS`, and the Rust files of `src` which are only compiled for
+/// tests, with `#![cfg(test)]` or with `#[cfg(test)] mod name;`.
+pub(crate) fn test_globs(roots: &[&Path]) -> Result<Vec<String>> {
+    let mut res: Vec<String> = TEST_DIRS.iter().map(|it| format!("{}/**", it)).collect();
+    for root in roots {
+        let mut files = Vec::new();
+        rust_files(root, Path::new("src"), &mut files)?;
+        for path in files {
+            let tokens = match fs::read_to_string(root.join(&path)) {
+                Ok(text) => tokenize(&text),
+                Err(_) => continue,
+            };
+            let mut test_only = Vec::new();
+            if has_inner_cfg_test(&tokens) {
+                test_only.push(path.clone());
+            }
+            // The files of the submodules of `lib.rs`, `main.rs`, `mod.rs`
+            // and binaries are next to them, the others in a directory named
+            // after them.
+            let dir = path.parent().unwrap_or_else(|| Path::new(""));
+            let is_root = ["lib.rs", "main.rs", "mod.rs"]
+                .iter()
+                .any(|it| path.file_name().is_some_and(|name| name == *it))
+                || dir == Path::new("src/bin");
+            let dir = if is_root {
+                dir.to_path_buf()
+            } else {
+                dir.join(path.file_stem().unwrap_or_default())
+            };
+            for name in cfg_test_modules(&tokens) {
+                test_only.push(dir.join(format!("{}.rs", name)));
+                test_only.push(dir.join(&name).join("**"));
+            }
+            for path in test_only {
+                let glob: Vec<String> = path
+                    .components()
+                    .map(|it| match it.as_os_str().to_string_lossy() {
+                        ref it if it == "**" => it.to_string(),
+                        it => Pattern::escape(&it),
+                    })
+                    .collect();
+                let glob = glob.join("/");
+                if !res.contains(&glob) {
+                    res.push(glob);
+                }
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// The `.rs` files in `dir` of `root`, relative to `root`.
+fn rust_files(root: &Path, dir: &Path, res: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = match fs::read_dir(root.join(dir)) {
+        Ok(it) => it,
+        Err(_) => return Ok(()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            rust_files(root, &path, res)?;
+        } else if path.extension().is_some_and(|it| it == "rs") {
+            res.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Whether the inner attributes at the start of a file have `#![cfg(test)]`.
+fn has_inner_cfg_test(tokens: &[String]) -> bool {
+    let mut i = 0;
+    while tokens.get(i).is_some_and(|it| it == "#") && tokens.get(i + 1).is_some_and(|it| it == "!")
+    {
+        if tokens[i + 2..].starts_with(&cfg_test()) {
+            return true;
+        }
+        i = skip_attribute(tokens, i + 2);
+    }
+    false
+}
+
+/// The names of the modules declared with `#[cfg(test)] mod name;`, maybe
+/// with more attributes and a visibility.
+fn cfg_test_modules(tokens: &[String]) -> Vec<String> {
+    let mut res = Vec::new();
+    let cfg_test = cfg_test();
+    for start in 0..tokens.len() {
+        if tokens[start] != "#" || !tokens[start + 1..].starts_with(&cfg_test) {
+            continue;
+        }
+        let mut i = start + 1 + cfg_test.len();
+        while tokens.get(i).is_some_and(|it| it == "#") {
+            i = skip_attribute(tokens, i + 1);
+        }
+        if tokens.get(i).is_some_and(|it| it == "pub") {
+            i += 1;
+            if tokens.get(i).is_some_and(|it| it == "(") {
+                while i < tokens.len() && tokens[i] != ")" {
+                    i += 1;
+                }
+                i += 1;
+            }
+        }
+        if let [kw, name, semi, ..] = &tokens[i.min(tokens.len())..] {
+            if kw == "mod" && semi == ";" {
+                res.push(name.trim_start_matches("r#").to_string());
+            }
+        }
+    }
+    res
+}
+
+/// `[cfg(test)]`, as tokens.
+fn cfg_test() -> Vec<String> {
+    ["[", "cfg", "(", "test", ")", "]"]
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// The index after the attribute whose `[` is at `i`.
+fn skip_attribute(tokens: &[String], mut i: usize) -> usize {
+    let mut depth = 0;
+    while i < tokens.len() {
+        match tokens[i].as_str() {
+            "[" => depth += 1,
+            "]" => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    i
+}
+
+pub(crate) fn count_files(dir: &Path) -> Result<usize> {
+    let mut res = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        res += if entry.file_type()?.is_dir() {
+            count_files(&entry.path())?
+        } else {
+            1
+        };
+    }
+    Ok(res)
+}
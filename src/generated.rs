@@ -0,0 +1,91 @@
+//! Files nobody reviews line by line: lockfiles, and the code generated by
+//! protobuf, bindgen and the like. The diffs show a line with the numbers of
+//! changed lines instead of the hunks of such files, unless
+//! `--show-generated`.
+//!
+//! A file is generated if its name matches one of `DEFAULT_PATTERNS` or of
+//! the `--generated` globs, or if one of its first lines, on either side, has
+//! a marker like `@generated`. Globs with a `/` match the path from the root
+//! of the package rather than the name.
+
+use std::path::Path;
+
+use glob::{MatchOptions, Pattern};
+
+use builtin_diff::{as_text, count_lines, ChangedFile};
+use Result;
+
+const DEFAULT_PATTERNS: &[&str] = &["Cargo.lock", "*.pb.rs", "bindings.rs"];
+
+/// Lowercase, the lines are lowercased to look for them.
+const MARKERS: &[&str] = &["@generated", "automatically generated"];
+
+/// How many lines from the top the markers are looked for in.
+const MARKED_LINES: usize = 5;
+
+/// The patterns of the `--generated` globs.
+pub fn generated_patterns(globs: &[String]) -> Result<Vec<Pattern>> {
+    let mut patterns = Vec::new();
+    for glob in globs {
+        let pattern = Pattern::new(glob)
+            .map_err(|err| format_err!("invalid --generated pattern `{}`: {}", glob, err))?;
+        patterns.push(pattern);
+    }
+    Ok(patterns)
+}
+
+/// Whether `file` is generated, with the `patterns` of `--generated` on top
+/// of the default ones.
+pub(crate) fn is_generated(file: &ChangedFile, patterns: &[Pattern]) -> bool {
+    let sides = [&file.old, &file.new];
+    matches_pattern(&file.path, patterns) || sides.iter().any(|it| has_marker(it.as_deref()))
+}
+
+fn matches_pattern(path: &Path, patterns: &[Pattern]) -> bool {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    let name = Path::new(path.file_name().unwrap_or_default());
+    let matches = |pattern: &Pattern| {
+        let target = if pattern.as_str().contains('/') {
+            path
+        } else {
+            name
+        };
+        pattern.matches_path_with(target, options)
+    };
+    DEFAULT_PATTERNS
+        .iter()
+        .filter_map(|it| Pattern::new(it).ok())
+        .any(|it| matches(&it))
+        || patterns.iter().any(matches)
+}
+
+fn has_marker(contents: Option<&[u8]>) -> bool {
+    let text = match contents.and_then(as_text) {
+        Some(it) => it,
+        None => return false,
+    };
+    text.lines().take(MARKED_LINES).any(|line| {
+        let line = line.to_lowercase();
+        MARKERS.iter().any(|it| line.contains(it))
+    })
+}
+
+/// `Generated file changed: foo-1.1.0/Cargo.lock, +1032/-980 lines`, for the
+/// text `file`, named `name` on the side it is on.
+pub(crate) fn summary(file: &ChangedFile, name: &str, ignore_whitespace: bool) -> String {
+    let status = match (&file.old, &file.new) {
+        (None, _) => "added",
+        (_, None) => "deleted",
+        _ => "changed",
+    };
+    let old = file.old.as_deref().and_then(as_text).unwrap_or_default();
+    let new = file.new.as_deref().and_then(as_text).unwrap_or_default();
+    let (insertions, deletions) = count_lines(old, new, ignore_whitespace);
+    format!(
+        "Generated file {}: {}, +{}/-{} lines",
+        status, name, insertions, deletions
+    )
+}
@@ -0,0 +1,333 @@
+//! A single self-contained HTML page with the differences, for reviewing in a
+//! browser: a file tree on the side and side-by-side diffs, with the long
+//! unchanged regions collapsed.
+
+use std::{io::Write, ops::Range, path::Path};
+
+use sha2::{Digest, Sha256};
+use similar::{ChangeTag, DiffTag};
+
+use builtin_diff::{self, ChangedFile, DiffOptions};
+use {jobs, PackageId, Result};
+
+/// Rows of a diff shown for a single file, the rest is cut off.
+const MAX_ROWS: usize = 5000;
+
+const STYLE: &str = "
+body { margin: 0; font-family: sans-serif; display: flex; }
+nav { position: sticky; top: 0; height: 100vh; overflow: auto; min-width: 16em;
+      max-width: 24em; padding: 1em; box-sizing: border-box; background: #f6f8fa;
+      border-right: 1px solid #d0d7de; font-size: 0.9em; }
+nav ul { list-style: none; padding-left: 1em; margin: 0; }
+nav > ul { padding-left: 0; }
+nav a { text-decoration: none; }
+main { flex: 1; min-width: 0; padding: 1em; }
+section { margin-bottom: 2em; border: 1px solid #d0d7de; }
+h2 { margin: 0; padding: 0.4em; font-size: 1em; font-family: monospace;
+     background: #f6f8fa; border-bottom: 1px solid #d0d7de; }
+table { width: 100%; table-layout: fixed; border-collapse: collapse;
+        font-family: monospace; font-size: 0.85em; }
+col.num { width: 4em; }
+td { padding: 0 0.4em; vertical-align: top; white-space: pre-wrap; word-break: break-all; }
+td.num { color: #6e7781; text-align: right; user-select: none; }
+td.del { background: #ffebe9; }
+td.ins { background: #e6ffec; }
+td.none { background: #f6f8fa; }
+td.del span { background: #ffc1c0; }
+td.ins span { background: #abf2bc; }
+details > summary { padding: 0.2em 0.4em; color: #6e7781; background: #ddf4ff;
+                    font-size: 0.85em; cursor: pointer; }
+p.note { margin: 0; padding: 0.4em; color: #6e7781; }
+.added { color: #1a7f37; }
+.removed { color: #cf222e; }
+";
+
+/// Writes the page with the differences between `first`, checked out to `a`,
+/// and `second`, checked out to `b`, and returns whether they differ.
+/// Unchanged lines further than the context from a change are collapsed.
+pub(crate) fn write_report(
+    out: &mut dyn Write,
+    first: &PackageId,
+    a: &Path,
+    second: &PackageId,
+    b: &Path,
+    options: DiffOptions,
+) -> Result<bool> {
+    let (formatting_only, files): (Vec<ChangedFile>, Vec<ChangedFile>) =
+        builtin_diff::changed_files(a, b, options.jobs)?
+            .into_iter()
+            .partition(|file| options.ignore_whitespace && builtin_diff::is_formatting_only(file));
+    let title = escape(&format!("{} → {}", first, second));
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html>\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>{}</title>\n<style>{}</style>", title, STYLE)?;
+    writeln!(out, "</head>\n<body>")?;
+
+    writeln!(out, "<nav>")?;
+    write_tree(out, &files)?;
+    writeln!(out, "</nav>")?;
+
+    writeln!(out, "<main>\n<h1>{}</h1>", title)?;
+    let (insertions, deletions) = jobs::map(options.jobs, &files, |file| match texts(file) {
+        (Some(old), Some(new)) => builtin_diff::count_lines(old, new, options.ignore_whitespace),
+        _ => (0, 0),
+    })
+    .into_iter()
+    .fold((0, 0), |(insertions, deletions), (ins, del)| {
+        (insertions + ins, deletions + del)
+    });
+    writeln!(
+        out,
+        "<p>{} files changed, {} insertions(+), {} deletions(-)</p>",
+        files.len(),
+        insertions,
+        deletions
+    )?;
+    if !formatting_only.is_empty() {
+        let paths: Vec<String> = formatting_only
+            .iter()
+            .map(|it| format!("<code>{}</code>", escape(&it.path.display().to_string())))
+            .collect();
+        writeln!(out, "<p>Formatting-only changes: {}</p>", paths.join(", "))?;
+    }
+    let sections: Vec<(usize, &ChangedFile)> = files.iter().enumerate().collect();
+    for section in jobs::map(options.jobs, &sections, |&(idx, file)| -> Result<_> {
+        let mut section = Vec::new();
+        write_section(&mut section, idx, file, options)?;
+        Ok(section)
+    }) {
+        out.write_all(&section?)?;
+    }
+    writeln!(out, "</main>\n</body>\n</html>")?;
+    Ok(!files.is_empty())
+}
+
+/// The diff of the `idx`th of the changed files.
+fn write_section(
+    out: &mut dyn Write,
+    idx: usize,
+    file: &ChangedFile,
+    options: DiffOptions,
+) -> Result<()> {
+    writeln!(out, "<section id=\"file-{}\">", idx)?;
+    writeln!(
+        out,
+        "<h2>{}{}</h2>",
+        escape(&file.path.display().to_string()),
+        match status(file) {
+            "added" => " <span class=\"added\">(added)</span>",
+            "removed" => " <span class=\"removed\">(removed)</span>",
+            _ => "",
+        }
+    )?;
+    match texts(file) {
+        (Some(old), Some(new)) => write_text_diff(out, old, new, options)?,
+        _ => writeln!(
+            out,
+            "<p class=\"note\">Binary file, sha256 {} → {}</p>",
+            hash(&file.old),
+            hash(&file.new)
+        )?,
+    }
+    writeln!(out, "</section>")?;
+    Ok(())
+}
+
+/// Nested lists of the directories and the files in them, linking to the
+/// diffs. `files` are sorted by path, so each directory is listed once.
+fn write_tree(out: &mut dyn Write, files: &[ChangedFile]) -> Result<()> {
+    let mut open_dirs: Vec<String> = Vec::new();
+    writeln!(out, "<ul>")?;
+    for (idx, file) in files.iter().enumerate() {
+        let mut components: Vec<String> = file
+            .path
+            .iter()
+            .map(|it| it.to_string_lossy().into_owned())
+            .collect();
+        let name = components.pop().unwrap_or_default();
+        let common = open_dirs
+            .iter()
+            .zip(components.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        for _ in common..open_dirs.len() {
+            writeln!(out, "</ul></li>")?;
+        }
+        open_dirs.truncate(common);
+        for dir in &components[common..] {
+            writeln!(out, "<li>{}/<ul>", escape(dir))?;
+            open_dirs.push(dir.clone());
+        }
+        writeln!(
+            out,
+            "<li><a class=\"{}\" href=\"#file-{}\">{}</a></li>",
+            status(file),
+            idx,
+            escape(&name)
+        )?;
+    }
+    for _ in open_dirs {
+        writeln!(out, "</ul></li>")?;
+    }
+    writeln!(out, "</ul>")?;
+    Ok(())
+}
+
+/// A line of the side-by-side diff: the numbers and the texts of the old and
+/// the new lines, if any.
+struct Row<'a> {
+    old: Option<(usize, &'a str)>,
+    new: Option<(usize, &'a str)>,
+    changed: bool,
+}
+
+fn write_text_diff(out: &mut dyn Write, old: &str, new: &str, options: DiffOptions) -> Result<()> {
+    let diff = builtin_diff::line_diff(old, new, options.ignore_whitespace);
+    let (old_lines, new_lines, ops) = (&diff.old, &diff.new, &diff.ops);
+    let context = options.context;
+    let mut rows_left = MAX_ROWS;
+    for (op_idx, op) in ops.iter().enumerate() {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        let rows: Vec<Row> = (0..old_range.len().max(new_range.len()))
+            .map(|i| Row {
+                old: line(old_lines, &old_range, i),
+                new: line(new_lines, &new_range, i),
+                changed: tag != DiffTag::Equal,
+            })
+            .collect();
+        let truncated = rows.len() > rows_left;
+        let rows = &rows[..rows.len().min(rows_left)];
+        rows_left -= rows.len();
+        if tag == DiffTag::Equal {
+            // Unchanged lines are kept around the changes only, and a single
+            // line isn't worth collapsing.
+            let lead = if op_idx == 0 { 0 } else { context };
+            let trail = if op_idx + 1 == ops.len() { 0 } else { context };
+            if rows.len() > lead + trail + 1 {
+                write_rows(out, &rows[..lead], options)?;
+                writeln!(
+                    out,
+                    "<details><summary>{} unchanged lines</summary>",
+                    rows.len() - lead - trail
+                )?;
+                write_rows(out, &rows[lead..rows.len() - trail], options)?;
+                writeln!(out, "</details>")?;
+                write_rows(out, &rows[rows.len() - trail..], options)?;
+            } else {
+                write_rows(out, rows, options)?;
+            }
+        } else {
+            write_rows(out, rows, options)?;
+        }
+        if truncated {
+            writeln!(
+                out,
+                "<p class=\"note\">The diff is cut off after {} lines, view the full file \
+                 in the sources checked out with <code>--destination</code>.</p>",
+                MAX_ROWS
+            )?;
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The number and the text of the `i`th line of `range`, if it is that long.
+fn line<'a>(lines: &[&'a str], range: &Range<usize>, i: usize) -> Option<(usize, &'a str)> {
+    let idx = range.start + i;
+    if idx < range.end {
+        Some((idx + 1, lines[idx]))
+    } else {
+        None
+    }
+}
+
+fn write_rows(out: &mut dyn Write, rows: &[Row], options: DiffOptions) -> Result<()> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "<table><col class=\"num\"><col><col class=\"num\"><col>"
+    )?;
+    for row in rows {
+        // With `--color-words`, the changed words of a line replaced by
+        // another are highlighted.
+        let words = match (row.old, row.new) {
+            (Some((_, old)), Some((_, new))) if row.changed && options.color_words => {
+                Some(builtin_diff::word_diff(old, new, options.ignore_whitespace))
+            }
+            _ => None,
+        };
+        write!(out, "<tr>")?;
+        let sides = [
+            (row.old, " class=\"del\"", ChangeTag::Delete),
+            (row.new, " class=\"ins\"", ChangeTag::Insert),
+        ];
+        for (side, class, changed) in sides {
+            let text = |text: &str| match &words {
+                Some(words) => words
+                    .iter()
+                    .filter(|(tag, _)| *tag == ChangeTag::Equal || *tag == changed)
+                    .map(|(tag, text)| {
+                        let text = escape(text.trim_end_matches(['\r', '\n']));
+                        if *tag == changed {
+                            format!("<span>{}</span>", text)
+                        } else {
+                            text
+                        }
+                    })
+                    .collect(),
+                None => escape(text.trim_end_matches(['\r', '\n'])),
+            };
+            match side {
+                Some((number, line)) => write!(
+                    out,
+                    "<td class=\"num\">{}</td><td{}>{}</td>",
+                    number,
+                    if row.changed { class } else { "" },
+                    text(line)
+                )?,
+                None => write!(out, "<td class=\"num\"></td><td class=\"none\"></td>")?,
+            }
+        }
+        writeln!(out, "</tr>")?;
+    }
+    writeln!(out, "</table>")?;
+    Ok(())
+}
+
+fn texts(file: &ChangedFile) -> (Option<&str>, Option<&str>) {
+    let old = file.old.as_deref().unwrap_or_default();
+    let new = file.new.as_deref().unwrap_or_default();
+    (builtin_diff::as_text(old), builtin_diff::as_text(new))
+}
+
+fn status(file: &ChangedFile) -> &'static str {
+    match (&file.old, &file.new) {
+        (None, _) => "added",
+        (_, None) => "removed",
+        _ => "modified",
+    }
+}
+
+fn hash(contents: &Option<Vec<u8>>) -> String {
+    match contents {
+        Some(bytes) => format!("{:x}", Sha256::digest(bytes)),
+        None => "(none)".to_string(),
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            _ => res.push(c),
+        }
+    }
+    res
+}
@@ -0,0 +1,267 @@
+//! Lookups in the crates.io index, via its sparse HTTP protocol.
+//!
+//! See https://doc.rust-lang.org/cargo/reference/registry-index.html for the
+//! description of the format.
+
+use std::collections::HashMap;
+
+use semver::{Version, VersionReq};
+use serde_json;
+use ureq;
+
+use {jobs, Error, Result};
+
+const SPARSE_INDEX_URL: &str = "https://index.crates.io";
+
+/// A single published version of a crate.
+#[derive(Debug, Clone)]
+pub(crate) struct IndexEntry {
+    pub(crate) version: Version,
+    /// SHA-256 of the `.crate` file.
+    pub(crate) checksum: String,
+    pub(crate) yanked: bool,
+    /// Names of the features, including the implicit ones of optional
+    /// dependencies, sorted.
+    pub(crate) features: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct RawEntry {
+    name: String,
+    vers: String,
+    cksum: String,
+    #[serde(default)]
+    yanked: bool,
+    #[serde(default)]
+    features: HashMap<String, Vec<String>>,
+    /// Features using the newer syntax are stored separately, so that old
+    /// versions of Cargo don't choke on them.
+    #[serde(default)]
+    features2: Option<HashMap<String, Vec<String>>>,
+    #[serde(default)]
+    deps: Vec<RawDep>,
+}
+
+#[derive(Deserialize)]
+struct RawDep {
+    name: String,
+    #[serde(default)]
+    optional: bool,
+}
+
+/// Caches index lookups for the duration of a single command.
+#[derive(Debug, Default)]
+pub(crate) struct Index {
+    /// Published names, by the names they were looked up with.
+    names: HashMap<String, String>,
+    versions: HashMap<String, Vec<IndexEntry>>,
+}
+
+impl Index {
+    /// The name `name` is published under. Like crates.io, this doesn't
+    /// distinguish `-` from `_`, so `thread-local` is `thread_local`.
+    pub(crate) fn canonical_name(&mut self, name: &str) -> Result<&str> {
+        if !self.names.contains_key(name) {
+            let candidates = [
+                name.to_string(),
+                name.replace('-', "_"),
+                name.replace('_', "-"),
+            ];
+            let mut found = None;
+            for candidate in candidates.iter() {
+                if let Some(it) = fetch_versions(candidate)? {
+                    found = Some(it);
+                    break;
+                }
+            }
+            let (canonical, versions) = found.ok_or_else(|| unknown_crate_error(name))?;
+            self.names.insert(name.to_string(), canonical.clone());
+            self.versions.insert(canonical, versions);
+        }
+        Ok(&self.names[name])
+    }
+
+    /// All published versions of `name`, sorted by version.
+    pub(crate) fn versions(&mut self, name: &str) -> Result<&[IndexEntry]> {
+        let name = self.canonical_name(name)?.to_string();
+        Ok(&self.versions[&name])
+    }
+}
+
+/// The published versions of each of the `names`, looked up on `jobs`
+/// threads, leaving out the names crates.io doesn't know about. Unlike `Index`, this
+/// doesn't try the names with `-` and `_` swapped.
+pub(crate) fn versions_of(
+    names: &[String],
+    jobs: usize,
+) -> Result<HashMap<String, Vec<IndexEntry>>> {
+    let mut res = HashMap::new();
+    for (name, found) in names
+        .iter()
+        .zip(jobs::map(jobs, names, |it| fetch_versions(it)))
+    {
+        if let Some((_, versions)) = found? {
+            res.insert(name.clone(), versions);
+        }
+    }
+    Ok(res)
+}
+
+/// An error about a missing crate, with suggestions of similarly named ones.
+pub(crate) fn unknown_crate_error(name: &str) -> Error {
+    let similar = similar_names(name);
+    if similar.is_empty() {
+        return format_err!("crate `{}` not found on crates.io", name);
+    }
+    let similar: Vec<String> = similar.iter().map(|it| format!("`{}`", it)).collect();
+    format_err!(
+        "crate `{}` not found on crates.io; did you mean {}?",
+        name,
+        similar.join(" or ")
+    )
+}
+
+/// Names of existing crates which are a typo away from `name`. The index can
+/// only be queried by exact name, so this tries out the likely candidates:
+/// swapped `-` and `_`, swapped adjacent characters and a missing character.
+fn similar_names(name: &str) -> Vec<String> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut candidates = vec![name.replace('-', "_"), name.replace('_', "-")];
+    for i in 1..chars.len() {
+        let mut swapped = chars.clone();
+        swapped.swap(i - 1, i);
+        candidates.push(swapped.into_iter().collect());
+    }
+    if chars.len() > 1 {
+        for i in 0..chars.len() {
+            let mut removed = chars.clone();
+            removed.remove(i);
+            candidates.push(removed.into_iter().collect());
+        }
+    }
+
+    let mut res = Vec::new();
+    for candidate in candidates {
+        if candidate == name || res.contains(&candidate) {
+            continue;
+        }
+        if let Ok(Some(_)) = fetch_versions(&candidate) {
+            res.push(candidate);
+            if res.len() == 3 {
+                break;
+            }
+        }
+    }
+    res
+}
+
+/// Fetches the published name and versions of `name`, `None` if there's no
+/// such crate.
+fn fetch_versions(name: &str) -> Result<Option<(String, Vec<IndexEntry>)>> {
+    let url = format!("{}/{}", SPARSE_INDEX_URL, index_path(name));
+    let body = match ureq::get(&url).call() {
+        Ok(response) => response.into_string()?,
+        // crates.io answers with 404 or 403 for crates it does not know about.
+        Err(ureq::Error::Status(404, _)) | Err(ureq::Error::Status(403, _)) => return Ok(None),
+        Err(err) => bail!("failed to query crates.io index for `{}`: {}", name, err),
+    };
+    let mut canonical = name.to_string();
+    let mut res = Vec::new();
+    for line in body.lines().filter(|it| !it.trim().is_empty()) {
+        let raw: RawEntry = serde_json::from_str(line)?;
+        let mut features: Vec<String> = raw
+            .features
+            .into_iter()
+            .chain(raw.features2.unwrap_or_default())
+            .map(|(name, _)| name)
+            .chain(
+                raw.deps
+                    .into_iter()
+                    .filter(|it| it.optional)
+                    .map(|it| it.name),
+            )
+            .collect();
+        features.sort();
+        features.dedup();
+        canonical = raw.name;
+        res.push(IndexEntry {
+            version: Version::parse(&raw.vers)?,
+            checksum: raw.cksum,
+            yanked: raw.yanked,
+            features,
+        });
+    }
+    res.sort_by(|a, b| a.version.cmp(&b.version));
+    Ok(Some((canonical, res)))
+}
+
+/// The newest version, preferring stable releases over pre-releases, just
+/// like Cargo does for `*` requirements.
+pub(crate) fn latest(entries: &[IndexEntry], include_yanked: bool) -> Option<&IndexEntry> {
+    let candidates = || {
+        entries
+            .iter()
+            .rev()
+            .filter(move |it| include_yanked || !it.yanked)
+    };
+    candidates()
+        .find(|it| !it.version.is_prerelease())
+        .or_else(|| candidates().next())
+}
+
+/// The newest version matching `req`.
+pub(crate) fn max_matching<'a>(
+    entries: &'a [IndexEntry],
+    req: &VersionReq,
+    include_yanked: bool,
+) -> Option<&'a IndexEntry> {
+    entries
+        .iter()
+        .rev()
+        .find(|it| (include_yanked || !it.yanked) && req.matches(&it.version))
+}
+
+/// The version published right before `version`. Pre-releases are only
+/// considered if `version` is a pre-release itself.
+pub(crate) fn previous<'a>(
+    entries: &'a [IndexEntry],
+    version: &Version,
+    include_yanked: bool,
+) -> Option<&'a IndexEntry> {
+    entries.iter().rev().find(|it| {
+        (include_yanked || !it.yanked)
+            && it.version < *version
+            && (version.is_prerelease() || !it.version.is_prerelease())
+    })
+}
+
+/// The versions published between `a` and `b`, exclusive, sorted by version.
+/// Pre-releases are only considered if one of the ends is a pre-release.
+pub(crate) fn between<'a>(
+    entries: &'a [IndexEntry],
+    a: &Version,
+    b: &Version,
+    include_yanked: bool,
+) -> Vec<&'a IndexEntry> {
+    let (low, high) = if a < b { (a, b) } else { (b, a) };
+    let prereleases = low.is_prerelease() || high.is_prerelease();
+    entries
+        .iter()
+        .filter(|it| {
+            (include_yanked || !it.yanked)
+                && *low < it.version
+                && it.version < *high
+                && (prereleases || !it.version.is_prerelease())
+        })
+        .collect()
+}
+
+fn index_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{}", name),
+        2 => format!("2/{}", name),
+        3 => format!("3/{}/{}", &name[..1], name),
+        _ => format!("{}/{}/{}", &name[..2], &name[2..4], name),
+    }
+}
@@ -0,0 +1,152 @@
+//! `diff --interactive`: the changed files one at a time, the high-risk ones
+//! first, each with the question of whether to approve it, skip it or flag
+//! it. The decisions are written to `review-notes.json` as they are made, so
+//! that quitting early keeps them:
+//!
+//! ```json
+//! {
+//!   "first": "rand:0.6.0",
+//!   "second": "rand:0.6.1",
+//!   "files": [
+//!     { "file": "build.rs", "decision": "approve" },
+//!     { "file": "src/lib.rs", "decision": "flag", "note": "new unsafe block" }
+//!   ]
+//! }
+//! ```
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use serde_json;
+
+use builtin_diff::{self, DiffOptions};
+use {color, files, pager, prompt, risky, PackageId, Result};
+
+/// The name of the notes in the `--destination` directory.
+pub(crate) const REVIEW_NOTES: &str = "review-notes.json";
+
+#[derive(Serialize)]
+struct Notes {
+    first: String,
+    second: String,
+    files: Vec<Note>,
+}
+
+#[derive(Serialize)]
+struct Note {
+    file: String,
+    decision: Decision,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Decision {
+    Approve,
+    Skip,
+    Flag,
+}
+
+/// Asks about each file which differs between `first`, checked out to `a`,
+/// and `second`, checked out to `b`, and writes the answers to `notes_path`.
+/// The diff of each file is paged if `page` is set. Returns whether the
+/// packages differ.
+pub(crate) fn review(
+    first: &PackageId,
+    a: &Path,
+    second: &PackageId,
+    b: &Path,
+    options: DiffOptions,
+    notes_path: &Path,
+    page: bool,
+) -> Result<bool> {
+    let mut changed = builtin_diff::changed_files(a, b, options.jobs)?;
+    let risky = risky::risky_files(a, b);
+    let rank = |path: &Path| {
+        risky
+            .iter()
+            .position(|(it, _)| it == path)
+            .unwrap_or(risky.len())
+    };
+    // The sort is stable, so the other files stay sorted by path.
+    changed.sort_by_key(|file| rank(&file.path));
+
+    let mut notes = Notes {
+        first: first.to_string(),
+        second: second.to_string(),
+        files: Vec::new(),
+    };
+    write_notes(notes_path, &notes)?;
+    for (idx, file) in changed.iter().enumerate() {
+        let mut title = format!("[{}/{}] {}", idx + 1, changed.len(), file.path.display());
+        if let Some((_, kind)) = risky.iter().find(|(it, _)| *it == file.path) {
+            title.push_str(&format!(", high-risk: {}", kind));
+        }
+        let pager = if page { pager::start()? } else { None };
+        {
+            let color = color::stdout();
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
+            builtin_diff::paint(&mut out, color, builtin_diff::BOLD, &title)?;
+            builtin_diff::write_file_diff(
+                &mut out,
+                color,
+                file,
+                &first.dir_name(),
+                &second.dir_name(),
+                options,
+            )?;
+            out.flush()?;
+        }
+        drop(pager);
+
+        let choice = prompt::choose(
+            "[a]pprove / [s]kip / [f]lag / [q]uit?",
+            &['a', 's', 'f', 'q'],
+        )?;
+        let decision = match choice {
+            Some('a') => Decision::Approve,
+            Some('s') => Decision::Skip,
+            Some('f') => Decision::Flag,
+            _ => break,
+        };
+        let note = match decision {
+            Decision::Flag => prompt::ask("Note, or nothing:")?.filter(|it| !it.is_empty()),
+            _ => None,
+        };
+        notes.files.push(Note {
+            file: file.path.display().to_string(),
+            decision,
+            note,
+        });
+        write_notes(notes_path, &notes)?;
+    }
+
+    let count = |decision| {
+        notes
+            .files
+            .iter()
+            .filter(|it| it.decision == decision)
+            .count()
+    };
+    eprintln!(
+        "Approved {}, skipped {} and flagged {} of {}; wrote the notes to {}",
+        count(Decision::Approve),
+        count(Decision::Skip),
+        count(Decision::Flag),
+        files(changed.len()),
+        notes_path.display()
+    );
+    Ok(!changed.is_empty())
+}
+
+fn write_notes(path: &Path, notes: &Notes) -> Result<()> {
+    let mut text = serde_json::to_string_pretty(notes)?;
+    text.push('\n');
+    fs::write(path, text).map_err(|err| format_err!("can't write {}: {}", path.display(), err))?;
+    Ok(())
+}
@@ -0,0 +1,89 @@
+//! `current --list`: the packages `current` would check out, with where they
+//! come from, their license and whether they run code at build time, without
+//! copying anything. They are selected as for a checkout, and described as in
+//! `manifest.json`, so that both always agree.
+
+use cargo_metadata;
+use serde_json;
+
+use snapshot::has_target;
+use {package_source, Result};
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Item<'a> {
+    name: &'a str,
+    version: String,
+    source: &'static str,
+    license: Option<&'a str>,
+    repository: Option<&'a str>,
+    build_script: bool,
+    proc_macro: bool,
+    /// The directory it would be checked out to.
+    directory: String,
+}
+
+/// The `packages` of `meta`, to be checked out to the `dirs`.
+pub(crate) fn items<'a>(
+    meta: &cargo_metadata::Metadata,
+    packages: &[&'a cargo_metadata::Package],
+    dirs: &[String],
+) -> Vec<Item<'a>> {
+    packages
+        .iter()
+        .zip(dirs)
+        .map(|(pkg, dir)| Item {
+            name: &pkg.name,
+            version: pkg.version.to_string(),
+            source: package_source(meta, pkg).kind(),
+            license: pkg.license.as_deref(),
+            repository: pkg.repository.as_deref(),
+            build_script: has_target(pkg, "custom-build"),
+            proc_macro: has_target(pkg, "proc-macro"),
+            directory: dir.clone(),
+        })
+        .collect()
+}
+
+pub(crate) fn to_json(items: &[Item]) -> Result<String> {
+    Ok(serde_json::to_string_pretty(items)?)
+}
+
+/// A table of the `items`, with aligned columns.
+pub(crate) fn render(items: &[Item]) -> String {
+    let yes = |it: bool| if it { "yes" } else { "" }.to_string();
+    let mut rows = vec![[
+        "Package".to_string(),
+        "Version".to_string(),
+        "Source".to_string(),
+        "License".to_string(),
+        "build.rs".to_string(),
+        "proc-macro".to_string(),
+    ]];
+    for item in items {
+        rows.push([
+            item.name.to_string(),
+            item.version.clone(),
+            item.source.to_string(),
+            item.license.unwrap_or_default().to_string(),
+            yes(item.build_script),
+            yes(item.proc_macro),
+        ]);
+    }
+    let mut widths = [0; 6];
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut res = String::new();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        res += cells.join("  ").trim_end();
+        res += "\n";
+    }
+    res
+}
@@ -0,0 +1,54 @@
+//! How many threads read and diff the files, and copy the packages of
+//! `current`, as chosen with `--jobs`.
+//!
+//! Only the work on each file or package runs in parallel: the results are
+//! put back in their order, so the output is the same with any number of
+//! jobs.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+/// The number of threads `jobs` stands for, `0` for one per core.
+pub(crate) fn threads(jobs: usize) -> usize {
+    match jobs {
+        0 => thread::available_parallelism().map_or(1, |it| it.get()),
+        jobs => jobs,
+    }
+}
+
+/// `f` applied to each of the `items` on up to `jobs` threads, see
+/// `threads`, in the order of the items.
+pub(crate) fn map<T, R, F>(jobs: usize, items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let jobs = threads(jobs).min(items.len());
+    if jobs <= 1 {
+        return items.iter().map(f).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(items.len()));
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::SeqCst);
+                let item = match items.get(idx) {
+                    Some(it) => it,
+                    None => break,
+                };
+                let res = f(item);
+                results.lock().unwrap().push((idx, res));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(idx, _)| *idx);
+    results.into_iter().map(|(_, res)| res).collect()
+}
@@ -0,0 +1,297 @@
+//! Machine readable results of `diff --format json`.
+//!
+//! The document looks like this:
+//!
+//! ```json
+//! {
+//!   "schema_version": 2,
+//!   "old": { "name": "rand", "version": "0.6.0", "source": "crates.io" },
+//!   "new": { "name": "rand", "version": "0.6.1", "source": "crates.io" },
+//!   "files": [
+//!     { "path": "Cargo.toml", "status": "modified", "binary": false, "insertions": 1, "deletions": 1 }
+//!   ],
+//!   "totals": { "files": 1, "insertions": 1, "deletions": 1 }
+//! }
+//! ```
+//!
+//! `status` is one of `added`, `removed`, `modified` and `renamed`; renamed
+//! files have an `old_path`. Binary files have no line counts. With
+//! `--include-patches`, text files have a `patch` with the unified hunks.
+//! With `--ignore-whitespace`, the files which only differ in whitespace are
+//! listed in `formatting_only` instead of `files`. With `--brief`, lines
+//! aren't counted, and there are no `insertions` and `deletions` at all.
+//! `size` has the numbers of files, bytes and lines of Rust code of both
+//! versions, `before` and `after`, with the source files in other languages
+//! counted apart. `manifest` has the changes of the dependencies, the features and the
+//! `[package]` fields of Cargo.toml, if there are any; dependencies are
+//! matched by `package`, the name of the crate. With `--unsafe-report`, `unsafe_code` has the counts of `unsafe` items of
+//! each file before and after, and the crate roots which no longer have
+//! `#![forbid(unsafe_code)]` in `forbid_removed`. With `--normalize`,
+//! `normalized` names the program which formatted both sides first, like
+//! `rustfmt`.
+//! `license` has the changes of the `license` and `license-file` fields and
+//! of the license files, and the changed READMEs, if there are any; its
+//! `expression_changed` tells whether the `license` means other licenses.
+//! `dependency_graph` has the transitive dependencies `added`, `removed` and
+//! `changed` to other versions, with their `old` and `new` versions and
+//! whether they are a `proc_macro` or a `build` dependency, if Cargo could
+//! resolve both packages and they differ.
+//! `risk` has the `score` of the changes, their `level`, `low`, `medium` or
+//! `high`, and the `reasons` for it, like `build.rs changed`.
+//! Fields are only ever added within the same `schema_version`; the second
+//! one named the packages `old` and `new`, which were `first` and `second`.
+//!
+//! A chain of versions is a single document with a report for each step, in
+//! order:
+//!
+//! ```json
+//! {
+//!   "schema_version": 2,
+//!   "steps": [
+//!     { "old": ..., "new": ..., "files": [...], "totals": {...} }
+//!   ]
+//! }
+//! ```
+
+use std::{io::Write, path::Path};
+
+use serde_json;
+
+use builtin_diff::{self, ChangedFile, DiffOptions};
+use dep_graph;
+use license;
+use manifest_diff;
+use risk;
+use size;
+use unsafe_report;
+use {jobs, PackageId, Result, Source, VersionSpec};
+
+const SCHEMA_VERSION: u32 = 2;
+
+/// What the report says about each file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Detail {
+    /// Only the paths and the statuses, for `--brief`.
+    Paths,
+    /// The numbers of changed lines too.
+    Lines,
+    /// The unified hunks too, for `--include-patches`.
+    Patches,
+}
+
+#[derive(Serialize)]
+struct Document<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    report: &'a Report,
+}
+
+#[derive(Serialize)]
+struct ChainDocument<'a> {
+    schema_version: u32,
+    steps: &'a [Report],
+}
+
+/// The differences between two packages.
+#[derive(Serialize)]
+pub(crate) struct Report {
+    old: Package,
+    new: Package,
+    files: Vec<File>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    formatting_only: Vec<String>,
+    totals: Totals,
+    size: size::Delta,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    license: Option<license::Changes>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    manifest: Option<manifest_diff::Changes>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) unsafe_code: Option<unsafe_report::Report>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) normalized: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) dependency_graph: Option<dep_graph::Changes>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) risk: Option<risk::Score>,
+}
+
+#[derive(Serialize)]
+struct Package {
+    name: String,
+    /// `None` for git and local packages.
+    version: Option<String>,
+    /// `crates.io`, `registry:NAME`, `git:URL#REV` or `path:PATH`.
+    source: String,
+}
+
+#[derive(Serialize)]
+struct File {
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old_path: Option<String>,
+    status: Status,
+    /// The percentage of lines a renamed file kept.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    similarity: Option<u8>,
+    binary: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    insertions: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deletions: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    patch: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Added,
+    Removed,
+    Modified,
+    Renamed,
+}
+
+#[derive(Serialize)]
+struct Totals {
+    files: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    insertions: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deletions: Option<usize>,
+}
+
+impl Report {
+    pub(crate) fn differ(&self) -> bool {
+        !self.files.is_empty()
+    }
+}
+
+pub(crate) fn write_report(out: &mut dyn Write, report: &Report) -> Result<()> {
+    let document = Document {
+        schema_version: SCHEMA_VERSION,
+        report,
+    };
+    serde_json::to_writer_pretty(&mut *out, &document)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+pub(crate) fn write_chain(out: &mut dyn Write, steps: &[Report]) -> Result<()> {
+    let document = ChainDocument {
+        schema_version: SCHEMA_VERSION,
+        steps,
+    };
+    serde_json::to_writer_pretty(&mut *out, &document)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// The report on the differences between `first`, checked out to `a`, and
+/// `second`, checked out to `b`.
+pub(crate) fn report(
+    first: &PackageId,
+    a: &Path,
+    second: &PackageId,
+    b: &Path,
+    detail: Detail,
+    options: DiffOptions,
+) -> Result<Report> {
+    let (formatting_only, mut changed): (Vec<ChangedFile>, Vec<ChangedFile>) =
+        builtin_diff::changed_files(a, b, options.jobs)?
+            .into_iter()
+            .partition(|file| options.ignore_whitespace && builtin_diff::is_formatting_only(file));
+    if let Some(threshold) = options.rename_threshold {
+        changed = builtin_diff::find_renames(changed, threshold, options.jobs);
+    }
+    let mut files = Vec::new();
+    files.extend(jobs::map(options.jobs, &changed, |file| {
+        let status = match (&file.old, &file.new, &file.renamed) {
+            (_, _, Some(_)) => Status::Renamed,
+            (None, _, _) => Status::Added,
+            (_, None, _) => Status::Removed,
+            _ => Status::Modified,
+        };
+        let old = file.old.as_deref().unwrap_or_default();
+        let new = file.new.as_deref().unwrap_or_default();
+        let mut res = File {
+            path: file.path.display().to_string(),
+            old_path: file
+                .renamed
+                .as_ref()
+                .map(|it| it.from.display().to_string()),
+            status,
+            similarity: file.renamed.as_ref().map(|it| it.similarity),
+            binary: true,
+            insertions: None,
+            deletions: None,
+            patch: None,
+        };
+        if let (Some(old), Some(new)) = (builtin_diff::as_text(old), builtin_diff::as_text(new)) {
+            res.binary = false;
+            if detail != Detail::Paths {
+                let (insertions, deletions) =
+                    builtin_diff::count_lines(old, new, options.ignore_whitespace);
+                res.insertions = Some(insertions);
+                res.deletions = Some(deletions);
+            }
+            if detail == Detail::Patches {
+                res.patch = Some(builtin_diff::unified_hunks(old, new, options));
+            }
+        }
+        res
+    }));
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let sum = |count: fn(&File) -> Option<usize>| match detail {
+        Detail::Paths => None,
+        _ => Some(files.iter().filter_map(count).sum()),
+    };
+    let totals = Totals {
+        files: files.len(),
+        insertions: sum(|it| it.insertions),
+        deletions: sum(|it| it.deletions),
+    };
+    Ok(Report {
+        old: package(first),
+        new: package(second),
+        files,
+        formatting_only: formatting_only
+            .iter()
+            .map(|it| it.path.display().to_string())
+            .collect(),
+        totals,
+        size: size::compare(a, b)?,
+        license: Some(license::compare(a, b, options.jobs)?).filter(|it| !it.is_empty()),
+        manifest: Some(manifest_diff::compare(a, b)).filter(|it| !it.is_empty()),
+        unsafe_code: None,
+        normalized: None,
+        dependency_graph: None,
+        risk: None,
+    })
+}
+
+fn package(pkg_id: &PackageId) -> Package {
+    let (version, source) = match &pkg_id.source {
+        Source::Registry {
+            version, registry, ..
+        } => {
+            let version = match version {
+                VersionSpec::Exact(version) => Some(version.to_string()),
+                _ => None,
+            };
+            let source = match registry {
+                Some(registry) => format!("registry:{}", registry),
+                None => "crates.io".to_string(),
+            };
+            (version, source)
+        }
+        Source::Git { url, rev } => (None, format!("git:{}#{}", url, rev)),
+        Source::Path(path) => (None, format!("path:{}", path.display())),
+    };
+    Package {
+        name: pkg_id.name.clone(),
+        version,
+        source,
+    }
+}
@@ -1,119 +1,3435 @@
 #[macro_use]
 extern crate failure;
+extern crate glob;
+#[cfg(unix)]
+extern crate libc;
+#[macro_use]
+extern crate serde_derive;
 extern crate cargo_metadata;
-extern crate copy_dir;
+extern crate flate2;
+extern crate regex;
 extern crate semver;
+extern crate serde;
+extern crate serde_json;
+extern crate sha2;
+extern crate similar;
+extern crate tar;
 extern crate tempdir;
+extern crate terminal_size;
+extern crate toml;
+extern crate ureq;
+extern crate zip;
+
+/// `eprintln!`, unless `quiet`, as with `--quiet`: for what `current` tells
+/// on stderr but the errors.
+macro_rules! note {
+    ($quiet:expr, $($arg:tt)*) => {
+        if !$quiet {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+mod archive;
+mod builtin_diff;
+mod color;
+mod delta;
+mod dep_graph;
+mod duplicates;
+mod filter;
+mod generated;
+mod html_report;
+mod index;
+mod interactive;
+mod inventory;
+mod jobs;
+mod json_report;
+mod license;
+mod manifest_diff;
+mod markdown_report;
+mod normalize;
+mod offline;
+mod opaque;
+mod pager;
+mod policy;
+mod progress;
+mod prompt;
+mod repo;
+mod resolve;
+mod review_index;
+mod reviewignore;
+mod risk;
+mod risky;
+mod sbom;
+mod scan;
+mod side_by_side;
+mod size;
+mod snapshot;
+mod spec_file;
+mod summary;
+mod tree;
+mod truncate;
+mod unsafe_report;
+mod vendor;
+mod verify;
+mod yanked;
 
 use std::{
-    collections::HashMap,
-    ffi::OsString,
+    collections::{BTreeSet, HashMap, HashSet},
+    env,
+    ffi::{OsStr, OsString},
     fmt, fs,
+    io::{self, Read, Write},
+    mem,
     path::{Path, PathBuf},
     process::{Command, Stdio},
     str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
 };
 
-use copy_dir::copy_dir;
-use semver::Version;
-use tempdir::TempDir;
+use builtin_diff::DiffOptions;
+use cargo_metadata::DependencyKind;
+use filter::Filter;
+use glob::Pattern;
+use index::IndexEntry;
+use json_report::Detail;
+use resolve::{specify, Resolver};
+use reviewignore::ReviewIgnore;
+use semver::{Version, VersionReq};
+use sha2::{Digest, Sha256};
+use summary::Summary;
+use tempdir::TempDir;
+use truncate::Limited;
+
+pub use color::{error_label, set_color, warning_label, ColorChoice};
+pub use generated::generated_patterns;
+pub use policy::{DeniedSource, PolicyViolation};
+pub use sbom::SbomFormat;
+pub use spec_file::{read_diff_specs, read_package_ids};
+pub use truncate::Truncation;
+
+pub use failure::Error;
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Mirrors `PackageId` from Cargo. `PackageId` is an unambiguous reference to a
+/// package version.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PackageId {
+    name: String,
+    source: Source,
+}
+
+/// Which version of a registry package is meant.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VersionSpec {
+    /// Exactly this version, `name:x.y.z`.
+    Exact(Version),
+    /// No version at all, just `name`: the locked version on the first side
+    /// of a diff and the latest one on the second side.
+    Unspecified,
+    /// The newest published version, `name:latest`.
+    Latest,
+    /// The version in the Cargo.lock of the current workspace, `name:locked`.
+    Locked,
+    /// The version published right before the other side of the diff, or
+    /// before the locked one, `name:prev` or `name:-1`.
+    Prev,
+    /// The newest published version matching the requirement, `name:^x.y`.
+    /// Anything which does not parse as a full version is a requirement, so
+    /// `name:1.0` means `name:^1.0`.
+    Req(VersionReq),
+}
+
+impl VersionSpec {
+    /// The requirement to put into a conjured Cargo.toml.
+    fn to_requirement(&self) -> String {
+        match self {
+            // Cargo ignores (and warns about) build metadata in requirements.
+            VersionSpec::Exact(version) => {
+                let mut version = version.clone();
+                version.build.clear();
+                format!("={}", version)
+            }
+            VersionSpec::Unspecified | VersionSpec::Latest => "*".to_string(),
+            VersionSpec::Req(req) => req.to_string(),
+            VersionSpec::Locked | VersionSpec::Prev => {
+                unreachable!("`locked` and `prev` must be resolved first")
+            }
+        }
+    }
+}
+
+/// Where the sources of a package come from.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Source {
+    /// A version published to a registry, crates.io if `registry` is `None`.
+    /// Other registries are looked up by name in `.cargo/config.toml`.
+    /// `checksum` is the expected SHA-256 of the `.crate` file, as in
+    /// `name:x.y.z:sha256=...`.
+    Registry {
+        version: VersionSpec,
+        registry: Option<String>,
+        checksum: Option<String>,
+    },
+    /// A revision of a git repository, as in `git = "url", rev = "rev"`.
+    Git { url: String, rev: String },
+    /// A package on the local file system, for example a working copy of a
+    /// crate which is about to be published.
+    Path(PathBuf),
+}
+
+impl fmt::Display for PackageId {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.name.fmt(fmt)?;
+        match &self.source {
+            // The checksum is left out: it's verified, not reviewed.
+            Source::Registry {
+                version, registry, ..
+            } => {
+                match version {
+                    VersionSpec::Exact(version) => write!(fmt, ":{}", version)?,
+                    VersionSpec::Unspecified => (),
+                    VersionSpec::Latest => fmt.write_str(":latest")?,
+                    VersionSpec::Locked => fmt.write_str(":locked")?,
+                    VersionSpec::Prev => fmt.write_str(":prev")?,
+                    VersionSpec::Req(req) => write!(fmt, ":{}", req)?,
+                }
+                if let Some(registry) = registry {
+                    write!(fmt, "@{}", registry)?;
+                }
+                Ok(())
+            }
+            // Urls are long, so only the revision is shown.
+            Source::Git { rev, .. } => write!(fmt, ":git.{}", rev),
+            Source::Path(_) => fmt.write_str(":local"),
+        }
+    }
+}
+
+impl PackageId {
+    /// The name of the directory to check the package out to, like
+    /// `rand-0.6.0`. Unlike `Display`, this is a valid file name on every
+    /// platform and doesn't need escaping in urls.
+    pub fn dir_name(&self) -> String {
+        dir_name(&self.to_string().replacen(':', "-", 1))
+    }
+}
+
+/// Replaces characters which are not allowed or awkward in file names, like
+/// `:`, `/` and `+`, with `_`.
+fn dir_name(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+impl FromStr for PackageId {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<PackageId> {
+        if let Some(path) = s.strip_prefix("path:") {
+            return local_package_id(Path::new(path));
+        }
+        if ["registry+", "sparse+", "git+", "path+"]
+            .iter()
+            .any(|scheme| s.starts_with(scheme))
+        {
+            return parse_pkgid_url(s);
+        }
+        // Both Cargo's `name@x.y.z` and our `name:x.y.z` are accepted. Crate
+        // names can contain neither `:` nor `@`, so the first one wins.
+        let sep_idx = s.find([':', '@']).unwrap_or(s.len());
+        let name = s[..sep_idx].to_string();
+        if !is_valid_name(&name) {
+            bail!(
+                "invalid package specification: {:?}; expected \"name\", \"name:x.y.z\", \"name@x.y.z\", \"name:x.y.z@registry\" or \"name:git+URL#REV\"",
+                s
+            )
+        }
+        if sep_idx == s.len() {
+            return Ok(PackageId {
+                name,
+                source: Source::Registry {
+                    version: VersionSpec::Unspecified,
+                    registry: None,
+                    checksum: None,
+                },
+            });
+        }
+        let source = &s[sep_idx + 1..];
+        let source = if let Some(git) = source.strip_prefix("git+") {
+            parse_git_source(git).ok_or_else(|| {
+                format_err!("invalid git source: {:?}; expected \"name:git+URL#REV\"", s)
+            })?
+        } else {
+            let (version, registry) = match source.find('@') {
+                Some(at_idx) if at_idx + 1 < source.len() => {
+                    (&source[..at_idx], Some(source[at_idx + 1..].to_string()))
+                }
+                Some(_) => bail!(
+                    "invalid package specification: {:?}; registry name is empty",
+                    s
+                ),
+                None => (source, None),
+            };
+            let (version, checksum) = match version.find(":sha256=") {
+                Some(idx) => {
+                    let checksum = &version[idx + ":sha256=".len()..];
+                    if checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+                        bail!(
+                            "invalid package specification: {:?}; checksum must be 64 hex digits",
+                            s
+                        )
+                    }
+                    (&version[..idx], Some(checksum.to_ascii_lowercase()))
+                }
+                None => (version, None),
+            };
+            Source::Registry {
+                version: parse_version_spec(version)?,
+                registry,
+                checksum,
+            }
+        };
+        Ok(PackageId { name, source })
+    }
+}
+
+/// Both sides of a diff in a single argument, `name:old..new`. The new side
+/// can be a full package specification as well, `name:old..name:new`, but it
+/// must be the same crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffSpec {
+    pub first: PackageId,
+    pub second: PackageId,
+}
+
+impl DiffSpec {
+    /// The version of the crate of `second` in the Cargo.lock of the current
+    /// workspace against `second`, for a diff given a single crate.
+    pub fn from_locked(second: PackageId) -> DiffSpec {
+        let first = PackageId {
+            name: second.name.clone(),
+            source: Source::Registry {
+                version: VersionSpec::Locked,
+                registry: None,
+                checksum: None,
+            },
+        };
+        DiffSpec { first, second }
+    }
+}
+
+impl FromStr for DiffSpec {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<DiffSpec> {
+        // Versions never contain `..`, the last one is the separator.
+        let (first, second) = match s.rfind("..") {
+            Some(idx) if idx > 0 && idx + 2 < s.len() => (&s[..idx], &s[idx + 2..]),
+            _ => bail!(
+                "invalid diff specification: {:?}; expected \"name:old..new\" or \"name:old..name:new\"",
+                s
+            ),
+        };
+        let first: PackageId = first.parse()?;
+        let second: PackageId = if second.contains(':') {
+            second.parse()?
+        } else {
+            format!("{}:{}", first.name, second).parse()?
+        };
+        if !same_name(&first.name, &second.name) {
+            bail!(
+                "invalid diff specification: {:?}; both sides must be the same crate, got `{}` and `{}`",
+                s,
+                first.name,
+                second.name
+            )
+        }
+        Ok(DiffSpec { first, second })
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Reads the name of the package in `dir` from its Cargo.toml.
+fn local_package_id(dir: &Path) -> Result<PackageId> {
+    let manifest_path = dir.join("Cargo.toml");
+    let manifest = fs::read_to_string(&manifest_path)
+        .map_err(|err| format_err!("can't read {}: {}", manifest_path.display(), err))?;
+    let manifest: toml::Value = toml::from_str(&manifest)
+        .map_err(|err| format_err!("can't parse {}: {}", manifest_path.display(), err))?;
+    let name = manifest
+        .get("package")
+        .and_then(|it| it.get("name"))
+        .and_then(|it| it.as_str())
+        .ok_or_else(|| format_err!("{} has no package name", manifest_path.display()))?;
+    Ok(PackageId {
+        name: name.to_string(),
+        source: Source::Path(dir.to_path_buf()),
+    })
+}
+
+fn parse_version_spec(s: &str) -> Result<VersionSpec> {
+    match s {
+        "latest" => return Ok(VersionSpec::Latest),
+        "locked" => return Ok(VersionSpec::Locked),
+        "prev" | "-1" => return Ok(VersionSpec::Prev),
+        _ => (),
+    }
+    if let Ok(version) = Version::parse(s) {
+        return Ok(VersionSpec::Exact(version));
+    }
+    let req = VersionReq::parse(s)
+        .map_err(|err| format_err!("invalid version or requirement {:?}: {}", s, err))?;
+    Ok(VersionSpec::Req(req))
+}
+
+/// Parses Cargo's package id specifications, as printed by `cargo pkgid` and
+/// `cargo metadata`, for example
+/// `registry+https://github.com/rust-lang/crates.io-index#rand@0.6.0`.
+/// See https://doc.rust-lang.org/cargo/reference/pkgid-spec.html.
+fn parse_pkgid_url(s: &str) -> Result<PackageId> {
+    let (url, fragment) = match s.rfind('#') {
+        Some(hash_idx) => (&s[..hash_idx], Some(&s[hash_idx + 1..])),
+        None => (s, None),
+    };
+    let (kind, url) = url.split_at(url.find('+').unwrap() + 1);
+    let (url, query) = match url.find('?') {
+        Some(idx) => (&url[..idx], Some(&url[idx + 1..])),
+        None => (url, None),
+    };
+    if !url.contains("://") {
+        bail!(
+            "invalid package id specification: {:?}; expected \"registry+URL#name@x.y.z\" or \"git+URL?rev=REV#name@x.y.z\"",
+            s
+        )
+    }
+    let fragment = fragment.map(percent_decode);
+    // The name defaults to the last segment of the url.
+    let (name, version) = match fragment.as_deref() {
+        Some(fragment) => match fragment.find('@') {
+            Some(at_idx) => (&fragment[..at_idx], Some(&fragment[at_idx + 1..])),
+            None if fragment.starts_with(|c: char| c.is_ascii_digit()) => ("", Some(fragment)),
+            None => (fragment, None),
+        },
+        None => ("", None),
+    };
+    let name = if name.is_empty() {
+        url.trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or("")
+            .trim_end_matches(".git")
+    } else {
+        name
+    };
+
+    let source = match kind {
+        "path+" => {
+            let path = url.strip_prefix("file://").ok_or_else(|| {
+                format_err!(
+                    "invalid package id specification: {:?}; expected a file url",
+                    s
+                )
+            })?;
+            return local_package_id(Path::new(&percent_decode(path)));
+        }
+        "git+" => {
+            let param = |key: &str| {
+                query?
+                    .split('&')
+                    .find_map(|it| it.strip_prefix(key)?.strip_prefix('='))
+                    .map(percent_decode)
+            };
+            // Cargo accepts branches and tags as `rev`, if spelled out in full.
+            let rev = param("rev")
+                .or_else(|| param("tag").map(|it| format!("refs/tags/{}", it)))
+                .or_else(|| param("branch").map(|it| format!("refs/heads/{}", it)))
+                .ok_or_else(|| {
+                    format_err!(
+                        "invalid package id specification: {:?}; the git revision is unknown, use \"name:git+URL#REV\"",
+                        s
+                    )
+                })?;
+            Source::Git {
+                url: url.to_string(),
+                rev,
+            }
+        }
+        _ => {
+            let is_crates_io = url.trim_end_matches('/')
+                == "https://github.com/rust-lang/crates.io-index"
+                || url.trim_end_matches('/') == "https://index.crates.io";
+            if !is_crates_io {
+                bail!(
+                    "invalid package id specification: {:?}; only crates.io registry urls are known, use \"name:x.y.z@registry\" for other registries",
+                    s
+                )
+            }
+            let version = match version {
+                Some(version) => parse_version_spec(version)?,
+                None => VersionSpec::Unspecified,
+            };
+            Source::Registry {
+                version,
+                registry: None,
+                checksum: None,
+            }
+        }
+    };
+    if !is_valid_name(name) {
+        bail!(
+            "invalid package id specification: {:?}; can't determine the package name",
+            s
+        )
+    }
+    Ok(PackageId {
+        name: name.to_string(),
+        source,
+    })
+}
+
+/// Decodes `%XX` escapes, which Cargo uses in the query part of git urls.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' {
+            s.get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(byte) => {
+                res.push(byte);
+                i += 3;
+            }
+            None => {
+                res.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+fn parse_git_source(s: &str) -> Option<Source> {
+    let hash_idx = s.rfind('#')?;
+    let url = &s[..hash_idx];
+    let rev = &s[hash_idx + 1..];
+    if !url.contains("://") || rev.is_empty() {
+        return None;
+    }
+    Some(Source::Git {
+        url: url.to_string(),
+        rev: rev.to_string(),
+    })
+}
+
+#[derive(Debug)]
+pub struct Diff {
+    pub first: PackageId,
+    pub second: PackageId,
+    pub dest: Option<PathBuf>,
+    /// Consider yanked versions when resolving `latest`, `prev` and version
+    /// requirements.
+    pub include_yanked: bool,
+    /// Fail instead of warning if one of the versions is yanked.
+    pub deny_yanked: bool,
+    /// Don't access the network: skip yanked checks and run Cargo with
+    /// `--offline`.
+    pub offline: bool,
+    pub features: Features,
+    /// The program to compare the sources with, `diff -r` if `None`.
+    pub tool: Option<OsString>,
+    /// Arguments passed to the tool before the two directories.
+    pub tool_args: Vec<OsString>,
+    /// Print the diff without any external program. This is also done if
+    /// there's no `diff` command.
+    pub builtin: bool,
+    /// Lines of context around the changes in the builtin diff.
+    pub context: usize,
+    /// Show the removed and the added files which have at least this
+    /// percentage of their lines in common as renamed, in the builtin diff,
+    /// `--brief`, `--stat` and the JSON report.
+    pub rename_threshold: u8,
+    /// Compare lines without their whitespace, like `diff -w`.
+    pub ignore_whitespace: bool,
+    /// Print only a summary of the changed files and lines.
+    pub stat: bool,
+    /// Print the builtin diff in two columns.
+    pub side_by_side: bool,
+    /// The width of the side-by-side diff, that of the terminal if `None`.
+    pub width: Option<usize>,
+    /// Globs of the files to compare, relative to the crate root. All files
+    /// are compared if empty.
+    pub include: Vec<String>,
+    /// Don't include Cargo.toml, build scripts and proc-macros implicitly.
+    pub include_strict: bool,
+    /// Globs of the files to leave out of the comparison, applied after the
+    /// includes.
+    pub exclude: Vec<String>,
+    /// Leave out the tests, the benches, the examples and the modules which
+    /// are only compiled for tests.
+    pub no_tests: bool,
+    /// Leave out the files listed in the `.reviewignore` of the workspace and
+    /// in the user's ignore file, see `reviewignore`.
+    pub reviewignore: bool,
+    /// Format the sources of both sides the same way before comparing them,
+    /// see `normalize`.
+    pub normalize: Option<Normalizer>,
+    pub format: OutputFormat,
+    /// Add unified hunks of the changed files to the JSON output.
+    pub include_patches: bool,
+    /// List only the paths of the changed files, without diffing the lines.
+    pub brief: bool,
+    /// The file to write the JSON or HTML report to instead of stdout. With
+    /// the text format, a patch of the changes is written there.
+    pub output: Option<PathBuf>,
+    /// Compare the sources of the tag of `first` in its repository with the
+    /// published `first`. `second` is unused.
+    pub against_repo: bool,
+    /// Compare the copy of `first` in the vendored sources of the workspace
+    /// with the published `first`. `second` is unused.
+    pub against_vendored: bool,
+    /// More versions to compare after `second`, each with the one before it.
+    pub chain: Vec<PackageId>,
+    /// Swap the sides once they are resolved, so that `second` is the old
+    /// one. Along a chain, the versions go backwards.
+    pub reverse: bool,
+    /// Compare every release on the way from `first` to `second`, and along
+    /// the chain, one step at a time.
+    pub all_intermediate: bool,
+    /// The most steps `all_intermediate` may take.
+    pub max_steps: usize,
+    /// Compare packages with different names, like a crate and its fork.
+    pub allow_different_names: bool,
+    /// Show the changes of the manifest, the build script and the proc-macro
+    /// entry point before the rest.
+    pub prioritize_risky: bool,
+    /// Count the `unsafe` code of both packages, and report where there's
+    /// more of it.
+    pub unsafe_report: bool,
+    /// Print the risk score of each step, see `risk`.
+    pub risk: bool,
+    /// Fail if the risk of a step is this or higher.
+    pub risk_threshold: Option<RiskLevel>,
+    /// Fail if the second package adds binary files.
+    pub deny_new_binaries: bool,
+    /// Fail if the second package pulls in dependencies the first one
+    /// doesn't, see `dep_graph`.
+    pub deny_new_deps: bool,
+    /// Highlight the changed words of the changed lines, with the builtin
+    /// diff or in the HTML report.
+    pub color_words: bool,
+    /// Show the hunks of the generated files too, rather than a summary
+    /// line, see `generated`.
+    pub show_generated: bool,
+    /// The files, besides those `generated` knows, to show a summary line
+    /// for, see `generated_patterns`.
+    pub generated: Vec<Pattern>,
+    /// Page the differences when stdout is a terminal.
+    pub pager: bool,
+    /// Leave out the files Cargo adds to packages, and compare the original
+    /// Cargo.toml files.
+    pub strip_artifacts: bool,
+    /// Tell how many unchanged files are left out before running the diff
+    /// tool.
+    pub verbose: bool,
+    /// Replace the packages already checked out to the destination.
+    pub force: bool,
+    /// Open the sources with a GUI diff tool instead of comparing them.
+    pub open: bool,
+    /// Print the directories of the sources, as they would be compared, and
+    /// keep them, instead of comparing them.
+    pub keep: bool,
+    /// Pack the sources, the patch and the summary into this `.tar.gz` or
+    /// `.zip` file.
+    pub archive: Option<PathBuf>,
+    /// The longest patch of a file in the markdown report, in bytes.
+    pub max_patch_size: usize,
+    /// Print only the hunks whose added lines match suspicious patterns.
+    pub scan: bool,
+    /// Regexes to scan for, on top of the default ones.
+    pub scan_patterns: Vec<String>,
+    /// Ask about each changed file, and write the answers to the notes.
+    pub interactive: bool,
+    /// Where the notes of `interactive` go, `review-notes.json` in the
+    /// destination if `None`.
+    pub notes: Option<PathBuf>,
+    /// How many threads compare the files, as many as the CPUs if `0`.
+    pub jobs: usize,
+    /// Where the diffs printed to the terminal stop, shared by all the
+    /// runs of an invocation, which tells it with `Truncation::report`.
+    pub truncation: Arc<Truncation>,
+}
+
+/// How `diff` prints the differences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// For humans, with an external diff program or the builtin one.
+    Text,
+    /// A JSON document with the list of changed files, see `json_report`.
+    Json,
+    /// A self-contained HTML page, see `html_report`.
+    Html,
+    /// A summary to paste into a pull request, see `markdown_report`.
+    Markdown,
+}
+
+impl OutputFormat {
+    fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Text => "text",
+            OutputFormat::Json => "json",
+            OutputFormat::Html => "html",
+            OutputFormat::Markdown => "markdown",
+        }
+    }
+}
+
+/// How `diff --normalize` formats the sources before comparing them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalizer {
+    /// The Rust files, with `rustfmt` and its default style.
+    Rustfmt,
+}
+
+impl Normalizer {
+    fn name(self) -> &'static str {
+        match self {
+            Normalizer::Rustfmt => "rustfmt",
+        }
+    }
+}
+
+/// How risky the changes of a diff are, see `risk`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RiskLevel {
+    Low,
+    Medium,
+    High,
+}
+
+impl RiskLevel {
+    fn name(self) -> &'static str {
+        match self {
+            RiskLevel::Low => "low",
+            RiskLevel::Medium => "medium",
+            RiskLevel::High => "high",
+        }
+    }
+}
+
+/// Features of the packages to enable, as with `cargo build --features`. The
+/// sources of a package don't depend on them, but its optional dependencies
+/// do.
+#[derive(Debug, Clone, Default)]
+pub struct Features {
+    pub features: Vec<String>,
+    /// Only for `current`, which resolves them with Cargo.
+    pub all_features: bool,
+    pub no_default_features: bool,
+}
+
+impl Diff {
+    /// Compares the packages, and returns whether they differ. Only checking
+    /// the sources out to a destination, or keeping them, doesn't compare
+    /// them.
+    pub fn run(self) -> Result<bool> {
+        self.check_args()?;
+        let filter = Filter::new(&self.include, &self.exclude, self.include_strict)?;
+        let reviewignore = if self.reviewignore {
+            ReviewIgnore::load()?
+        } else {
+            ReviewIgnore::default()
+        };
+        let options = DiffOptions {
+            context: self.context,
+            ignore_whitespace: self.ignore_whitespace,
+            color_words: self.color_words,
+            rename_threshold: Some(self.rename_threshold),
+            collapse_generated: !self.show_generated,
+            generated: &self.generated,
+            jobs: self.jobs,
+            truncation: &self.truncation,
+        };
+        let mut resolver = Resolver::new(self.include_yanked, self.offline);
+        let pkg_ids = self.select(&mut resolver)?;
+        let mut sources = self.fetch_sources(&mut resolver, &pkg_ids)?;
+        self.filter_sources(&mut sources, &filter, &reviewignore)?;
+        let sides = &sources.sides;
+        if let Some(dir) = &self.dest {
+            let steps = sides.len() - 1;
+            let mut dirs = Vec::new();
+            for (idx, pair) in sides.windows(2).enumerate() {
+                let (first, second) = (&pair[0].0, &pair[1].0);
+                let step = if steps > 1 {
+                    dir.join(step_dir_name(idx, first, second))
+                } else {
+                    dir.clone()
+                };
+                let (old, new) = side_dirs(&step, first, second);
+                dirs.extend([old, new]);
+            }
+            check_dests(&dirs, self.force)?;
+        }
+        if self.keep {
+            let ids: Vec<String> = sides.iter().map(|(it, _)| it.to_string()).collect();
+            eprintln!(
+                "Kept the sources of {}, one directory per line, the old ones first:",
+                enumerate(&ids)
+            );
+            for (_, src) in sides.iter() {
+                println!("{}", fs::canonicalize(src)?.display());
+            }
+            // The sources in the registry or in a git checkout stay anyway.
+            if sides
+                .iter()
+                .any(|(_, src)| src.starts_with(sources.tmpdir.path()))
+            {
+                sources.tmpdir.into_path();
+            }
+            return Ok(false);
+        }
+        // Only the differences are paged, the progress comes before them. An
+        // external tool may well be interactive.
+        let pager = if self.pager
+            && self.dest.is_none()
+            && !self.interactive
+            && self.tool.is_none()
+            && !self.open
+            && self.archive.is_none()
+        {
+            pager::start()?
+        } else {
+            None
+        };
+
+        let mut findings = Findings::default();
+        if self.format == OutputFormat::Json {
+            let differ = self.write_json(&sources, options, &mut findings)?;
+            drop(pager);
+            return self.finish(&findings).map(|()| differ);
+        }
+        // Without --destination, the sources are opened from a temporary
+        // directory, which is kept if there's no tool to open them with.
+        let open_tool = if self.open {
+            gui_tool(self.tool.as_deref())
+        } else {
+            None
+        };
+        let open_dir = if self.open && self.dest.is_none() {
+            Some(TempDir::new("cargo-review-deps-open")?)
+        } else {
+            None
+        };
+        // Likewise, the archive is packed from a temporary directory.
+        let archive_dir = if self.archive.is_some() && self.dest.is_none() {
+            Some(TempDir::new("cargo-review-deps-archive")?)
+        } else {
+            None
+        };
+        let dest_root = self
+            .dest
+            .clone()
+            .or_else(|| open_dir.as_ref().map(|it| it.path().to_path_buf()))
+            .or_else(|| archive_dir.as_ref().map(|it| it.path().to_path_buf()));
+        let differ = self.compare_steps(
+            &sources,
+            dest_root.as_deref(),
+            open_tool.as_deref(),
+            options,
+            &mut findings,
+        )?;
+        if let (Some(path), Some(dir)) = (&self.archive, &dest_root) {
+            archive::write(path, dir)?;
+        }
+        // The sizes come last, after the pager is done.
+        drop(pager);
+        if let (None, Some(dir)) = (&open_tool, open_dir) {
+            dir.into_path();
+        }
+        self.finish(&findings)?;
+        Ok(differ)
+    }
+
+    /// Fails if some of the options can't be used together.
+    fn check_args(&self) -> Result<()> {
+        if self.format != OutputFormat::Text {
+            let other = [
+                ("--destination", self.dest.is_some()),
+                ("--tool", self.tool.is_some()),
+                ("--builtin", self.builtin),
+                ("--stat", self.stat),
+                ("--side-by-side", self.side_by_side),
+                ("--prioritize-risky", self.prioritize_risky),
+                ("--open", self.open),
+                ("--keep", self.keep),
+                ("--archive", self.archive.is_some()),
+                ("--scan", self.scan),
+                ("--interactive", self.interactive),
+            ];
+            if let Some((arg, _)) = other.iter().find(|(_, given)| *given) {
+                bail!("--format {} can't be used with {}", self.format.name(), arg);
+            }
+        }
+        if self.brief && matches!(self.format, OutputFormat::Html | OutputFormat::Markdown) {
+            bail!("--format {} can't be used with --brief", self.format.name());
+        }
+        if self.color_words && matches!(self.format, OutputFormat::Json | OutputFormat::Markdown) {
+            bail!(
+                "--format {} can't be used with --color-words",
+                self.format.name()
+            );
+        }
+        if self.unsafe_report && self.format == OutputFormat::Html {
+            bail!("--format html can't be used with --unsafe-report");
+        }
+        if self.risk && self.format == OutputFormat::Html {
+            bail!("--format html can't be used with --risk");
+        }
+        if let Some(path) = &self.archive {
+            archive::check_format(path)?;
+        }
+        if self.normalize == Some(Normalizer::Rustfmt) {
+            normalize::check_rustfmt()?;
+        }
+        if self.scan {
+            scan::patterns(&self.scan_patterns)?;
+        }
+        if self.interactive && self.dest.is_none() && self.notes.is_none() {
+            bail!("--interactive needs --destination or --notes, to write the review notes to");
+        }
+        if self.dest.is_some() && !self.open {
+            if self.tool.is_some() {
+                bail!("--tool can't be used with --destination, except with --open");
+            }
+            if !self.tool_args.is_empty() {
+                bail!("arguments for the diff program can't be used with --destination, except with --open");
+            }
+        }
+        if let Some(width) = self.width {
+            if width < side_by_side::MIN_WIDTH {
+                bail!("--width must be at least {}", side_by_side::MIN_WIDTH);
+            }
+        }
+        Ok(())
+    }
+
+    /// The packages to compare, one after the other, as `resolver` resolves
+    /// them, with the releases in between with `all_intermediate`.
+    fn select(&self, resolver: &mut Resolver) -> Result<Vec<PackageId>> {
+        let mut pkg_ids = if self.against_repo || self.against_vendored {
+            vec![resolver.resolve(specify(self.first.clone(), VersionSpec::Locked), None)?]
+        } else {
+            let (first, second) = resolver.resolve_pair(self.first.clone(), self.second.clone())?;
+            vec![first, second]
+        };
+        // Like on the second side, a bare name further down the chain means
+        // the latest version.
+        for pkg_id in self.chain.iter().cloned() {
+            let pkg_id = resolver.resolve(specify(pkg_id, VersionSpec::Latest), pkg_ids.last())?;
+            pkg_ids.push(pkg_id);
+        }
+        if let Some(other) = pkg_ids
+            .iter()
+            .find(|it| !same_name(&it.name, &pkg_ids[0].name))
+        {
+            if !self.allow_different_names {
+                bail!(
+                    "{} and {} are different crates; pass --allow-different-names to compare them anyway",
+                    pkg_ids[0],
+                    other
+                );
+            }
+            let names: Vec<String> = pkg_ids.iter().map(ToString::to_string).collect();
+            eprintln!(
+                "{} comparing DIFFERENT crates, {}; all of their files may differ",
+                warning_label(),
+                enumerate(&names)
+            );
+        }
+        if self.all_intermediate {
+            let mut all = vec![pkg_ids[0].clone()];
+            for pair in pkg_ids.windows(2) {
+                all.extend(resolver.intermediate(&pair[0], &pair[1])?);
+                all.push(pair[1].clone());
+            }
+            if all.len() - 1 > self.max_steps {
+                bail!(
+                    "reviewing every release from {} to {} takes {} steps, more than --max-steps {}",
+                    all[0],
+                    all[all.len() - 1],
+                    all.len() - 1,
+                    self.max_steps
+                );
+            }
+            pkg_ids = all;
+        }
+        if pkg_ids.len() > 2 {
+            if matches!(self.format, OutputFormat::Html | OutputFormat::Markdown) {
+                bail!(
+                    "--format {} can't be used with more than two versions",
+                    self.format.name()
+                );
+            }
+            if self.interactive {
+                bail!("--interactive can't be used with more than two versions");
+            }
+            if self.format == OutputFormat::Text && self.output.is_some() {
+                bail!(
+                    "--output can't be used with more than two versions, except with --format json"
+                );
+            }
+        }
+        Ok(pkg_ids)
+    }
+
+    /// Downloads the `pkg_ids`, as `resolver` resolved them, and checks out
+    /// the sources to compare, the local ones aside.
+    fn fetch_sources(&self, resolver: &mut Resolver, pkg_ids: &[PackageId]) -> Result<Sources> {
+        // Each package is checked and downloaded once, even if a chain
+        // compares it twice.
+        let mut unique: Vec<PackageId> = Vec::new();
+        for pkg_id in pkg_ids.iter() {
+            if !unique.contains(pkg_id) {
+                unique.push(pkg_id.clone());
+            }
+        }
+        let mut yanked = Vec::new();
+        for pkg_id in unique.iter() {
+            let entry = resolver.yanked(pkg_id)?;
+            if entry.is_some() {
+                if self.deny_yanked {
+                    bail!("{} is yanked from crates.io", pkg_id);
+                }
+                eprintln!(
+                    "{} {} is YANKED from crates.io, review it with extra care",
+                    warning_label(),
+                    pkg_id
+                );
+            }
+            yanked.push(entry);
+        }
+        for pkg_id in unique.iter() {
+            if let Some(entry) = resolver.published(pkg_id)? {
+                check_features(pkg_id, entry, &self.features)?;
+            }
+        }
+        let mut srcs = HashMap::new();
+        let mut graphs = HashMap::new();
+        for (pkg_id, yanked) in unique.iter().zip(&yanked) {
+            // The vendored sources replace crates.io in the workspace.
+            let fetched = fetch(
+                pkg_id,
+                yanked.as_ref(),
+                &self.features,
+                self.offline,
+                self.against_vendored,
+            )?;
+            verify_checksum(pkg_id, &fetched.src)?;
+            srcs.insert(pkg_id, fetched.src);
+            if let Some(graph) = fetched.deps {
+                graphs.insert(pkg_id.clone(), graph);
+            }
+        }
+        let tmpdir = TempDir::new("cargo-review-deps-local")?;
+        let mut sides: Vec<(PackageId, PathBuf)> = Vec::new();
+        if self.against_repo {
+            let published = &pkg_ids[0];
+            let checkout = repo::checkout(published, &srcs[published], tmpdir.path())?;
+            // The repository goes first, so that the code which is only in
+            // the published package shows up as added.
+            sides.push((checkout.repo_id, checkout.repo));
+            sides.push((published.clone(), checkout.published));
+        } else if self.against_vendored {
+            let published = &pkg_ids[0];
+            let checkout = vendor::checkout(published, &srcs[published], tmpdir.path())?;
+            sides.push((checkout.vendored_id, checkout.vendored));
+            sides.push((published.clone(), checkout.published));
+        } else {
+            for pkg_id in pkg_ids.iter() {
+                sides.push((pkg_id.clone(), srcs[pkg_id].clone()));
+            }
+        }
+        if self.reverse {
+            sides.reverse();
+        }
+        for (idx, (pkg_id, src)) in sides.iter_mut().enumerate() {
+            // Local packages are copied aside, to get rid of the build artifacts.
+            let name = format!("{}-{}", idx + 1, pkg_id.dir_name());
+            let (checkout, special) =
+                checkout_local(pkg_id, src.clone(), &tmpdir.path().join(&name))?;
+            special.print(pkg_id);
+            *src = checkout;
+        }
+        Ok(Sources {
+            sides,
+            graphs,
+            tmpdir,
+        })
+    }
+
+    /// Replaces the sources of each side with copies of them without the
+    /// files Cargo adds, those `reviewignore` and `filter` leave out, and the
+    /// tests with `no_tests`, formatted if they're normalized.
+    fn filter_sources(
+        &self,
+        sources: &mut Sources,
+        filter: &Filter,
+        reviewignore: &ReviewIgnore,
+    ) -> Result<()> {
+        let tmp = sources.tmpdir.path();
+        let mut skipped = Vec::new();
+        let mut unfiltered = Vec::new();
+        let mut ignored = reviewignore.counts();
+        for (idx, (pkg_id, src)) in sources.sides.iter_mut().enumerate() {
+            let name = format!("{}-{}", idx + 1, pkg_id.dir_name());
+            if self.strip_artifacts {
+                let dst = tmp.join(format!("{}-stripped", name));
+                *src = strip_artifacts(src, &dst)?;
+            }
+            unfiltered.push(src.clone());
+            if !reviewignore.is_empty() {
+                let dst = tmp.join(format!("{}-reviewignore", name));
+                reviewignore.copy(&pkg_id.name, src, &dst, &mut ignored)?;
+                *src = dst;
+            }
+            if !filter.is_empty() {
+                let dst = tmp.join(format!("{}-filtered", name));
+                skipped.push(format!("{} of {}", files(filter.copy(src, &dst)?), pkg_id));
+                *src = dst;
+            }
+        }
+        reviewignore.report(&ignored, self.verbose, false);
+        if !skipped.is_empty() {
+            eprintln!("Filtered out {}", enumerate(&skipped));
+        }
+        if self.no_tests {
+            // The test modules are found before the other filters, which may
+            // leave out the modules declaring them.
+            let roots: Vec<&Path> = unfiltered.iter().map(PathBuf::as_path).collect();
+            let tests = Filter::new(&[], &filter::test_globs(&roots)?, false)?;
+            let mut skipped = 0;
+            for step in sources.sides.windows(2) {
+                let (changed, _) = builtin_diff::changed_paths(&step[0].1, &step[1].1, self.jobs)?;
+                skipped += changed.iter().filter(|it| tests.excludes(it)).count();
+            }
+            for (idx, (pkg_id, src)) in sources.sides.iter_mut().enumerate() {
+                let dst = tmp.join(format!("{}-{}-no-tests", idx + 1, pkg_id.dir_name()));
+                tests.copy(src, &dst)?;
+                *src = dst;
+            }
+            if skipped > 0 {
+                eprintln!(
+                    "Skipped {} changed file{} under tests/, benches/ and examples/, and in test modules \
+                     (use --all to include them)",
+                    skipped,
+                    if skipped == 1 { "" } else { "s" }
+                );
+            }
+        }
+        if let Some(normalizer) = self.normalize {
+            let mut normalized = Vec::new();
+            for (idx, (pkg_id, src)) in sources.sides.iter_mut().enumerate() {
+                let dst = tmp.join(format!("{}-{}-normalized", idx + 1, pkg_id.dir_name()));
+                let count = normalize::rustfmt(pkg_id, src, &dst, tmp, self.jobs)?;
+                normalized.push(format!("{} of {}", files(count), pkg_id));
+                *src = dst;
+            }
+            eprintln!(
+                "Normalized {} with {}, formatting-only changes don't show",
+                enumerate(&normalized),
+                normalizer.name()
+            );
+        }
+        Ok(())
+    }
+
+    /// Writes the JSON report of each step of the `sources`, and adds what
+    /// they bring to `findings`. Returns whether some step differs.
+    fn write_json<'a>(
+        &self,
+        sources: &'a Sources,
+        options: DiffOptions,
+        findings: &mut Findings<'a>,
+    ) -> Result<bool> {
+        let started = Instant::now();
+        let mut reports = Vec::new();
+        for pair in sources.sides.windows(2) {
+            let ((first, first_src), (second, second_src)) = (&pair[0], &pair[1]);
+            let mut report = self.json_report(first, first_src, second, second_src, options)?;
+            let step_deps = dep_changes(&sources.graphs, first, second);
+            let score = risk::score(
+                first_src,
+                second_src,
+                step_deps.as_ref(),
+                self.ignore_whitespace,
+                self.jobs,
+            )?;
+            findings.risks.push((first, second, score.risk));
+            report.risk = Some(score);
+            report.dependency_graph = step_deps.clone();
+            reports.push(report);
+            findings.deps.push((second, step_deps));
+            findings
+                .binaries
+                .extend(new_binaries(second, first_src, second_src, self.jobs)?);
+            findings
+                .sizes
+                .push((first, second, size::compare(first_src, second_src)?));
+        }
+        self.print_time(started);
+        self.write_report(|out| {
+            match reports.as_slice() {
+                [report] => json_report::write_report(out, report)?,
+                _ => json_report::write_chain(out, &reports)?,
+            }
+            Ok(reports.iter().any(json_report::Report::differ))
+        })
+    }
+
+    /// Compares each step of the `sources`, checking them out under
+    /// `dest_root` if there's one and opening them with `open_tool` with
+    /// `open`, and adds what they bring to `findings`. Returns whether some
+    /// step differs.
+    fn compare_steps<'a>(
+        &self,
+        sources: &'a Sources,
+        dest_root: Option<&Path>,
+        open_tool: Option<&OsStr>,
+        options: DiffOptions,
+        findings: &mut Findings<'a>,
+    ) -> Result<bool> {
+        let steps = sources.sides.len() - 1;
+        let mut differ = false;
+        let mut totals = Vec::new();
+        for (idx, pair) in sources.sides.windows(2).enumerate() {
+            let ((first, first_src), (second, second_src)) = (&pair[0], &pair[1]);
+            let mut dest = dest_root.map(Path::to_path_buf);
+            if steps > 1 {
+                if idx > 0 {
+                    println!();
+                }
+                println!("Step {} of {}: {} -> {}", idx + 1, steps, first, second);
+                dest = dest.map(|it| it.join(step_dir_name(idx, first, second)));
+            }
+            if self.format == OutputFormat::Text
+                && dest.is_none()
+                && self.output.is_none()
+                && !self.interactive
+            {
+                println!("--- old: {}\n+++ new: {}", first, second);
+            }
+            let step_deps = dep_changes(&sources.graphs, first, second);
+            if let (OutputFormat::Text, None, None, false, Some(changes)) =
+                (self.format, &dest, &self.output, self.scan, &step_deps)
+            {
+                changes.print();
+            }
+            let score = if self.risk
+                || self.risk_threshold.is_some()
+                || self.format == OutputFormat::Markdown
+            {
+                let score = risk::score(
+                    first_src,
+                    second_src,
+                    step_deps.as_ref(),
+                    self.ignore_whitespace,
+                    self.jobs,
+                )?;
+                findings.risks.push((first, second, score.risk));
+                Some(score)
+            } else {
+                None
+            };
+            findings.deps.push((second, step_deps));
+            differ |= if self.prioritize_risky {
+                let tmp = sources.tmpdir.path().join(format!("{}-risky", idx + 1));
+                self.compare_risky_first(first, first_src, second, second_src, &tmp, options)?
+            } else if let (OutputFormat::Markdown, Some(score)) = (self.format, &score) {
+                self.markdown_report(first, first_src, second, second_src, score, options)?
+            } else {
+                self.compare(
+                    first,
+                    first_src,
+                    second,
+                    second_src,
+                    dest.as_deref(),
+                    options,
+                )?
+            };
+            if let (true, Some(dir)) = (self.open, &dest) {
+                let (old, new) = side_dirs(dir, first, second);
+                open_sources(open_tool, &self.tool_args, &old, &new)?;
+            }
+            findings
+                .binaries
+                .extend(new_binaries(second, first_src, second_src, self.jobs)?);
+            findings
+                .sizes
+                .push((first, second, size::compare(first_src, second_src)?));
+            if self.unsafe_report && self.format == OutputFormat::Text {
+                println!();
+                unsafe_report::print_report(&unsafe_report::report(first_src, second_src)?);
+            }
+            if let (OutputFormat::Text, Some(score)) = (self.format, &score) {
+                println!("\n{}", score.line(first, second));
+            }
+            if steps > 1 {
+                let step_totals =
+                    builtin_diff::totals(first_src, second_src, self.ignore_whitespace, self.jobs)?;
+                totals.push((first, second, step_totals));
+            }
+        }
+        if !totals.is_empty() {
+            println!("\nSummary:");
+            for (first, second, step_totals) in totals {
+                println!(" {} -> {}: {}", first, second, step_totals);
+            }
+        }
+        Ok(differ)
+    }
+
+    /// Prints the sizes of the steps, and fails if `findings` has what the
+    /// options deny.
+    fn finish(&self, findings: &Findings) -> Result<()> {
+        print_sizes(&findings.sizes);
+        self.deny_binaries(&findings.binaries)?;
+        self.deny_deps(&findings.deps)?;
+        self.deny_risk(&findings.risks)
+    }
+
+    /// Fails with `deny_new_binaries` if there are `binaries`, as returned by
+    /// `new_binaries`.
+    fn deny_binaries(&self, binaries: &[String]) -> Result<()> {
+        if self.deny_new_binaries && !binaries.is_empty() {
+            bail!("new binary files: {}", enumerate(binaries));
+        }
+        Ok(())
+    }
+
+    /// Fails with `deny_new_deps` if one of the `deps` of the new package of
+    /// a step, as returned by `dep_changes`, is new, or if they are unknown.
+    fn deny_deps(&self, deps: &[(&PackageId, Option<dep_graph::Changes>)]) -> Result<()> {
+        if !self.deny_new_deps {
+            return Ok(());
+        }
+        for (second, changes) in deps {
+            let added = match changes {
+                Some(changes) => changes.added(),
+                None => bail!(
+                    "--deny-new-deps can't tell the dependencies of {}, Cargo couldn't resolve them",
+                    second
+                ),
+            };
+            if !added.is_empty() {
+                bail!("new dependencies in {}: {}", second, enumerate(&added));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails with `risk_threshold` if the risk of one of the steps is as high
+    /// or higher.
+    fn deny_risk(&self, risks: &[(&PackageId, &PackageId, RiskLevel)]) -> Result<()> {
+        let threshold = match self.risk_threshold {
+            Some(it) => it,
+            None => return Ok(()),
+        };
+        if let Some((first, second, risk)) = risks.iter().find(|(_, _, it)| *it >= threshold) {
+            bail!(
+                "the risk of {} -> {} is {}, at or above --risk-threshold {}",
+                first,
+                second,
+                risk.name().to_uppercase(),
+                threshold.name()
+            );
+        }
+        Ok(())
+    }
+
+    /// Compares the build related files of `first` and `second` first, then
+    /// the rest of them, splitting the sources in `tmp`.
+    fn compare_risky_first(
+        &self,
+        first: &PackageId,
+        first_src: &Path,
+        second: &PackageId,
+        second_src: &Path,
+        tmp: &Path,
+        options: DiffOptions,
+    ) -> Result<bool> {
+        let risky = risky::risky_files(first_src, second_src);
+        let paths: Vec<PathBuf> = risky.iter().map(|(path, _)| path.clone()).collect();
+        let dirs = |side: &str| (tmp.join(format!("risky-{}", side)), tmp.join(side));
+        let (first_risky, first_rest) = dirs("first");
+        let (second_risky, second_rest) = dirs("second");
+        risky::split(first_src, &paths, &first_risky, &first_rest)?;
+        risky::split(second_src, &paths, &second_risky, &second_rest)?;
+
+        println!("High-risk changes:");
+        risky::print_verdicts(first_src, second_src, &risky, self.ignore_whitespace)?;
+        let mut differ = self.compare(first, &first_risky, second, &second_risky, None, options)?;
+        println!("\nOther changes:");
+        differ |= self.compare(first, &first_rest, second, &second_rest, None, options)?;
+        Ok(differ)
+    }
+
+    /// Compares `first`, checked out to `first_src`, with `second`, or checks
+    /// them out to `dest`.
+    fn compare(
+        &self,
+        first: &PackageId,
+        first_src: &Path,
+        second: &PackageId,
+        second_src: &Path,
+        dest: Option<&Path>,
+        options: DiffOptions,
+    ) -> Result<bool> {
+        if let Some(dir) = dest {
+            let (first_dst, second_dst) = side_dirs(dir, first, second);
+            replace_dir(&first_dst, |dst| {
+                copy_package(first, first_src, dst).map(drop)
+            })?;
+            replace_dir(&second_dst, |dst| {
+                copy_package(second, second_src, dst).map(drop)
+            })?;
+            if self.interactive {
+                let notes = self
+                    .notes
+                    .clone()
+                    .unwrap_or_else(|| dir.join(interactive::REVIEW_NOTES));
+                return interactive::review(
+                    first,
+                    &first_dst,
+                    second,
+                    &second_dst,
+                    options,
+                    &notes,
+                    self.pager,
+                );
+            }
+            if self.archive.is_some() {
+                return self.write_summary(first, &first_dst, second, &second_dst, dir, options);
+            }
+            return match &self.output {
+                Some(path) => write_patch(path, &first_dst, &second_dst, options),
+                None => Ok(false),
+            };
+        }
+        if self.format == OutputFormat::Text && self.output.is_none() && !self.scan {
+            // The lists of changed files have the READMEs already.
+            license::compare(first_src, second_src, self.jobs)?.print(!self.brief && !self.stat);
+            manifest_diff::compare(first_src, second_src).print();
+        }
+        let started = Instant::now();
+        let differ = if self.format != OutputFormat::Text {
+            // The JSON report is written with the dependencies, in `run`.
+            self.write_report(|out| {
+                html_report::write_report(out, first, first_src, second, second_src, options)
+            })?
+        } else if let Some(path) = &self.output {
+            write_patch(path, first_src, second_src, options)?
+        } else if let (true, Some(notes)) = (self.interactive, &self.notes) {
+            return interactive::review(
+                first, first_src, second, second_src, options, notes, self.pager,
+            );
+        } else if self.scan {
+            let patterns = scan::patterns(&self.scan_patterns)?;
+            scan::print_matches(first_src, second_src, &patterns, options)?
+        } else if self.brief {
+            builtin_diff::print_brief(first_src, second_src, options)?
+        } else if self.stat {
+            builtin_diff::print_stat(first_src, second_src, options)?
+        } else if self.side_by_side {
+            side_by_side::print_diff(
+                first_src,
+                &first.dir_name(),
+                second_src,
+                &second.dir_name(),
+                options,
+                self.width.unwrap_or_else(side_by_side::detect_width),
+            )?
+        } else if self.builtin || self.color_words || (self.tool.is_none() && !has_diff_cmd()) {
+            builtin_diff::print_diff(
+                first_src,
+                &first.dir_name(),
+                second_src,
+                &second.dir_name(),
+                options,
+            )?
+        } else {
+            let mut args = self.tool_args.clone();
+            if self.ignore_whitespace {
+                match self.tool {
+                    None => args.insert(0, "-w".into()),
+                    Some(_) => eprintln!(
+                        "{} --ignore-whitespace only applies to diff and the builtin diff, \
+                         pass the option of your tool after --",
+                        warning_label()
+                    ),
+                }
+            }
+            return self.run_on_changed(
+                &args,
+                first,
+                first_src,
+                second,
+                second_src,
+                options.truncation,
+            );
+        };
+        self.print_time(started);
+        Ok(differ)
+    }
+
+    /// Writes the markdown report of `first` and `second`, with the `risk` of
+    /// their changes.
+    fn markdown_report(
+        &self,
+        first: &PackageId,
+        first_src: &Path,
+        second: &PackageId,
+        second_src: &Path,
+        risk: &risk::Score,
+        options: DiffOptions,
+    ) -> Result<bool> {
+        let started = Instant::now();
+        let summary = markdown_report::Summary {
+            risk,
+            max_patch_size: self.max_patch_size,
+        };
+        let differ = self.write_report(|out| {
+            markdown_report::write_report(
+                out, first, first_src, second, second_src, options, &summary,
+            )
+        })?;
+        self.print_time(started);
+        Ok(differ)
+    }
+
+    /// Tells how long diffing the files took since `started`, with `verbose`.
+    fn print_time(&self, started: Instant) {
+        if self.verbose {
+            let jobs = jobs::threads(self.jobs);
+            eprintln!(
+                "Diffed the files in {:.0?} with {} job{}",
+                started.elapsed(),
+                jobs,
+                if jobs == 1 { "" } else { "s" }
+            );
+        }
+    }
+
+    /// Writes the `CHANGES.patch` and the `summary.json` of `first` and
+    /// `second`, checked out to `first_dst` and `second_dst`, to `dir`, for
+    /// the archive. Returns whether they differ.
+    fn write_summary(
+        &self,
+        first: &PackageId,
+        first_dst: &Path,
+        second: &PackageId,
+        second_dst: &Path,
+        dir: &Path,
+        options: DiffOptions,
+    ) -> Result<bool> {
+        let mut out = create_output(&dir.join(CHANGES_PATCH))?;
+        let options = DiffOptions {
+            color_words: false,
+            collapse_generated: false,
+            ..options
+        };
+        let patch_options = DiffOptions {
+            rename_threshold: None,
+            ..options
+        };
+        let differ = builtin_diff::write_diff(
+            &mut out,
+            false,
+            first_dst,
+            "a",
+            second_dst,
+            "b",
+            patch_options,
+        )?;
+        out.flush()?;
+        let report = self.json_report(first, first_dst, second, second_dst, options)?;
+        let mut out = create_output(&dir.join(SUMMARY_JSON))?;
+        json_report::write_report(&mut out, &report)?;
+        out.flush()?;
+        Ok(differ)
+    }
+
+    /// Runs the diff tool on copies of the files which differ between
+    /// `first_src` and `second_src`, so that it doesn't read the rest of
+    /// them again. The output of `diff` is cut at `truncation`.
+    fn run_on_changed(
+        &self,
+        args: &[OsString],
+        first: &PackageId,
+        first_src: &Path,
+        second: &PackageId,
+        second_src: &Path,
+        truncation: &Truncation,
+    ) -> Result<bool> {
+        let started = Instant::now();
+        let (mut changed, unchanged) =
+            builtin_diff::changed_paths(first_src, second_src, self.jobs)?;
+        if changed.is_empty() {
+            return Ok(false);
+        }
+        if !self.show_generated {
+            // The tool never sees the generated files, which are summed up
+            // before its output.
+            let mut rest = Vec::new();
+            for path in changed {
+                let file = builtin_diff::changed_file(first_src, second_src, &path)?;
+                let is_text = [&file.old, &file.new]
+                    .iter()
+                    .all(|it| builtin_diff::as_text(it.as_deref().unwrap_or_default()).is_some());
+                if is_text && generated::is_generated(&file, &self.generated) {
+                    let (dir_name, side) = match file.new {
+                        Some(_) => ("b", second),
+                        None => ("a", first),
+                    };
+                    let name = format!("{}/{}/{}", dir_name, side.dir_name(), path.display());
+                    println!(
+                        "{}",
+                        generated::summary(&file, &name, self.ignore_whitespace)
+                    );
+                } else {
+                    rest.push(path);
+                }
+            }
+            if rest.is_empty() {
+                return Ok(true);
+            }
+            changed = rest;
+        }
+        let tmpdir = TempDir::new("cargo-review-deps-changed")?;
+        // `diff` runs in `tmpdir`, so that the paths it prints are the same
+        // from one run to the next.
+        let first_dir = Path::new("a").join(first.dir_name());
+        let second_dir = Path::new("b").join(second.dir_name());
+        for (src, dir) in [(first_src, &first_dir), (second_src, &second_dir)] {
+            let dst = tmpdir.path().join(dir);
+            fs::create_dir_all(&dst)?;
+            for path in changed.iter() {
+                if src.join(path).is_file() {
+                    if let Some(dir) = path.parent() {
+                        fs::create_dir_all(dst.join(dir))?;
+                    }
+                    fs::copy(src.join(path), dst.join(path))?;
+                }
+            }
+        }
+        if self.verbose {
+            eprintln!(
+                "Left out {} which are the same on both sides ({} bytes) in {:.0?}, {} to diff",
+                files(unchanged.files),
+                unchanged.bytes,
+                started.elapsed(),
+                files(changed.len())
+            );
+        }
+        run_diff_tool(
+            self.tool.as_deref(),
+            args,
+            Some(tmpdir.path()),
+            &first_dir,
+            &second_dir,
+            truncation,
+        )
+    }
+
+    fn json_report(
+        &self,
+        first: &PackageId,
+        first_src: &Path,
+        second: &PackageId,
+        second_src: &Path,
+        options: DiffOptions,
+    ) -> Result<json_report::Report> {
+        let detail = match (self.brief, self.include_patches) {
+            (true, _) => Detail::Paths,
+            (false, true) => Detail::Patches,
+            (false, false) => Detail::Lines,
+        };
+        let mut report =
+            json_report::report(first, first_src, second, second_src, detail, options)?;
+        if self.unsafe_report {
+            report.unsafe_code = Some(unsafe_report::report(first_src, second_src)?);
+        }
+        report.normalized = self.normalize.map(Normalizer::name);
+        Ok(report)
+    }
+
+    /// Writes a report to the output file, or to stdout. `write` returns
+    /// whether the packages differ.
+    fn write_report(&self, write: impl FnOnce(&mut dyn Write) -> Result<bool>) -> Result<bool> {
+        let stdout = io::stdout();
+        let mut out: Box<dyn Write> = match &self.output {
+            Some(path) => Box::new(create_output(path)?),
+            None => Box::new(stdout.lock()),
+        };
+        let differ = write(&mut out)?;
+        out.flush()?;
+        if let Some(path) = &self.output {
+            eprintln!("Wrote the report to {}", path.display());
+        }
+        Ok(differ)
+    }
+}
+
+/// The sources `Diff` compares, checked out.
+struct Sources {
+    /// The packages, in the order they are compared, with their sources.
+    sides: Vec<(PackageId, PathBuf)>,
+    /// The dependencies Cargo resolved for the packages, if it could.
+    graphs: HashMap<PackageId, dep_graph::Graph>,
+    /// Where the local packages, and the sources once filtered, are copied.
+    tmpdir: TempDir,
+}
+
+/// What the steps of a `Diff` bring, told or denied once they're all
+/// compared.
+#[derive(Default)]
+struct Findings<'a> {
+    /// Like `foo.so of rand:0.6.1`, see `new_binaries`.
+    binaries: Vec<String>,
+    sizes: Vec<(&'a PackageId, &'a PackageId, size::Delta)>,
+    /// The changes of the dependencies of each step, with its new side.
+    deps: Vec<(&'a PackageId, Option<dep_graph::Changes>)>,
+    risks: Vec<(&'a PackageId, &'a PackageId, RiskLevel)>,
+}
+
+/// `1 file` or `N files`.
+fn files(n: usize) -> String {
+    format!("{} file{}", n, if n == 1 { "" } else { "s" })
+}
+
+/// `a`, `a and b` or `a, b and c`.
+fn enumerate(items: &[String]) -> String {
+    match items.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
+/// The changes of the dependencies from `first` to `second`, if Cargo
+/// resolved both.
+fn dep_changes(
+    graphs: &HashMap<PackageId, dep_graph::Graph>,
+    first: &PackageId,
+    second: &PackageId,
+) -> Option<dep_graph::Changes> {
+    Some(dep_graph::compare(graphs.get(first)?, graphs.get(second)?))
+}
+
+/// Prints the sizes of the packages of each step to stderr.
+fn print_sizes(sizes: &[(&PackageId, &PackageId, size::Delta)]) {
+    for (first, second, delta) in sizes {
+        delta.print(&format!("Size of {} -> {}:", first, second));
+    }
+}
+
+/// Calls out the opaque files `second`, checked out to `b`, brings compared
+/// with `a`, on `jobs` threads. Returns its new binary files, like `foo.so
+/// of rand:0.6.1`.
+fn new_binaries(second: &PackageId, a: &Path, b: &Path, jobs: usize) -> Result<Vec<String>> {
+    let findings = opaque::find(a, b, jobs)?;
+    findings.print();
+    let res = findings
+        .added_binaries()
+        .iter()
+        .map(|it| format!("{} of {}", it, second))
+        .collect();
+    Ok(res)
+}
+
+fn create_output(path: &Path) -> Result<io::BufWriter<fs::File>> {
+    let file = fs::File::create(path)
+        .map_err(|err| format_err!("can't create {}: {}", path.display(), err))?;
+    Ok(io::BufWriter::new(file))
+}
+
+/// Writes the unified diff of the `a` and `b` directories to `path`, with the
+/// `a/` and `b/` prefixes `git apply` and `patch -p1` expect. Returns whether
+/// the directories differ.
+fn write_patch(path: &Path, a: &Path, b: &Path, options: DiffOptions) -> Result<bool> {
+    let mut out = create_output(path)?;
+    // The patch has to apply, so it's never a word diff nor a summary of a
+    // generated file, and, as `patch` doesn't rename files, renamed files are
+    // removed and added.
+    let options = DiffOptions {
+        color_words: false,
+        rename_threshold: None,
+        collapse_generated: false,
+        ..options
+    };
+    let differ = builtin_diff::write_diff(&mut out, false, a, "a", b, "b", options)?;
+    out.flush()?;
+    eprintln!("Wrote the patch to {}", path.display());
+    Ok(differ)
+}
+
+/// The Cargo.toml of a package as written by its authors. The Cargo.toml of
+/// a published package is normalized by Cargo.
+const ORIGINAL_MANIFEST: &str = "Cargo.toml.orig";
+
+/// The commit a package was published from.
+const VCS_INFO: &str = ".cargo_vcs_info.json";
+
+/// Files Cargo adds to a package when publishing or unpacking it. They are
+/// left out of the comparisons, and the original Cargo.toml is compared
+/// instead of the normalized one.
+const PACKAGING_ARTIFACTS: &[&str] = &[".cargo-ok", VCS_INFO, ORIGINAL_MANIFEST];
+
+/// Copies `src` to `dst` without the packaging artifacts, or returns `src`
+/// if it has none.
+fn strip_artifacts(src: &Path, dst: &Path) -> Result<PathBuf> {
+    if !PACKAGING_ARTIFACTS.iter().any(|it| src.join(it).exists()) {
+        return Ok(src.to_path_buf());
+    }
+    tree::copy_tree(src, dst)?;
+    strip_artifacts_in(dst)?;
+    Ok(dst.to_path_buf())
+}
+
+/// Removes the packaging artifacts of the package in `dir`, putting the
+/// original Cargo.toml back.
+fn strip_artifacts_in(dir: &Path) -> Result<()> {
+    if dir.join(ORIGINAL_MANIFEST).exists() {
+        fs::rename(dir.join(ORIGINAL_MANIFEST), dir.join("Cargo.toml"))?;
+    }
+    for name in PACKAGING_ARTIFACTS {
+        match fs::remove_file(dir.join(name)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Files in a local checkout which never make it into a published crate.
+const LOCAL_ONLY_FILES: &[&str] = &["target", ".git", ".hg", ".svn"];
+
+/// How many chains of dependencies tell why a package is checked out.
+const PROVENANCE_CHAINS: usize = 3;
+
+/// Copies sources of `pkg_id` from `src` to `dst`. Build artifacts of local
+/// packages are not copied.
+fn copy_package(pkg_id: &PackageId, src: &Path, dst: &Path) -> Result<tree::Special> {
+    match pkg_id.source {
+        Source::Path(_) => tree::copy_entries(src, dst, LOCAL_ONLY_FILES),
+        _ => tree::copy_tree(src, dst),
+    }
+}
+
+/// Fails, unless `force`, if some of the `dirs` to check packages out to
+/// exist already: files left from another version would look like a part of
+/// this one.
+fn check_dests(dirs: &[PathBuf], force: bool) -> Result<()> {
+    let existing: Vec<String> = dirs
+        .iter()
+        .filter(|it| !force && fs::symlink_metadata(it).is_ok())
+        .map(|it| it.display().to_string())
+        .collect();
+    match existing.as_slice() {
+        [] => Ok(()),
+        [dir] => bail!("{} exists already, pass --force to replace it", dir),
+        _ => bail!(
+            "{} exist already, pass --force to replace them",
+            enumerate(&existing)
+        ),
+    }
+}
+
+/// Checks a package out to `dst` with `fill`. An existing `dst` is replaced
+/// once the new one is complete, so that it's never left half copied.
+fn replace_dir(dst: &Path, fill: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+    if fs::symlink_metadata(dst).is_err() {
+        return fill(dst);
+    }
+    let name = dst.file_name().unwrap_or_default().to_string_lossy();
+    let new = dst.with_file_name(format!(".{}.new", name));
+    let old = dst.with_file_name(format!(".{}.old", name));
+    for dir in [&new, &old] {
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+    }
+    if let Err(err) = fill(&new) {
+        let _ = fs::remove_dir_all(&new);
+        return Err(err);
+    }
+    fs::rename(dst, &old)?;
+    fs::rename(&new, dst)?;
+    if old.is_dir() {
+        fs::remove_dir_all(&old)?;
+    } else {
+        fs::remove_file(&old)?;
+    }
+    Ok(())
+}
+
+/// Returns the directory to diff for `pkg_id`, with its symlinks and special
+/// files: `src` itself for published packages, and a cleaned up copy in `tmp`
+/// for local ones and for those with symlinks or special files.
+fn checkout_local(
+    pkg_id: &PackageId,
+    src: PathBuf,
+    tmp: &Path,
+) -> Result<(PathBuf, tree::Special)> {
+    let special = match pkg_id.source {
+        Source::Path(_) => copy_package(pkg_id, &src, tmp)?,
+        _ => {
+            let special = tree::find(&src)?;
+            if special.is_empty() {
+                return Ok((src, special));
+            }
+            tree::copy_tree(&src, tmp)?
+        }
+    };
+    Ok((tmp.to_path_buf(), special))
+}
+
+pub fn run_diff_cmd(a: &Path, b: &Path) -> Result<()> {
+    if !has_diff_cmd() {
+        let label = |dir: &Path| {
+            dir.file_name()
+                .map(|it| it.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        let options = DiffOptions {
+            context: 3,
+            ignore_whitespace: false,
+            color_words: false,
+            rename_threshold: None,
+            collapse_generated: true,
+            generated: &[],
+            jobs: 0,
+            truncation: &truncate::UNLIMITED,
+        };
+        builtin_diff::print_diff(a, &label(a), b, &label(b), options)?;
+        return Ok(());
+    }
+    run_diff_tool(None, &[], None, a, b, &truncate::UNLIMITED)?;
+    Ok(())
+}
+
+/// The directory of step `idx` of a chain in the destination. Each step gets
+/// one, so that the packages in the middle of the chain show up in both of
+/// their steps.
+fn step_dir_name(idx: usize, first: &PackageId, second: &PackageId) -> String {
+    format!("{}-{}..{}", idx + 1, first.dir_name(), second.dir_name())
+}
+
+/// Where the old and the new sources of `first` and `second` go in `dir`,
+/// under `old/` and `new/`, so that they can't clash even with the same
+/// names.
+fn side_dirs(dir: &Path, first: &PackageId, second: &PackageId) -> (PathBuf, PathBuf) {
+    (
+        dir.join(OLD_DIR).join(first.dir_name()),
+        dir.join(NEW_DIR).join(second.dir_name()),
+    )
+}
+
+const OLD_DIR: &str = "old";
+const NEW_DIR: &str = "new";
+
+/// The unified diff and the JSON report in the archive, next to the sources.
+const CHANGES_PATCH: &str = "CHANGES.patch";
+const SUMMARY_JSON: &str = "summary.json";
+
+/// The GUI diff tools `--open` looks for, in this order. They all compare
+/// directories, unlike `code --diff`.
+const GUI_TOOLS: &[&str] = &["meld", "kdiff3", "bcompare"];
+
+/// The program `--open` opens the sources with: `tool`, then
+/// `$CARGO_REVIEW_DEPS_TOOL`, then the first of `GUI_TOOLS` in `$PATH`.
+fn gui_tool(tool: Option<&OsStr>) -> Option<OsString> {
+    if let Some(tool) = tool {
+        return Some(tool.to_owned());
+    }
+    match env::var_os("CARGO_REVIEW_DEPS_TOOL") {
+        Some(tool) if !tool.is_empty() => return Some(tool),
+        _ => {}
+    }
+    let path = env::var_os("PATH")?;
+    GUI_TOOLS
+        .iter()
+        .map(OsStr::new)
+        .find(|tool| env::split_paths(&path).any(|dir| dir.join(tool).is_file()))
+        .map(OsStr::to_owned)
+}
+
+/// Opens `a` and `b` with `tool` and waits for it to exit, or prints their
+/// paths if there's no tool. The exit status of GUI tools means different
+/// things for each of them, so it is ignored.
+fn open_sources(tool: Option<&OsStr>, args: &[OsString], a: &Path, b: &Path) -> Result<()> {
+    let tool = match tool {
+        Some(tool) => tool,
+        None => {
+            eprintln!(
+                "{} found none of {}, set CARGO_REVIEW_DEPS_TOOL or pass --tool; the sources are in:",
+                warning_label(),
+                enumerate(&GUI_TOOLS.iter().map(ToString::to_string).collect::<Vec<_>>())
+            );
+            println!("{}\n{}", a.display(), b.display());
+            return Ok(());
+        }
+    };
+    eprintln!("Opening the sources with `{}`", tool.to_string_lossy());
+    Command::new(tool)
+        .args(args)
+        .arg(a)
+        .arg(b)
+        .status()
+        .map_err(|err| format_err!("can't run `{}`: {}", tool.to_string_lossy(), err))?;
+    Ok(())
+}
+
+/// Runs `tool`, or `diff -r` by default, with `args` and the two directories,
+/// which are relative to `dir` if it is given. Like with `diff`, exit code 1 means that
+/// there are differences, not that the tool failed. The output of `diff` stops
+/// at `truncation`. Returns whether there are differences.
+fn run_diff_tool(
+    tool: Option<&OsStr>,
+    args: &[OsString],
+    dir: Option<&Path>,
+    a: &Path,
+    b: &Path,
+    truncation: &Truncation,
+) -> Result<bool> {
+    let mut diff_cmd = match tool {
+        Some(tool) => Command::new(tool),
+        None => {
+            let mut cmd = Command::new("diff");
+            // Through the pager, stdout is no terminal anymore.
+            cmd.arg(if color::stdout() {
+                "--color=always"
+            } else {
+                "--color=never"
+            });
+            cmd.arg("-r").arg(format!("--exclude={}", tree::GIT_DIR));
+            // `diff -r` sorts the files by the collation of the locale; in
+            // byte order, the output is the same everywhere, and the files
+            // come in the order of the builtin diff.
+            cmd.env("LC_COLLATE", "C");
+            if let Some(all) = env::var_os("LC_ALL").filter(|it| !it.is_empty()) {
+                cmd.env_remove("LC_ALL").env("LANG", all);
+            }
+            cmd
+        }
+    };
+    let tool_name = tool.unwrap_or_else(|| OsStr::new("diff")).to_string_lossy();
+    // Only `diff` runs in `dir`: the args of a tool may be paths relative to
+    // the current directory.
+    let (a, b) = match dir {
+        Some(dir) if tool.is_some() => (dir.join(a), dir.join(b)),
+        Some(dir) => {
+            diff_cmd.current_dir(dir);
+            (a.to_path_buf(), b.to_path_buf())
+        }
+        None => (a.to_path_buf(), b.to_path_buf()),
+    };
+    // The output of `diff` is cut at --max-lines like that of the builtin
+    // diff, the tools may well be interactive.
+    let limited = tool.is_none() && truncation.is_limited();
+    let child = diff_cmd
+        .args(args)
+        .arg(a)
+        .arg(b)
+        .stdout(if limited {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        })
+        .stderr(Stdio::inherit())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(_) if tool.is_none() && !has_diff_cmd() => {
+            bail!("looks like you don't have a suitable diff command installed.\n\
+                   Try using --tool flag to run another diff program, or --destination flag to compare sources manually.")
+        }
+        Err(err) => bail!("can't run `{}`: {}", tool_name, err),
+    };
+    if let Some(mut diff_out) = child.stdout.take() {
+        let stdout = io::stdout();
+        let mut out = Limited {
+            out: stdout.lock(),
+            truncation,
+        };
+        let mut buf = [0; 8192];
+        loop {
+            let len = diff_out.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            let full = truncation.is_full();
+            out.write_all(&buf[..len])?;
+            if full {
+                // There's more than fits, and `diff` would go on through
+                // all of the files.
+                let _ = child.kill();
+                let _ = child.wait();
+                return Ok(true);
+            }
+        }
+        out.flush()?;
+    }
+    let diff_status = child.wait()?;
+    match diff_status.code() {
+        Some(0) => Ok(false),
+        Some(1) => Ok(true),
+        _ => bail!("`{}` failed: {}", tool_name, diff_status),
+    }
+}
+
+/// How `current` lays the packages out in the destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// For reviewing, without the files Cargo adds nor the ignored ones.
+    Review,
+    /// As `cargo vendor` does, to replace the sources of the packages with
+    /// the destination, see `vendor`.
+    Vendor,
+}
+
+/// How `current` copies the files of the packages from the caches of
+/// Cargo, the published and the git ones. The local packages are always
+/// copied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkMode {
+    #[default]
+    Copy,
+    /// Hard links to the files of the cache: changing a file of the
+    /// destination changes it in the cache too.
+    Hardlink,
+    /// Copy-on-write clones, on the filesystems which support them.
+    Reflink,
+}
+
+#[derive(Debug)]
+pub struct Current {
+    pub dest: PathBuf,
+    /// The manifest of the workspace, that of the current directory if
+    /// `None`.
+    pub manifest_path: Option<PathBuf>,
+    /// Check out only the dependencies with these names, or `name:version`.
+    /// All of them if empty.
+    pub only: Vec<String>,
+    /// Check out the git dependencies too, not only the published ones.
+    pub include_git: bool,
+    /// Check out the path dependencies outside of the workspace too.
+    pub include_path: bool,
+    /// Check out the members of the workspace too.
+    pub include_workspace: bool,
+    /// Check out only the dependencies the members of the workspace name,
+    /// not the ones they pull in.
+    pub direct_only: bool,
+    /// Leave out the packages which are only pulled in by
+    /// `[dev-dependencies]`.
+    pub no_dev_deps: bool,
+    /// Leave out the packages which are only pulled in by
+    /// `[build-dependencies]`.
+    pub no_build_deps: bool,
+    /// Check out only the packages built for these targets, for all of
+    /// them if empty.
+    pub targets: Vec<String>,
+    /// Add the target of the host to `targets`.
+    pub host_target: bool,
+    /// The features to resolve the dependencies with.
+    pub features: Features,
+    /// `Json` to print `manifest.json` to stdout too, see `snapshot`.
+    pub format: OutputFormat,
+    /// `Vendor` keeps the packages as Cargo unpacked them, whatever
+    /// `strip_artifacts` and `reviewignore` say.
+    pub layout: Layout,
+    /// Check the sources against the checksums of Cargo.lock before copying
+    /// them, see `verify`.
+    pub verify: bool,
+    /// Copy the packages which are checked out already again, even if they
+    /// didn't change.
+    pub refresh: bool,
+    /// Remove the packages of earlier snapshots which aren't checked out this
+    /// time, as they aren't dependencies any more, or aren't selected.
+    pub prune: bool,
+    /// Check out these packages instead of the dependencies of the current
+    /// workspace. A bare name means the locked version.
+    pub packages: Option<Vec<PackageId>>,
+    /// Leave out the files Cargo adds to packages, see `Diff`.
+    pub strip_artifacts: bool,
+    /// Leave out the files listed in `.reviewignore`, see `Diff`.
+    pub reviewignore: bool,
+    /// Replace the packages already checked out to `dest`.
+    pub force: bool,
+    /// Pack the packages into this `.tar.gz` or `.zip` file, straight from
+    /// their sources, rather than checking them out to `dest`.
+    pub archive: Option<PathBuf>,
+    /// Leave out the files matching these globs, but the build related ones,
+    /// see `filter::Omit`.
+    pub exclude: Vec<String>,
+    /// Leave out the files larger than this many bytes, but the build
+    /// related ones and those in `src`.
+    pub max_file_size: Option<u64>,
+    /// Fail if one of these crates is checked out in several versions, see
+    /// `duplicates`.
+    pub deny_duplicates: Vec<String>,
+    /// Link the files to the caches of Cargo instead of copying them. The
+    /// files which can't be linked are copied.
+    pub link_mode: LinkMode,
+    /// Print the packages which would be checked out instead, see
+    /// `inventory`.
+    pub list: bool,
+    /// Leave out the packages whose license expression these licenses
+    /// allow, keeping them in the manifest. The others are checked out for
+    /// a review of their license.
+    pub license_allow: Vec<String>,
+    /// An earlier snapshot: the packages it has in the same version are
+    /// referenced instead of copied, and `delta.md` tells what changed
+    /// since.
+    pub since: Option<PathBuf>,
+    /// Never access the network: fail, before copying anything, if the
+    /// sources of some packages aren't downloaded.
+    pub offline: bool,
+    /// Download the sources which aren't yet, with `cargo fetch`.
+    pub fetch_missing: bool,
+    /// Write a bill of materials of the packages to `sbom_output`, see
+    /// `sbom`.
+    pub sbom: Option<SbomFormat>,
+    pub sbom_output: Option<PathBuf>,
+    /// Fail instead of warning if some versions are yanked from crates.io,
+    /// see `yanked`.
+    pub deny_yanked: bool,
+    /// Fail if some dependencies come from these sources, see `policy`.
+    pub deny_source: Vec<DeniedSource>,
+    /// Stop at the first package which can't be checked out, rather than
+    /// checking the others out and marking it as failed in the manifest.
+    pub fail_fast: bool,
+    /// Check out only the packages these members of the workspace pull in.
+    pub member: Vec<String>,
+    /// Fail if some dependencies come from registries other than crates.io
+    /// and these ones, by name or index URL.
+    pub allow_registry: Vec<String>,
+    /// How many threads copy the packages, as many as the CPUs if `0`.
+    pub jobs: usize,
+    /// Print nothing on stderr but the errors, see `note!`.
+    pub quiet: bool,
+}
+
+impl Current {
+    pub fn run(self) -> Result<()> {
+        let started = Instant::now();
+        let mut summary = Summary::default();
+        if let Some(path) = &self.archive {
+            archive::check_format(path)?;
+        }
+        let reviewignore = if self.reviewignore && self.layout == Layout::Review {
+            ReviewIgnore::load()?
+        } else {
+            ReviewIgnore::default()
+        };
+        let omit = filter::Omit::new(&self.exclude, self.max_file_size)?;
+        let allowed = license::Allowed::parse(&self.license_allow)?;
+        let policy = policy::Policy::new(&self.deny_source, &self.allow_registry)?;
+        if let Some(packages) = &self.packages {
+            return self.check_out_packages(
+                packages,
+                &reviewignore,
+                &omit,
+                &policy,
+                summary,
+                started,
+            );
+        }
+        let mut targets = self.targets.clone();
+        if self.host_target {
+            let host = host_triple()?;
+            if !targets.contains(&host) {
+                targets.push(host);
+            }
+        }
+        let (metadata, unfiltered) = self.metadata(&targets, &policy)?;
+        let mut selection = self.select(&metadata, unfiltered.as_ref(), targets, &mut summary)?;
+        if let (Some(format), Some(path)) = (self.sbom, &self.sbom_output) {
+            let checksums = verify::Checksums::read(Path::new(&metadata.workspace_root))?;
+            sbom::write(
+                format,
+                path,
+                &metadata,
+                &selection.packages,
+                &checksums,
+                self.quiet,
+            )?;
+        }
+        if !self.offline {
+            let published = selection
+                .packages
+                .iter()
+                .filter(|pkg| PackageSource::of(pkg) == PackageSource::CratesIo)
+                .map(|pkg| (pkg.name.as_str(), &pkg.version));
+            yanked::report(
+                &yanked::audit(published, self.jobs)?,
+                self.deny_yanked,
+                self.quiet,
+            )?;
+        }
+        let since = match &self.since {
+            Some(dir) => Some(read_snapshot(dir)?),
+            None => None,
+        };
+        self.set_aside(
+            &metadata,
+            &mut selection,
+            &allowed,
+            since.as_ref(),
+            &mut summary,
+        );
+        if self.list {
+            let dirs: Vec<String> = checkout_dirs(&metadata, &selection.packages, Path::new(""))
+                .iter()
+                .map(|it| it.to_string_lossy().into_owned())
+                .collect();
+            let items = inventory::items(&metadata, &selection.packages, &dirs);
+            if self.format == OutputFormat::Json {
+                println!("{}", inventory::to_json(&items)?);
+            } else {
+                print!("{}", inventory::render(&items));
+            }
+            return Ok(());
+        }
+        // All of them, before copying any.
+        let missing = offline::not_downloaded(&selection.packages);
+        if !missing.is_empty() {
+            return Err(offline::missing_error(
+                &missing,
+                self.manifest_path.as_deref(),
+                self.offline,
+            ));
+        }
+        if let Some(path) = &self.archive {
+            return self.archive(path, &metadata, &selection, &reviewignore, summary, started);
+        }
+        let mut checkout = self.check_out(&metadata, &selection, &reviewignore, &omit)?;
+        if !allowed.is_empty() {
+            for entry in checkout.entries.iter_mut() {
+                entry.license_policy = Some(snapshot::LicensePolicy::Review);
+            }
+        }
+        reviewignore.report(&checkout.ignored, false, self.quiet);
+        let (manifest, delta) = self.write_snapshot(
+            &metadata,
+            &selection,
+            &checkout,
+            since.as_ref(),
+            &mut summary,
+        )?;
+        self.report_selection(&selection);
+        let mut locs: Vec<usize> = checkout
+            .dirs
+            .iter()
+            .map(|dir| manifest.entry(dir).map_or(0, |it| it.loc))
+            .collect();
+        // With the ones of `--since`, which are checked out all the same.
+        let mut all = selection.packages.clone();
+        for (pkg, known) in selection.referenced.iter() {
+            all.push(pkg);
+            locs.push(known.loc);
+        }
+        let duplicates = duplicates::find(&metadata, &all, &locs)?;
+        if let (Some(delta), Some(since_dir)) = (&delta, &self.since) {
+            note!(
+                self.quiet,
+                "Since {}: {}",
+                since_dir.display(),
+                delta.counts()
+            );
+        }
+        duplicates::print(&duplicates, self.quiet);
+        summary.copied = checkout.copied;
+        summary.unchanged = checkout.unchanged;
+        summary.failed = checkout.failures.len();
+        self.finish(&manifest, &checkout.entries, summary, started)?;
+        let failures: Vec<_> = checkout
+            .failures
+            .iter()
+            .map(|(pkg, _, err)| (format!("{} {}", pkg.name, pkg.version), err))
+            .collect();
+        report_failures(&failures, selection.packages.len() - checkout.unchanged)?;
+        if self.layout == Layout::Vendor {
+            note!(
+                self.quiet,
+                "To build with the packages checked out, add to .cargo/config.toml:\n"
+            );
+            let config = vendor::source_config(&selection.packages, &checkout.dest);
+            // stdout is for the manifest, with `--format json`.
+            if self.format == OutputFormat::Json {
+                note!(self.quiet, "{}", config);
+            } else {
+                println!("{}", config);
+            }
+        }
+        duplicates::deny(&duplicates, &self.deny_duplicates)
+    }
+
+    /// Checks out the `packages` of `--packages-from` rather than the
+    /// dependencies of the workspace, unless some violate the `policy`, with
+    /// the files `reviewignore` and `omit` leave out, and prints the `summary` of what's done since
+    /// `started`.
+    fn check_out_packages(
+        &self,
+        packages: &[PackageId],
+        reviewignore: &ReviewIgnore,
+        omit: &filter::Omit,
+        policy: &policy::Policy,
+        mut summary: Summary,
+        started: Instant,
+    ) -> Result<()> {
+        if packages.is_empty() {
+            note!(self.quiet, "{} no packages to check out", warning_label());
+        }
+        let mut resolver = Resolver::new(false, self.offline);
+        resolver.manifest_path = self.manifest_path.clone();
+        let mut pkg_ids = Vec::new();
+        for pkg_id in packages {
+            pkg_ids.push(resolver.resolve(specify(pkg_id.clone(), VersionSpec::Locked), None)?);
+        }
+        if !policy.is_empty() {
+            policy.check_ids(&pkg_ids)?;
+        }
+        if !self.offline {
+            let published = pkg_ids.iter().filter_map(|it| match &it.source {
+                Source::Registry {
+                    version: VersionSpec::Exact(version),
+                    registry: None,
+                    ..
+                } => Some((it.name.as_str(), version)),
+                _ => None,
+            });
+            yanked::report(
+                &yanked::audit(published, self.jobs)?,
+                self.deny_yanked,
+                self.quiet,
+            )?;
+        }
+        let dirs: Vec<PathBuf> = pkg_ids
+            .iter()
+            .map(|it| self.dest.join(it.dir_name()))
+            .collect();
+        check_dests(&dirs, self.force)?;
+        fs::create_dir_all(&self.dest)?;
+        let mut yanked = Vec::new();
+        for pkg_id in pkg_ids.iter() {
+            yanked.push(resolver.yanked(pkg_id)?);
+        }
+        let work: Vec<_> = pkg_ids.iter().zip(dirs.iter()).zip(yanked.iter()).collect();
+        let progress = progress::Progress::new(work.len(), self.quiet);
+        // With `--fail-fast`, the packages after the first failure aren't
+        // fetched at all.
+        let stop = AtomicBool::new(false);
+        let results = jobs::map(self.jobs, &work, |&((pkg_id, dst), yanked)| {
+            if stop.load(Ordering::SeqCst) {
+                return None;
+            }
+            progress.start(&pkg_id.to_string());
+            let res = self.fetch_one(pkg_id, yanked.as_ref(), dst, reviewignore, omit);
+            if res.is_err() && self.fail_fast {
+                stop.store(true, Ordering::SeqCst);
+            }
+            progress.finish_one();
+            Some(res)
+        });
+        progress.finish();
+        let mut entries = Vec::new();
+        let mut ignored = reviewignore.counts();
+        let mut failures = Vec::new();
+        for (&((pkg_id, dst), _), res) in work.iter().zip(results) {
+            match res {
+                Some(Ok((Some(entry), counts))) => {
+                    entries.push(entry);
+                    ignored.add(&counts);
+                }
+                Some(Ok((None, counts))) => {
+                    ignored.add(&counts);
+                    note!(
+                        self.quiet,
+                        "{} {} is left out of {}: Cargo can't read its manifest",
+                        warning_label(),
+                        pkg_id,
+                        snapshot::MANIFEST_FILE
+                    );
+                }
+                Some(Err(err)) if self.fail_fast => {
+                    return Err(format_err!("can't copy {}: {}", pkg_id, err))
+                }
+                Some(Err(err)) => failures.push((pkg_id, dst, err)),
+                None => (),
+            }
+        }
+        reviewignore.report(&ignored, false, self.quiet);
+        let mut manifest = snapshot::Manifest {
+            packages: entries.clone(),
+        };
+        // Their directories, if they have one, may well be half copied.
+        for (pkg_id, dst, err) in failures.iter() {
+            let version = match &pkg_id.source {
+                Source::Registry {
+                    version: VersionSpec::Exact(version),
+                    ..
+                } => version.to_string(),
+                _ => String::new(),
+            };
+            let directory = dst
+                .file_name()
+                .map(|it| it.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            manifest.packages.push(snapshot::Entry::unfetched(
+                pkg_id.name.clone(),
+                version,
+                directory,
+                err.to_string(),
+            ));
+        }
+        self.write_manifest(&manifest)?;
+        summary.copied = entries.len();
+        summary.failed = failures.len();
+        summary.skip(
+            "left out of the manifest",
+            pkg_ids.len() - summary.copied - summary.failed,
+        );
+        self.finish(&manifest, &entries, summary, started)?;
+        let failures: Vec<_> = failures
+            .iter()
+            .map(|(pkg_id, _, err)| (pkg_id.to_string(), err))
+            .collect();
+        report_failures(&failures, pkg_ids.len())
+    }
+
+    /// Fetches `pkg_id`, which is `yanked` if it's this entry of the index,
+    /// and copies it to `dst` with the files `reviewignore` and `omit` leave
+    /// out. Returns its entry in the manifest, if Cargo can read its
+    /// manifest, and what `reviewignore` left out.
+    fn fetch_one(
+        &self,
+        pkg_id: &PackageId,
+        yanked: Option<&IndexEntry>,
+        dst: &Path,
+        reviewignore: &ReviewIgnore,
+        omit: &filter::Omit,
+    ) -> Result<(Option<snapshot::Entry>, reviewignore::Counts)> {
+        let fetched = fetch(pkg_id, yanked, &Features::default(), self.offline, false)?;
+        replace_dir(dst, |dst| {
+            copy_package(pkg_id, &fetched.src, dst)?;
+            if self.strip_artifacts {
+                strip_artifacts_in(dst)?;
+            }
+            Ok(())
+        })?;
+        let mut ignored = reviewignore.counts();
+        reviewignore.remove(&pkg_id.name, dst, &mut ignored)?;
+        let skipped_files = omit.remove(dst)?;
+        let entry = match &fetched.package {
+            Some(pkg) => {
+                let mut entry = snapshot::Entry::new(pkg, dst)?;
+                entry.skipped_files = skipped_files;
+                Some(entry)
+            }
+            None => None,
+        };
+        Ok((entry, ignored))
+    }
+
+    /// The dependencies of the workspace, as Cargo resolves them for the
+    /// `targets`, and for all the targets too if there are some, to tell
+    /// which packages aren't built for them. Downloads the missing sources
+    /// first with `fetch_missing`, and fails if some violate the `policy`.
+    fn metadata(
+        &self,
+        targets: &[String],
+        policy: &policy::Policy,
+    ) -> Result<(cargo_metadata::Metadata, Option<cargo_metadata::Metadata>)> {
+        let manifest_path = self.manifest_path.as_deref();
+        if self.fetch_missing && !offline::not_cached(manifest_path)?.is_empty() {
+            offline::fetch(manifest_path, self.quiet)?;
+        }
+        let metadata = Metadata {
+            manifest_path,
+            offline: self.offline,
+            filter_platforms: targets,
+            features: Some(&self.features),
+            current_dir: None,
+        }
+        .run();
+        let metadata = match metadata {
+            Ok(it) => it,
+            // Cargo stops at the first package it can't download.
+            Err(err) if self.offline => {
+                let missing = offline::not_cached(manifest_path)?;
+                if missing.is_empty() {
+                    return Err(err);
+                }
+                return Err(offline::missing_error(&missing, manifest_path, true));
+            }
+            Err(err) => return Err(err),
+        };
+        if !policy.is_empty() {
+            policy.check(&metadata)?;
+        }
+        // The packages for all the targets, to tell which ones are skipped.
+        let unfiltered = if targets.is_empty() {
+            None
+        } else {
+            let it = Metadata {
+                manifest_path,
+                offline: self.offline,
+                filter_platforms: &[],
+                features: Some(&self.features),
+                current_dir: None,
+            }
+            .run()?;
+            Some(it)
+        };
+        Ok((metadata, unfiltered))
+    }
+
+    /// Packs the packages of the `selection` of `metadata` into the archive
+    /// at `path`, see `pack`, and prints the `summary` of what's done since
+    /// `started`.
+    fn archive(
+        &self,
+        path: &Path,
+        metadata: &cargo_metadata::Metadata,
+        selection: &Selection,
+        reviewignore: &ReviewIgnore,
+        mut summary: Summary,
+        started: Instant,
+    ) -> Result<()> {
+        let dirs = checkout_dirs(metadata, &selection.packages, Path::new(""));
+        let (manifest, ignored) = self.pack(path, metadata, selection, &dirs, reviewignore)?;
+        let locs: Vec<usize> = dirs
+            .iter()
+            .map(|dir| manifest.entry(dir).map_or(0, |it| it.loc))
+            .collect();
+        let duplicates = duplicates::find(metadata, &selection.packages, &locs)?;
+        reviewignore.report(&ignored, false, self.quiet);
+        self.report_selection(selection);
+        duplicates::print(&duplicates, self.quiet);
+        summary.copied = selection.packages.len();
+        summary.packed = true;
+        self.finish(&manifest, &manifest.packages, summary, started)?;
+        duplicates::deny(&duplicates, &self.deny_duplicates)
+    }
+
+    /// The packages of `metadata` which pass the filters, out of those of
+    /// `unfiltered`, for all the targets, if it's there as Cargo resolved
+    /// them only for the `targets`. Counts the ones left out in `summary`.
+    fn select<'a>(
+        &self,
+        metadata: &'a cargo_metadata::Metadata,
+        unfiltered: Option<&'a cargo_metadata::Metadata>,
+        targets: Vec<String>,
+        summary: &mut Summary,
+    ) -> Result<Selection<'a>> {
+        let mut packages: Vec<_> = unfiltered
+            .unwrap_or(metadata)
+            .packages
+            .iter()
+            .filter(|pkg| self.includes(metadata, pkg, summary))
+            .collect();
+        let mut skipped = Vec::new();
+        if unfiltered.is_some() {
+            let built: HashSet<&str> = match &metadata.resolve {
+                Some(resolve) => resolve.nodes.iter().map(|it| it.id.repr.as_str()).collect(),
+                None => HashSet::new(),
+            };
+            let why = format!(
+                "not built for {}, leave out --target to check it out",
+                enumerate(&targets)
+            );
+            skipped = packages
+                .iter()
+                .filter(|it| !built.contains(it.id.repr.as_str()))
+                .map(|it| format!("{} {}", it.name, it.version))
+                .collect();
+            keep_packages(&mut packages, &self.only, &why, |pkg| {
+                built.contains(pkg.id.repr.as_str())
+            })?;
+            summary.skip(
+                &format!("not built for {}", enumerate(&targets)),
+                skipped.len(),
+            );
+        }
+        let mut excluded = Vec::new();
+        if self.no_dev_deps {
+            excluded.push((
+                DependencyKind::Development,
+                "dev-dependencies",
+                "--no-dev-deps",
+            ));
+        }
+        if self.no_build_deps {
+            excluded.push((
+                DependencyKind::Build,
+                "build-dependencies",
+                "--no-build-deps",
+            ));
+        }
+        let kinds: Vec<DependencyKind> = excluded.iter().map(|it| it.0).collect();
+        let mut left_out = None;
+        if !excluded.is_empty() {
+            let names: Vec<String> = excluded.iter().map(|it| it.1.to_string()).collect();
+            let flags: Vec<String> = excluded.iter().map(|it| it.2.to_string()).collect();
+            let reachable = dep_graph::reachable(metadata, &kinds)?;
+            let why = format!(
+                "only pulled in by {}, leave out {} to check it out",
+                enumerate(&names),
+                enumerate(&flags)
+            );
+            let all = packages.len();
+            keep_packages(&mut packages, &self.only, &why, |pkg| {
+                reachable.contains(pkg.id.repr.as_str())
+            })?;
+            left_out = Some((all - packages.len(), enumerate(&names)));
+            summary.skip(
+                &format!("only pulled in by {}", enumerate(&names)),
+                all - packages.len(),
+            );
+        }
+        if !self.member.is_empty() {
+            let roots = selected_members(metadata, &self.member)?;
+            let reachable = dep_graph::reachable_from(metadata, &roots, &kinds)?;
+            let why = format!(
+                "not pulled in by {}, leave out --member to check it out",
+                enumerate(&self.member)
+            );
+            let all = packages.len();
+            keep_packages(&mut packages, &self.only, &why, |pkg| {
+                reachable.contains(pkg.id.repr.as_str())
+            })?;
+            summary.skip(
+                &format!("not pulled in by --member {}", enumerate(&self.member)),
+                all - packages.len(),
+            );
+        }
+        let mut transitive = 0;
+        if self.direct_only {
+            let direct = direct_dependencies(metadata)?;
+            let all = packages.len();
+            keep_packages(
+                &mut packages,
+                &self.only,
+                "only a transitive dependency, leave out --direct-only to check it out",
+                |pkg| {
+                    direct.contains(pkg.id.repr.as_str())
+                        || package_source(metadata, pkg) == PackageSource::Workspace
+                },
+            )?;
+            transitive = all - packages.len();
+            summary.skip("transitive dependencies", transitive);
+        }
+        let direct = packages.len();
+        if !self.only.is_empty() {
+            packages = select_packages(packages, &self.only, self.quiet)?;
+            summary.skip("not selected with -p", direct - packages.len());
+        }
+        Ok(Selection {
+            targets,
+            packages,
+            allowed: Vec::new(),
+            referenced: Vec::new(),
+            skipped,
+            left_out,
+            direct,
+            transitive,
+            provenance: dep_graph::provenance(metadata, PROVENANCE_CHAINS)?,
+        })
+    }
+
+    /// Sets the packages of the `selection` whose license is `allowed`
+    /// apart, and then the ones which are the same in the snapshot of
+    /// `--since`, and counts them in `summary`.
+    fn set_aside<'a>(
+        &self,
+        metadata: &cargo_metadata::Metadata,
+        selection: &mut Selection<'a>,
+        allowed: &license::Allowed,
+        since: Option<&'a snapshot::Manifest>,
+        summary: &mut Summary,
+    ) {
+        if !allowed.is_empty() {
+            let (left_out, kept) = mem::take(&mut selection.packages)
+                .into_iter()
+                .partition(|pkg| allowed.covers(pkg.license.as_deref()));
+            selection.packages = kept;
+            selection.allowed = left_out;
+            summary.skip("allowed by --license-allow", selection.allowed.len());
+            for pkg in selection.packages.iter() {
+                summary.review_license(pkg);
+            }
+        }
+        if let Some(previous) = since {
+            let mut kept = Vec::new();
+            for pkg in mem::take(&mut selection.packages) {
+                match previous.find(pkg) {
+                    Some(entry) if package_source(metadata, pkg).is_immutable() => {
+                        selection.referenced.push((pkg, entry))
+                    }
+                    _ => kept.push(pkg),
+                }
+            }
+            selection.packages = kept;
+            summary.skip("the same in --since", selection.referenced.len());
+        }
+    }
+
+    /// Checks the packages of the `selection` of `metadata` out to the
+    /// destination, without the files `reviewignore` and `omit` leave out,
+    /// and writes why each of them is there next to it.
+    fn check_out<'a>(
+        &self,
+        metadata: &cargo_metadata::Metadata,
+        selection: &Selection<'a>,
+        reviewignore: &ReviewIgnore,
+        omit: &filter::Omit,
+    ) -> Result<CheckOut<'a>> {
+        let packages = &selection.packages;
+        let dirs = checkout_dirs(metadata, packages, &self.dest);
+        // What's checked out already is left as it is, if it's still the
+        // tree of the same package, unless it's a local one, which may have
+        // changed since.
+        let previous = snapshot::Manifest::read(&self.dest)?;
+        let mut entries: Vec<Option<snapshot::Entry>> = Vec::new();
+        let mut unknown = Vec::new();
+        for (pkg, dir) in packages.iter().zip(dirs.iter()) {
+            let known = match previous.entry(dir) {
+                // What's left of a package which couldn't be checked out is
+                // replaced, without `--force`.
+                Some(known) if known.failed.is_some() => {
+                    entries.push(None);
+                    continue;
+                }
+                Some(known) if dir.is_dir() => known,
+                _ => {
+                    unknown.push(dir.clone());
+                    entries.push(None);
+                    continue;
+                }
+            };
+            // With what it says about the package up to date, but how it was
+            // checked, filtered and copied.
+            let mut entry = snapshot::Entry::new(pkg, dir)?;
+            let same = entry.same_tree(known);
+            entry.verification = known.verification;
+            entry.skipped_files = known.skipped_files.clone();
+            entry.link_mode = known.link_mode;
+            let immutable = package_source(metadata, pkg).is_immutable();
+            if !same {
+                unknown.push(dir.clone());
+            }
+            entries.push(Some(entry).filter(|_| same && immutable && !self.refresh));
+        }
+        check_dests(&unknown, self.force || self.refresh)?;
+        fs::create_dir_all(&self.dest)?;
+        let dest = fs::canonicalize(&self.dest)?;
+        for pkg in packages.iter() {
+            let src = pkg_dir(pkg)?;
+            if dest.starts_with(&src) {
+                bail!(
+                    "can't check {} out to {}, which is inside of it",
+                    pkg.name,
+                    self.dest.display()
+                );
+            }
+        }
+        let checksums = if self.verify || self.layout == Layout::Vendor {
+            verify::Checksums::read(Path::new(&metadata.workspace_root))?
+        } else {
+            verify::Checksums::default()
+        };
+        // The entries of the manifest, of the unchanged packages so far.
+        let mut slots: Vec<Option<snapshot::Entry>> = Vec::new();
+        let mut work = Vec::new();
+        for ((pkg, dst), entry) in packages.iter().zip(dirs.iter()).zip(entries) {
+            if entry.is_none() {
+                // Sources which may have been tampered with stop everything,
+                // before anything is copied.
+                let verification = self.verification(metadata, pkg, &checksums)?;
+                work.push((slots.len(), *pkg, dst.as_path(), verification));
+            }
+            slots.push(entry);
+        }
+        let unchanged = slots.len() - work.len();
+        let progress = progress::Progress::new(work.len(), self.quiet);
+        // With `--fail-fast`, the packages after the first failure aren't
+        // copied at all.
+        let stop = AtomicBool::new(false);
+        let results = jobs::map(self.jobs, &work, |&(_, pkg, dst, verification)| {
+            if stop.load(Ordering::SeqCst) {
+                return None;
+            }
+            progress.start(&format!("{} {}", pkg.name, pkg.version));
+            let res = self.copy_one(pkg, dst, Some(verification), &checksums, reviewignore, omit);
+            if res.is_err() && self.fail_fast {
+                stop.store(true, Ordering::SeqCst);
+            }
+            progress.finish_one();
+            Some(res)
+        });
+        progress.finish();
+        let mut copied = 0;
+        let mut ignored = reviewignore.counts();
+        let mut failures = Vec::new();
+        for (&(idx, pkg, dst, _), res) in work.iter().zip(results) {
+            match res {
+                Some(Ok((entry, counts))) => {
+                    slots[idx] = Some(entry);
+                    ignored.add(&counts);
+                    copied += 1;
+                }
+                Some(Err(err)) if self.fail_fast => {
+                    return Err(format_err!(
+                        "can't copy {} {}: {}",
+                        pkg.name,
+                        pkg.version,
+                        err
+                    ))
+                }
+                Some(Err(err)) => failures.push((pkg, dst.to_path_buf(), err)),
+                None => (),
+            }
+        }
+        // Written again for the unchanged ones too, the graph may have
+        // changed since.
+        for ((slot, pkg), dst) in slots.iter_mut().zip(packages.iter()).zip(dirs.iter()) {
+            if let Some(entry) = slot {
+                entry.provenance = selection.provenance_of(pkg);
+                if self.layout == Layout::Review {
+                    snapshot::write_provenance(pkg, dst, &entry.provenance)?;
+                }
+            }
+        }
+        Ok(CheckOut {
+            dest,
+            dirs,
+            entries: slots.into_iter().flatten().collect(),
+            failures,
+            copied,
+            unchanged,
+            ignored,
+            previous,
+        })
+    }
+
+    /// Writes the manifest of the `checkout` of the `selection` of
+    /// `metadata`, with the packages which couldn't be checked out, the ones
+    /// the selection sets apart, and those of earlier snapshots which are
+    /// still there, or prunes them, counting them in `summary`. Writes
+    /// `delta.md` if there's a snapshot `since`, and `snapshot.toml` if
+    /// needed. Returns the manifest and what changed since.
+    fn write_snapshot(
+        &self,
+        metadata: &cargo_metadata::Metadata,
+        selection: &Selection,
+        checkout: &CheckOut,
+        since: Option<&snapshot::Manifest>,
+        summary: &mut Summary,
+    ) -> Result<(snapshot::Manifest, Option<delta::Delta>)> {
+        let mut manifest = snapshot::Manifest {
+            packages: checkout.entries.clone(),
+        };
+        // Their directories, if they have one, may well be half copied.
+        for (pkg, dst, err) in checkout.failures.iter() {
+            let directory = dst
+                .file_name()
+                .map(|it| it.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let mut entry = snapshot::Entry::failed(pkg, directory, err.to_string());
+            entry.provenance = selection.provenance_of(pkg);
+            manifest.packages.push(entry);
+        }
+        // The packages of earlier snapshots which aren't checked out this
+        // time stay in the manifest as long as they are there.
+        let mut pruned = 0;
+        let mut stale = Vec::new();
+        for entry in checkout.previous.packages.iter() {
+            let dir = self.dest.join(entry.directory());
+            let is_plain_name = Path::new(entry.directory()).components().count() == 1
+                && !entry.directory().starts_with('.');
+            let is_referenced = selection
+                .referenced
+                .iter()
+                .any(|(_, it)| it.directory() == entry.directory());
+            if checkout.dirs.contains(&dir) || !is_plain_name || !dir.is_dir() || is_referenced {
+                continue;
+            }
+            if self.prune {
+                fs::remove_dir_all(&dir)?;
+                pruned += 1;
+            } else {
+                stale.push(entry.clone());
+            }
+        }
+        let allowed_dirs = checkout_dirs(metadata, &selection.allowed, Path::new(""));
+        for (pkg, dir) in selection.allowed.iter().zip(allowed_dirs) {
+            let directory = dir.to_string_lossy().into_owned();
+            let mut entry = snapshot::Entry::allowed(pkg, directory);
+            entry.provenance = selection.provenance_of(pkg);
+            manifest.packages.push(entry);
+        }
+        if let Some(since_dir) = &self.since {
+            let since_dir = fs::canonicalize(since_dir)?;
+            for (pkg, known) in selection.referenced.iter() {
+                let location = match &known.snapshot {
+                    Some(it) => since_dir.join(it),
+                    None => since_dir.clone(),
+                };
+                let location = fs::canonicalize(&location).unwrap_or(location);
+                let mut entry = (*known).clone();
+                entry.snapshot = Some(delta::relative(&location, &checkout.dest));
+                entry.provenance = selection.provenance_of(pkg);
+                manifest.packages.push(entry);
+            }
+        }
+        let delta = since.map(|it| delta::compare(&it.packages, &manifest.packages));
+        manifest.packages.extend(stale);
+        self.write_manifest(&manifest)?;
+        if let (Some(delta), Some(since_dir)) = (&delta, &self.since) {
+            delta.write(&self.dest, &since_dir.to_string_lossy())?;
+        }
+        if !self.features.is_default() || !selection.targets.is_empty() {
+            snapshot::write_config(&self.dest, &self.features, &selection.targets)?;
+        }
+        summary.pruned = Some(pruned).filter(|_| self.prune);
+        Ok((manifest, delta))
+    }
+
+    /// Copies `pkg` to `dst`, as `cargo package` would pack it if it's a
+    /// local one, without the ignored files and the ones `omit` leaves out,
+    /// or with the `.cargo-checksum.json` of its files and of its checksum in
+    /// `checksums` in the vendor layout. Returns its entry in the manifest,
+    /// with `verification`, and how many files were ignored.
+    fn copy_one(
+        &self,
+        pkg: &cargo_metadata::Package,
+        dst: &Path,
+        verification: Option<verify::Verification>,
+        checksums: &verify::Checksums,
+        reviewignore: &ReviewIgnore,
+        omit: &filter::Omit,
+    ) -> Result<(snapshot::Entry, reviewignore::Counts)> {
+        let src = pkg_dir(pkg)?;
+        let local = !PackageSource::of(pkg).is_registry();
+        let mut links = tree::Links::default();
+        replace_dir(dst, |dst| {
+            if PackageSource::of(pkg) == PackageSource::Path {
+                tree::copy_entries(&src, dst, LOCAL_ONLY_FILES)?;
+                filter::Packaged::read(dst).remove()?;
+            } else {
+                let skip = if local { LOCAL_ONLY_FILES } else { &[] };
+                links = tree::link_entries(&src, dst, skip, self.link_mode)?;
+            }
+            if self.strip_artifacts && self.layout == Layout::Review {
+                strip_artifacts_in(dst)?;
+            }
+            Ok(())
+        })?;
+        let mut ignored = reviewignore.counts();
+        reviewignore.remove(&pkg.name, dst, &mut ignored)?;
+        let skipped_files = omit.remove(dst)?;
+        if self.layout == Layout::Vendor {
+            vendor::finish_package(dst, checksums.get(pkg))?;
+        }
+        if let Some(err) = &links.error {
+            note!(
+                self.quiet,
+                "{} can't link {} of {} {}, copied {} instead: {}",
+                warning_label(),
+                files(links.copied),
+                pkg.name,
+                pkg.version,
+                if links.copied == 1 { "it" } else { "them" },
+                err
+            );
+        }
+        let mut entry = snapshot::Entry::new(pkg, dst)?;
+        entry.verification = verification;
+        entry.skipped_files = skipped_files;
+        entry.link_mode = links.effective(self.link_mode);
+        Ok((entry, ignored))
+    }
+
+    /// Whether the sources of `pkg` of `meta` are the ones of Cargo.lock,
+    /// with its `checksums`. Fails if they aren't.
+    fn verification(
+        &self,
+        meta: &cargo_metadata::Metadata,
+        pkg: &cargo_metadata::Package,
+        checksums: &verify::Checksums,
+    ) -> Result<verify::Verification> {
+        let verification = if !self.verify {
+            verify::Verification::Skipped
+        } else if package_source(meta, pkg).is_registry() {
+            verify::verify(pkg, checksums)?
+        } else {
+            verify::Verification::NoChecksum
+        };
+        if verification == verify::Verification::NotCached {
+            note!(
+                self.quiet,
+                "{} the .crate file of {} {} isn't in the cache of Cargo, its sources can't be verified",
+                warning_label(),
+                pkg.name,
+                pkg.version
+            );
+        }
+        Ok(verification)
+    }
+
+    /// Packs the packages of the `selection` of `metadata` into the archive
+    /// at `path`, to the `dirs`, in the order of the directories, as
+    /// `copy_one` would check them out, with `REVIEW.md`, `manifest.json`,
+    /// and `snapshot.toml` if needed. Returns the manifest, and how many
+    /// files were ignored.
+    fn pack(
+        &self,
+        path: &Path,
+        metadata: &cargo_metadata::Metadata,
+        selection: &Selection,
+        dirs: &[PathBuf],
+        reviewignore: &ReviewIgnore,
+    ) -> Result<(snapshot::Manifest, reviewignore::Counts)> {
+        let packages = &selection.packages;
+        let checksums = if self.verify {
+            verify::Checksums::read(Path::new(&metadata.workspace_root))?
+        } else {
+            verify::Checksums::default()
+        };
+        // All of them, before packing any.
+        let verifications = packages
+            .iter()
+            .map(|pkg| self.verification(metadata, pkg, &checksums))
+            .collect::<Result<Vec<_>>>()?;
+        let omit = filter::Omit::new(&self.exclude, self.max_file_size)?;
+        let mut writer = archive::Writer::create(path)?;
+        let mut manifest = snapshot::Manifest::default();
+        let mut ignored = reviewignore.counts();
+        let mut order: Vec<usize> = (0..packages.len()).collect();
+        order.sort_by_key(|&idx| &dirs[idx]);
+        let progress = progress::Progress::new(packages.len(), self.quiet);
+        for idx in order {
+            let (pkg, dir) = (packages[idx], &dirs[idx]);
+            progress.start(&format!("{} {}", pkg.name, pkg.version));
+            let src = pkg_dir(pkg)?;
+            let skip = if PackageSource::of(pkg).is_registry() {
+                &[]
+            } else {
+                LOCAL_ONLY_FILES
+            };
+            let packaged = Some(filter::Packaged::read(&src))
+                .filter(|_| PackageSource::of(pkg) == PackageSource::Path);
+            let kept = reviewignore.kept(&pkg.name, &src, &mut ignored)?;
+            let has_original = src.join(ORIGINAL_MANIFEST).exists();
+            let build: Vec<PathBuf> = filter::build_files(&src)
+                .into_iter()
+                .map(|(it, _)| it)
+                .collect();
+            let mut skipped_files = Vec::new();
+            // The files in the archive, with the ones they come from.
+            let mut files = Vec::new();
+            for (path, item) in tree::list(&src, skip)? {
+                if packaged.as_ref().is_some_and(|it| it.excludes(&path)) {
+                    continue;
+                }
+                let name = if !self.strip_artifacts {
+                    path.clone()
+                } else if path == Path::new(ORIGINAL_MANIFEST) {
+                    PathBuf::from("Cargo.toml")
+                } else if PACKAGING_ARTIFACTS.iter().any(|it| path == Path::new(it))
+                    || (has_original && path == Path::new("Cargo.toml"))
+                {
+                    continue;
+                } else {
+                    path.clone()
+                };
+                if !kept.contains(&name) {
+                    continue;
+                }
+                let size = match &item {
+                    tree::Item::File => fs::metadata(src.join(&path))?.len(),
+                    tree::Item::Note(note) => note.len() as u64,
+                    tree::Item::Dir | tree::Item::Symlink(_) => 0,
+                };
+                match omit.reason(&name, size, &build) {
+                    Some(reason) => {
+                        skipped_files.push(snapshot::SkippedFile::new(&name, size, reason))
+                    }
+                    None => files.push((name, path, item)),
+                }
+            }
+            files.sort_by(|a, b| a.0.cmp(&b.0));
+            skipped_files.sort_by(|a, b| a.path().cmp(b.path()));
 
-pub use failure::Error;
-pub type Result<T> = ::std::result::Result<T, Error>;
+            writer.add_dir(dir)?;
+            let mut added_dirs = HashSet::new();
+            let mut tree = snapshot::TreeHash::default();
+            for (name, path, item) in files {
+                let parents: Vec<&Path> = name.ancestors().skip(1).collect();
+                for parent in parents.into_iter().rev() {
+                    if parent != Path::new("") && added_dirs.insert(parent.to_path_buf()) {
+                        writer.add_dir(&dir.join(parent))?;
+                    }
+                }
+                match item {
+                    tree::Item::Dir => (),
+                    tree::Item::File => {
+                        let executable = archive::is_executable(&fs::metadata(src.join(&path))?);
+                        let contents = fs::read(src.join(&path))?;
+                        writer.add_file(&dir.join(&name), &contents, executable)?;
+                        tree.add(&name, &contents, false);
+                    }
+                    tree::Item::Symlink(target) => {
+                        writer.add_symlink(&dir.join(&name), &target)?;
+                        tree.add(&name, target.to_string_lossy().as_bytes(), true);
+                    }
+                    tree::Item::Note(note) => {
+                        writer.add_file(&dir.join(&name), note.as_bytes(), false)?;
+                        tree.add(&name, note.as_bytes(), false);
+                    }
+                }
+            }
+            let chains = selection.provenance_of(pkg);
+            if !chains.is_empty() && !snapshot::ships_provenance(pkg) {
+                let text = chains.join("\n") + "\n";
+                writer.add_file(&dir.join(snapshot::PROVENANCE_FILE), text.as_bytes(), false)?;
+            }
+            let directory = dir.to_string_lossy().into_owned();
+            let mut entry = snapshot::Entry::with_tree(pkg, directory, tree);
+            entry.verification = Some(verifications[idx]);
+            entry.skipped_files = skipped_files;
+            entry.provenance = chains;
+            manifest.packages.push(entry);
+            progress.finish_one();
+        }
+        progress.finish();
+        let review = review_index::render(&manifest.packages);
+        writer.add_file(
+            Path::new(review_index::REVIEW_FILE),
+            review.as_bytes(),
+            false,
+        )?;
+        let json = manifest.to_json()? + "\n";
+        writer.add_file(Path::new(snapshot::MANIFEST_FILE), json.as_bytes(), false)?;
+        if !self.features.is_default() || !selection.targets.is_empty() {
+            let config = snapshot::config_text(&self.features, &selection.targets)?;
+            writer.add_file(Path::new(snapshot::CONFIG_FILE), config.as_bytes(), false)?;
+        }
+        writer.finish(self.quiet)?;
+        Ok((manifest, ignored))
+    }
 
-/// Mirrors `PackageId` from Cargo. `PackageId` is an unambiguous reference to a
-/// package version.
-///
-/// Future work: support git dependencies and alternative registries.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct PackageId {
-    name: String,
-    version: Version,
-}
+    /// Tells which packages the filters of the `selection` left out.
+    fn report_selection(&self, selection: &Selection) {
+        if !selection.skipped.is_empty() {
+            note!(
+                self.quiet,
+                "Skipped (not built for {}):",
+                enumerate(&selection.targets)
+            );
+            for package in selection.skipped.iter() {
+                note!(self.quiet, "  {}", package);
+            }
+        }
+        if let Some((left_out, kinds)) = &selection.left_out {
+            note!(
+                self.quiet,
+                "Left out {} only pulled in by {}",
+                packages_word(*left_out),
+                kinds
+            );
+        }
+        if self.direct_only {
+            note!(
+                self.quiet,
+                "Direct dependencies: {}, transitive ones left out: {}",
+                selection.direct,
+                selection.transitive
+            );
+        }
+    }
 
-impl fmt::Display for PackageId {
-    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        self.name.fmt(fmt)?;
-        fmt.write_str(":")?;
-        self.version.fmt(fmt)
+    /// Writes `manifest.json` and `REVIEW.md` to the destination.
+    fn write_manifest(&self, manifest: &snapshot::Manifest) -> Result<()> {
+        manifest.write(&self.dest)?;
+        review_index::write(&self.dest, &manifest.packages)?;
+        Ok(())
     }
-}
 
-impl FromStr for PackageId {
-    type Err = Error;
-    fn from_str(s: &str) -> Result<PackageId> {
-        let colon_idx = s.find(':').ok_or_else(|| {
-            format_err!(
-                "invalid package specification: {:?}; expected \"name:x.y.z\"",
-                s
-            )
-        })?;
-        let name = s[..colon_idx].to_string();
-        let version: Version = s[colon_idx + 1..].parse()?;
-        Ok(PackageId { name, version })
+    /// Prints the `summary` of the `entries` checked out, since `started`,
+    /// and the manifest with it to stdout with `--format json`.
+    fn finish(
+        &self,
+        manifest: &snapshot::Manifest,
+        entries: &[snapshot::Entry],
+        mut summary: Summary,
+        started: Instant,
+    ) -> Result<()> {
+        summary.finish(entries, started.elapsed());
+        if self.format == OutputFormat::Json {
+            println!("{}", summary.to_json(manifest)?);
+        }
+        if !self.quiet {
+            summary.print();
+        }
+        Ok(())
+    }
+
+    /// Whether the package `pkg` of `meta` is checked out, with its source.
+    /// Tells about the ones which aren't, and counts them in `summary`.
+    fn includes(
+        &self,
+        meta: &cargo_metadata::Metadata,
+        pkg: &cargo_metadata::Package,
+        summary: &mut Summary,
+    ) -> bool {
+        let source = package_source(meta, pkg);
+        let (included, flag, kind) = match source {
+            PackageSource::CratesIo | PackageSource::Registry => return true,
+            PackageSource::Git(_) => (self.include_git, "--include-git", "git dependencies"),
+            PackageSource::Path => (self.include_path, "--include-path", "path dependencies"),
+            PackageSource::Workspace => (
+                self.include_workspace,
+                "--include-workspace",
+                "members of the workspace",
+            ),
+        };
+        if !included {
+            note!(
+                self.quiet,
+                "Skipping package `{}`: {}, pass {} to check it out",
+                pkg.name,
+                source.description(),
+                flag
+            );
+            summary.skip(kind, 1);
+        }
+        included
     }
 }
 
-#[derive(Debug)]
-pub struct Diff {
-    pub first: PackageId,
-    pub second: PackageId,
-    pub dest: Option<PathBuf>,
+/// The packages `current` checks out, and what its filters left out.
+struct Selection<'a> {
+    /// The targets the packages are built for, all of them if empty.
+    targets: Vec<String>,
+    packages: Vec<&'a cargo_metadata::Package>,
+    /// The packages left out as their license is allowed, which are in the
+    /// manifest all the same.
+    allowed: Vec<&'a cargo_metadata::Package>,
+    /// The packages of the snapshot of `--since` in the same version, with
+    /// their entry there.
+    referenced: Vec<(&'a cargo_metadata::Package, &'a snapshot::Entry)>,
+    /// The packages which aren't built for the `targets`, like `rand 0.6.1`.
+    skipped: Vec<String>,
+    /// How many packages only the left out kinds of dependencies pull in,
+    /// and these kinds.
+    left_out: Option<(usize, String)>,
+    /// How many packages are left once the transitive dependencies are left
+    /// out, and how many of them are.
+    direct: usize,
+    transitive: usize,
+    /// The chains of dependencies which pull each package in, see
+    /// `dep_graph::provenance`.
+    provenance: HashMap<&'a str, Vec<String>>,
 }
 
-impl Diff {
-    pub fn run(self) -> Result<()> {
-        let first_src = fetch(&self.first)?;
-        let second_src = fetch(&self.second)?;
-        if let Some(dir) = self.dest {
-            fs::create_dir_all(&dir)?;
-            copy_dir(&first_src, &dir.join(self.first.to_string()))?;
-            copy_dir(&second_src, &dir.join(self.second.to_string()))?;
-        } else {
-            run_diff_cmd(&first_src, &second_src)?;
-        }
-        Ok(())
+impl<'a> Selection<'a> {
+    /// Why `pkg` is there, as chains of dependencies from the members.
+    fn provenance_of(&self, pkg: &cargo_metadata::Package) -> Vec<String> {
+        self.provenance
+            .get(pkg.id.repr.as_str())
+            .cloned()
+            .unwrap_or_default()
     }
 }
 
-pub fn run_diff_cmd(a: &Path, b: &Path) -> Result<()> {
-    let mut diff_cmd = Command::new("diff");
-    let diff_status = diff_cmd
-        .args(&["--color=auto", "-r"])
-        .arg(a)
-        .arg(b)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status();
-    if diff_status.is_err() {
-        if !has_diff_cmd() {
-            bail!("looks like you don't have a suitable diff command installed.\n\
-                   Try using --destination flag to run a custom diff tool or to compare sources manually.")
-        }
-    }
-    diff_status?;
-    Ok(())
+/// The packages `Current::check_out` checked out, and the ones it couldn't.
+struct CheckOut<'a> {
+    /// The destination, as an absolute path.
+    dest: PathBuf,
+    /// The directories of the packages of the selection, in their order.
+    dirs: Vec<PathBuf>,
+    /// The entries of the manifest of the packages which are checked out,
+    /// copied or unchanged, in the order of the selection.
+    entries: Vec<snapshot::Entry>,
+    /// The packages which couldn't be checked out, with their directory and
+    /// why.
+    failures: Vec<(&'a cargo_metadata::Package, PathBuf, Error)>,
+    copied: usize,
+    unchanged: usize,
+    /// How many files the rules of `.reviewignore` left out.
+    ignored: reviewignore::Counts,
+    /// The manifest of what was checked out before.
+    previous: snapshot::Manifest,
 }
 
-#[derive(Debug)]
-pub struct Current {
-    pub dest: PathBuf,
+/// Prints the `failures`, the packages which couldn't be checked out and
+/// why, and fails if there are some, out of `total` packages.
+fn report_failures(failures: &[(String, &Error)], total: usize) -> Result<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+    // Even with `--quiet`, as it's what went wrong.
+    eprintln!("Failed packages:");
+    for (pkg, err) in failures {
+        eprintln!("  {}: {}", pkg, err);
+    }
+    bail!(
+        "{} of {} couldn't be checked out, marked as failed in {}",
+        failures.len(),
+        packages_word(total),
+        snapshot::MANIFEST_FILE
+    )
 }
 
-impl Current {
-    pub fn run(self) -> Result<()> {
-        let metadata = Metadata {
-            manifest_path: None,
-        }
-        .run()?;
+impl Features {
+    /// Whether Cargo resolves with these features by default.
+    fn is_default(&self) -> bool {
+        self.features.is_empty() && !self.all_features && !self.no_default_features
+    }
 
-        fs::create_dir_all(&self.dest)?;
-        for pkg in crates_io_packages(&metadata) {
-            let src = pkg_dir(&pkg)?;
-            let dst = self.dest.join(format!("{}:{}", pkg.name, pkg.version));
-            copy_dir(&src, &dst)?;
+    /// The flags of `cargo metadata` for these features.
+    fn args(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        if !self.features.is_empty() {
+            res.push("--features".to_string());
+            res.push(self.features.join(","));
         }
-        Ok(())
+        if self.all_features {
+            res.push("--all-features".to_string());
+        }
+        if self.no_default_features {
+            res.push("--no-default-features".to_string());
+        }
+        res
     }
 }
 
@@ -121,12 +3437,27 @@ impl Current {
 pub struct UpdateDiff {
     pub dest: Option<PathBuf>,
     pub args: Vec<OsString>,
+    /// Leave out the files Cargo adds to packages, see `Diff`.
+    pub strip_artifacts: bool,
+    /// Leave out the files listed in `.reviewignore`, see `Diff`.
+    pub reviewignore: bool,
+    /// Replace the packages already checked out to `dest`.
+    pub force: bool,
 }
 
 impl UpdateDiff {
     pub fn run(self) -> Result<()> {
+        let reviewignore = if self.reviewignore {
+            ReviewIgnore::load()?
+        } else {
+            ReviewIgnore::default()
+        };
         let before_metadata = Metadata {
             manifest_path: None,
+            offline: false,
+            filter_platforms: &[],
+            features: None,
+            current_dir: None,
         }
         .run()?;
         let workspace_root = Path::new(&before_metadata.workspace_root);
@@ -145,6 +3476,10 @@ impl UpdateDiff {
         }
         let after_metadata = Metadata {
             manifest_path: None,
+            offline: false,
+            filter_platforms: &[],
+            features: None,
+            current_dir: None,
         }
         .run()?;
         let tmpdir;
@@ -159,9 +3494,20 @@ impl UpdateDiff {
         let after_dir = dest.join("after");
         fs::create_dir_all(&before_dir)?;
         fs::create_dir_all(&after_dir)?;
-        for pdiff in metadata_diff(&before_metadata, &after_metadata)? {
-            pdiff.dump_to(&dest)?;
+        let pdiffs = metadata_diff(&before_metadata, &after_metadata)?;
+        let dirs: Vec<PathBuf> = pdiffs.iter().flat_map(|it| it.dirs(dest)).collect();
+        check_dests(&dirs, self.force)?;
+        let mut ignored = reviewignore.counts();
+        for pdiff in pdiffs {
+            pdiff.dump_to(dest, self.strip_artifacts)?;
+            for side in ["before", "after"] {
+                let dir = dest.join(side).join(&pdiff.name);
+                if dir.exists() {
+                    reviewignore.remove(pdiff.package(), &dir, &mut ignored)?;
+                }
+            }
         }
+        reviewignore.report(&ignored, false, false);
 
         if self.dest.is_none() {
             run_diff_cmd(&before_dir, &after_dir)?
@@ -173,20 +3519,43 @@ impl UpdateDiff {
 
 #[derive(Debug)]
 struct PackageDiff {
+    /// Its directory: the name of the package and its semver compatible
+    /// version, like `rand-0.6`, with a hash of its source if it comes from
+    /// several.
     name: String,
+    package: String,
     before: Option<PathBuf>,
     after: Option<PathBuf>,
 }
 
 impl PackageDiff {
-    fn dump_to(&self, dest: &Path) -> Result<()> {
-        if let Some(src) = self.before.as_ref() {
-            let dst = dest.join("before").join(&self.name);
-            copy_dir(&src, &dst)?;
-        }
-        if let Some(src) = self.after.as_ref() {
-            let dst = dest.join("after").join(&self.name);
-            copy_dir(&src, &dst)?;
+    /// The name of the package alone.
+    fn package(&self) -> &str {
+        &self.package
+    }
+
+    /// The directories `dump_to` checks the package out to.
+    fn dirs(&self, dest: &Path) -> Vec<PathBuf> {
+        self.sides()
+            .map(|(side, _)| dest.join(side).join(&self.name))
+            .collect()
+    }
+
+    fn sides(&self) -> impl Iterator<Item = (&'static str, &PathBuf)> {
+        vec![("before", &self.before), ("after", &self.after)]
+            .into_iter()
+            .filter_map(|(side, src)| src.as_ref().map(|it| (side, it)))
+    }
+
+    fn dump_to(&self, dest: &Path, strip_artifacts: bool) -> Result<()> {
+        for (side, src) in self.sides() {
+            replace_dir(&dest.join(side).join(&self.name), |dst| {
+                tree::copy_tree(src, dst)?;
+                if strip_artifacts {
+                    strip_artifacts_in(dst)?;
+                }
+                Ok(())
+            })?;
         }
         Ok(())
     }
@@ -198,72 +3567,399 @@ fn metadata_diff(
 ) -> Result<Vec<PackageDiff>> {
     let before = extract_packages(before)?;
     let after = extract_packages(after)?;
+    let keys: BTreeSet<&PackageKey> = before.keys().chain(after.keys()).collect();
     let mut res = Vec::new();
-    for (name, before_path) in before.iter() {
-        if !after.contains_key(name) {
-            res.push(PackageDiff {
-                name: name.clone(),
-                before: Some(before_path.clone()),
-                after: None,
-            })
-        }
-    }
-    for (name, after_path) in after.iter() {
-        if !before.contains_key(name) {
-            res.push(PackageDiff {
-                name: name.clone(),
-                before: None,
-                after: Some(after_path.clone()),
-            })
-        }
-    }
-    for (name, before_path) in before.iter() {
-        if let Some(after_path) = after.get(name) {
-            if before_path == after_path {
-                continue;
-            }
-            res.push(PackageDiff {
-                name: name.clone(),
-                before: Some(before_path.clone()),
-                after: Some(after_path.clone()),
-            });
+    for &key in keys.iter() {
+        let (name, version, source) = key;
+        let (before_path, after_path) = (before.get(key), after.get(key));
+        if before_path == after_path {
+            continue;
+        }
+        // The same version from other sources gets other directories, as
+        // with `checkout_dirs`.
+        let mut dir = format!("{}-{}", name, version);
+        if keys
+            .iter()
+            .any(|it| it.0 == *name && it.1 == *version && it.2 != *source)
+        {
+            let hash = format!("{:x}", Sha256::digest(source.as_bytes()));
+            dir = dir_name(&format!("{}+{}", dir, &hash[..8]));
         }
+        res.push(PackageDiff {
+            name: dir,
+            package: name.clone(),
+            before: before_path.cloned(),
+            after: after_path.cloned(),
+        });
     }
-
     Ok(res)
 }
 
-fn extract_packages(meta: &cargo_metadata::Metadata) -> Result<HashMap<String, PathBuf>> {
+/// A registry package: its name, its semver compatible version, like `0.6`,
+/// and its source.
+type PackageKey = (String, String, String);
+
+fn extract_packages(meta: &cargo_metadata::Metadata) -> Result<HashMap<PackageKey, PathBuf>> {
     let mut res = HashMap::new();
-    for pkg in crates_io_packages(meta) {
-        let version = Version::parse(&pkg.version)?;
+    for pkg in registry_packages(meta) {
+        let version = &pkg.version;
         let semver_compatible_version = if version.major == 0 {
             format!("0.{}", version.minor)
         } else {
             format!("{}", version.major)
         };
-        let name = format!("{}:{}", pkg.name, semver_compatible_version);
-        res.insert(name, pkg_dir(pkg)?);
+        let source = snapshot::id_source(&pkg.id.repr).unwrap_or(&pkg.id.repr);
+        let key = (
+            pkg.name.clone(),
+            semver_compatible_version,
+            source.to_string(),
+        );
+        res.insert(key, pkg_dir(pkg)?);
     }
     Ok(res)
 }
 
-fn crates_io_packages<'a>(
-    meta: &'a cargo_metadata::Metadata,
-) -> impl Iterator<Item = &'a cargo_metadata::Package> {
-    meta.packages.iter().filter(|pkg| {
-        // Ideally we should look at the `source`, but that is private.
-        let is_cratesio_dep = pkg.id.contains("crates.io-index");
-        if !is_cratesio_dep {
+/// Packages coming from crates.io or from one of the alternative registries.
+fn registry_packages(
+    meta: &cargo_metadata::Metadata,
+) -> impl Iterator<Item = &cargo_metadata::Package> {
+    meta.packages.iter().filter(move |pkg| {
+        let source = package_source(meta, pkg);
+        if !source.is_registry() {
             eprintln!(
-                "Skipping package `{}`: not a crates.io dependency",
-                pkg.name
+                "Skipping package `{}`: {}, not a registry dependency",
+                pkg.name,
+                source.description()
             );
         }
-        is_cratesio_dep
+        source.is_registry()
     })
 }
 
+/// The sources of crates.io in the package ids, with the git and the sparse
+/// protocols.
+const CRATES_IO_SOURCES: &[&str] = &[
+    "registry+https://github.com/rust-lang/crates.io-index",
+    "sparse+https://index.crates.io/",
+];
+
+/// Where a package of the dependency graph comes from.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum PackageSource {
+    CratesIo,
+    /// An alternative registry, or a mirror of crates.io.
+    Registry,
+    /// A git repository, at the short revision of the checkout if it's known.
+    Git(Option<String>),
+    /// A directory outside of the workspace.
+    Path,
+    Workspace,
+}
+
+impl PackageSource {
+    /// Where `pkg` comes from, telling the members of the workspace from the
+    /// other local packages is left to `package_source`.
+    pub(crate) fn of(pkg: &cargo_metadata::Package) -> PackageSource {
+        let source = match &pkg.source {
+            Some(it) => it.to_string(),
+            None => return PackageSource::Path,
+        };
+        if CRATES_IO_SOURCES.contains(&source.as_str()) {
+            PackageSource::CratesIo
+        } else if source.starts_with("registry+") || source.starts_with("sparse+") {
+            PackageSource::Registry
+        } else if source.starts_with("git+") {
+            // Cargo checks each revision out to `git/checkouts/REPO/REV`, the
+            // sources don't always have it.
+            let components: Vec<String> = pkg
+                .manifest_path
+                .components()
+                .map(|it| it.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            let rev = components
+                .iter()
+                .position(|it| it == "checkouts")
+                .and_then(|idx| components.get(idx + 2))
+                .cloned();
+            PackageSource::Git(rev)
+        } else {
+            PackageSource::Path
+        }
+    }
+
+    pub(crate) fn is_registry(&self) -> bool {
+        matches!(self, PackageSource::CratesIo | PackageSource::Registry)
+    }
+
+    /// Whether its sources can't change for the same version.
+    pub(crate) fn is_immutable(&self) -> bool {
+        matches!(
+            self,
+            PackageSource::CratesIo | PackageSource::Registry | PackageSource::Git(Some(_))
+        )
+    }
+
+    /// How `current --list` tells it.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            PackageSource::CratesIo => "crates.io",
+            PackageSource::Registry => "registry",
+            PackageSource::Git(_) => "git",
+            PackageSource::Path => "path",
+            PackageSource::Workspace => "workspace",
+        }
+    }
+
+    /// Like `a git dependency`, for the messages.
+    pub(crate) fn description(&self) -> &'static str {
+        match self {
+            PackageSource::CratesIo => "a crates.io dependency",
+            PackageSource::Registry => "a dependency from another registry",
+            PackageSource::Git(_) => "a git dependency",
+            PackageSource::Path => "a path dependency",
+            PackageSource::Workspace => "a member of the workspace",
+        }
+    }
+}
+
+fn package_source(meta: &cargo_metadata::Metadata, pkg: &cargo_metadata::Package) -> PackageSource {
+    if meta.workspace_members.contains(&pkg.id) {
+        PackageSource::Workspace
+    } else {
+        PackageSource::of(pkg)
+    }
+}
+
+/// The `packages` which `specs`, like `ring` or `ring:0.16.20`, name, in the
+/// order of `packages`. A name in several versions selects all of them, which
+/// is told unless `quiet`.
+fn select_packages<'a>(
+    packages: Vec<&'a cargo_metadata::Package>,
+    specs: &[String],
+    quiet: bool,
+) -> Result<Vec<&'a cargo_metadata::Package>> {
+    let mut selected = vec![false; packages.len()];
+    for spec in specs {
+        let name = spec_name(spec);
+        let version = spec.get(name.len() + 1..);
+        let named: Vec<usize> = (0..packages.len())
+            .filter(|&idx| same_name(&packages[idx].name, name))
+            .collect();
+        let versions: Vec<String> = named
+            .iter()
+            .map(|&idx| packages[idx].version.to_string())
+            .collect();
+        if named.is_empty() {
+            let names: Vec<&str> = packages.iter().map(|it| it.name.as_str()).collect();
+            let similar: Vec<String> = similar_names(name, &names)
+                .iter()
+                .map(|it| format!("`{}`", it))
+                .collect();
+            if similar.is_empty() {
+                bail!("`{}` is not a dependency of the workspace", name);
+            }
+            bail!(
+                "`{}` is not a dependency of the workspace; did you mean {}?",
+                name,
+                similar.join(" or ")
+            );
+        }
+        match version {
+            Some(version) => match versions.iter().position(|it| it == version) {
+                Some(idx) => selected[named[idx]] = true,
+                None => bail!(
+                    "the workspace depends on `{}` {}, not {}",
+                    name,
+                    enumerate(&versions),
+                    version
+                ),
+            },
+            None => {
+                if named.len() > 1 {
+                    note!(
+                        quiet,
+                        "Checking out the {} versions of `{}` in the dependencies, {}",
+                        named.len(),
+                        name,
+                        enumerate(&versions)
+                    );
+                }
+                for idx in named {
+                    selected[idx] = true;
+                }
+            }
+        }
+    }
+    let res = packages
+        .into_iter()
+        .zip(selected)
+        .filter_map(|(pkg, selected)| if selected { Some(pkg) } else { None })
+        .collect();
+    Ok(res)
+}
+
+/// The directories in `dest` to check the `packages` of `meta` out to. Other
+/// sources of the same version get other directories: a git or local package
+/// is suffixed with where it comes from, and packages which would still share
+/// a directory, like the same version from two registries, with a hash of
+/// their source.
+fn checkout_dirs(
+    meta: &cargo_metadata::Metadata,
+    packages: &[&cargo_metadata::Package],
+    dest: &Path,
+) -> Vec<PathBuf> {
+    let names: Vec<String> = packages
+        .iter()
+        .map(|pkg| {
+            let qualifier = match package_source(meta, pkg) {
+                PackageSource::CratesIo | PackageSource::Registry => String::new(),
+                PackageSource::Git(Some(rev)) => format!("+git.{}", rev),
+                PackageSource::Git(None) => "+git".to_string(),
+                PackageSource::Path => "+local".to_string(),
+                PackageSource::Workspace => "+workspace".to_string(),
+            };
+            format!("{}-{}{}", pkg.name, pkg.version, qualifier)
+        })
+        .collect();
+    packages
+        .iter()
+        .zip(names.iter())
+        .map(|(pkg, name)| {
+            if names.iter().filter(|it| *it == name).count() == 1 {
+                return dest.join(dir_name(name));
+            }
+            let source = snapshot::id_source(&pkg.id.repr).unwrap_or(&pkg.id.repr);
+            let hash = format!("{:x}", Sha256::digest(source.as_bytes()));
+            dest.join(dir_name(&format!("{}+{}", name, &hash[..8])))
+        })
+        .collect()
+}
+
+/// Keeps the `packages` which `keep`. Fails, saying that it's `why`, if one
+/// of the `-p` `specs` only names packages which it doesn't, rather than
+/// telling it isn't a dependency.
+fn keep_packages(
+    packages: &mut Vec<&cargo_metadata::Package>,
+    specs: &[String],
+    why: &str,
+    keep: impl Fn(&cargo_metadata::Package) -> bool,
+) -> Result<()> {
+    for spec in specs {
+        let name = spec_name(spec);
+        let named: Vec<&&cargo_metadata::Package> = packages
+            .iter()
+            .filter(|it| same_name(&it.name, name))
+            .collect();
+        if !named.is_empty() && !named.iter().any(|it| keep(it)) {
+            bail!("`{}` is {}", name, why);
+        }
+    }
+    packages.retain(|it| keep(it));
+    Ok(())
+}
+
+/// The manifest of the snapshot in `dir`, which has to be there.
+fn read_snapshot(dir: &Path) -> Result<snapshot::Manifest> {
+    if !dir.join(snapshot::MANIFEST_FILE).is_file() {
+        bail!(
+            "{} has no {}, it's not a snapshot of `current`",
+            dir.display(),
+            snapshot::MANIFEST_FILE
+        );
+    }
+    snapshot::Manifest::read(dir)
+}
+
+/// `1 package` or `N packages`.
+fn packages_word(n: usize) -> String {
+    format!("{} package{}", n, if n == 1 { "" } else { "s" })
+}
+
+/// The name of a package in `-p`, like `ring` in `ring:0.16.20`.
+fn spec_name(spec: &str) -> &str {
+    spec.split([':', '@']).next().unwrap_or_default()
+}
+
+/// The ids of the members of the workspace of `meta` named `names`. Fails,
+/// with the names of the members, if one of them isn't.
+fn selected_members<'a>(
+    meta: &'a cargo_metadata::Metadata,
+    names: &[String],
+) -> Result<Vec<&'a str>> {
+    let members: Vec<&cargo_metadata::Package> = meta
+        .packages
+        .iter()
+        .filter(|pkg| meta.workspace_members.contains(&pkg.id))
+        .collect();
+    let mut res = Vec::new();
+    for name in names {
+        match members.iter().find(|it| same_name(&it.name, name)) {
+            Some(member) => res.push(member.id.repr.as_str()),
+            None => {
+                let mut names: Vec<String> = members.iter().map(|it| it.name.clone()).collect();
+                names.sort();
+                bail!(
+                    "--member {}: `{}` is not a member of the workspace, its members are {}",
+                    name,
+                    name,
+                    enumerate(&names)
+                );
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// The ids of the packages the members of the workspace of `meta` depend on.
+fn direct_dependencies(meta: &cargo_metadata::Metadata) -> Result<HashSet<&str>> {
+    let resolve = meta
+        .resolve
+        .as_ref()
+        .ok_or_else(|| format_err!("Cargo didn't resolve the dependencies of the workspace"))?;
+    let res = resolve
+        .nodes
+        .iter()
+        .filter(|node| meta.workspace_members.contains(&node.id))
+        .flat_map(|node| node.dependencies.iter().map(|it| it.repr.as_str()))
+        .collect();
+    Ok(res)
+}
+
+/// The `names` a typo or two away from `name`, the closest first, at most
+/// three of them.
+fn similar_names<'a>(name: &str, names: &[&'a str]) -> Vec<&'a str> {
+    let name = name.replace('-', "_");
+    let max_distance = if name.chars().count() > 4 { 2 } else { 1 };
+    let mut res: Vec<(usize, &str)> = names
+        .iter()
+        .map(|it| (edit_distance(&name, &it.replace('-', "_")), *it))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    res.sort();
+    res.dedup();
+    res.into_iter().take(3).map(|(_, it)| it).collect()
+}
+
+/// How many characters to insert, remove or replace to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
 /// We run real `cargo update` which writes to the lockfile. This struct makes sure (in
 /// Drop), that we restore it propertly afterwards.
 #[derive(Debug)]
@@ -307,43 +4003,284 @@ impl Drop for LockfileGuard {
 
 struct Metadata<'a> {
     manifest_path: Option<&'a Path>,
+    offline: bool,
+    /// Resolve only the dependencies built for these targets, all of them if
+    /// empty.
+    filter_platforms: &'a [String],
+    /// The default features of the workspace if `None`.
+    features: Option<&'a Features>,
+    /// Where Cargo runs, and looks for its config, the current directory if
+    /// `None`.
+    current_dir: Option<&'a Path>,
 }
 
 impl<'a> Metadata<'a> {
     fn run(self) -> Result<cargo_metadata::Metadata> {
-        let metadata = cargo_metadata::metadata_deps(
-            self.manifest_path,
-            true, // include dependencies
-        )
-        .map_err(|err| format_err!("cargo metadata failed: {}", err))?; // error_chain is not sync :-(
+        let mut cmd = Command::new("cargo");
+        cmd.args(["metadata", "--format-version", "1"]);
+        if let Some(manifest_path) = self.manifest_path {
+            cmd.arg("--manifest-path").arg(manifest_path);
+        }
+        if self.offline {
+            cmd.arg("--offline");
+        }
+        for target in self.filter_platforms {
+            cmd.arg("--filter-platform").arg(target);
+        }
+        if let Some(features) = self.features {
+            cmd.args(features.args());
+        }
+        if let Some(dir) = self.current_dir {
+            cmd.current_dir(dir);
+        }
+        let output = cmd.stdin(Stdio::null()).output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            return Err(CargoMetadataError { stderr }.into());
+        }
+        let metadata = serde_json::from_slice(&output.stdout)?;
         Ok(metadata)
     }
 }
 
+/// `cargo metadata` exited with an error, which is explained on its stderr.
+#[derive(Debug)]
+struct CargoMetadataError {
+    stderr: String,
+}
+
+impl fmt::Display for CargoMetadataError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "cargo metadata failed:\n{}", self.stderr)
+    }
+}
+
+impl failure::Fail for CargoMetadataError {}
+
 fn has_diff_cmd() -> bool {
-    match Command::new("diff").arg("--version").status() {
+    let status = Command::new("diff")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match status {
         Err(_) => false,
         Ok(status) => status.success(),
     }
 }
 
-/// Shells out to Cargo to download `pkg_id` from crates io.
-/// Returns the directory with the downloaded package;
-fn fetch(pkg_id: &PackageId) -> Result<PathBuf> {
+/// The target of the host, like `x86_64-unknown-linux-gnu`, from the rustc
+/// which Cargo runs.
+fn host_triple() -> Result<String> {
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let output = Command::new(rustc)
+        .arg("-vV")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| format_err!("can't run rustc to find the target of the host: {}", err))?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    match text.lines().find_map(|it| it.strip_prefix("host: ")) {
+        Some(host) if output.status.success() => Ok(host.trim().to_string()),
+        _ => bail!("rustc -vV doesn't tell the target of the host"),
+    }
+}
+
+/// A package downloaded by `fetch`.
+struct Fetched {
+    src: PathBuf,
+    /// The dependencies Cargo resolved for it, if it could, see `dep_graph`.
+    deps: Option<dep_graph::Graph>,
+    /// As Cargo reads it, if it could.
+    package: Option<cargo_metadata::Package>,
+}
+
+/// Shells out to Cargo to download `pkg_id` from its registry or git repository.
+/// Returns the directory with the downloaded package, and its dependencies;
+/// a local package is only resolved.
+///
+/// `pkg_id` must be resolved. `yanked` is the index entry of `pkg_id` if it is
+/// yanked: such versions can only be downloaded if they are in Cargo.lock.
+/// With `outside_workspace`, Cargo doesn't read the `.cargo/config.toml` of
+/// the current directory.
+fn fetch(
+    pkg_id: &PackageId,
+    yanked: Option<&IndexEntry>,
+    features: &Features,
+    offline: bool,
+    outside_workspace: bool,
+) -> Result<Fetched> {
     let dir = TempDir::new("cargo-diff-fetches")?;
     let temp_manifest = dir.path().join("Cargo.toml");
-    fs::write(&temp_manifest, format_cargo_toml(pkg_id))?;
+    // The path of a local package is relative to the current directory, not
+    // to the temporary one.
+    let manifest = match &pkg_id.source {
+        Source::Path(path) => format_cargo_toml(
+            &PackageId {
+                name: pkg_id.name.clone(),
+                source: Source::Path(fs::canonicalize(path)?),
+            },
+            features,
+        ),
+        _ => format_cargo_toml(pkg_id, features),
+    };
+    fs::write(&temp_manifest, manifest)?;
+    if let Some(entry) = yanked {
+        fs::write(
+            dir.path().join("Cargo.lock"),
+            format_cargo_lock(pkg_id, entry),
+        )?;
+    }
     let metadata = Metadata {
         manifest_path: Some(temp_manifest.as_path()),
+        offline,
+        filter_platforms: &[],
+        features: None,
+        current_dir: Some(dir.path()).filter(|_| outside_workspace),
     }
-    .run()?;
+    .run();
+    if let Source::Path(path) = &pkg_id.source {
+        // A local package may well not build, and its sources are there
+        // anyway.
+        let resolved = metadata.ok().and_then(|metadata| {
+            let package = metadata.packages.iter().find(|it| {
+                same_name(&it.name, &pkg_id.name) && PackageSource::of(it) == PackageSource::Path
+            })?;
+            Some((
+                dep_graph::graph(&metadata, &package.id.repr),
+                package.clone(),
+            ))
+        });
+        let (deps, package) = match resolved {
+            Some((deps, package)) => (Some(deps), Some(package)),
+            None => (None, None),
+        };
+        return Ok(Fetched {
+            src: path.clone(),
+            deps,
+            package,
+        });
+    }
+    let metadata = metadata.map_err(|err| {
+        let is_unknown_crate = match (err.downcast_ref::<CargoMetadataError>(), &pkg_id.source) {
+            (Some(err), Source::Registry { registry: None, .. }) => {
+                err.stderr.contains("no matching package named")
+            }
+            _ => false,
+        };
+        if is_unknown_crate {
+            index::unknown_crate_error(&pkg_id.name)
+        } else {
+            err
+        }
+    })?;
 
     let package = metadata
         .packages
         .iter()
-        .find(|it| it.name == pkg_id.name && it.version == pkg_id.version.to_string())
+        .find(|it| {
+            same_name(&it.name, &pkg_id.name)
+                && match &pkg_id.source {
+                    Source::Registry {
+                        version: VersionSpec::Exact(version),
+                        ..
+                    } => {
+                        // Compare parsed versions: equality ignores build
+                        // metadata, which might be omitted in `pkg_id`.
+                        &it.version == version
+                    }
+                    Source::Registry { .. } => true,
+                    Source::Git { .. } => matches!(PackageSource::of(it), PackageSource::Git(_)),
+                    Source::Path(_) => unreachable!(),
+                }
+        })
         .ok_or_else(|| format_err!("unexpected error: can't find package {:?}", pkg_id))?;
-    pkg_dir(&package)
+    Ok(Fetched {
+        src: pkg_dir(package)?,
+        deps: Some(dep_graph::graph(&metadata, &package.id.repr)),
+        package: Some(package.clone()),
+    })
+}
+
+/// Fails if `features` names a feature the published package doesn't have.
+/// Features of dependencies, like `serde/derive`, are left to Cargo.
+fn check_features(pkg_id: &PackageId, entry: &IndexEntry, features: &Features) -> Result<()> {
+    let unknown = features
+        .features
+        .iter()
+        .find(|it| !it.contains('/') && !entry.features.contains(it));
+    match unknown {
+        Some(feature) if entry.features.is_empty() => {
+            bail!(
+                "{} has no feature `{}`; it has no features at all",
+                pkg_id,
+                feature
+            )
+        }
+        Some(feature) => bail!(
+            "{} has no feature `{}`; available features: {}",
+            pkg_id,
+            feature,
+            entry.features.join(", ")
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Checks the `.crate` file `pkg_id` was unpacked from to `src` against the
+/// expected checksum, or prints the checksum if none is given, so that it can
+/// be pinned next time.
+fn verify_checksum(pkg_id: &PackageId, src: &Path) -> Result<()> {
+    let expected = match &pkg_id.source {
+        Source::Registry { checksum, .. } => checksum,
+        _ => return Ok(()),
+    };
+    let actual = crate_checksum(src)?;
+    match expected {
+        Some(expected) if *expected != actual => bail!(
+            "checksum mismatch for {}: expected sha256={}, got sha256={}\n\
+             The sources might have been tampered with!",
+            pkg_id,
+            expected,
+            actual
+        ),
+        Some(_) => eprintln!("Verified checksum of {}", pkg_id),
+        None => eprintln!("Checksum of {}: sha256={}", pkg_id, actual),
+    }
+    Ok(())
+}
+
+/// SHA-256 of the `.crate` file unpacked to `src`.
+fn crate_checksum(src: &Path) -> Result<String> {
+    let crate_file = crate_file(src)?;
+    let bytes = fs::read(&crate_file)
+        .map_err(|err| format_err!("can't read {}: {}", crate_file.display(), err))?;
+    Ok(format!("{:x}", Sha256::digest(&bytes)))
+}
+
+/// The `.crate` file unpacked to `src`, which may be gone. Cargo unpacks
+/// `registry/cache/<index>/<name>-<version>.crate` to
+/// `registry/src/<index>/<name>-<version>`.
+fn crate_file(src: &Path) -> Result<PathBuf> {
+    match (src.file_name(), src.parent()) {
+        (Some(dir_name), Some(index_dir)) => match (index_dir.file_name(), index_dir.parent()) {
+            (Some(index_name), Some(src_dir)) if src_dir.ends_with("registry/src") => {
+                let mut file_name = dir_name.to_os_string();
+                file_name.push(".crate");
+                Ok(src_dir
+                    .with_file_name("cache")
+                    .join(index_name)
+                    .join(file_name))
+            }
+            _ => bail!("can't find the .crate file of {}", src.display()),
+        },
+        _ => bail!("can't find the .crate file of {}", src.display()),
+    }
+}
+
+/// Whether `a` and `b` name the same crate: Cargo doesn't distinguish `-`
+/// from `_`.
+fn same_name(a: &str, b: &str) -> bool {
+    a.replace('-', "_") == b.replace('-', "_")
 }
 
 fn pkg_dir(pkg: &cargo_metadata::Package) -> Result<PathBuf> {
@@ -360,7 +4297,26 @@ fn pkg_dir(pkg: &cargo_metadata::Package) -> Result<PathBuf> {
 }
 
 /// Conjures up a Cargo.toml with `pkg_id` as a dependency.
-fn format_cargo_toml(pkg_id: &PackageId) -> String {
+fn format_cargo_toml(pkg_id: &PackageId, features: &Features) -> String {
+    let mut dependency = match &pkg_id.source {
+        Source::Registry {
+            version, registry, ..
+        } => {
+            let mut res = vec![format!("version = {:?}", version.to_requirement())];
+            if let Some(registry) = registry {
+                res.push(format!("registry = {:?}", registry));
+            }
+            res
+        }
+        Source::Git { url, rev } => vec![format!("git = {:?}", url), format!("rev = {:?}", rev)],
+        Source::Path(path) => vec![format!("path = {:?}", path)],
+    };
+    if !features.features.is_empty() {
+        dependency.push(format!("features = {:?}", features.features));
+    }
+    if features.no_default_features {
+        dependency.push("default-features = false".to_string());
+    }
     format!(
         r#"
 [package]
@@ -371,8 +4327,170 @@ version = "0.0.0"
 path = "./Cargo.toml"
 
 [dependencies]
-{} = "={}"
+{} = {{ {} }}
+"#,
+        pkg_id.name,
+        dependency.join(", ")
+    )
+}
+
+/// Conjures up a Cargo.lock which pins `pkg_id`, even if it is yanked.
+fn format_cargo_lock(pkg_id: &PackageId, entry: &IndexEntry) -> String {
+    format!(
+        r#"
+version = 3
+
+[[package]]
+name = "{}"
+version = "{}"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "{}"
 "#,
-        pkg_id.name, pkg_id.version
+        pkg_id.name, entry.version, entry.checksum
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry(name: &str, version: &str) -> PackageId {
+        PackageId {
+            name: name.to_string(),
+            source: Source::Registry {
+                version: VersionSpec::Exact(version.parse().unwrap()),
+                registry: None,
+                checksum: None,
+            },
+        }
+    }
+
+    fn git(name: &str, url: &str, rev: &str) -> PackageId {
+        PackageId {
+            name: name.to_string(),
+            source: Source::Git {
+                url: url.to_string(),
+                rev: rev.to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn parses_registry_pkgid_urls() {
+        assert_eq!(
+            "registry+https://github.com/rust-lang/crates.io-index#rand@0.8.5"
+                .parse::<PackageId>()
+                .unwrap(),
+            registry("rand", "0.8.5")
+        );
+        assert_eq!(
+            "sparse+https://index.crates.io/#rand@0.8.5"
+                .parse::<PackageId>()
+                .unwrap(),
+            registry("rand", "0.8.5")
+        );
+        assert_eq!(
+            "sparse+https://index.crates.io/#wasi@0.10.2%2Bwasi-snapshot-preview1"
+                .parse::<PackageId>()
+                .unwrap(),
+            registry("wasi", "0.10.2+wasi-snapshot-preview1")
+        );
+    }
+
+    #[test]
+    fn parses_git_pkgid_urls() {
+        assert_eq!(
+            "git+https://github.com/rust-random/rand?rev=0123abc#rand_core@0.6.4"
+                .parse::<PackageId>()
+                .unwrap(),
+            git(
+                "rand_core",
+                "https://github.com/rust-random/rand",
+                "0123abc"
+            )
+        );
+        assert_eq!(
+            "git+https://github.com/rust-random/rand.git?branch=feature%2Fx#0.8.5"
+                .parse::<PackageId>()
+                .unwrap(),
+            git(
+                "rand",
+                "https://github.com/rust-random/rand.git",
+                "refs/heads/feature/x"
+            )
+        );
+        assert_eq!(
+            "git+https://github.com/rust-random/rand?tag=0.8.5#rand@0.8.5"
+                .parse::<PackageId>()
+                .unwrap(),
+            git(
+                "rand",
+                "https://github.com/rust-random/rand",
+                "refs/tags/0.8.5"
+            )
+        );
+        assert_eq!(
+            "git+https://example.com/my%20repo?rev=abc#my%2Dcrate@1.0.0"
+                .parse::<PackageId>()
+                .unwrap(),
+            git("my-crate", "https://example.com/my%20repo", "abc")
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_pkgid_urls() {
+        for spec in &[
+            "registry+#foo@1.0.0",
+            "registry+https://example.com/index#foo@1.0.0",
+            "git+https://github.com/rust-random/rand#rand@0.8.5",
+            "registry+https://index.crates.io/#foo@bar",
+            "path+https://example.com/foo#foo@1.0.0",
+        ] {
+            assert!(spec.parse::<PackageId>().is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn names_directories_validly_on_windows() {
+        let mut with_registry = registry("rand", "0.6.0");
+        if let Source::Registry { registry, .. } = &mut with_registry.source {
+            *registry = Some("mirror".to_string());
+        }
+        for (id, expected) in &[
+            (registry("rand", "0.6.0"), "rand-0.6.0"),
+            (
+                registry("wasi", "0.10.2+wasi-snapshot-preview1"),
+                "wasi-0.10.2_wasi-snapshot-preview1",
+            ),
+            (with_registry, "rand-0.6.0_mirror"),
+            (
+                git("rand", "https://github.com/rust-random/rand?x=1", "0123abc"),
+                "rand-git.0123abc",
+            ),
+            (
+                git(
+                    "rand",
+                    "https://github.com/rust-random/rand",
+                    "refs/heads/a?b",
+                ),
+                "rand-git.refs_heads_a_b",
+            ),
+        ] {
+            let name = id.dir_name();
+            assert_eq!(name, *expected);
+            // Those Windows doesn't allow in file names, and those which
+            // need escaping in urls.
+            let forbidden: &[char] = &[':', '+', '/', '@', '?', '\\', '*', '<', '>', '|', '"'];
+            assert!(!name.contains(forbidden), "{}", name);
+        }
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("feature%2Fx"), "feature/x");
+        assert_eq!(percent_decode("a%20b%2bc"), "a b+c");
+        // Incomplete or invalid escapes are left as they are.
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+    }
+}
@@ -0,0 +1,417 @@
+//! Changes of the license, which legal needs to hear about: the `license`
+//! and `license-file` fields of Cargo.toml, and the `LICENSE*` and `COPYING*`
+//! files. The READMEs which changed are listed along, in one line.
+//!
+//! License expressions are compared in a normal form, so that the deprecated
+//! `MIT/Apache-2.0` is the same as `MIT OR Apache-2.0`, and so is
+//! `Apache-2.0 OR MIT`.
+//!
+//! `current --license-allow` parses them for real, to tell whether the
+//! licenses it allows are enough for one of the choices an expression leaves.
+
+use std::{fmt, fs, path::Path};
+
+use toml;
+
+use builtin_diff;
+use Result;
+
+/// The changes of the license between two versions.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Changes {
+    /// Whether the `license` expressions mean different licenses.
+    expression_changed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expression: Option<Field>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    license_file: Option<Field>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    files: Vec<File>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    readmes: Vec<File>,
+}
+
+#[derive(Debug, Serialize)]
+struct Field {
+    old: Option<String>,
+    new: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct File {
+    path: String,
+    /// `added`, `removed` or `modified`.
+    status: &'static str,
+}
+
+impl Changes {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.expression.is_none()
+            && self.license_file.is_none()
+            && self.files.is_empty()
+            && self.readmes.is_empty()
+    }
+
+    /// The changes of the license itself, without the READMEs.
+    pub(crate) fn license_lines(&self) -> Vec<String> {
+        let show = |value: &Option<String>| value.clone().unwrap_or_else(|| "none".into());
+        let mut res = Vec::new();
+        if let Some(field) = &self.expression {
+            let meaning = if self.expression_changed {
+                ""
+            } else {
+                ", the same licenses"
+            };
+            res.push(format!(
+                "license: {} -> {}{}",
+                show(&field.old),
+                show(&field.new),
+                meaning
+            ));
+        }
+        if let Some(field) = &self.license_file {
+            res.push(format!(
+                "license-file: {} -> {}",
+                show(&field.old),
+                show(&field.new)
+            ));
+        }
+        for file in self.files.iter() {
+            res.push(format!("{} {}", file.status, file.path));
+        }
+        res
+    }
+
+    /// The changed READMEs, like `README.md (modified)`, empty if there are
+    /// none.
+    pub(crate) fn readme_list(&self) -> String {
+        let files: Vec<String> = self
+            .readmes
+            .iter()
+            .map(|it| format!("{} ({})", it.path, it.status))
+            .collect();
+        files.join(", ")
+    }
+
+    /// Prints the changes, if there are any, and the changed READMEs with
+    /// `readmes`.
+    pub(crate) fn print(&self, readmes: bool) {
+        let lines = self.license_lines();
+        if !lines.is_empty() {
+            println!("License changes:");
+            for line in lines {
+                println!(" {}", line);
+            }
+            println!();
+        }
+        if readmes && !self.readmes.is_empty() {
+            println!("Changed READMEs: {}", self.readme_list());
+            println!();
+        }
+    }
+}
+
+pub(crate) fn compare(a: &Path, b: &Path, jobs: usize) -> Result<Changes> {
+    let mut res = Changes::default();
+    let (old, new) = (read(a), read(b));
+    let field = |manifest: &Option<toml::Value>, name: &str| {
+        manifest
+            .as_ref()
+            .and_then(|it| it.get("package"))
+            .and_then(|it| it.get(name))
+            .map(|it| match it.as_str() {
+                Some(it) => it.to_string(),
+                None => it.to_string(),
+            })
+    };
+    let (old_expr, new_expr) = (field(&old, "license"), field(&new, "license"));
+    if old_expr != new_expr {
+        res.expression_changed =
+            old_expr.as_deref().map(normalize) != new_expr.as_deref().map(normalize);
+        res.expression = Some(Field {
+            old: old_expr,
+            new: new_expr,
+        });
+    }
+    let (old_file, new_file) = (field(&old, "license-file"), field(&new, "license-file"));
+    if old_file != new_file {
+        res.license_file = Some(Field {
+            old: old_file,
+            new: new_file,
+        });
+    }
+
+    let (changed, _) = builtin_diff::changed_paths(a, b, jobs)?;
+    for path in changed {
+        let name = match path.file_name() {
+            Some(it) => it.to_string_lossy().to_uppercase(),
+            None => continue,
+        };
+        let status = match (a.join(&path).exists(), b.join(&path).exists()) {
+            (false, _) => "added",
+            (_, false) => "removed",
+            _ => "modified",
+        };
+        let file = File {
+            path: path.display().to_string(),
+            status,
+        };
+        if ["LICENSE", "LICENCE", "COPYING"]
+            .iter()
+            .any(|it| name.starts_with(it))
+        {
+            res.files.push(file);
+        } else if name.starts_with("README") {
+            res.readmes.push(file);
+        }
+    }
+    Ok(res)
+}
+
+fn read(root: &Path) -> Option<toml::Value> {
+    let text = fs::read_to_string(root.join("Cargo.toml")).ok()?;
+    toml::from_str(&text).ok()
+}
+
+/// The normal form of an SPDX expression: the operators in upper case, `/`
+/// as `OR`, and the licenses of an expression which only has `OR`s sorted.
+fn normalize(expression: &str) -> String {
+    let spaced = expression
+        .replace('/', " OR ")
+        .replace('(', " ( ")
+        .replace(')', " ) ");
+    let mut tokens: Vec<String> = spaced
+        .split_whitespace()
+        .map(|it| match it.to_uppercase().as_str() {
+            op @ ("OR" | "AND" | "WITH") => op.to_string(),
+            _ => it.to_string(),
+        })
+        .collect();
+    let only_or = tokens
+        .iter()
+        .all(|it| !matches!(it.as_str(), "AND" | "WITH" | "(" | ")"));
+    if only_or {
+        tokens.retain(|it| it != "OR");
+        tokens.sort();
+        tokens.dedup();
+        return tokens.join(" OR ");
+    }
+    tokens.join(" ")
+}
+
+/// A parsed SPDX license expression.
+#[derive(Debug, PartialEq)]
+enum Expression {
+    /// A license, like `GPL-2.0+` or `Apache-2.0 WITH LLVM-exception`.
+    License(Term),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+}
+
+/// A license with its later versions with `+`, and an exception, compared
+/// without the case, as SPDX does.
+#[derive(Debug)]
+struct Term {
+    id: String,
+    or_later: bool,
+    exception: Option<String>,
+}
+
+impl Expression {
+    fn parse(expression: &str) -> Result<Expression> {
+        let spaced = expression
+            .replace('/', " OR ")
+            .replace('(', " ( ")
+            .replace(')', " ) ");
+        let tokens: Vec<&str> = spaced.split_whitespace().collect();
+        let mut parser = Parser { tokens, next: 0 };
+        let res = parser.or()?;
+        match parser.tokens.get(parser.next) {
+            Some(token) => bail!("unexpected `{}`", token),
+            None => Ok(res),
+        }
+    }
+
+    /// Whether the licenses of `allowed` cover it.
+    fn allowed_by(&self, allowed: &[Term]) -> bool {
+        match self {
+            Expression::License(term) => allowed.iter().any(|it| it == term),
+            Expression::And(a, b) => a.allowed_by(allowed) && b.allowed_by(allowed),
+            Expression::Or(a, b) => a.allowed_by(allowed) || b.allowed_by(allowed),
+        }
+    }
+
+    /// The licenses it names.
+    fn terms(self, res: &mut Vec<Term>) {
+        match self {
+            Expression::License(term) => res.push(term),
+            Expression::And(a, b) | Expression::Or(a, b) => {
+                a.terms(res);
+                b.terms(res);
+            }
+        }
+    }
+}
+
+impl PartialEq for Term {
+    fn eq(&self, other: &Term) -> bool {
+        let same = |a: &str, b: &str| a.eq_ignore_ascii_case(b);
+        same(&self.id, &other.id)
+            && self.or_later == other.or_later
+            && match (&self.exception, &other.exception) {
+                (Some(a), Some(b)) => same(a, b),
+                (a, b) => a.is_none() && b.is_none(),
+            }
+    }
+}
+
+impl fmt::Display for Expression {
+    /// The expression as SPDX writes it, with the parentheses it needs.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::License(term) => {
+                write!(f, "{}", term.id)?;
+                if term.or_later {
+                    write!(f, "+")?;
+                }
+                if let Some(exception) = &term.exception {
+                    write!(f, " WITH {}", exception)?;
+                }
+                Ok(())
+            }
+            Expression::And(a, b) => {
+                for (idx, it) in [a, b].iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, " AND ")?;
+                    }
+                    match ***it {
+                        Expression::Or(..) => write!(f, "({})", it)?,
+                        _ => write!(f, "{}", it)?,
+                    }
+                }
+                Ok(())
+            }
+            Expression::Or(a, b) => write!(f, "{} OR {}", a, b),
+        }
+    }
+}
+
+/// The `license` of a Cargo.toml as a valid SPDX expression, with the
+/// deprecated `/` as `OR`, if it's one.
+pub(crate) fn spdx_expression(license: &str) -> Option<String> {
+    Expression::parse(license).ok().map(|it| it.to_string())
+}
+
+/// A recursive descent parser, `WITH` binding tighter than `AND`, and `AND`
+/// than `OR`.
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    next: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek_is(&self, operator: &str) -> bool {
+        self.tokens
+            .get(self.next)
+            .is_some_and(|it| it.eq_ignore_ascii_case(operator))
+    }
+
+    fn take(&mut self) -> Result<&'a str> {
+        match self.tokens.get(self.next) {
+            Some(token) => {
+                self.next += 1;
+                Ok(token)
+            }
+            None => bail!("the expression ends too early"),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expression> {
+        let mut res = self.and()?;
+        while self.peek_is("OR") {
+            self.next += 1;
+            res = Expression::Or(Box::new(res), Box::new(self.and()?));
+        }
+        Ok(res)
+    }
+
+    fn and(&mut self) -> Result<Expression> {
+        let mut res = self.license()?;
+        while self.peek_is("AND") {
+            self.next += 1;
+            res = Expression::And(Box::new(res), Box::new(self.license()?));
+        }
+        Ok(res)
+    }
+
+    fn license(&mut self) -> Result<Expression> {
+        let token = self.take()?;
+        if token == "(" {
+            let res = self.or()?;
+            if self.take()? != ")" {
+                bail!("a `(` isn't closed");
+            }
+            return Ok(res);
+        }
+        if !is_id(token.trim_end_matches('+'))
+            || ["AND", "OR", "WITH"]
+                .iter()
+                .any(|it| token.eq_ignore_ascii_case(it))
+        {
+            bail!("unexpected `{}`", token);
+        }
+        let exception = if self.peek_is("WITH") {
+            self.next += 1;
+            let exception = self.take()?;
+            if !is_id(exception) {
+                bail!("unexpected `{}`", exception);
+            }
+            Some(exception.to_string())
+        } else {
+            None
+        };
+        Ok(Expression::License(Term {
+            id: token.trim_end_matches('+').to_string(),
+            or_later: token.ends_with('+'),
+            exception,
+        }))
+    }
+}
+
+/// Whether `token` can be a license or an exception, with `LicenseRef-`s.
+fn is_id(token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .chars()
+            .all(|it| it.is_ascii_alphanumeric() || it == '-' || it == '.' || it == ':')
+}
+
+/// The licenses `current --license-allow` doesn't check out.
+#[derive(Debug, Default)]
+pub(crate) struct Allowed(Vec<Term>);
+
+impl Allowed {
+    /// The licenses the `expressions` name.
+    pub(crate) fn parse(expressions: &[String]) -> Result<Allowed> {
+        let mut res = Vec::new();
+        for expression in expressions {
+            match Expression::parse(expression) {
+                Ok(it) => it.terms(&mut res),
+                Err(err) => bail!("--license-allow can't parse `{}`: {}", expression, err),
+            }
+        }
+        Ok(Allowed(res))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether it allows the `license` expression, which has to be there
+    /// and be valid for that.
+    pub(crate) fn covers(&self, license: Option<&str>) -> bool {
+        license
+            .and_then(|it| Expression::parse(it).ok())
+            .is_some_and(|it| it.allowed_by(&self.0))
+    }
+}
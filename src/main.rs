@@ -1,9 +1,18 @@
 extern crate cargo_review_deps;
 extern crate clap;
 
-use std::{ffi::OsStr, path::PathBuf};
+use std::{
+    ffi::{OsStr, OsString},
+    path::PathBuf,
+    sync::Arc,
+};
 
-use cargo_review_deps::{Current, Diff, PackageId, Result, UpdateDiff};
+use cargo_review_deps::{
+    error_label, generated_patterns, read_diff_specs, read_package_ids, set_color, warning_label,
+    ColorChoice, Current, DeniedSource, Diff, DiffSpec, Features, Layout, LinkMode, Normalizer,
+    OutputFormat, PackageId, PolicyViolation, Result, RiskLevel, SbomFormat, Truncation,
+    UpdateDiff,
+};
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
 fn main() {
@@ -11,6 +20,13 @@ fn main() {
     std::process::exit(exit_code);
 }
 
+/// The exit code of errors, the same as of a panic.
+const ERROR_EXIT_CODE: i32 = 101;
+
+/// The exit code of `current` when some dependencies come from sources
+/// `--deny-source` or `--allow-registry` deny.
+const POLICY_EXIT_CODE: i32 = 2;
+
 fn main_inner() -> i32 {
     let matches = App::new("cargo-review-deps")
         .bin_name("cargo")
@@ -21,23 +37,54 @@ fn main_inner() -> i32 {
                 .author("Aleksey Kladov <aleksey.kladov@ferrous-systems.com>")
                 .about("Helps you to review source code of your crates.io dependencies")
                 .setting(AppSettings::SubcommandRequired)
+                .arg(
+                    Arg::with_name("color")
+                        .long("color")
+                        .takes_value(true)
+                        .value_name("WHEN")
+                        .possible_values(&["auto", "always", "never"])
+                        .global(true)
+                        .help("Color the diffs and the warnings: auto, the default, only does on a terminal and without $NO_COLOR; always also does through pipes and in files, never doesn't")
+                )
                 .subcommand(
                     SubCommand::with_name("diff")
                         .about("Show the diff between two crate versions")
                         .after_help("By default, diff -r command is used for diffing. \
                                If you want to use a custom diff tool, specify the --destination \
-                               argument and run the diff command manually.")
+                               argument and run the diff command manually.\n\n\
+                               The exit status is 0 if the sources are the same, after the filters, \
+                               1 if they differ and 101 if there was trouble. With --destination \
+                               alone, the sources aren't compared and the exit status is 0.")
                         .arg(
                             Arg::with_name("FIRST_PACKAGE_ID")
-                                .required(true)
+                                .required_unless("specs-from")
+                                .conflicts_with("specs-from")
                                 .index(1)
-                                .help("First crate to diff, in the form of name:version, for example rand:0.6.0"),
+                                .help("First crate to diff, in the form of name:version (or name@version), name:version@registry or name:git+URL#REV, for example rand:0.6.0. Use rand:prev (or rand:-1) for the version published before the second one, or just rand (or rand:locked) for the version in Cargo.lock"),
                         )
                         .arg(
                             Arg::with_name("SECOND_PACKAGE_ID")
-                                .required(true)
                                 .index(2)
-                                .help("Second crate to diff, for example rand:0.6.1"),
+                                .multiple(true)
+                                .help("Second crate to diff, for example rand:0.6.1, rand:^0.6 for the newest matching version, rand:latest (or just rand) for the latest one or path:../rand for a local checkout. Can be omitted if the first argument is a range, like rand:0.6.0..0.6.1, or to compare the version in Cargo.lock with the first argument. More versions can follow, to review a chain of updates one step at a time"),
+                        )
+                        .arg(
+                            Arg::with_name("no-strip-artifacts")
+                                .long("no-strip-artifacts")
+                                .help("Keep the files Cargo adds to packages, like .cargo_vcs_info.json, and the normalized Cargo.toml instead of Cargo.toml.orig")
+                        )
+                        .arg(
+                            Arg::with_name("no-reviewignore")
+                                .long("no-reviewignore")
+                                .help("Keep the files listed in the .reviewignore of the workspace and in ~/.config/cargo-review-deps/ignore")
+                        )
+                        .arg(
+                            Arg::with_name("normalize")
+                                .long("normalize")
+                                .takes_value(true)
+                                .value_name("FORMATTER")
+                                .possible_values(&["rustfmt"])
+                                .help("Format the Rust files of both sides with rustfmt, on copies, before comparing them, so that formatting-only changes don't show")
                         )
                         .arg(
                             Arg::with_name("destination")
@@ -45,7 +92,365 @@ fn main_inner() -> i32 {
                                 .long("destination")
                                 .takes_value(true)
                                 .value_name("DIR")
-                                .help("Checkout sources of the two versions to the specified directory")
+                                .help("Checkout sources of the two versions to the old/ and new/ subdirectories of the specified directory")
+                        )
+                        .arg(
+                            Arg::with_name("force")
+                                .long("force")
+                                .requires("destination")
+                                .help("Replace the package directories which exist already in the destination, instead of failing")
+                        )
+                        .arg(
+                            Arg::with_name("tool")
+                                .long("tool")
+                                .takes_value(true)
+                                .value_name("PROGRAM")
+                                .help("Compare the sources with this program instead of diff -r, or open them with it with --open. It gets the two source directories as the last arguments")
+                        )
+                        .arg(
+                            Arg::with_name("tool-args")
+                                .last(true)
+                                .multiple(true)
+                                .help("Extra arguments for the diff program")
+                        )
+                        .arg(
+                            Arg::with_name("open")
+                                .long("open")
+                                .conflicts_with_all(&["builtin", "brief", "stat", "side-by-side", "color-words", "output", "prioritize-risky"])
+                                .help("Open the two source directories with a GUI diff program and wait for it: --tool, $CARGO_REVIEW_DEPS_TOOL, or the first of meld, kdiff3 and bcompare found. Without one, the paths are printed")
+                        )
+                        .arg(
+                            Arg::with_name("keep")
+                                .long("keep")
+                                .conflicts_with_all(&["destination", "tool", "builtin", "brief", "stat", "side-by-side", "color-words", "output", "prioritize-risky", "open", "archive", "interactive", "scan", "specs-from"])
+                                .help("Don't compare the sources, print the directory of each package on a line of its own, the old one first, and keep them for you to look into. There's no copy unless the files are filtered")
+                        )
+                        .arg(
+                            Arg::with_name("archive")
+                                .long("archive")
+                                .takes_value(true)
+                                .value_name("FILE")
+                                .conflicts_with_all(&["tool", "builtin", "brief", "stat", "side-by-side", "color-words", "output", "prioritize-risky", "open", "specs-from"])
+                                .help("Pack the sources, as with --destination, with the CHANGES.patch and the summary.json of the diff, into a .tar.gz, .tgz or .zip file. The same sources always give the same archive")
+                        )
+                        .arg(
+                            Arg::with_name("scan")
+                                .long("scan")
+                                .conflicts_with_all(&["destination", "tool", "builtin", "brief", "stat", "side-by-side", "color-words", "output", "prioritize-risky", "open", "archive"])
+                                .help("Print only the hunks whose added lines run commands, open connections, read the environment, embed files, run before main or look like base64 blobs. Exits with 1 if any matched, for CI")
+                        )
+                        .arg(
+                            Arg::with_name("scan-pattern")
+                                .long("scan-pattern")
+                                .takes_value(true)
+                                .value_name("REGEX")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .requires("scan")
+                                .help("Scan for the added lines matching REGEX too")
+                        )
+                        .arg(
+                            Arg::with_name("interactive")
+                                .long("interactive")
+                                .conflicts_with_all(&["tool", "builtin", "brief", "stat", "side-by-side", "output", "prioritize-risky", "open", "archive", "scan", "specs-from"])
+                                .help("Show the changed files one at a time, the high-risk ones first, and ask whether to approve, skip or flag each. The answers go to review-notes.json in --destination, or to --notes")
+                        )
+                        .arg(
+                            Arg::with_name("notes")
+                                .long("notes")
+                                .takes_value(true)
+                                .value_name("FILE")
+                                .requires("interactive")
+                                .help("Write the notes of --interactive to FILE")
+                        )
+                        .arg(
+                            Arg::with_name("builtin")
+                                .long("builtin")
+                                .conflicts_with_all(&["destination", "tool"])
+                                .help("Print a unified diff without running an external program. This is the default if there's no diff command")
+                        )
+                        .arg(
+                            Arg::with_name("brief")
+                                .long("brief")
+                                .conflicts_with_all(&["destination", "tool", "builtin", "stat", "side-by-side", "output", "include-patches"])
+                                .help("List only the paths of the changed files, with A for added, D for deleted and M for modified ones")
+                        )
+                        .arg(
+                            Arg::with_name("stat")
+                                .long("stat")
+                                .conflicts_with_all(&["destination", "tool", "builtin"])
+                                .help("Show only the numbers of changed lines per file, like git diff --stat")
+                        )
+                        .arg(
+                            Arg::with_name("prioritize-risky")
+                                .long("prioritize-risky")
+                                .conflicts_with_all(&["destination", "output"])
+                                .help("Show the changes of Cargo.toml, the build script and the proc-macro entry point first, with a verdict for each")
+                        )
+                        .arg(
+                            Arg::with_name("unsafe-report")
+                                .long("unsafe-report")
+                                .conflicts_with("destination")
+                                .help("Count the unsafe functions, impls and blocks of each file, and list the files with more of them")
+                        )
+                        .arg(
+                            Arg::with_name("deny-new-binaries")
+                                .long("deny-new-binaries")
+                                .help("Fail if the second package adds binary files. They are listed anyway, with the changed binaries and the text with very long lines")
+                        )
+                        .arg(
+                            Arg::with_name("deny-new-deps")
+                                .long("deny-new-deps")
+                                .help("Fail if the second package pulls in dependencies the first one doesn't, directly or not. The changes of the dependency graph are listed anyway")
+                        )
+                        .arg(
+                            Arg::with_name("risk")
+                                .long("risk")
+                                .help("Print a line with the risk score of each diff, from the changes of the build script and of the proc-macros, the new unsafe code, dependencies and binaries, and the number of changed lines")
+                        )
+                        .arg(
+                            Arg::with_name("risk-threshold")
+                                .long("risk-threshold")
+                                .takes_value(true)
+                                .value_name("LEVEL")
+                                .possible_values(&["low", "medium", "high"])
+                                .help("Fail if the risk of a diff is this level or higher. The risk is printed as with --risk")
+                        )
+                        .arg(
+                            Arg::with_name("color-words")
+                                .long("color-words")
+                                .conflicts_with_all(&["destination", "tool", "brief", "stat", "side-by-side", "output"])
+                                .help("Show the changed lines of the builtin diff and of the HTML report word by word, like git diff --color-words")
+                        )
+                        .arg(
+                            Arg::with_name("verbose")
+                                .long("verbose")
+                                .short("v")
+                                .help("Tell how many unchanged files are left out before running the diff tool, and how long the diff takes")
+                        )
+                        .arg(
+                            Arg::with_name("generated")
+                                .long("generated")
+                                .takes_value(true)
+                                .value_name("GLOB")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("Count the matching files as generated, on top of Cargo.lock, *.pb.rs, bindings.rs and the files marked @generated or \"automatically generated\" in their first lines. A glob without a / matches file names anywhere")
+                        )
+                        .arg(
+                            Arg::with_name("show-generated")
+                                .long("show-generated")
+                                .help("Show the diffs of the generated files, instead of a line with their numbers of changed lines")
+                        )
+                        .arg(
+                            Arg::with_name("no-pager")
+                                .long("no-pager")
+                                .help("Don't page the differences through $PAGER, or less, when stdout is a terminal")
+                        )
+                        .arg(
+                            Arg::with_name("ignore-whitespace")
+                                .long("ignore-whitespace")
+                                .short("w")
+                                .help("Compare lines without their whitespace, and list the files with formatting-only changes separately")
+                        )
+                        .arg(
+                            Arg::with_name("side-by-side")
+                                .long("side-by-side")
+                                .short("y")
+                                .conflicts_with_all(&["destination", "tool", "stat", "output"])
+                                .help("Print the builtin diff in two columns, with line numbers and the changed parts of the lines highlighted")
+                        )
+                        .arg(
+                            Arg::with_name("width")
+                                .long("width")
+                                .short("W")
+                                .takes_value(true)
+                                .value_name("N")
+                                .requires("side-by-side")
+                                .validator(|it| it.parse::<usize>().map(drop).map_err(|err| err.to_string()))
+                                .help("The width of the side-by-side diff, the width of the terminal by default")
+                        )
+                        .arg(
+                            Arg::with_name("include")
+                                .long("include")
+                                .short("i")
+                                .takes_value(true)
+                                .value_name("GLOB")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("Compare only the matching files, and Cargo.toml, the build script and the proc-macro entry point. Includes are applied before --exclude")
+                        )
+                        .arg(
+                            Arg::with_name("include-strict")
+                                .long("include-strict")
+                                .requires("include")
+                                .help("Don't include Cargo.toml, the build script and the proc-macro entry point unless they match --include")
+                        )
+                        .arg(
+                            Arg::with_name("exclude")
+                                .long("exclude")
+                                .short("x")
+                                .takes_value(true)
+                                .value_name("GLOB")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("Leave the matching files out of the comparison, even if they match --include. Globs are relative to the crate root, and a glob without a / matches file names anywhere, like '*.snap'")
+                        )
+                        .arg(
+                            Arg::with_name("no-tests")
+                                .long("no-tests")
+                                .overrides_with("all")
+                                .help("Leave out the tests/, benches/ and examples/ directories, and the modules only compiled for tests, like `#[cfg(test)] mod tests;`")
+                        )
+                        .arg(
+                            Arg::with_name("all")
+                                .long("all")
+                                .overrides_with("no-tests")
+                                .help("Compare the tests, benches and examples too, undoing an earlier --no-tests")
+                        )
+                        .arg(
+                            Arg::with_name("format")
+                                .long("format")
+                                .takes_value(true)
+                                .possible_values(&["text", "json", "html", "markdown"])
+                                .default_value("text")
+                                .help("Print the list of changed files as JSON, for other tools to consume, as an HTML page, or as a markdown summary to paste into a pull request")
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .long("output")
+                                .short("o")
+                                .takes_value(true)
+                                .value_name("FILE")
+                                .conflicts_with_all(&["tool", "stat"])
+                                .help("Write the JSON or HTML report to FILE instead of stdout, or a patch of the changes with the text format")
+                        )
+                        .arg(
+                            Arg::with_name("include-patches")
+                                .long("include-patches")
+                                .help("Include the unified hunks of the changed files in the JSON output")
+                        )
+                        .arg(
+                            Arg::with_name("max-patch-size")
+                                .long("max-patch-size")
+                                .takes_value(true)
+                                .value_name("BYTES")
+                                .help("Cut off the patch of each file in the markdown report after this many bytes [default: 8000]")
+                        )
+                        .arg(
+                            Arg::with_name("context")
+                                .long("context")
+                                .short("U")
+                                .takes_value(true)
+                                .value_name("N")
+                                .default_value("3")
+                                .validator(|it| it.parse::<usize>().map(drop).map_err(|err| err.to_string()))
+                                .help("Lines of context in the builtin diff")
+                        )
+                        .arg(
+                            Arg::with_name("max-lines")
+                                .long("max-lines")
+                                .takes_value(true)
+                                .value_name("N")
+                                .default_value("50000")
+                                .validator(|it| it.parse::<usize>().map(drop).map_err(|err| err.to_string()))
+                                .help("Stop printing the diff after N lines in all, 0 for no limit. --stat, --brief and the reports stay complete")
+                        )
+                        .arg(
+                            Arg::with_name("rename-threshold")
+                                .long("rename-threshold")
+                                .takes_value(true)
+                                .value_name("PERCENT")
+                                .default_value("60")
+                                .validator(|it| match it.parse::<u8>() {
+                                    Ok(1..=100) => Ok(()),
+                                    Ok(_) => Err("must be between 1 and 100".into()),
+                                    Err(err) => Err(err.to_string()),
+                                })
+                                .help("Show removed and added files with at least this percentage of the same lines as renamed, in the builtin diff, --brief, --stat and the JSON report")
+                        )
+                        .arg(
+                            Arg::with_name("jobs")
+                                .long("jobs")
+                                .short("j")
+                                .takes_value(true)
+                                .value_name("N")
+                                .validator(|it| match it.parse::<usize>() {
+                                    Ok(0) => Err("must be at least 1".into()),
+                                    Ok(_) => Ok(()),
+                                    Err(err) => Err(err.to_string()),
+                                })
+                                .help("Read and diff the files on N threads, except with an external diff tool [default: the number of cores]")
+                        )
+                        .arg(
+                            Arg::with_name("specs-from")
+                                .long("specs-from")
+                                .takes_value(true)
+                                .value_name("FILE")
+                                .help("Read the crates to diff from a file, with two crates (or a range) per line and # comments")
+                        )
+                        .arg(
+                            Arg::with_name("include-yanked")
+                                .long("include-yanked")
+                                .help("Consider yanked versions when resolving latest, prev and version requirements")
+                        )
+                        .arg(
+                            Arg::with_name("deny-yanked")
+                                .long("deny-yanked")
+                                .help("Fail if one of the versions is yanked, instead of printing a warning")
+                        )
+                        .arg(
+                            Arg::with_name("features")
+                                .long("features")
+                                .takes_value(true)
+                                .value_name("FEATURES")
+                                .help("Comma separated list of features to enable, which affects the optional dependencies")
+                        )
+                        .arg(
+                            Arg::with_name("no-default-features")
+                                .long("no-default-features")
+                                .help("Do not enable the default features")
+                        )
+                        .arg(
+                            Arg::with_name("offline")
+                                .long("offline")
+                                .help("Run without accessing the network; yanked versions are not detected")
+                        )
+                        .arg(
+                            Arg::with_name("all-intermediate")
+                                .long("all-intermediate")
+                                .conflicts_with_all(&["against-repo", "against-vendored", "offline"])
+                                .help("Review every release between the versions, one step at a time. Yanked releases are skipped, unless --include-yanked is given, and so are pre-releases, unless one of the versions is a pre-release")
+                        )
+                        .arg(
+                            Arg::with_name("max-steps")
+                                .long("max-steps")
+                                .takes_value(true)
+                                .value_name("N")
+                                .requires("all-intermediate")
+                                .help("Fail instead of downloading more than N releases for --all-intermediate [default: 20]")
+                        )
+                        .arg(
+                            Arg::with_name("reverse")
+                                .long("reverse")
+                                .help("Swap the sides once the versions are resolved, to see what going back from the second to the first would change. The output always labels the sides old and new")
+                        )
+                        .arg(
+                            Arg::with_name("allow-different-names")
+                                .long("allow-different-names")
+                                .help("Compare different crates, like a crate and its maintained fork. Without it, a diff of two crates with different names fails, to catch typos")
+                        )
+                        .arg(
+                            Arg::with_name("against-repo")
+                                .long("against-repo")
+                                .conflicts_with_all(&["SECOND_PACKAGE_ID", "specs-from", "offline"])
+                                .help("Compare the published crate with the tag of its version in its repository, like v1.2.3 or 1.2.3. Only the published files are compared, and the files Cargo adds on publish are ignored")
+                        )
+                        .arg(
+                            Arg::with_name("against-vendored")
+                                .long("against-vendored")
+                                .conflicts_with_all(&["SECOND_PACKAGE_ID", "specs-from", "against-repo"])
+                                .help("Compare the published crate with its copy in the vendored sources of the workspace, the directory which replaces crates.io in .cargo/config.toml, to see what re-vendoring would change. The .cargo-checksum.json files are ignored")
                         ),
                 )
                 .subcommand(
@@ -54,14 +459,312 @@ fn main_inner() -> i32 {
                         .after_help("By default, diff -r command is used for diffing. \
                                If you want to use a custom diff tool, specify the --destination \
                                argument and run the diff command manually.")
+                        .arg(
+                            Arg::with_name("no-strip-artifacts")
+                                .long("no-strip-artifacts")
+                                .help("Keep the files Cargo adds to packages, like .cargo_vcs_info.json, and the normalized Cargo.toml instead of Cargo.toml.orig")
+                        )
+                        .arg(
+                            Arg::with_name("no-reviewignore")
+                                .long("no-reviewignore")
+                                .help("Keep the files listed in the .reviewignore of the workspace and in ~/.config/cargo-review-deps/ignore")
+                        )
                         .arg(
                             Arg::with_name("destination")
                                 .short("d")
                                 .long("destination")
                                 .takes_value(true)
                                 .value_name("DIR")
-                                .required(true)
+                                .required_unless_one(&["archive", "list"])
                                 .help("Checkout sources of the two versions to the specified directory")
+                        )
+                        .arg(
+                            Arg::with_name("archive")
+                                .long("archive")
+                                .takes_value(true)
+                                .value_name("FILE")
+                                .conflicts_with_all(&["destination", "refresh", "prune", "force", "layout", "packages-from"])
+                                .help("Pack the packages, with their manifest.json, into a .tar.gz, .tgz or .zip file instead of checking them out, straight from their sources. The same sources always give the same archive")
+                        )
+                        .arg(
+                            Arg::with_name("manifest-path")
+                                .long("manifest-path")
+                                .takes_value(true)
+                                .value_name("PATH")
+                                .help("The Cargo.toml of the workspace to check the dependencies of out, instead of that of the current directory")
+                        )
+                        .arg(
+                            Arg::with_name("package")
+                                .short("p")
+                                .long("package")
+                                .takes_value(true)
+                                .value_name("NAME")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .conflicts_with("packages-from")
+                                .help("Check out only this dependency, or this version of it with name:version. All of its versions without one. Can be repeated")
+                        )
+                        .arg(
+                            Arg::with_name("include-git")
+                                .long("include-git")
+                                .help("Check out the git dependencies too, to directories like mycrate-0.3.0_git.abc1234")
+                        )
+                        .arg(
+                            Arg::with_name("include-path")
+                                .long("include-path")
+                                .help("Check out the path dependencies outside of the workspace too, to directories like mycrate-0.3.0_local")
+                        )
+                        .arg(
+                            Arg::with_name("all-sources")
+                                .long("all-sources")
+                                .help("Check out the git and the path dependencies too, like --include-git --include-path")
+                        )
+                        .arg(
+                            Arg::with_name("include-workspace")
+                                .long("include-workspace")
+                                .help("Check out the members of the workspace too, to directories like mycrate-0.4.0_workspace, as cargo package would pack them")
+                        )
+                        .arg(
+                            Arg::with_name("direct-only")
+                                .long("direct-only")
+                                .help("Check out only the dependencies the Cargo.toml files of the workspace name, not the ones they pull in")
+                        )
+                        .arg(
+                            Arg::with_name("no-dev-deps")
+                                .long("no-dev-deps")
+                                .conflicts_with("packages-from")
+                                .help("Leave out the packages only pulled in by dev-dependencies")
+                        )
+                        .arg(
+                            Arg::with_name("no-build-deps")
+                                .long("no-build-deps")
+                                .conflicts_with("packages-from")
+                                .help("Leave out the packages only pulled in by build-dependencies")
+                        )
+                        .arg(
+                            Arg::with_name("member")
+                                .long("member")
+                                .value_name("NAME")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .conflicts_with("packages-from")
+                                .help("Check out only the packages this member of the workspace pulls in, may be given several times")
+                        )
+                        .arg(
+                            Arg::with_name("target")
+                                .long("target")
+                                .value_name("TRIPLE")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .conflicts_with("packages-from")
+                                .help("Check out only the packages built for this target, may be given several times")
+                        )
+                        .arg(
+                            Arg::with_name("filter-platform-host")
+                                .long("filter-platform-host")
+                                .conflicts_with("packages-from")
+                                .help("Check out only the packages built for the target of the host")
+                        )
+                        .arg(
+                            Arg::with_name("features")
+                                .long("features")
+                                .takes_value(true)
+                                .value_name("FEATURES")
+                                .help("Comma separated list of features of the workspace to resolve the dependencies with")
+                        )
+                        .arg(
+                            Arg::with_name("all-features")
+                                .long("all-features")
+                                .help("Resolve the dependencies with all the features of the workspace")
+                        )
+                        .arg(
+                            Arg::with_name("no-default-features")
+                                .long("no-default-features")
+                                .help("Resolve the dependencies without the default features of the workspace")
+                        )
+                        .arg(
+                            Arg::with_name("format")
+                                .long("format")
+                                .takes_value(true)
+                                .possible_values(&["text", "json"])
+                                .default_value("text")
+                                .help("Print the manifest.json of the packages checked out to stdout too, for other tools to consume")
+                        )
+                        .arg(
+                            Arg::with_name("layout")
+                                .long("layout")
+                                .takes_value(true)
+                                .possible_values(&["review", "vendor"])
+                                .conflicts_with_all(&["packages-from", "include-path", "all-sources", "include-workspace"])
+                                .help("vendor to check the packages out as cargo vendor does instead of for reviewing, with their .cargo-checksum.json, and print the [source] tables which build with them")
+                        )
+                        .arg(
+                            Arg::with_name("no-verify")
+                                .long("no-verify")
+                                .conflicts_with("packages-from")
+                                .help("Don't check the sources against the checksums of Cargo.lock")
+                        )
+                        .arg(
+                            Arg::with_name("refresh")
+                                .long("refresh")
+                                .conflicts_with("packages-from")
+                                .help("Copy the packages checked out to the destination already again, even if they didn't change")
+                        )
+                        .arg(
+                            Arg::with_name("prune")
+                                .long("prune")
+                                .conflicts_with("packages-from")
+                                .help("Remove the packages checked out to the destination earlier which aren't checked out this time")
+                        )
+                        .arg(
+                            Arg::with_name("exclude")
+                                .long("exclude")
+                                .short("x")
+                                .takes_value(true)
+                                .value_name("GLOB")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("Leave the matching files out of the packages, but Cargo.toml and the build script, and list them in manifest.json. Globs are relative to the crate root, and a glob without a / matches file names anywhere, like '*.png'")
+                        )
+                        .arg(
+                            Arg::with_name("link-mode")
+                                .long("link-mode")
+                                .takes_value(true)
+                                .possible_values(&["copy", "hardlink", "reflink"])
+                                .conflicts_with_all(&["archive", "packages-from"])
+                                .help("hardlink to link the files of the published and the git packages to the caches of Cargo instead of copying them, reflink to clone them copy-on-write, where the filesystem can. Files which can't be linked are copied. Don't edit hard linked files: that changes the cache too [default: copy]")
+                        )
+                        .arg(
+                            Arg::with_name("deny-duplicates")
+                                .long("deny-duplicates")
+                                .takes_value(true)
+                                .value_name("NAME")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .conflicts_with("packages-from")
+                                .help("Fail if the crate NAME is checked out in several versions, once they are checked out, for CI. Can be repeated")
+                        )
+                        .arg(
+                            Arg::with_name("license-allow")
+                                .long("license-allow")
+                                .takes_value(true)
+                                .value_name("SPDX")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .conflicts_with_all(&["archive", "packages-from"])
+                                .help("Leave out the packages whose license expression the licenses of the SPDX expression allow, like MIT or \"Apache-2.0 WITH LLVM-exception\", keeping them in manifest.json, and check the others out for a review of their license. Can be repeated")
+                        )
+                        .arg(
+                            Arg::with_name("offline")
+                                .long("offline")
+                                .conflicts_with("fetch-missing")
+                                .help("Run without accessing the network: fail before copying anything if the sources of some packages aren't downloaded, telling which ones and the cargo fetch to run")
+                        )
+                        .arg(
+                            Arg::with_name("fetch-missing")
+                                .long("fetch-missing")
+                                .conflicts_with("packages-from")
+                                .help("Run cargo fetch --locked first if the sources of some packages aren't downloaded")
+                        )
+                        .arg(
+                            Arg::with_name("since")
+                                .long("since")
+                                .takes_value(true)
+                                .value_name("DIR")
+                                .conflicts_with_all(&["archive", "packages-from", "list", "layout"])
+                                .help("Reference the packages the earlier snapshot in DIR has in the same version instead of copying them, and write delta.md, the packages added, removed and upgraded since")
+                        )
+                        .arg(
+                            Arg::with_name("fail-fast")
+                                .long("fail-fast")
+                                .conflicts_with_all(&["archive", "list"])
+                                .help("Stop at the first package which can't be checked out, without writing manifest.json, rather than checking the others out and marking it as failed in manifest.json")
+                        )
+                        .arg(
+                            Arg::with_name("deny-source")
+                                .long("deny-source")
+                                .takes_value(true)
+                                .possible_values(&["git", "path"])
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("Fail, with an exit status of 2, if some dependencies are git or path ones, listing them with the chain of dependencies which pulls them in. The whole dependency graph is checked, or the packages of --packages-from, before anything is checked out. Can be repeated")
+                        )
+                        .arg(
+                            Arg::with_name("allow-registry")
+                                .long("allow-registry")
+                                .takes_value(true)
+                                .value_name("NAME")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("Fail, with an exit status of 2, if some dependencies come from registries other than crates.io and NAME, as named in .cargo/config.toml or as the URL of its index. Can be repeated")
+                        )
+                        .arg(
+                            Arg::with_name("deny-yanked")
+                                .long("deny-yanked")
+                                .help("Fail if some versions are yanked from crates.io, instead of listing them with their newest version. Yanked versions aren't looked up with --offline")
+                        )
+                        .arg(
+                            Arg::with_name("sbom")
+                                .long("sbom")
+                                .takes_value(true)
+                                .possible_values(&["cyclonedx", "spdx"])
+                                .requires("sbom-output")
+                                .conflicts_with("packages-from")
+                                .help("Write a software bill of materials of the packages to the file of --sbom-output, as CycloneDX 1.5 or SPDX 2.3 JSON, with their purls, license expressions, the checksums of Cargo.lock and their dependencies. Works with --list too")
+                        )
+                        .arg(
+                            Arg::with_name("sbom-output")
+                                .long("sbom-output")
+                                .takes_value(true)
+                                .value_name("FILE")
+                                .requires("sbom")
+                                .help("The file to write the bill of materials of --sbom to")
+                        )
+                        .arg(
+                            Arg::with_name("list")
+                                .long("list")
+                                .conflicts_with_all(&["destination", "archive", "packages-from", "force", "refresh", "prune", "layout", "link-mode", "deny-duplicates"])
+                                .help("Print the packages which would be checked out, with their source, license and whether they run code at build time, instead of copying them. As JSON with --format json")
+                        )
+                        .arg(
+                            Arg::with_name("quiet")
+                                .long("quiet")
+                                .short("q")
+                                .help("Print nothing on stderr but the errors, not the progress, the warnings nor the summary")
+                        )
+                        .arg(
+                            Arg::with_name("max-file-size")
+                                .long("max-file-size")
+                                .takes_value(true)
+                                .value_name("BYTES")
+                                .validator(|it| it.parse::<u64>().map(drop).map_err(|err| err.to_string()))
+                                .help("Leave the files larger than BYTES out of the packages, but Cargo.toml, the build script and those in src/, and list them in manifest.json")
+                        )
+                        .arg(
+                            Arg::with_name("jobs")
+                                .long("jobs")
+                                .short("j")
+                                .takes_value(true)
+                                .value_name("N")
+                                .validator(|it| match it.parse::<usize>() {
+                                    Ok(0) => Err("must be at least 1".into()),
+                                    Ok(_) => Ok(()),
+                                    Err(err) => Err(err.to_string()),
+                                })
+                                .help("Copy the packages on N threads [default: the number of cores]")
+                        )
+                        .arg(
+                            Arg::with_name("force")
+                                .long("force")
+                                .requires("destination")
+                                .help("Replace the package directories which exist already in the destination, instead of failing")
+                        )
+                        .arg(
+                            Arg::with_name("packages-from")
+                                .long("packages-from")
+                                .takes_value(true)
+                                .value_name("FILE")
+                                .help("Checkout the crates listed in a file, one per line with # comments, instead of the current dependencies")
                         ),
                 )
                 .subcommand(
@@ -71,6 +774,16 @@ fn main_inner() -> i32 {
                                If you want to use a custom diff tool, specify the --destination \
                                argument and run the diff command manually.")
                        .arg(
+                            Arg::with_name("no-strip-artifacts")
+                                .long("no-strip-artifacts")
+                                .help("Keep the files Cargo adds to packages, like .cargo_vcs_info.json, and the normalized Cargo.toml instead of Cargo.toml.orig")
+                        )
+                        .arg(
+                            Arg::with_name("no-reviewignore")
+                                .long("no-reviewignore")
+                                .help("Keep the files listed in the .reviewignore of the workspace and in ~/.config/cargo-review-deps/ignore")
+                        )
+                        .arg(
                             Arg::with_name("destination")
                                 .short("d")
                                 .long("destination")
@@ -78,32 +791,65 @@ fn main_inner() -> i32 {
                                 .value_name("DIR")
                                 .help("Checkout sources of dependencies to the specified directory")
                         )
+                        .arg(
+                            Arg::with_name("force")
+                                .long("force")
+                                .requires("destination")
+                                .help("Replace the package directories which exist already in the destination, instead of failing")
+                        )
                         .arg(
                             Arg::with_name("args")
                                 .last(true)
                                 .multiple(true)
                         )
                 ),
-        ).get_matches();
+        ).get_matches_safe();
+    let matches = match matches {
+        Ok(it) => it,
+        // The help and the version aren't errors.
+        Err(err) if !err.use_stderr() => err.exit(),
+        Err(err) => {
+            // Clap exits with 1, which means that there are differences.
+            eprintln!("{}", err.message);
+            return ERROR_EXIT_CODE;
+        }
+    };
 
     let matches = matches.subcommand_matches("review-deps").unwrap(); // Cargo always calls us using `cargo review-deps ...` as `argv`
-    let (cmd, matches) = match matches.subcommand() {
-        (cmd, Some(matches)) => (cmd, matches),
+    let (cmd, sub_matches) = match matches.subcommand() {
+        (cmd, Some(sub_matches)) => (cmd, sub_matches),
         (_, None) => unreachable!("AppSettings::SubcommandRequired is set"),
     };
+    // `--color` is global, it may come before or after the subcommand.
+    let color = sub_matches
+        .value_of("color")
+        .or_else(|| matches.value_of("color"));
+    set_color(match color {
+        Some("always") => ColorChoice::Always,
+        Some("never") => ColorChoice::Never,
+        _ => ColorChoice::Auto,
+    });
+    let matches = sub_matches;
 
     let res = match cmd {
-        "diff" => exec_diff(&matches),
-        "current" => exec_current(&matches),
-        "update-diff" => exec_update_diff(&matches),
+        "diff" => exec_diff(matches),
+        "current" => exec_current(matches).map(|()| false),
+        "update-diff" => exec_update_diff(matches).map(|()| false),
         _ => unreachable!("no such cmd: {:?}", cmd),
     };
 
-    if let Err(err) = res {
-        eprintln!("error: {}", err);
-        return 101;
+    match res {
+        Ok(false) => 0,
+        Ok(true) => 1,
+        Err(err) => {
+            eprintln!("{} {}", error_label(), err);
+            if err.downcast_ref::<PolicyViolation>().is_some() {
+                POLICY_EXIT_CODE
+            } else {
+                ERROR_EXIT_CODE
+            }
+        }
     }
-    0
 }
 
 fn value_of_pkg_id(matches: &ArgMatches, arg_name: &str) -> Result<PackageId> {
@@ -111,21 +857,352 @@ fn value_of_pkg_id(matches: &ArgMatches, arg_name: &str) -> Result<PackageId> {
     value.parse()
 }
 
-fn exec_diff(matches: &ArgMatches) -> Result<()> {
-    let first = value_of_pkg_id(&matches, "FIRST_PACKAGE_ID")?;
-    let second = value_of_pkg_id(&matches, "SECOND_PACKAGE_ID")?;
+/// `--features`, separated by commas or spaces, `--all-features` and
+/// `--no-default-features`.
+fn value_of_features(matches: &ArgMatches) -> Features {
+    Features {
+        features: matches
+            .value_of("features")
+            .unwrap_or_default()
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|it| !it.is_empty())
+            .map(String::from)
+            .collect(),
+        all_features: matches.is_present("all-features"),
+        no_default_features: matches.is_present("no-default-features"),
+    }
+}
+
+/// Returns whether any of the packages differ.
+fn exec_diff(matches: &ArgMatches) -> Result<bool> {
+    let against_repo = matches.is_present("against-repo");
+    let against_vendored = matches.is_present("against-vendored");
+    let all_intermediate = matches.is_present("all-intermediate");
+    let allow_different_names = matches.is_present("allow-different-names");
+    let reverse = matches.is_present("reverse");
+    let max_steps = match matches.value_of("max-steps") {
+        Some(max_steps) => max_steps.parse()?,
+        None => 20,
+    };
+    let mut chain = Vec::new();
+    let specs = if let Some(path) = matches.value_of("specs-from") {
+        let specs = read_diff_specs(path.as_ref())?;
+        if specs.is_empty() {
+            eprintln!("{} {} lists no crates to diff", warning_label(), path);
+        }
+        specs
+    } else if against_repo || against_vendored {
+        let pkg_id = value_of_pkg_id(matches, "FIRST_PACKAGE_ID")?;
+        vec![DiffSpec {
+            first: pkg_id.clone(),
+            second: pkg_id,
+        }]
+    } else if let Some(values) = matches.values_of("SECOND_PACKAGE_ID") {
+        let mut rest = values.map(str::parse).collect::<Result<Vec<PackageId>>>()?;
+        chain = rest.split_off(1);
+        vec![DiffSpec {
+            first: value_of_pkg_id(matches, "FIRST_PACKAGE_ID")?,
+            second: rest.remove(0),
+        }]
+    } else {
+        let spec = matches.value_of("FIRST_PACKAGE_ID").unwrap();
+        if spec.contains("..") {
+            vec![spec.parse()?]
+        } else {
+            // A single crate is what the Cargo.lock is about to change to.
+            vec![DiffSpec::from_locked(spec.parse()?)]
+        }
+    };
     let dest = matches.value_of("destination").map(PathBuf::from);
-    Diff {
-        first,
-        second,
-        dest,
+    let tool = matches.value_of_os("tool").map(OsStr::to_owned);
+    let tool_args: Vec<OsString> = matches
+        .values_of_os("tool-args")
+        .unwrap_or_default()
+        .map(OsStr::to_owned)
+        .collect();
+    let builtin = matches.is_present("builtin");
+    let brief = matches.is_present("brief");
+    let stat = matches.is_present("stat");
+    let side_by_side = matches.is_present("side-by-side");
+    let prioritize_risky = matches.is_present("prioritize-risky");
+    let unsafe_report = matches.is_present("unsafe-report");
+    let deny_new_binaries = matches.is_present("deny-new-binaries");
+    let deny_new_deps = matches.is_present("deny-new-deps");
+    let risk = matches.is_present("risk");
+    let risk_threshold = match matches.value_of("risk-threshold") {
+        Some("low") => Some(RiskLevel::Low),
+        Some("medium") => Some(RiskLevel::Medium),
+        Some("high") => Some(RiskLevel::High),
+        _ => None,
+    };
+    let color_words = matches.is_present("color-words");
+    let pager = !matches.is_present("no-pager");
+    let strip_artifacts = !matches.is_present("no-strip-artifacts");
+    let reviewignore = !matches.is_present("no-reviewignore");
+    let normalize = match matches.value_of("normalize") {
+        Some("rustfmt") => Some(Normalizer::Rustfmt),
+        _ => None,
+    };
+    let verbose = matches.is_present("verbose");
+    let open = matches.is_present("open");
+    let force = matches.is_present("force");
+    let keep = matches.is_present("keep");
+    let archive = matches.value_of("archive").map(PathBuf::from);
+    let globs = |name: &str| -> Vec<String> {
+        matches
+            .values_of(name)
+            .map(|it| it.map(String::from).collect())
+            .unwrap_or_default()
+    };
+    let include = globs("include");
+    let include_strict = matches.is_present("include-strict");
+    let exclude = globs("exclude");
+    let no_tests = matches.is_present("no-tests");
+    let width = match matches.value_of("width") {
+        Some(width) => Some(width.parse()?),
+        None => None,
+    };
+    let format = match matches.value_of("format") {
+        Some("json") => OutputFormat::Json,
+        Some("html") => OutputFormat::Html,
+        Some("markdown") => OutputFormat::Markdown,
+        _ => OutputFormat::Text,
+    };
+    let include_patches = matches.is_present("include-patches");
+    let interactive = matches.is_present("interactive");
+    let notes = matches.value_of_os("notes").map(PathBuf::from);
+    let scan = matches.is_present("scan");
+    let scan_patterns = globs("scan-pattern");
+    let max_patch_size = match matches.value_of("max-patch-size") {
+        Some(max_patch_size) => max_patch_size.parse()?,
+        None => 8000,
+    };
+    let output: Option<PathBuf> = matches.value_of("output").map(Into::into);
+    let context = matches.value_of("context").unwrap().parse()?;
+    let rename_threshold = matches.value_of("rename-threshold").unwrap().parse()?;
+    let jobs = match matches.value_of("jobs") {
+        Some(jobs) => jobs.parse()?,
+        None => 0,
+    };
+    let truncation = Arc::new(Truncation::new(
+        matches.value_of("max-lines").unwrap().parse()?,
+    ));
+    let generated = generated_patterns(&globs("generated"))?;
+    let show_generated = matches.is_present("show-generated");
+    let ignore_whitespace = matches.is_present("ignore-whitespace");
+    let include_yanked = matches.is_present("include-yanked");
+    let deny_yanked = matches.is_present("deny-yanked");
+    let offline = matches.is_present("offline");
+    let features = value_of_features(matches);
+    let mut differ = false;
+    for DiffSpec { first, second } in specs {
+        differ |= Diff {
+            first,
+            second,
+            dest: dest.clone(),
+            include_yanked,
+            deny_yanked,
+            offline,
+            features: features.clone(),
+            tool: tool.clone(),
+            tool_args: tool_args.clone(),
+            builtin,
+            context,
+            rename_threshold,
+            ignore_whitespace,
+            stat,
+            side_by_side,
+            width,
+            include: include.clone(),
+            include_strict,
+            exclude: exclude.clone(),
+            no_tests,
+            format,
+            include_patches,
+            brief,
+            output: output.clone(),
+            against_repo,
+            against_vendored,
+            chain: chain.clone(),
+            reverse,
+            all_intermediate,
+            max_steps,
+            allow_different_names,
+            prioritize_risky,
+            unsafe_report,
+            deny_new_binaries,
+            deny_new_deps,
+            risk,
+            risk_threshold,
+            color_words,
+            show_generated,
+            generated: generated.clone(),
+            pager,
+            strip_artifacts,
+            reviewignore,
+            normalize,
+            verbose,
+            force,
+            open,
+            keep,
+            archive: archive.clone(),
+            max_patch_size,
+            scan,
+            scan_patterns: scan_patterns.clone(),
+            interactive,
+            notes: notes.clone(),
+            jobs,
+            truncation: truncation.clone(),
+        }
+        .run()?;
     }
-    .run()
+    truncation.report();
+    Ok(differ)
 }
 
 fn exec_current(matches: &ArgMatches) -> Result<()> {
-    let dest = matches.value_of("destination").unwrap().into();
-    Current { dest }.run()
+    let dest = matches.value_of("destination").unwrap_or_default().into();
+    let archive = matches.value_of("archive").map(PathBuf::from);
+    let manifest_path = matches.value_of("manifest-path").map(PathBuf::from);
+    let only = matches
+        .values_of("package")
+        .unwrap_or_default()
+        .map(String::from)
+        .collect();
+    let all_sources = matches.is_present("all-sources");
+    let include_git = all_sources || matches.is_present("include-git");
+    let include_path = all_sources || matches.is_present("include-path");
+    let include_workspace = matches.is_present("include-workspace");
+    let direct_only = matches.is_present("direct-only");
+    let no_dev_deps = matches.is_present("no-dev-deps");
+    let no_build_deps = matches.is_present("no-build-deps");
+    let targets = matches
+        .values_of("target")
+        .unwrap_or_default()
+        .map(String::from)
+        .collect();
+    let host_target = matches.is_present("filter-platform-host");
+    let features = value_of_features(matches);
+    let format = match matches.value_of("format") {
+        Some("json") => OutputFormat::Json,
+        _ => OutputFormat::Text,
+    };
+    let layout = match matches.value_of("layout") {
+        Some("vendor") => Layout::Vendor,
+        _ => Layout::Review,
+    };
+    let verify = !matches.is_present("no-verify");
+    let refresh = matches.is_present("refresh");
+    let prune = matches.is_present("prune");
+    let jobs = match matches.value_of("jobs") {
+        Some(jobs) => jobs.parse()?,
+        None => 0,
+    };
+    let quiet = matches.is_present("quiet");
+    let packages = match matches.value_of("packages-from") {
+        Some(path) => Some(read_package_ids(path.as_ref())?),
+        None => None,
+    };
+    let strip_artifacts = !matches.is_present("no-strip-artifacts");
+    let reviewignore = !matches.is_present("no-reviewignore");
+    let force = matches.is_present("force");
+    let exclude = matches
+        .values_of("exclude")
+        .unwrap_or_default()
+        .map(String::from)
+        .collect();
+    let list = matches.is_present("list");
+    let since = matches.value_of("since").map(PathBuf::from);
+    let sbom = match matches.value_of("sbom") {
+        Some("spdx") => Some(SbomFormat::Spdx),
+        Some(_) => Some(SbomFormat::CycloneDx),
+        None => None,
+    };
+    let sbom_output = matches.value_of("sbom-output").map(PathBuf::from);
+    let deny_yanked = matches.is_present("deny-yanked");
+    let fail_fast = matches.is_present("fail-fast");
+    let deny_source = matches
+        .values_of("deny-source")
+        .unwrap_or_default()
+        .map(|it| match it {
+            "git" => DeniedSource::Git,
+            _ => DeniedSource::Path,
+        })
+        .collect();
+    let allow_registry = matches
+        .values_of("allow-registry")
+        .unwrap_or_default()
+        .map(String::from)
+        .collect();
+    let member = matches
+        .values_of("member")
+        .unwrap_or_default()
+        .map(String::from)
+        .collect();
+    let offline = matches.is_present("offline");
+    let fetch_missing = matches.is_present("fetch-missing");
+    let license_allow = matches
+        .values_of("license-allow")
+        .unwrap_or_default()
+        .map(String::from)
+        .collect();
+    let link_mode = match matches.value_of("link-mode") {
+        Some("hardlink") => LinkMode::Hardlink,
+        Some("reflink") => LinkMode::Reflink,
+        _ => LinkMode::Copy,
+    };
+    let deny_duplicates = matches
+        .values_of("deny-duplicates")
+        .unwrap_or_default()
+        .map(String::from)
+        .collect();
+    let max_file_size = match matches.value_of("max-file-size") {
+        Some(size) => Some(size.parse()?),
+        None => None,
+    };
+    Current {
+        dest,
+        manifest_path,
+        only,
+        include_git,
+        include_path,
+        include_workspace,
+        direct_only,
+        no_dev_deps,
+        no_build_deps,
+        targets,
+        host_target,
+        features,
+        format,
+        layout,
+        verify,
+        refresh,
+        prune,
+        packages,
+        strip_artifacts,
+        reviewignore,
+        force,
+        archive,
+        exclude,
+        max_file_size,
+        deny_duplicates,
+        link_mode,
+        list,
+        license_allow,
+        since,
+        offline,
+        fetch_missing,
+        sbom,
+        sbom_output,
+        deny_yanked,
+        deny_source,
+        allow_registry,
+        fail_fast,
+        member,
+        jobs,
+        quiet,
+    }
+    .run()
 }
 
 fn exec_update_diff(matches: &ArgMatches) -> Result<()> {
@@ -135,5 +1212,15 @@ fn exec_update_diff(matches: &ArgMatches) -> Result<()> {
         .unwrap_or_default()
         .map(OsStr::to_owned)
         .collect();
-    UpdateDiff { dest, args }.run()
+    let strip_artifacts = !matches.is_present("no-strip-artifacts");
+    let reviewignore = !matches.is_present("no-reviewignore");
+    let force = matches.is_present("force");
+    UpdateDiff {
+        dest,
+        args,
+        strip_artifacts,
+        reviewignore,
+        force,
+    }
+    .run()
 }
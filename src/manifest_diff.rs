@@ -0,0 +1,472 @@
+//! What changed in Cargo.toml, for the reviewer: the dependencies, the
+//! features and the edition, rather than the lines of the file.
+//!
+//! Dependencies are matched by the crate they are, not by their name in the
+//! code, so renaming one with `package = "..."` is a change of the same
+//! dependency. Dependencies of specific targets are told apart from the
+//! others.
+
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+use toml;
+
+/// The fields of `[package]` which matter for the review.
+const PACKAGE_FIELDS: &[&str] = &["edition", "rust-version", "links", "build"];
+
+/// The kinds of dependencies, with the keys of their tables.
+const KINDS: &[(&str, &str, Option<&str>)] = &[
+    ("normal", "dependencies", None),
+    ("build", "build-dependencies", Some("build_dependencies")),
+    ("dev", "dev-dependencies", Some("dev_dependencies")),
+];
+
+/// The structural differences between two manifests.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Changes {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    dependencies: Vec<DependencyChange>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    features: Vec<FeatureChange>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    package: Vec<FieldChange>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Debug, Serialize)]
+struct DependencyChange {
+    change: Change,
+    /// `normal`, `dev` or `build`.
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    /// The name of the crate, which the dependencies are matched by.
+    package: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old: Option<Dependency>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new: Option<Dependency>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Dependency {
+    /// The name in the code, which is the name of the crate unless it is
+    /// renamed.
+    name: String,
+    #[serde(skip)]
+    package: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    req: Option<String>,
+    /// `git:URL`, `path:PATH`, `registry:NAME` or `workspace`, none for
+    /// crates.io.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    optional: bool,
+    default_features: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    features: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct FeatureChange {
+    change: Change,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+struct FieldChange {
+    field: &'static str,
+    old: Option<String>,
+    new: Option<String>,
+}
+
+/// Key of the dependencies: the kind, the target and the crate.
+type DependencyKey = (&'static str, Option<String>, String);
+
+impl Changes {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.dependencies.is_empty() && self.features.is_empty() && self.package.is_empty()
+    }
+
+    /// Prints the changes, one per line, if there are any.
+    pub(crate) fn print(&self) {
+        if self.is_empty() {
+            return;
+        }
+        println!("Changes in Cargo.toml:");
+        for line in self.lines() {
+            println!(" {}", line);
+        }
+        println!();
+    }
+
+    /// The changes, as printed.
+    pub(crate) fn lines(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        for change in self.package.iter() {
+            let show = |value: &Option<String>| value.clone().unwrap_or_else(|| "none".into());
+            res.push(format!(
+                "{}: {} -> {}",
+                change.field,
+                show(&change.old),
+                show(&change.new)
+            ));
+        }
+        for change in self.dependencies.iter() {
+            let kind = match change.kind {
+                "normal" => "dependency".to_string(),
+                kind => format!("{}-dependency", kind),
+            };
+            let kind = match &change.target {
+                Some(target) => format!("{} for {}", kind, target),
+                None => kind,
+            };
+            match (&change.old, &change.new) {
+                (None, Some(new)) => res.push(format!("added {}: {}", kind, new)),
+                (Some(old), None) => res.push(format!("removed {}: {}", kind, old)),
+                (Some(old), Some(new)) => res.push(format!(
+                    "changed {}: {}: {}",
+                    kind,
+                    change.package,
+                    dependency_changes(old, new).join(", ")
+                )),
+                (None, None) => (),
+            }
+        }
+        for change in self.features.iter() {
+            let list = |it: &Option<Vec<String>>| {
+                format!("[{}]", it.clone().unwrap_or_default().join(", "))
+            };
+            res.push(match change.change {
+                Change::Added => format!("added feature: {} = {}", change.name, list(&change.new)),
+                Change::Removed => format!("removed feature: {}", change.name),
+                Change::Changed => format!(
+                    "changed feature: {}: {} -> {}",
+                    change.name,
+                    list(&change.old),
+                    list(&change.new)
+                ),
+            });
+        }
+        res
+    }
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.package)?;
+        if self.package != self.name {
+            write!(f, " as {}", self.name)?;
+        }
+        if let Some(req) = &self.req {
+            write!(f, " {}", req)?;
+        }
+        if let Some(source) = &self.source {
+            write!(f, " from {}", source)?;
+        }
+        if self.optional {
+            write!(f, ", optional")?;
+        }
+        if !self.default_features {
+            write!(f, ", no default features")?;
+        }
+        if !self.features.is_empty() {
+            write!(f, ", features [{}]", self.features.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The differences between two versions of the same dependency, like
+/// `1.0 -> 2.0` or `features +derive`.
+fn dependency_changes(old: &Dependency, new: &Dependency) -> Vec<String> {
+    let mut res = Vec::new();
+    if old.name != new.name {
+        res.push(format!("renamed {} -> {}", old.name, new.name));
+    }
+    if old.req != new.req {
+        let show = |req: &Option<String>| req.clone().unwrap_or_else(|| "*".into());
+        res.push(format!("{} -> {}", show(&old.req), show(&new.req)));
+    }
+    if old.source != new.source {
+        let show = |source: &Option<String>| source.clone().unwrap_or_else(|| "crates.io".into());
+        res.push(format!(
+            "from {} -> {}",
+            show(&old.source),
+            show(&new.source)
+        ));
+    }
+    if old.optional != new.optional {
+        res.push(
+            if new.optional {
+                "now optional"
+            } else {
+                "no longer optional"
+            }
+            .to_string(),
+        );
+    }
+    if old.default_features != new.default_features {
+        res.push(
+            if new.default_features {
+                "default features on"
+            } else {
+                "default features off"
+            }
+            .to_string(),
+        );
+    }
+    let added: Vec<String> = new
+        .features
+        .iter()
+        .filter(|it| !old.features.contains(it))
+        .map(|it| format!("+{}", it))
+        .collect();
+    let removed: Vec<String> = old
+        .features
+        .iter()
+        .filter(|it| !new.features.contains(it))
+        .map(|it| format!("-{}", it))
+        .collect();
+    if !added.is_empty() || !removed.is_empty() {
+        res.push(format!("features {}", [added, removed].concat().join(" ")));
+    }
+    res
+}
+
+/// Compares the Cargo.toml files of the `a` and `b` trees. There are no
+/// changes if either of them is missing or invalid, the diff of the lines
+/// still shows what happened.
+pub(crate) fn compare(a: &Path, b: &Path) -> Changes {
+    let (old, new) = match (read(a), read(b)) {
+        (Some(old), Some(new)) => (old, new),
+        _ => return Changes::default(),
+    };
+    let mut res = Changes::default();
+    for &field in PACKAGE_FIELDS {
+        let value = |manifest: &toml::Value| {
+            manifest
+                .get("package")
+                .and_then(|it| it.get(field))
+                .map(show_value)
+        };
+        let (old, new) = (value(&old), value(&new));
+        if old != new {
+            res.package.push(FieldChange { field, old, new });
+        }
+    }
+
+    let (old_deps, new_deps) = (dependencies(&old), dependencies(&new));
+    let mut keys: Vec<&DependencyKey> = old_deps.keys().chain(new_deps.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        let (old_dep, new_dep) = (old_deps.get(key), new_deps.get(key));
+        let change = match (old_dep, new_dep) {
+            (Some(old_dep), Some(new_dep)) if old_dep == new_dep => continue,
+            (Some(_), Some(_)) => Change::Changed,
+            (Some(_), None) => Change::Removed,
+            _ => Change::Added,
+        };
+        let (kind, target, _) = key.clone();
+        res.dependencies.push(DependencyChange {
+            change,
+            kind,
+            target,
+            package: new_dep
+                .or(old_dep)
+                .map(|it| it.package.clone())
+                .unwrap_or_default(),
+            old: old_dep.cloned(),
+            new: new_dep.cloned(),
+        });
+    }
+    let rank = |kind: &str| KINDS.iter().position(|it| it.0 == kind);
+    res.dependencies.sort_by(|a, b| {
+        (a.change, rank(a.kind), &a.target, &a.package).cmp(&(
+            b.change,
+            rank(b.kind),
+            &b.target,
+            &b.package,
+        ))
+    });
+
+    let (old_features, new_features) = (features(&old), features(&new));
+    for (name, old_list) in old_features.iter() {
+        match new_features.get(name) {
+            Some(new_list) if new_list == old_list => (),
+            new_list => res.features.push(FeatureChange {
+                change: if new_list.is_some() {
+                    Change::Changed
+                } else {
+                    Change::Removed
+                },
+                name: name.clone(),
+                old: Some(old_list.clone()),
+                new: new_list.cloned(),
+            }),
+        }
+    }
+    for (name, new_list) in new_features.iter() {
+        if !old_features.contains_key(name) {
+            res.features.push(FeatureChange {
+                change: Change::Added,
+                name: name.clone(),
+                old: None,
+                new: Some(new_list.clone()),
+            });
+        }
+    }
+    res.features
+        .sort_by(|a, b| (a.change, &a.name).cmp(&(b.change, &b.name)));
+    res
+}
+
+fn read(root: &Path) -> Option<toml::Value> {
+    let text = fs::read_to_string(root.join("Cargo.toml")).ok()?;
+    toml::from_str(&text).ok()
+}
+
+/// All the dependencies of a manifest, by kind, target and crate. A crate
+/// depended on twice under different names is told apart by the names.
+fn dependencies(manifest: &toml::Value) -> BTreeMap<DependencyKey, Dependency> {
+    let mut tables: Vec<(Option<String>, &toml::Value)> = vec![(None, manifest)];
+    if let Some(targets) = manifest.get("target").and_then(|it| it.as_table()) {
+        for (target, table) in targets {
+            tables.push((Some(target.clone()), table));
+        }
+    }
+    let mut res = BTreeMap::new();
+    for (target, table) in tables {
+        for &(kind, key, alias) in KINDS {
+            let deps = table
+                .get(key)
+                .or_else(|| alias.and_then(|it| table.get(it)))
+                .and_then(|it| it.as_table());
+            let deps = match deps {
+                Some(deps) => deps,
+                None => continue,
+            };
+            let mut packages: Vec<(String, Dependency)> = deps
+                .iter()
+                .map(|(name, value)| {
+                    let dep = dependency(name, value);
+                    // Like crates.io, `foo_bar` and `foo-bar` are the same.
+                    (dep.package.replace('_', "-"), dep)
+                })
+                .collect();
+            packages.sort_by(|a, b| a.0.cmp(&b.0));
+            for (idx, (package, dep)) in packages.iter().enumerate() {
+                let twice = packages
+                    .iter()
+                    .enumerate()
+                    .any(|(other, it)| other != idx && it.0 == *package);
+                let package = if twice {
+                    format!("{} as {}", package, dep.name)
+                } else {
+                    package.clone()
+                };
+                res.insert((kind, target.clone(), package), dep.clone());
+            }
+        }
+    }
+    res
+}
+
+/// The name of the crate of a dependency.
+fn package(name: &str, value: &toml::Value) -> String {
+    value
+        .get("package")
+        .and_then(|it| it.as_str())
+        .unwrap_or(name)
+        .to_string()
+}
+
+fn dependency(name: &str, value: &toml::Value) -> Dependency {
+    let str_of = |key: &str| value.get(key).and_then(|it| it.as_str()).map(String::from);
+    let bool_of = |key: &str, alias: &str| {
+        value
+            .get(key)
+            .or_else(|| value.get(alias))
+            .and_then(|it| it.as_bool())
+    };
+    let req = match value {
+        toml::Value::String(req) => Some(req.clone()),
+        _ => str_of("version"),
+    };
+    let source = if let Some(url) = str_of("git") {
+        let rev = str_of("rev")
+            .or_else(|| str_of("tag"))
+            .or_else(|| str_of("branch"));
+        Some(match rev {
+            Some(rev) => format!("git:{}#{}", url, rev),
+            None => format!("git:{}", url),
+        })
+    } else if let Some(path) = str_of("path") {
+        Some(format!("path:{}", path))
+    } else if let Some(registry) = str_of("registry") {
+        Some(format!("registry:{}", registry))
+    } else if bool_of("workspace", "workspace") == Some(true) {
+        Some("workspace".to_string())
+    } else {
+        None
+    };
+    let mut features: Vec<String> = value
+        .get("features")
+        .and_then(|it| it.as_array())
+        .map(|it| {
+            it.iter()
+                .filter_map(|it| it.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default();
+    features.sort();
+    Dependency {
+        name: name.to_string(),
+        package: package(name, value),
+        req,
+        source,
+        optional: bool_of("optional", "optional").unwrap_or(false),
+        default_features: bool_of("default-features", "default_features").unwrap_or(true),
+        features,
+    }
+}
+
+fn features(manifest: &toml::Value) -> BTreeMap<String, Vec<String>> {
+    let mut res = BTreeMap::new();
+    if let Some(features) = manifest.get("features").and_then(|it| it.as_table()) {
+        for (name, list) in features {
+            let list = list
+                .as_array()
+                .map(|it| {
+                    it.iter()
+                        .filter_map(|it| it.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default();
+            res.insert(name.clone(), list);
+        }
+    }
+    res
+}
+
+/// A value of `[package]`, without the quotes of strings.
+fn show_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(it) => it.clone(),
+        toml::Value::Table(it) if it.get("workspace").and_then(|it| it.as_bool()) == Some(true) => {
+            "workspace".to_string()
+        }
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,272 @@
+//! A review summary in GitHub-flavored markdown, to be pasted as a comment on
+//! the pull request of a dependency update: links to both versions, the risk
+//! score, the changes of the license, the changed files, the sizes, the changes of
+//! Cargo.toml, what needs a closer look and the patches, folded away in
+//! `<details>` blocks.
+
+use std::{io::Write, path::Path};
+
+use builtin_diff::{self, ChangedFile, DiffOptions};
+use resolve::crates_io_version;
+use {files, license, manifest_diff, opaque, risk, risky, size, unsafe_report, PackageId, Result};
+
+/// What the report says besides the changes of the files.
+pub(crate) struct Summary<'a> {
+    pub(crate) risk: &'a risk::Score,
+    /// The patch of each file is cut off after this many bytes.
+    pub(crate) max_patch_size: usize,
+}
+
+/// Writes the summary of the differences between `first`, checked out to `a`,
+/// and `second`, checked out to `b`, and returns whether they differ.
+pub(crate) fn write_report(
+    out: &mut dyn Write,
+    first: &PackageId,
+    a: &Path,
+    second: &PackageId,
+    b: &Path,
+    options: DiffOptions,
+    summary: &Summary,
+) -> Result<bool> {
+    let changed = builtin_diff::changed_files(a, b, options.jobs)?;
+    writeln!(
+        out,
+        "## {} → {}",
+        code(&first.to_string()),
+        code(&second.to_string())
+    )?;
+    writeln!(out)?;
+    writeln!(out, "- Old: {}", links(first))?;
+    writeln!(out, "- New: {}", links(second))?;
+    writeln!(out, "- Risk: {}", escape(&summary.risk.to_string()))?;
+    writeln!(out)?;
+
+    // The license goes first, for legal to see.
+    let license = license::compare(a, b, options.jobs)?;
+    if !license.is_empty() {
+        writeln!(out, "### License")?;
+        writeln!(out)?;
+        let lines = license.license_lines();
+        if !lines.is_empty() {
+            write_list(out, &lines)?;
+            writeln!(out)?;
+        }
+        let readmes = license.readme_list();
+        if !readmes.is_empty() {
+            writeln!(out, "<details>")?;
+            writeln!(out, "<summary>Changed READMEs</summary>")?;
+            writeln!(out)?;
+            writeln!(out, "{}", escape(&readmes))?;
+            writeln!(out)?;
+            writeln!(out, "</details>")?;
+            writeln!(out)?;
+        }
+    }
+
+    writeln!(out, "### Changed files")?;
+    writeln!(out)?;
+    if changed.is_empty() {
+        writeln!(out, "No differences.")?;
+    } else {
+        write_stat(out, &changed, options)?;
+    }
+    writeln!(out)?;
+
+    writeln!(out, "### Size")?;
+    writeln!(out)?;
+    writeln!(out, "| | Old | New | Change |")?;
+    writeln!(out, "| --- | ---: | ---: | ---: |")?;
+    for (what, before, after) in size::compare(a, b)?.rows() {
+        writeln!(
+            out,
+            "| {} | {} | {} | {} |",
+            what,
+            before,
+            after,
+            size::change(before, after)
+        )?;
+    }
+    writeln!(out)?;
+
+    writeln!(out, "### Changes in Cargo.toml")?;
+    writeln!(out)?;
+    write_list(out, &manifest_diff::compare(a, b).lines())?;
+    writeln!(out)?;
+
+    let mut findings = Vec::new();
+    for (path, kind) in risky::risky_files(a, b) {
+        let verdict = risky::verdict(a, b, &path, options.ignore_whitespace);
+        if verdict != "unchanged" {
+            findings.push(format!("{} ({}): {}", path.display(), kind, verdict));
+        }
+    }
+    findings.extend(opaque::find(a, b, options.jobs)?.lines());
+    findings.extend(unsafe_report::report(a, b)?.findings());
+    writeln!(out, "### High-risk findings")?;
+    writeln!(out)?;
+    write_list(out, &findings)?;
+
+    let patches: Vec<(&ChangedFile, String)> = changed
+        .iter()
+        .filter_map(|file| Some((file, patch(file, options)?)))
+        .filter(|(_, patch)| !patch.is_empty())
+        .collect();
+    if !patches.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "### Patches")?;
+        for (file, patch) in patches {
+            writeln!(out)?;
+            write_patch(out, file, &patch, summary.max_patch_size)?;
+        }
+    }
+    Ok(!changed.is_empty())
+}
+
+/// The id of a package, with links to crates.io and docs.rs for the versions
+/// published there.
+fn links(pkg_id: &PackageId) -> String {
+    match crates_io_version(pkg_id) {
+        Some(version) => format!(
+            "{} ([crates.io](https://crates.io/crates/{name}/{version}), \
+             [docs.rs](https://docs.rs/{name}/{version}))",
+            code(&pkg_id.to_string()),
+            name = pkg_id.name,
+            version = version
+        ),
+        None => code(&pkg_id.to_string()),
+    }
+}
+
+/// A table with the changed lines of each file, and the totals.
+fn write_stat(out: &mut dyn Write, changes: &[ChangedFile], options: DiffOptions) -> Result<()> {
+    writeln!(out, "| File | Change | Added | Removed |")?;
+    writeln!(out, "| --- | --- | ---: | ---: |")?;
+    let (mut insertions, mut deletions) = (0, 0);
+    for file in changes {
+        let old = file.old.as_deref().unwrap_or_default();
+        let new = file.new.as_deref().unwrap_or_default();
+        let change = match (&file.old, &file.new) {
+            (None, _) => "added",
+            (_, None) => "removed",
+            _ => "modified",
+        };
+        let (change, counts) = match (builtin_diff::as_text(old), builtin_diff::as_text(new)) {
+            (Some(old), Some(new)) => {
+                let counts = builtin_diff::count_lines(old, new, options.ignore_whitespace);
+                insertions += counts.0;
+                deletions += counts.1;
+                (change.to_string(), Some(counts))
+            }
+            _ => (format!("{}, binary", change), None),
+        };
+        let (added, removed) = match counts {
+            Some((added, removed)) => (added.to_string(), removed.to_string()),
+            None => (String::new(), String::new()),
+        };
+        writeln!(
+            out,
+            "| {} | {} | {} | {} |",
+            code(&file.path.display().to_string()).replace('|', "\\|"),
+            change,
+            added,
+            removed
+        )?;
+    }
+    writeln!(
+        out,
+        "| **{} changed** | | **{}** | **{}** |",
+        files(changes.len()),
+        insertions,
+        deletions
+    )?;
+    Ok(())
+}
+
+fn write_list(out: &mut dyn Write, items: &[String]) -> Result<()> {
+    if items.is_empty() {
+        writeln!(out, "None.")?;
+    }
+    for item in items {
+        writeln!(out, "- {}", escape(item))?;
+    }
+    Ok(())
+}
+
+/// The hunks of the unified diff of a text file, `None` for binary files.
+fn patch(file: &ChangedFile, options: DiffOptions) -> Option<String> {
+    let old = builtin_diff::as_text(file.old.as_deref().unwrap_or_default())?;
+    let new = builtin_diff::as_text(file.new.as_deref().unwrap_or_default())?;
+    Some(builtin_diff::unified_hunks(old, new, options))
+}
+
+/// Writes the `patch` of `file` in a folded `<details>` block, cut off after
+/// the last line which fits in `max_size` bytes.
+fn write_patch(
+    out: &mut dyn Write,
+    file: &ChangedFile,
+    patch: &str,
+    max_size: usize,
+) -> Result<()> {
+    let mut shown = 0;
+    for line in patch.split_inclusive('\n') {
+        if shown + line.len() > max_size {
+            break;
+        }
+        shown += line.len();
+    }
+    let longest_backticks = patch
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest_backticks.max(2) + 1);
+    writeln!(out, "<details>")?;
+    writeln!(
+        out,
+        "<summary><code>{}</code></summary>",
+        escape_html(&file.path.display().to_string())
+    )?;
+    writeln!(out)?;
+    writeln!(out, "{}diff", fence)?;
+    write!(out, "{}", &patch[..shown])?;
+    writeln!(out, "{}", fence)?;
+    if shown < patch.len() {
+        writeln!(out)?;
+        writeln!(
+            out,
+            "The patch is cut off after {} of {} bytes.",
+            shown,
+            patch.len()
+        )?;
+    }
+    writeln!(out)?;
+    writeln!(out, "</details>")?;
+    Ok(())
+}
+
+/// `text` as inline code, with enough backticks around it.
+fn code(text: &str) -> String {
+    if text.contains('`') {
+        format!("`` {} ``", text)
+    } else {
+        format!("`{}`", text)
+    }
+}
+
+/// Escapes the characters markdown would take for formatting.
+pub(crate) fn escape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_[]<>|#~".contains(c) {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -0,0 +1,133 @@
+//! `diff --normalize rustfmt`: the Rust files of both sides are formatted
+//! before they are compared, so that a release which only reformats its code
+//! shows no changes, and the real changes of one which does stand out.
+//!
+//! Copies of the files are formatted, never the downloaded sources. Each
+//! file goes through the standard input of rustfmt, which doesn't follow
+//! the `mod` declarations out of it, with the edition of the package and
+//! the default style: the `rustfmt.toml` of the packages would format both
+//! sides differently. A file rustfmt can't parse is compared as it is.
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use toml;
+
+use {jobs, tree, warning_label, PackageId, Result};
+
+/// The edition of packages without one in their manifest.
+const DEFAULT_EDITION: &str = "2015";
+
+/// Fails if there's no rustfmt to run.
+pub(crate) fn check_rustfmt() -> Result<()> {
+    let status = Command::new("rustfmt")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        _ => bail!(
+            "--normalize rustfmt needs rustfmt, install it with `rustup component add rustfmt`"
+        ),
+    }
+}
+
+/// Copies `pkg_id`, checked out to `src`, to `dst` with its Rust files
+/// formatted on `jobs` threads, with the empty config in `tmp`. Returns how
+/// many files it formatted.
+pub(crate) fn rustfmt(
+    pkg_id: &PackageId,
+    src: &Path,
+    dst: &Path,
+    tmp: &Path,
+    jobs: usize,
+) -> Result<usize> {
+    tree::copy_tree(src, dst)?;
+    let config = tmp.join("rustfmt.toml");
+    if !config.exists() {
+        fs::write(&config, "")?;
+    }
+    let edition = edition(dst);
+    let mut paths = Vec::new();
+    rust_files(dst, Path::new(""), &mut paths)?;
+    let results = jobs::map(jobs, &paths, |path| {
+        format_file(&dst.join(path), &edition, &config)
+    });
+    let mut formatted = 0;
+    for (path, res) in paths.iter().zip(results) {
+        if res? {
+            formatted += 1;
+        } else {
+            eprintln!(
+                "{} rustfmt can't parse {} of {}, it is compared as it is",
+                warning_label(),
+                path.display(),
+                pkg_id
+            );
+        }
+    }
+    Ok(formatted)
+}
+
+/// The edition in the manifest of the package in `dir`.
+fn edition(dir: &Path) -> String {
+    let manifest: Option<toml::Value> = fs::read_to_string(dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|it| toml::from_str(&it).ok());
+    manifest
+        .as_ref()
+        .and_then(|it| it.get("package"))
+        .and_then(|it| it.get("edition"))
+        .and_then(|it| it.as_str())
+        .unwrap_or(DEFAULT_EDITION)
+        .to_string()
+}
+
+/// The `.rs` files under `dir` of `root`, sorted, without following
+/// symlinks.
+fn rust_files(root: &Path, dir: &Path, res: &mut Vec<PathBuf>) -> Result<()> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(root.join(dir))? {
+        names.push(entry?.file_name());
+    }
+    names.sort();
+    for name in names {
+        let path = dir.join(name);
+        let file_type = fs::symlink_metadata(root.join(&path))?.file_type();
+        if file_type.is_dir() {
+            rust_files(root, &path, res)?;
+        } else if file_type.is_file() && path.extension().is_some_and(|it| it == "rs") {
+            res.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Formats the file at `path` in place. Returns whether rustfmt could.
+fn format_file(path: &Path, edition: &str, config: &Path) -> Result<bool> {
+    let text = fs::read(path)?;
+    let mut child = Command::new("rustfmt")
+        .args(["--edition", edition, "--config-path"])
+        .arg(config)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| format_err!("can't run rustfmt: {}", err))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // rustfmt only writes once it read everything, so it can't block on a
+    // full stdout meanwhile.
+    let written = stdin.write_all(&text);
+    drop(stdin);
+    let output = child.wait_with_output()?;
+    if written.is_err() || !output.status.success() {
+        return Ok(false);
+    }
+    fs::write(path, output.stdout)?;
+    Ok(true)
+}
@@ -0,0 +1,127 @@
+//! `current --offline`, for air-gapped review machines: nothing is downloaded,
+//! so the sources of all the packages have to be in the cache of Cargo
+//! already. Those which aren't are all reported at once, before anything is
+//! copied, with the `cargo fetch` to run where there's a network.
+//! `--fetch-missing` runs it first instead.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use cargo_metadata;
+use failure;
+
+use verify::Checksums;
+use {enumerate, packages_word, pkg_dir, Result};
+
+/// `CARGO_HOME`, `~/.cargo` by default.
+pub(crate) fn cargo_home() -> Option<PathBuf> {
+    env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|it| PathBuf::from(it).join(".cargo")))
+}
+
+/// The directory of the Cargo.lock of the workspace of `manifest_path`, or
+/// of the current directory: the closest one which has one.
+fn lockfile_dir(manifest_path: Option<&Path>) -> Option<PathBuf> {
+    let start = match manifest_path {
+        Some(path) => fs::canonicalize(path).ok()?.parent()?.to_path_buf(),
+        None => env::current_dir().ok()?,
+    };
+    start
+        .ancestors()
+        .find(|it| it.join("Cargo.lock").is_file())
+        .map(Path::to_path_buf)
+}
+
+/// The published packages of the Cargo.lock of the workspace, as
+/// `name version`, whose sources and `.crate` file aren't in the cache of
+/// Cargo.
+pub(crate) fn not_cached(manifest_path: Option<&Path>) -> Result<Vec<String>> {
+    let (root, home) = match (lockfile_dir(manifest_path), cargo_home()) {
+        (Some(root), Some(home)) => (root, home.join("registry")),
+        _ => return Ok(Vec::new()),
+    };
+    // The caches of all the registries, as Cargo names them after a hash of
+    // their URL.
+    let indices = |dir: &str| -> Vec<PathBuf> {
+        fs::read_dir(home.join(dir))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|it| it.path())
+            .collect()
+    };
+    let (sources, caches) = (indices("src"), indices("cache"));
+    let mut res = Vec::new();
+    for (name, version) in Checksums::read(&root)?.packages() {
+        let dir_name = format!("{}-{}", name, version);
+        let crate_file = format!("{}.crate", dir_name);
+        let found = sources.iter().any(|it| it.join(&dir_name).is_dir())
+            || caches.iter().any(|it| it.join(&crate_file).is_file());
+        if !found {
+            res.push(format!("{} {}", name, version));
+        }
+    }
+    res.sort();
+    res.dedup();
+    Ok(res)
+}
+
+/// The `packages` whose sources aren't there, as `name version`.
+pub(crate) fn not_downloaded(packages: &[&cargo_metadata::Package]) -> Vec<String> {
+    packages
+        .iter()
+        .filter(|pkg| !pkg_dir(pkg).is_ok_and(|it| it.is_dir()))
+        .map(|pkg| format!("{} {}", pkg.name, pkg.version))
+        .collect()
+}
+
+/// `cargo fetch --locked`, for the workspace of `manifest_path`.
+fn fetch_command(manifest_path: Option<&Path>) -> String {
+    match manifest_path {
+        Some(path) => format!("cargo fetch --locked --manifest-path {}", path.display()),
+        None => "cargo fetch --locked".to_string(),
+    }
+}
+
+/// Downloads the sources of the workspace of `manifest_path`.
+pub(crate) fn fetch(manifest_path: Option<&Path>, quiet: bool) -> Result<()> {
+    note!(quiet, "Running `{}`", fetch_command(manifest_path));
+    let mut cmd = Command::new("cargo");
+    cmd.args(["fetch", "--locked"]);
+    if quiet {
+        cmd.arg("--quiet");
+    }
+    if let Some(path) = manifest_path {
+        cmd.arg("--manifest-path").arg(path);
+    }
+    let status = cmd.stdin(Stdio::null()).status()?;
+    if !status.success() {
+        bail!("`{}` failed", fetch_command(manifest_path));
+    }
+    Ok(())
+}
+
+/// The error for the `missing` packages, telling how to download them, or
+/// to pass `--fetch-missing` unless `offline`.
+pub(crate) fn missing_error(
+    missing: &[String],
+    manifest_path: Option<&Path>,
+    offline: bool,
+) -> failure::Error {
+    let hint = if offline {
+        String::new()
+    } else {
+        ", or pass --fetch-missing".to_string()
+    };
+    format_err!(
+        "the sources of {} aren't downloaded: {}\nrun `{}` on a machine with network access{}",
+        packages_word(missing.len()),
+        enumerate(missing),
+        fetch_command(manifest_path),
+        hint
+    )
+}
@@ -0,0 +1,131 @@
+//! The files of a new version which can't be reviewed by reading them:
+//! binaries, like prebuilt libraries, and text with lines too long to read,
+//! like embedded base64 blobs.
+
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use builtin_diff::{self, ChangedFile};
+use Result;
+
+/// Text with a line longer than this many characters is called out.
+const LONG_LINE: usize = 1000;
+
+/// Hex digits of the hashes shown.
+const HASH_LEN: usize = 16;
+
+/// The opaque files which are added or changed between two versions.
+pub(crate) struct Findings {
+    files: Vec<Finding>,
+}
+
+enum Finding {
+    AddedBinary {
+        path: String,
+        size: usize,
+        hash: String,
+    },
+    ChangedBinary {
+        path: String,
+        sizes: (usize, usize),
+        hashes: (String, String),
+    },
+    LongLine {
+        path: String,
+        length: usize,
+    },
+}
+
+impl Findings {
+    /// The paths of the binary files which aren't in the first version.
+    pub(crate) fn added_binaries(&self) -> Vec<&str> {
+        self.files
+            .iter()
+            .filter_map(|it| match it {
+                Finding::AddedBinary { path, .. } => Some(path.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Prints the findings to stderr, if there are any.
+    pub(crate) fn print(&self) {
+        if self.files.is_empty() {
+            return;
+        }
+        eprintln!("Opaque files:");
+        for line in self.lines() {
+            eprintln!(" {}", line);
+        }
+    }
+
+    /// The findings, as printed.
+    pub(crate) fn lines(&self) -> Vec<String> {
+        self.files
+            .iter()
+            .map(|file| match file {
+                Finding::AddedBinary { path, size, hash } => {
+                    format!("added binary: {} ({} bytes, sha256 {})", path, size, hash)
+                }
+                Finding::ChangedBinary {
+                    path,
+                    sizes,
+                    hashes,
+                } => format!(
+                    "changed binary: {} ({} -> {} bytes, sha256 {} -> {})",
+                    path, sizes.0, sizes.1, hashes.0, hashes.1
+                ),
+                Finding::LongLine { path, length } => format!(
+                    "long line: {} (the longest line has {} characters)",
+                    path, length
+                ),
+            })
+            .collect()
+    }
+}
+
+/// Looks for the opaque files of `b` which differ from `a`, on `jobs`
+/// threads. Removed files don't matter.
+pub(crate) fn find(a: &Path, b: &Path, jobs: usize) -> Result<Findings> {
+    let mut files = Vec::new();
+    for file in builtin_diff::changed_files(a, b, jobs)? {
+        let ChangedFile { path, old, new, .. } = file;
+        let new = match new {
+            Some(new) => new,
+            None => continue,
+        };
+        let path = path.display().to_string();
+        match builtin_diff::as_text(&new) {
+            Some(text) => {
+                let length = text
+                    .lines()
+                    .map(|it| it.chars().count())
+                    .max()
+                    .unwrap_or_default();
+                if length > LONG_LINE {
+                    files.push(Finding::LongLine { path, length });
+                }
+            }
+            None => match old {
+                None => files.push(Finding::AddedBinary {
+                    path,
+                    size: new.len(),
+                    hash: hash(&new),
+                }),
+                Some(old) => files.push(Finding::ChangedBinary {
+                    path,
+                    sizes: (old.len(), new.len()),
+                    hashes: (hash(&old), hash(&new)),
+                }),
+            },
+        }
+    }
+    Ok(Findings { files })
+}
+
+fn hash(bytes: &[u8]) -> String {
+    let mut res = format!("{:x}", Sha256::digest(bytes));
+    res.truncate(HASH_LEN);
+    res
+}
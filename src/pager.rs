@@ -0,0 +1,126 @@
+//! Paging the output of `diff` through `$PAGER`, like git does, when stdout is
+//! a terminal.
+//!
+//! stdout itself is pointed at the pager, so that the external diff programs
+//! write to it too. The colors and the width are still those of the terminal.
+
+use std::io;
+#[cfg(unix)]
+use std::{
+    env,
+    io::{IsTerminal, Write},
+    os::unix::io::AsRawFd,
+    process::{Child, Command, Stdio},
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+use terminal_size::{self, Height, Width};
+
+#[cfg(unix)]
+use color;
+use Result;
+
+/// The pager of git when there's no `$PAGER`. `-F` quits right away if the
+/// output fits on the screen, and `-R` keeps the colors.
+#[cfg(unix)]
+const DEFAULT_PAGER: &str = "less -RFX";
+
+/// stdout from before the paging, while it goes to the pager.
+#[cfg(unix)]
+static TERMINAL: AtomicI32 = AtomicI32::new(-1);
+
+/// The running pager. It gets the rest of the output when dropped, and is
+/// waited for, so that the terminal is back to normal afterwards.
+pub(crate) struct Pager {
+    #[cfg(unix)]
+    child: Child,
+}
+
+/// Starts paging stdout, if it is a terminal and `$PAGER` isn't empty or
+/// `cat`.
+#[cfg(unix)]
+pub(crate) fn start() -> Result<Option<Pager>> {
+    if !io::stdout().is_terminal() || TERMINAL.load(Ordering::SeqCst) != -1 {
+        return Ok(None);
+    }
+    let pager = env::var("PAGER").unwrap_or_else(|_| DEFAULT_PAGER.to_string());
+    if pager.trim().is_empty() || pager.trim() == "cat" {
+        return Ok(None);
+    }
+    let mut child = match Command::new("sh")
+        .arg("-c")
+        .arg(&pager)
+        .stdin(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            eprintln!(
+                "{} can't run the pager `{}`: {}",
+                color::warning_label(),
+                pager,
+                err
+            );
+            return Ok(None);
+        }
+    };
+    let stdin = child.stdin.take().expect("stdin is piped");
+    io::stdout().flush()?;
+    // SAFETY: only file descriptors owned by this process are duplicated.
+    unsafe {
+        let terminal = libc::dup(libc::STDOUT_FILENO);
+        if terminal == -1 || libc::dup2(stdin.as_raw_fd(), libc::STDOUT_FILENO) == -1 {
+            bail!(
+                "can't redirect stdout to the pager: {}",
+                io::Error::last_os_error()
+            );
+        }
+        TERMINAL.store(terminal, Ordering::SeqCst);
+        // Quitting the pager early ends the output, as with git.
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+    Ok(Some(Pager { child }))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn start() -> Result<Option<Pager>> {
+    Ok(None)
+}
+
+#[cfg(unix)]
+impl Drop for Pager {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        let terminal = TERMINAL.swap(-1, Ordering::SeqCst);
+        // SAFETY: `terminal` is the duplicate of stdout made by `start`.
+        // Putting it back closes the pipe, so the pager sees the end.
+        unsafe {
+            libc::dup2(terminal, libc::STDOUT_FILENO);
+            libc::close(terminal);
+        }
+        let _ = self.child.wait();
+    }
+}
+
+/// Whether the output ends up on a terminal, directly or through the pager.
+pub(crate) fn is_terminal() -> bool {
+    #[cfg(unix)]
+    {
+        if TERMINAL.load(Ordering::SeqCst) != -1 {
+            return true;
+        }
+    }
+    io::IsTerminal::is_terminal(&io::stdout())
+}
+
+/// The size of the terminal the output ends up on.
+pub(crate) fn terminal_size() -> Option<(Width, Height)> {
+    #[cfg(unix)]
+    {
+        let terminal = TERMINAL.load(Ordering::SeqCst);
+        if terminal != -1 {
+            return terminal_size::terminal_size_using_fd(terminal);
+        }
+    }
+    terminal_size::terminal_size()
+}
@@ -0,0 +1,220 @@
+//! `current --deny-source` and `--allow-registry`: where the dependencies may
+//! come from. The packages from other sources fail the command before
+//! anything is checked out, each with the chain of dependencies which pulls it
+//! into the workspace, as a `PolicyViolation`, which has an exit code of its
+//! own so that CI can tell it from trouble.
+//!
+//! The whole dependency graph is checked, whatever `current` checks out, or
+//! the packages of `--packages-from` if it's given. The members of the
+//! workspace are never violations.
+
+use std::{env, fmt, path::PathBuf};
+
+use cargo_metadata;
+use failure::Fail;
+use toml;
+
+use dep_graph;
+use vendor::read_configs;
+use {package_source, packages_word, PackageId, PackageSource, Result, Source};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeniedSource {
+    Git,
+    Path,
+}
+
+/// The packages which come from sources the policy denies.
+#[derive(Debug)]
+pub struct PolicyViolation {
+    /// Like `evil 0.1.0: a git dependency, via test-pkg 0.0.0 -> evil 0.1.0`.
+    packages: Vec<String>,
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let n = self.packages.len();
+        write!(
+            f,
+            "{} {} from sources the policy denies:",
+            packages_word(n),
+            if n == 1 { "comes" } else { "come" }
+        )?;
+        for package in self.packages.iter() {
+            write!(f, "\n  {}", package)?;
+        }
+        Ok(())
+    }
+}
+
+impl Fail for PolicyViolation {}
+
+#[derive(Debug, Default)]
+pub(crate) struct Policy {
+    denied: Vec<DeniedSource>,
+    /// The index URLs of the registries other than crates.io which are
+    /// allowed, all of them if `None`.
+    registries: Option<Vec<String>>,
+}
+
+impl Policy {
+    /// The `denied` sources, and the registries `allowed` by name, as in the
+    /// `[registries]` of `.cargo/config.toml`, or by index URL.
+    pub(crate) fn new(denied: &[DeniedSource], allowed: &[String]) -> Result<Policy> {
+        let registries = if allowed.is_empty() {
+            None
+        } else {
+            let configs = read_configs();
+            let mut res = Vec::new();
+            for name in allowed {
+                let index = if name.contains("://") {
+                    Some(name.clone())
+                } else {
+                    registry_index(name, &configs)
+                };
+                match index {
+                    Some(index) => res.push(index_url(&index).to_string()),
+                    None => bail!(
+                        "--allow-registry {}: there's no registry `{}` in .cargo/config.toml",
+                        name,
+                        name
+                    ),
+                }
+            }
+            Some(res)
+        };
+        Ok(Policy {
+            denied: denied.to_vec(),
+            registries,
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.denied.is_empty() && self.registries.is_none()
+    }
+
+    /// Why the policy denies `pkg`, if it does.
+    fn denies(
+        &self,
+        meta: &cargo_metadata::Metadata,
+        pkg: &cargo_metadata::Package,
+    ) -> Option<String> {
+        let source = package_source(meta, pkg);
+        let denied = match source {
+            PackageSource::Git(_) => self.denied.contains(&DeniedSource::Git),
+            PackageSource::Path => self.denied.contains(&DeniedSource::Path),
+            PackageSource::Registry => {
+                let registries = self.registries.as_ref()?;
+                let index = index_url(pkg.source.as_ref()?.to_string().as_str()).to_string();
+                if registries.contains(&index) {
+                    return None;
+                }
+                return Some(format!(
+                    "from the registry {}, which --allow-registry doesn't allow",
+                    index
+                ));
+            }
+            PackageSource::CratesIo | PackageSource::Workspace => false,
+        };
+        if denied {
+            Some(source.description().to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Why the policy denies `pkg_id`, of `--packages-from`, if it does.
+    fn denies_id(&self, pkg_id: &PackageId) -> Option<String> {
+        let (denied, source) = match &pkg_id.source {
+            Source::Git { .. } => (DeniedSource::Git, PackageSource::Git(None)),
+            Source::Path(_) => (DeniedSource::Path, PackageSource::Path),
+            Source::Registry { registry: None, .. } => return None,
+            Source::Registry {
+                registry: Some(name),
+                ..
+            } => {
+                let registries = self.registries.as_ref()?;
+                let index = registry_index(name, &read_configs());
+                if index.is_some_and(|it| registries.contains(&index_url(&it).to_string())) {
+                    return None;
+                }
+                return Some(format!(
+                    "from the registry {}, which --allow-registry doesn't allow",
+                    name
+                ));
+            }
+        };
+        if self.denied.contains(&denied) {
+            Some(source.description().to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Fails with a `PolicyViolation` if some of the `pkg_ids` of
+    /// `--packages-from` come from sources the policy denies.
+    pub(crate) fn check_ids(&self, pkg_ids: &[PackageId]) -> Result<()> {
+        let mut packages: Vec<String> = pkg_ids
+            .iter()
+            .filter_map(|it| Some(format!("{}: {}", it, self.denies_id(it)?)))
+            .collect();
+        if packages.is_empty() {
+            return Ok(());
+        }
+        packages.sort();
+        Err(PolicyViolation { packages }.into())
+    }
+
+    /// Fails with a `PolicyViolation` if some of the packages of `meta` come
+    /// from sources the policy denies.
+    pub(crate) fn check(&self, meta: &cargo_metadata::Metadata) -> Result<()> {
+        let mut packages = Vec::new();
+        for pkg in meta.packages.iter() {
+            let why = match self.denies(meta, pkg) {
+                Some(it) => it,
+                None => continue,
+            };
+            let chain: Vec<String> = dep_graph::chain(meta, &pkg.id.repr)?
+                .into_iter()
+                .filter_map(|id| meta.packages.iter().find(|it| it.id.repr == id))
+                .map(|it| format!("{} {}", it.name, it.version))
+                .collect();
+            let mut line = format!("{} {}: {}", pkg.name, pkg.version, why);
+            if !chain.is_empty() {
+                line += &format!(", via {}", chain.join(" -> "));
+            }
+            packages.push(line);
+        }
+        if packages.is_empty() {
+            return Ok(());
+        }
+        packages.sort();
+        Err(PolicyViolation { packages }.into())
+    }
+}
+
+/// The index URL of the registry `name`, as the environment or the `configs`
+/// of Cargo tell it.
+fn registry_index(name: &str, configs: &[(PathBuf, toml::Value)]) -> Option<String> {
+    let var = format!(
+        "CARGO_REGISTRIES_{}_INDEX",
+        name.to_uppercase().replace('-', "_")
+    );
+    env::var(var).ok().or_else(|| {
+        configs.iter().find_map(|(_, config)| {
+            let index = config.get("registries")?.get(name)?.get("index")?;
+            index.as_str().map(String::from)
+        })
+    })
+}
+
+/// The URL of a registry index, as in `source` of Cargo.lock or in `index` of
+/// `.cargo/config.toml`, without the protocol, git or sparse, Cargo prefixes
+/// it with.
+fn index_url(source: &str) -> &str {
+    let url = ["registry+", "sparse+"]
+        .iter()
+        .find_map(|it| source.strip_prefix(it))
+        .unwrap_or(source);
+    url.trim_end_matches('/')
+}
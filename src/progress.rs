@@ -0,0 +1,56 @@
+//! How far `current` is with copying the packages, on stderr: a line which
+//! updates itself in a terminal, like `[12/400] thread_local-0.3.6`, and else
+//! a line every tenth of the way, not to clutter CI logs.
+
+use std::{
+    io::{self, IsTerminal},
+    sync::Mutex,
+};
+
+/// Below this, the packages are copied too fast for lines in a log to help.
+const MIN_LOGGED_STEP: usize = 10;
+
+pub(crate) struct Progress {
+    total: usize,
+    /// With `--quiet`, nothing is printed.
+    quiet: bool,
+    terminal: bool,
+    /// The packages copied so far.
+    done: Mutex<usize>,
+}
+
+impl Progress {
+    pub(crate) fn new(total: usize, quiet: bool) -> Progress {
+        Progress {
+            total,
+            quiet,
+            terminal: !quiet && io::stderr().is_terminal(),
+            done: Mutex::new(0),
+        }
+    }
+
+    /// Tells that the copy of `name` starts.
+    pub(crate) fn start(&self, name: &str) {
+        let done = self.done.lock().unwrap();
+        if self.terminal {
+            eprint!("\r\x1b[K[{}/{}] {}", *done, self.total, name);
+        }
+    }
+
+    /// Counts one more package as copied, or failed.
+    pub(crate) fn finish_one(&self) {
+        let mut done = self.done.lock().unwrap();
+        *done += 1;
+        let step = (self.total / 10).max(MIN_LOGGED_STEP);
+        if !self.terminal && done.is_multiple_of(step) && *done < self.total {
+            note!(self.quiet, "Copied {}/{} packages", *done, self.total);
+        }
+    }
+
+    /// Clears the line of the progress, in a terminal.
+    pub(crate) fn finish(&self) {
+        if self.terminal && self.total > 0 {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
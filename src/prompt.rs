@@ -0,0 +1,35 @@
+//! Questions asked on the terminal. The question goes to stderr, so that it
+//! shows up even if stdout is redirected, and the answer is read from stdin.
+
+use std::io::{self, BufRead, Write};
+
+use Result;
+
+/// Asks `question` and returns the answer, trimmed, or `None` at the end of
+/// the input.
+pub(crate) fn ask(question: &str) -> Result<Option<String>> {
+    eprint!("{} ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer)? == 0 {
+        // The input has no line break of its own then.
+        eprintln!();
+        return Ok(None);
+    }
+    Ok(Some(answer.trim().to_string()))
+}
+
+/// Asks `question` until the answer starts with one of the `choices`, and
+/// returns that choice, or `None` at the end of the input.
+pub(crate) fn choose(question: &str, choices: &[char]) -> Result<Option<char>> {
+    loop {
+        let answer = match ask(question)? {
+            Some(it) => it,
+            None => return Ok(None),
+        };
+        let choice = answer.chars().next().map(|it| it.to_ascii_lowercase());
+        if let Some(choice) = choice.filter(|it| choices.contains(it)) {
+            return Ok(Some(choice));
+        }
+    }
+}
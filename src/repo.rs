@@ -0,0 +1,290 @@
+//! Checking a published package against the sources in its repository, for
+//! `diff --against-repo`.
+//!
+//! The repository is the `repository` of the manifest, cloned shallowly at
+//! the tag of the version. Files Cargo adds or rewrites on publish don't
+//! count: the original Cargo.toml is compared instead of the normalized one,
+//! and `.cargo_vcs_info.json` is left out. Only the files of the repository
+//! which made it into the package are compared, so the differences are
+//! exactly the code which isn't in the repository.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use semver::Version;
+use serde_json;
+use toml;
+
+use {
+    copy_package, files, same_name, warning_label, PackageId, Result, Source, VersionSpec,
+    ORIGINAL_MANIFEST, VCS_INFO,
+};
+
+/// The sources of `pkg_id`, unpacked to `src`, and of the tag of its version,
+/// checked out to `tmp`.
+pub(crate) struct Checkout {
+    /// The repository at the tag, a git package.
+    pub(crate) repo_id: PackageId,
+    pub(crate) repo: PathBuf,
+    /// The published package without the files Cargo adds.
+    pub(crate) published: PathBuf,
+}
+
+pub(crate) fn checkout(pkg_id: &PackageId, src: &Path, tmp: &Path) -> Result<Checkout> {
+    let manifest = read_manifest(&src.join("Cargo.toml"))?;
+    let version = match &pkg_id.source {
+        Source::Registry {
+            version: VersionSpec::Exact(version),
+            ..
+        } => version.clone(),
+        Source::Path(_) => manifest_str(&manifest, "version")
+            .and_then(|it| Version::parse(it).ok())
+            .ok_or_else(|| format_err!("{} has no version in its manifest", pkg_id))?,
+        _ => bail!("--against-repo needs a published package, got {}", pkg_id),
+    };
+    let url = manifest_str(&manifest, "repository")
+        .ok_or_else(|| format_err!("{} has no repository in its manifest", pkg_id))?;
+    let tag = find_tag(url, &pkg_id.name, &version, &tags(url)?)?;
+    let clone = tmp.join("repo-clone");
+    git_clone(url, &tag, &clone)?;
+
+    let published = tmp.join("published");
+    copy_package(pkg_id, src, &published)?;
+    let vcs_info = published.join(VCS_INFO);
+    let path_in_vcs = match fs::read(&vcs_info) {
+        Ok(bytes) => {
+            fs::remove_file(&vcs_info)?;
+            let info: serde_json::Value = serde_json::from_slice(&bytes)?;
+            let commit = info["git"]["sha1"].as_str().unwrap_or_default();
+            let head = git_head(&clone)?;
+            if !commit.is_empty() && commit != head {
+                eprintln!(
+                    "{} {} was published from commit {}, but tag {} is at {}",
+                    warning_label(),
+                    pkg_id,
+                    commit,
+                    tag,
+                    head
+                );
+            }
+            info["path_in_vcs"].as_str().map(PathBuf::from)
+        }
+        Err(_) => None,
+    };
+    if published.join(ORIGINAL_MANIFEST).exists() {
+        fs::rename(
+            published.join(ORIGINAL_MANIFEST),
+            published.join("Cargo.toml"),
+        )?;
+    }
+    let package_dir = match path_in_vcs {
+        // Published from the root of the repository.
+        Some(path) if path.as_os_str().is_empty() => clone.clone(),
+        Some(path) => clone.join(path),
+        None => find_package(&clone, &pkg_id.name)?.ok_or_else(|| {
+            format_err!("there's no package `{}` in {} at {}", pkg_id.name, url, tag)
+        })?,
+    };
+    // Binaries are published with the lockfile of their workspace.
+    if !package_dir.join("Cargo.lock").exists() {
+        let _ = fs::remove_file(published.join("Cargo.lock"));
+    }
+
+    let repo = tmp.join("repo");
+    fs::create_dir_all(&repo)?;
+    copy_published(&package_dir, &published, &repo, Path::new(""))?;
+    let unpublished = count_unpublished(&package_dir, &published, Path::new(""))?;
+    if unpublished > 0 {
+        eprintln!(
+            "Left out {} of the repository, not in the published package",
+            files(unpublished)
+        );
+    }
+    let repo_id = PackageId {
+        name: pkg_id.name.clone(),
+        source: Source::Git {
+            url: url.to_string(),
+            rev: tag,
+        },
+    };
+    Ok(Checkout {
+        repo_id,
+        repo,
+        published,
+    })
+}
+
+fn read_manifest(path: &Path) -> Result<toml::Value> {
+    let manifest = fs::read_to_string(path)
+        .map_err(|err| format_err!("can't read {}: {}", path.display(), err))?;
+    toml::from_str(&manifest).map_err(|err| format_err!("can't parse {}: {}", path.display(), err))
+}
+
+fn manifest_str<'a>(manifest: &'a toml::Value, key: &str) -> Option<&'a str> {
+    manifest.get("package")?.get(key)?.as_str()
+}
+
+/// The tag of `version`: `v1.2.3` or `1.2.3`, optionally prefixed with the
+/// name of the package, as in workspaces. The error lists the tags of the
+/// nearest versions.
+fn find_tag(url: &str, name: &str, version: &Version, tags: &[String]) -> Result<String> {
+    let candidates = [
+        format!("v{}", version),
+        version.to_string(),
+        format!("{}-v{}", name, version),
+        format!("{}-{}", name, version),
+    ];
+    if let Some(tag) = candidates.iter().find(|it| tags.contains(it)) {
+        return Ok(tag.clone());
+    }
+    let mut versioned: Vec<(Version, &String)> = tags
+        .iter()
+        .filter_map(|tag| tag_version(tag).map(|it| (it, tag)))
+        .collect();
+    versioned.sort();
+    let idx = versioned
+        .iter()
+        .position(|(it, _)| it > version)
+        .unwrap_or(versioned.len());
+    let nearby: Vec<&str> = versioned[idx.saturating_sub(3)..(idx + 3).min(versioned.len())]
+        .iter()
+        .map(|(_, tag)| tag.as_str())
+        .collect();
+    let nearby = if nearby.is_empty() {
+        "it has no version tags at all".to_string()
+    } else {
+        format!("nearby tags: {}", nearby.join(", "))
+    };
+    bail!(
+        "no tag for version {} in {} (tried {}); {}",
+        version,
+        url,
+        candidates.join(", "),
+        nearby
+    )
+}
+
+/// The version at the end of a tag like `v1.2.3` or `rand-0.6.0`.
+fn tag_version(tag: &str) -> Option<Version> {
+    tag.char_indices()
+        .filter(|&(idx, c)| {
+            c.is_ascii_digit()
+                && tag[..idx]
+                    .chars()
+                    .last()
+                    .is_none_or(|prev| "v-_@/".contains(prev))
+        })
+        .find_map(|(idx, _)| Version::parse(&tag[idx..]).ok())
+}
+
+fn tags(url: &str) -> Result<Vec<String>> {
+    let output = git()
+        .args(["ls-remote", "--tags", "--refs", url])
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|err| format_err!("can't run `git`: {}", err))?;
+    if !output.status.success() {
+        bail!("can't list the tags of {}", url);
+    }
+    let res = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split('\t').nth(1))
+        .filter_map(|it| it.strip_prefix("refs/tags/"))
+        .map(String::from)
+        .collect();
+    Ok(res)
+}
+
+fn git_clone(url: &str, tag: &str, dst: &Path) -> Result<()> {
+    eprintln!("Cloning {} at {}", url, tag);
+    let status = git()
+        .args(["-c", "advice.detachedHead=false", "clone", "--quiet"])
+        .args(["--depth", "1", "--branch", tag, url])
+        .arg(dst)
+        .status()
+        .map_err(|err| format_err!("can't run `git`: {}", err))?;
+    if !status.success() {
+        bail!("can't clone {} at {}", url, tag);
+    }
+    Ok(())
+}
+
+fn git_head(repo: &Path) -> Result<String> {
+    let output = git()
+        .arg("-C")
+        .arg(repo)
+        .args(["rev-parse", "HEAD"])
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn git() -> Command {
+    let mut cmd = Command::new("git");
+    // Never ask for credentials, a private repository is just an error.
+    cmd.env("GIT_TERMINAL_PROMPT", "0");
+    cmd
+}
+
+/// The directory of the package `name` in the repository checked out to
+/// `root`, the one closest to the root if there are several.
+fn find_package(root: &Path, name: &str) -> Result<Option<PathBuf>> {
+    let mut dirs = vec![root.to_path_buf()];
+    while !dirs.is_empty() {
+        let mut next = Vec::new();
+        for dir in dirs {
+            let manifest = dir.join("Cargo.toml");
+            if manifest.exists() {
+                let manifest = read_manifest(&manifest)?;
+                if manifest_str(&manifest, "name").is_some_and(|it| same_name(it, name)) {
+                    return Ok(Some(dir));
+                }
+            }
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let file_name = entry.file_name();
+                if entry.file_type()?.is_dir() && file_name != ".git" && file_name != "target" {
+                    next.push(entry.path());
+                }
+            }
+        }
+        next.sort();
+        dirs = next;
+    }
+    Ok(None)
+}
+
+/// Copies the files of `src` which are in `published` to `dst`.
+fn copy_published(src: &Path, published: &Path, dst: &Path, dir: &Path) -> Result<()> {
+    for entry in fs::read_dir(published.join(dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_published(src, published, dst, &path)?;
+        } else if src.join(&path).is_file() {
+            fs::create_dir_all(dst.join(dir))?;
+            fs::copy(src.join(&path), dst.join(&path))?;
+        }
+    }
+    Ok(())
+}
+
+/// The number of files of `src` which aren't in `published`.
+fn count_unpublished(src: &Path, published: &Path, dir: &Path) -> Result<usize> {
+    let mut res = 0;
+    for entry in fs::read_dir(src.join(dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        if path == Path::new(".git") {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            res += count_unpublished(src, published, &path)?;
+        } else if !published.join(&path).exists() {
+            res += 1;
+        }
+    }
+    Ok(res)
+}
@@ -0,0 +1,328 @@
+//! Pins down package specifications like `rand:^0.6` or `rand:prev` to
+//! concrete versions.
+
+use std::path::{Path, PathBuf};
+
+use semver::Version;
+
+use index::{self, Index, IndexEntry};
+use {same_name, Metadata, PackageId, PackageSource, Result, Source, VersionSpec};
+
+#[derive(Debug)]
+pub(crate) struct Resolver {
+    index: Index,
+    include_yanked: bool,
+    offline: bool,
+    /// The manifest of the workspace with the locked versions, that of the
+    /// current directory if `None`.
+    pub(crate) manifest_path: Option<PathBuf>,
+}
+
+impl Resolver {
+    pub(crate) fn new(include_yanked: bool, offline: bool) -> Resolver {
+        Resolver {
+            index: Index::default(),
+            include_yanked,
+            offline,
+            manifest_path: None,
+        }
+    }
+
+    /// The index entry of `pkg_id` if it is a yanked crates.io package. Always
+    /// `None` in offline mode.
+    pub(crate) fn yanked(&mut self, pkg_id: &PackageId) -> Result<Option<IndexEntry>> {
+        let entry = self.published(pkg_id)?.filter(|it| it.yanked).cloned();
+        Ok(entry)
+    }
+
+    /// The index entry of an exact crates.io version. Fails early, with a
+    /// better message than Cargo's, if there's no such crate or version.
+    /// Other registries and offline mode are left to Cargo.
+    pub(crate) fn published(&mut self, pkg_id: &PackageId) -> Result<Option<&IndexEntry>> {
+        let version = match &pkg_id.source {
+            Source::Registry {
+                version: VersionSpec::Exact(version),
+                registry: None,
+                ..
+            } if !self.offline => version,
+            _ => return Ok(None),
+        };
+        let entries = self.index.versions(&pkg_id.name)?;
+        match entries.iter().find(|it| it.version == *version) {
+            Some(entry) => Ok(Some(entry)),
+            None => match index::latest(entries, self.include_yanked) {
+                Some(latest) => bail!(
+                    "crate `{}` has no version {} (latest is {})",
+                    pkg_id.name,
+                    version,
+                    latest.version
+                ),
+                None => bail!("crate `{}` has no version {}", pkg_id.name, version),
+            },
+        }
+    }
+
+    /// Resolves both sides of a diff. A bare name is the locked version on the
+    /// first side, and the latest one on the second. `prev` is counted from the
+    /// other side, so the other side is resolved first.
+    pub(crate) fn resolve_pair(
+        &mut self,
+        first: PackageId,
+        second: PackageId,
+    ) -> Result<(PackageId, PackageId)> {
+        let first = specify(first, VersionSpec::Locked);
+        let second = specify(second, VersionSpec::Latest);
+        if is_prev(&first) && !is_prev(&second) {
+            let second = self.resolve(second, None)?;
+            let first = self.resolve(first, Some(&second))?;
+            Ok((first, second))
+        } else {
+            let first = self.resolve(first, None)?;
+            let second = self.resolve(second, Some(&first))?;
+            Ok((first, second))
+        }
+    }
+
+    /// Resolves `pkg_id` to an exact version. `other` is the already resolved
+    /// package `prev` is relative to; without it, the version locked in the
+    /// current workspace is used.
+    pub(crate) fn resolve(
+        &mut self,
+        pkg_id: PackageId,
+        other: Option<&PackageId>,
+    ) -> Result<PackageId> {
+        let version = match &pkg_id.source {
+            Source::Git { .. } | Source::Path(_) => return Ok(pkg_id),
+            Source::Registry {
+                version: VersionSpec::Exact(_),
+                ..
+            } => return self.canonicalize(pkg_id),
+            Source::Registry {
+                registry: Some(registry),
+                ..
+            } => bail!(
+                "can't resolve `{}` in registry `{}`; specify the exact version",
+                pkg_id,
+                registry
+            ),
+            // The lockfile is available offline.
+            Source::Registry {
+                version: version @ VersionSpec::Locked,
+                ..
+            } => version,
+            Source::Registry { .. } if self.offline => bail!(
+                "can't resolve `{}` in offline mode; specify the exact version",
+                pkg_id
+            ),
+            Source::Registry { version, .. } => version,
+        };
+        let include_yanked = self.include_yanked;
+        let version = match version {
+            VersionSpec::Exact(_) => unreachable!(),
+            VersionSpec::Unspecified | VersionSpec::Latest => {
+                let entries = self.index.versions(&pkg_id.name)?;
+                index::latest(entries, include_yanked)
+                    .ok_or_else(|| format_err!("crate `{}` has no versions", pkg_id.name))?
+                    .version
+                    .clone()
+            }
+            VersionSpec::Locked => {
+                locked_version(&pkg_id.name, self.offline, self.manifest_path.as_deref())?
+            }
+            VersionSpec::Req(req) => {
+                let entries = self.index.versions(&pkg_id.name)?;
+                index::max_matching(entries, req, include_yanked)
+                    .ok_or_else(|| {
+                        format_err!("no version of `{}` matches `{}`", pkg_id.name, req)
+                    })?
+                    .version
+                    .clone()
+            }
+            VersionSpec::Prev => {
+                let base = match other.and_then(|it| exact_version(it, &pkg_id.name)) {
+                    Some(version) => version.clone(),
+                    None => {
+                        locked_version(&pkg_id.name, self.offline, self.manifest_path.as_deref())?
+                    }
+                };
+                let entries = self.index.versions(&pkg_id.name)?;
+                index::previous(entries, &base, include_yanked)
+                    .ok_or_else(|| {
+                        format_err!("no version of `{}` precedes {}", pkg_id.name, base)
+                    })?
+                    .version
+                    .clone()
+            }
+        };
+        let name = if self.offline {
+            pkg_id.name.clone()
+        } else {
+            self.index.canonical_name(&pkg_id.name)?.to_string()
+        };
+        let checksum = match &pkg_id.source {
+            Source::Registry { checksum, .. } => checksum.clone(),
+            _ => None,
+        };
+        let res = PackageId {
+            name,
+            source: Source::Registry {
+                version: VersionSpec::Exact(version),
+                registry: None,
+                checksum,
+            },
+        };
+        eprintln!("Resolved `{}` to {}", pkg_id, res);
+        Ok(res)
+    }
+
+    /// The versions published between `first` and `second`, in the order
+    /// from `first` to `second`. Both must be exact crates.io versions of the
+    /// same crate.
+    pub(crate) fn intermediate(
+        &mut self,
+        first: &PackageId,
+        second: &PackageId,
+    ) -> Result<Vec<PackageId>> {
+        let (a, b) = match (crates_io_version(first), crates_io_version(second)) {
+            (Some(a), Some(b)) if same_name(&first.name, &second.name) => (a, b),
+            _ => bail!(
+                "--all-intermediate needs two crates.io versions of the same crate, got {} and {}",
+                first,
+                second
+            ),
+        };
+        let entries = self.index.versions(&first.name)?;
+        let mut versions: Vec<Version> = index::between(entries, a, b, self.include_yanked)
+            .into_iter()
+            .map(|it| it.version.clone())
+            .collect();
+        if a > b {
+            versions.reverse();
+        }
+        let res = versions
+            .into_iter()
+            .map(|version| PackageId {
+                name: first.name.clone(),
+                source: Source::Registry {
+                    version: VersionSpec::Exact(version),
+                    registry: None,
+                    checksum: None,
+                },
+            })
+            .collect();
+        Ok(res)
+    }
+
+    /// Replaces the name of a crates.io package with the published one.
+    fn canonicalize(&mut self, mut pkg_id: PackageId) -> Result<PackageId> {
+        if self.offline {
+            return Ok(pkg_id);
+        }
+        if let Source::Registry { registry: None, .. } = pkg_id.source {
+            let canonical = self.index.canonical_name(&pkg_id.name)?;
+            if canonical != pkg_id.name {
+                let res = PackageId {
+                    name: canonical.to_string(),
+                    source: pkg_id.source.clone(),
+                };
+                eprintln!("Resolved `{}` to {}", pkg_id, res);
+                pkg_id = res;
+            }
+        }
+        Ok(pkg_id)
+    }
+}
+
+/// Replaces the unspecified version of `pkg_id` with `version`.
+pub(crate) fn specify(mut pkg_id: PackageId, version: VersionSpec) -> PackageId {
+    if let Source::Registry {
+        version: version_spec @ VersionSpec::Unspecified,
+        ..
+    } = &mut pkg_id.source
+    {
+        *version_spec = version;
+    }
+    pkg_id
+}
+
+fn is_prev(pkg_id: &PackageId) -> bool {
+    matches!(
+        pkg_id.source,
+        Source::Registry {
+            version: VersionSpec::Prev,
+            ..
+        }
+    )
+}
+
+fn exact_version<'a>(pkg_id: &'a PackageId, name: &str) -> Option<&'a Version> {
+    match &pkg_id.source {
+        Source::Registry {
+            version: VersionSpec::Exact(version),
+            ..
+        } if pkg_id.name == name => Some(version),
+        _ => None,
+    }
+}
+
+pub(crate) fn crates_io_version(pkg_id: &PackageId) -> Option<&Version> {
+    match &pkg_id.source {
+        Source::Registry {
+            version: VersionSpec::Exact(version),
+            registry: None,
+            ..
+        } => Some(version),
+        _ => None,
+    }
+}
+
+/// The version of `name` in the Cargo.lock of the workspace of
+/// `manifest_path`, or of the current one.
+fn locked_version(name: &str, offline: bool, manifest_path: Option<&Path>) -> Result<Version> {
+    let metadata = Metadata {
+        manifest_path,
+        offline,
+        filter_platforms: &[],
+        features: None,
+        current_dir: None,
+    }
+    .run()
+    .map_err(|err| {
+        format_err!(
+            "can't find the locked version of `{}`, are you inside a Cargo project? \
+             Outside of one, pass both crates to diff, like `{}:OLD {}:NEW`. {}",
+            name,
+            name,
+            name,
+            err
+        )
+    })?;
+    let mut versions = metadata
+        .packages
+        .iter()
+        .filter(|it| same_name(&it.name, name) && PackageSource::of(it) == PackageSource::CratesIo)
+        .map(|it| it.version.clone())
+        .collect::<Vec<_>>();
+    versions.sort();
+    versions.dedup();
+    match versions.len() {
+        0 => bail!(
+            "`{}` is not a dependency of the current workspace; pass both crates to diff, \
+             like `{}:OLD {}:NEW`",
+            name,
+            name,
+            name
+        ),
+        1 => Ok(versions.pop().unwrap()),
+        _ => {
+            let versions: Vec<String> = versions.iter().map(|it| it.to_string()).collect();
+            bail!(
+                "the current workspace depends on several versions of `{}`: {}; specify the version explicitly, like `{}:{}`",
+                name,
+                versions.join(", "),
+                name,
+                versions[0]
+            )
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! `REVIEW.md`, what `current` writes next to `manifest.json` for the
+//! reviewers to start from: a table of the packages checked out, the riskiest
+//! first. Those with a build script or which are proc-macros come first, as
+//! they run at build time, both before either, then the ones with the most
+//! lines of Rust.
+//!
+//! The table is made from the entries of `manifest.json`, which hold the same
+//! data for `--format json`.
+
+use std::{cmp::Reverse, fs, path::Path};
+
+use markdown_report::escape;
+use snapshot::{Entry, LicensePolicy};
+use {packages_word, Result};
+
+pub(crate) const REVIEW_FILE: &str = "REVIEW.md";
+
+pub(crate) fn write(dest: &Path, entries: &[Entry]) -> Result<()> {
+    fs::write(dest.join(REVIEW_FILE), render(entries))?;
+    Ok(())
+}
+
+pub(crate) fn render(entries: &[Entry]) -> String {
+    // The ones `--license-allow` left out aren't there to review, nor the
+    // ones of the snapshot of `--since`.
+    let mut entries: Vec<&Entry> = entries
+        .iter()
+        .filter(|it| {
+            it.license_policy != Some(LicensePolicy::Allowed)
+                && it.snapshot.is_none()
+                && it.failed.is_none()
+        })
+        .collect();
+    entries.sort_by_key(|it| {
+        let runs_at_build = it.build_script as u8 + it.proc_macro as u8;
+        (
+            Reverse(runs_at_build),
+            Reverse(it.loc),
+            &it.name,
+            &it.version,
+        )
+    });
+    let mut res = format!(
+        "# Review index\n\n{}, the riskiest first: the build scripts and the proc-macros, \
+         then the most lines of Rust.\n\n",
+        packages_word(entries.len())
+    );
+    res += "| Package | Version | License | build.rs | proc-macro | Rust files | Lines of Rust | Repository | Description |\n";
+    res += "|---|---|---|---|---|--:|--:|---|---|\n";
+    let yes = |it: bool| if it { "yes" } else { "" };
+    for entry in entries {
+        let repository = match &entry.repository {
+            Some(url) => format!("<{}>", url.replace('>', "%3E")),
+            None => String::new(),
+        };
+        // A cell can't hold line breaks.
+        let description = entry
+            .description
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        res += &format!(
+            "| [{}]({}/) | {} | {} | {} | {} | {} | {} | {} | {} |\n",
+            escape(&entry.name),
+            entry.directory().replace(' ', "%20"),
+            escape(&entry.version),
+            escape(entry.license.as_deref().unwrap_or_default()),
+            yes(entry.build_script),
+            yes(entry.proc_macro),
+            entry.rust_files,
+            entry.loc,
+            repository,
+            escape(&description),
+        );
+    }
+    res
+}
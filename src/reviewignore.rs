@@ -0,0 +1,315 @@
+//! Leaving out the files a project never wants to review, listed in the
+//! `.reviewignore` of the workspace and in `~/.config/cargo-review-deps/ignore`,
+//! with the syntax of `.gitignore`:
+//!
+//! ```text
+//! # Giant generated tables, in any package.
+//! *_tables.rs
+//! # The vendored grammar of one package only.
+//! tree-sitter-foo/src/parser.c
+//! # In any package, but not in foo.
+//! */benches/data/
+//! !foo/benches/data/
+//! ```
+//!
+//! A pattern without a `/`, but at the end, matches the names of files and
+//! directories in every package. Any other pattern starts with the name of
+//! the package it applies to, which may be a glob too, so that ignoring the
+//! files of a package doesn't hide the files at the same paths in another.
+//! The last pattern matching a file decides, and a file in an ignored
+//! directory can't be brought back. The files which decide what a package
+//! does at build time are never left out, like with `--exclude`.
+//!
+//! The `.reviewignore` is looked for in the current directory and its
+//! parents, up to the one with the Cargo.lock of the workspace.
+
+use std::{
+    collections::HashSet,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use glob::{MatchOptions, Pattern};
+
+use filter::{build_files, count_files};
+use {files, tree, Result};
+
+pub(crate) const REVIEWIGNORE: &str = ".reviewignore";
+
+#[derive(Debug, Default)]
+pub(crate) struct ReviewIgnore {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    /// The line as written, for `--verbose`.
+    text: String,
+    /// `file:line`.
+    origin: String,
+    pattern: Pattern,
+    negated: bool,
+    /// The pattern ended with a `/`.
+    dir_only: bool,
+    /// The pattern matches names, rather than `package/path`.
+    by_name: bool,
+}
+
+/// How many files each rule left out.
+#[derive(Debug)]
+pub(crate) struct Counts(Vec<usize>);
+
+impl Counts {
+    /// Adds the `other` counts, of other packages.
+    pub(crate) fn add(&mut self, other: &Counts) {
+        for (count, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *count += other;
+        }
+    }
+}
+
+impl ReviewIgnore {
+    /// Reads the user's ignore file, then the `.reviewignore` of the
+    /// workspace, so that the latter can bring files back.
+    pub(crate) fn load() -> Result<ReviewIgnore> {
+        let mut res = ReviewIgnore::default();
+        let paths = user_file().into_iter().chain(workspace_file());
+        for path in paths {
+            let text = match fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(_) => continue,
+            };
+            for (idx, line) in text.lines().enumerate() {
+                let origin = format!("{}:{}", path.display(), idx + 1);
+                if let Some(rule) = parse(line, origin)? {
+                    res.rules.push(rule);
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub(crate) fn counts(&self) -> Counts {
+        Counts(vec![0; self.rules.len()])
+    }
+
+    /// Copies the files of `package`, checked out to `src`, which aren't
+    /// ignored to `dst`.
+    pub(crate) fn copy(
+        &self,
+        package: &str,
+        src: &Path,
+        dst: &Path,
+        counts: &mut Counts,
+    ) -> Result<()> {
+        fs::create_dir_all(dst)?;
+        self.walk(package, src, counts, &mut |path, ignored| {
+            if !ignored {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dst.join(dir))?;
+                }
+                tree::copy_file(&src.join(path), &dst.join(path))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// The files of `package`, checked out to `root`, which aren't ignored.
+    pub(crate) fn kept(
+        &self,
+        package: &str,
+        root: &Path,
+        counts: &mut Counts,
+    ) -> Result<HashSet<PathBuf>> {
+        let mut res = HashSet::new();
+        self.walk(package, root, counts, &mut |path, ignored| {
+            if !ignored {
+                res.insert(path.to_path_buf());
+            }
+            Ok(())
+        })?;
+        Ok(res)
+    }
+
+    /// Removes the ignored files of `package`, checked out to `dir`.
+    pub(crate) fn remove(&self, package: &str, dir: &Path, counts: &mut Counts) -> Result<()> {
+        self.walk(package, dir, counts, &mut |path, ignored| {
+            if ignored {
+                let path = dir.join(path);
+                if fs::symlink_metadata(&path)?.is_dir() {
+                    fs::remove_dir_all(path)?;
+                } else {
+                    fs::remove_file(path)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Tells how many files were left out, and with `verbose`, by which
+    /// patterns, unless `quiet`.
+    pub(crate) fn report(&self, counts: &Counts, verbose: bool, quiet: bool) {
+        let total: usize = counts.0.iter().sum();
+        if total == 0 {
+            return;
+        }
+        note!(
+            quiet,
+            "Left out {} listed in {} (use --no-reviewignore to include them)",
+            files(total),
+            REVIEWIGNORE
+        );
+        if verbose {
+            for (rule, count) in self.rules.iter().zip(counts.0.iter()) {
+                if *count > 0 && !rule.negated {
+                    note!(quiet, " {} ({}): {}", rule.text, rule.origin, files(*count));
+                }
+            }
+        }
+    }
+
+    /// Calls `visit` with the files of `package` in `root` which aren't
+    /// ignored, and with the ignored files and directories, which it doesn't
+    /// look into.
+    fn walk(
+        &self,
+        package: &str,
+        root: &Path,
+        counts: &mut Counts,
+        visit: &mut dyn FnMut(&Path, bool) -> Result<()>,
+    ) -> Result<()> {
+        let build: Vec<PathBuf> = build_files(root).into_iter().map(|(it, _)| it).collect();
+        let walk = Walk {
+            package,
+            root,
+            build: &build,
+        };
+        self.walk_dir(&walk, Path::new(""), counts, visit)
+    }
+
+    fn walk_dir(
+        &self,
+        walk: &Walk,
+        dir: &Path,
+        counts: &mut Counts,
+        visit: &mut dyn FnMut(&Path, bool) -> Result<()>,
+    ) -> Result<()> {
+        let root = walk.root;
+        let mut names = Vec::new();
+        for entry in fs::read_dir(root.join(dir))? {
+            names.push(entry?.file_name());
+        }
+        names.sort();
+        for name in names {
+            let path = dir.join(name);
+            let is_dir = fs::symlink_metadata(root.join(&path))?.is_dir();
+            let rule = self
+                .ignoring_rule(walk.package, &path, is_dir)
+                .filter(|_| !walk.build.contains(&path));
+            match rule {
+                Some(rule) => {
+                    counts.0[rule] += if is_dir {
+                        count_files(&root.join(&path))?
+                    } else {
+                        1
+                    };
+                    visit(&path, true)?;
+                }
+                None if is_dir => self.walk_dir(walk, &path, counts, visit)?,
+                None => visit(&path, false)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// The rule which ignores `path` of `package`: the last one matching it,
+    /// unless it brings the file back.
+    fn ignoring_rule(&self, package: &str, path: &Path, is_dir: bool) -> Option<usize> {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        let full = Path::new(package).join(path);
+        let name = Path::new(path.file_name().unwrap_or_default());
+        let (idx, rule) = self.rules.iter().enumerate().rev().find(|(_, rule)| {
+            let target = if rule.by_name { name } else { &full };
+            (is_dir || !rule.dir_only) && rule.pattern.matches_path_with(target, options)
+        })?;
+        if rule.negated {
+            None
+        } else {
+            Some(idx)
+        }
+    }
+}
+
+/// What stays the same while walking the directories of a package.
+struct Walk<'a> {
+    package: &'a str,
+    root: &'a Path,
+    /// The build related files, which are never left out.
+    build: &'a [PathBuf],
+}
+
+fn parse(line: &str, origin: String) -> Result<Option<Rule>> {
+    let text = line.trim_end();
+    if text.is_empty() || text.starts_with('#') {
+        return Ok(None);
+    }
+    let (negated, pattern) = match text.strip_prefix('!') {
+        Some(it) => (true, it),
+        None => (false, text.strip_prefix('\\').unwrap_or(text)),
+    };
+    let (dir_only, pattern) = match pattern.strip_suffix('/') {
+        Some(it) => (true, it),
+        None => (false, pattern),
+    };
+    let by_name = !pattern.contains('/');
+    let pattern = pattern.trim_start_matches('/');
+    if !by_name && !pattern.contains('/') {
+        bail!(
+            "`{}` in {} needs the name of a package and a path, like `foo/src/tables.rs`",
+            text,
+            origin
+        );
+    }
+    let pattern = Pattern::new(pattern)
+        .map_err(|err| format_err!("invalid pattern `{}` in {}: {}", text, origin, err))?;
+    Ok(Some(Rule {
+        text: text.to_string(),
+        origin,
+        pattern,
+        negated,
+        dir_only,
+        by_name,
+    }))
+}
+
+/// `$XDG_CONFIG_HOME/cargo-review-deps/ignore`, or in `~/.config`.
+fn user_file() -> Option<PathBuf> {
+    let config = match env::var_os("XDG_CONFIG_HOME").filter(|it| !it.is_empty()) {
+        Some(it) => PathBuf::from(it),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config.join("cargo-review-deps").join("ignore"))
+}
+
+/// The `.reviewignore` in the current directory or the closest of its
+/// parents, which are looked into up to the root of the workspace.
+fn workspace_file() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    for dir in cwd.ancestors() {
+        let path = dir.join(REVIEWIGNORE);
+        if path.is_file() {
+            return Some(path);
+        }
+        if dir.join("Cargo.lock").exists() {
+            break;
+        }
+    }
+    None
+}
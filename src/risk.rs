@@ -0,0 +1,133 @@
+//! A risk score for each diff, from the signals the other passes compute:
+//! what a review of the update should start with, and what `--risk-threshold`
+//! fails on.
+//!
+//! The signals add up to the score with these weights:
+//!
+//! - a changed, added or removed build script or proc-macro entry point: 30,
+//!   they run at build time;
+//! - a crate root which no longer has `#![forbid(unsafe_code)]`: 30;
+//! - each new binary file: 20;
+//! - each new transitive dependency: 10;
+//! - each new `unsafe` function, impl or block: 5;
+//! - each 100 changed lines: 1, up to 20.
+//!
+//! The risk is `low` under 25, `medium` under 60 and `high` from there.
+
+use std::{fmt, path::Path};
+
+use builtin_diff;
+use dep_graph;
+use opaque;
+use risky;
+use unsafe_report;
+use {PackageId, Result, RiskLevel};
+
+const BUILD_TIME_CODE: u32 = 30;
+const FORBID_REMOVED: u32 = 30;
+const NEW_BINARY: u32 = 20;
+const NEW_DEPENDENCY: u32 = 10;
+const NEW_UNSAFE: u32 = 5;
+const LINES_PER_POINT: usize = 100;
+const MAX_LINE_POINTS: u32 = 20;
+
+const MEDIUM: u32 = 25;
+const HIGH: u32 = 60;
+
+/// The risk of the changes between two packages, as in the JSON output.
+#[derive(Debug, Serialize)]
+pub(crate) struct Score {
+    score: u32,
+    level: &'static str,
+    /// What the score comes from, like `build.rs changed`.
+    reasons: Vec<String>,
+    #[serde(skip)]
+    pub(crate) risk: RiskLevel,
+}
+
+/// Scores the changes between `a` and `b`, with the `deps` changes of their
+/// dependencies if Cargo could resolve them, comparing on `jobs` threads.
+pub(crate) fn score(
+    a: &Path,
+    b: &Path,
+    deps: Option<&dep_graph::Changes>,
+    ignore_whitespace: bool,
+    jobs: usize,
+) -> Result<Score> {
+    let mut score = 0;
+    let mut reasons = Vec::new();
+    for (path, kind) in risky::risky_files(a, b) {
+        // The dependencies of the manifest are counted apart.
+        if kind == "manifest" {
+            continue;
+        }
+        let verdict = risky::verdict(a, b, &path, ignore_whitespace);
+        if verdict != "unchanged" && verdict != "formatting only" {
+            score += BUILD_TIME_CODE;
+            reasons.push(format!("{} {}", path.display(), verdict.to_lowercase()));
+        }
+    }
+    let unsafe_code = unsafe_report::report(a, b)?;
+    for root in unsafe_code.forbid_removed() {
+        score += FORBID_REMOVED;
+        reasons.push(format!("{} no longer forbids unsafe code", root));
+    }
+    let added = unsafe_code.added();
+    if added > 0 {
+        score += NEW_UNSAFE * added as u32;
+        reasons.push(format!("+{} unsafe", added));
+    }
+    if let Some(deps) = deps {
+        let added = deps.added().len();
+        if added > 0 {
+            score += NEW_DEPENDENCY * added as u32;
+            reasons.push(plural(added, "new dependency", "new dependencies"));
+        }
+    }
+    let binaries = opaque::find(a, b, jobs)?.added_binaries().len();
+    if binaries > 0 {
+        score += NEW_BINARY * binaries as u32;
+        reasons.push(plural(binaries, "new binary file", "new binary files"));
+    }
+    let totals = builtin_diff::totals(a, b, ignore_whitespace, jobs)?;
+    let lines = totals.insertions + totals.deletions;
+    if lines > 0 {
+        score += ((lines / LINES_PER_POINT) as u32).min(MAX_LINE_POINTS);
+        reasons.push(plural(lines, "line changed", "lines changed"));
+    }
+    let risk = match score {
+        _ if score >= HIGH => RiskLevel::High,
+        _ if score >= MEDIUM => RiskLevel::Medium,
+        _ => RiskLevel::Low,
+    };
+    Ok(Score {
+        score,
+        level: risk.name(),
+        reasons,
+        risk,
+    })
+}
+
+fn plural(n: usize, one: &str, many: &str) -> String {
+    format!("{} {}", n, if n == 1 { one } else { many })
+}
+
+impl Score {
+    /// `foo:1.0.0 -> foo:1.1.0: risk MEDIUM (build.rs changed, +2 unsafe,
+    /// 1 new dependency, 312 lines changed)`.
+    pub(crate) fn line(&self, first: &PackageId, second: &PackageId) -> String {
+        format!("{} -> {}: risk {}", first, second, self)
+    }
+}
+
+/// `MEDIUM (build.rs changed, 312 lines changed)`.
+impl fmt::Display for Score {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let reasons = if self.reasons.is_empty() {
+            "no changes".to_string()
+        } else {
+            self.reasons.join(", ")
+        };
+        write!(fmt, "{} ({})", self.level.to_uppercase(), reasons)
+    }
+}
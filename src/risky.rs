@@ -0,0 +1,100 @@
+//! `diff --prioritize-risky`: the changes of the files which decide what a
+//! crate does at build time, the manifest, the build script and the entry
+//! point of a proc-macro, come before the rest of the tree.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use builtin_diff::{self, ChangedFile};
+use filter::build_files;
+use tree;
+use Result;
+
+/// The build related files of the `a` and `b` trees, with what they are.
+/// Only the files which exist on either side are included.
+pub(crate) fn risky_files(a: &Path, b: &Path) -> Vec<(PathBuf, &'static str)> {
+    let mut res: Vec<(PathBuf, &'static str)> = Vec::new();
+    for (path, kind) in build_files(a).into_iter().chain(build_files(b)) {
+        let exists = a.join(&path).is_file() || b.join(&path).is_file();
+        if exists && !res.iter().any(|(it, _)| *it == path) {
+            res.push((path, kind));
+        }
+    }
+    res
+}
+
+/// Copies the `risky` files of `src` to `risky_dst`, and the rest of them to
+/// `rest_dst`.
+pub(crate) fn split(
+    src: &Path,
+    risky: &[PathBuf],
+    risky_dst: &Path,
+    rest_dst: &Path,
+) -> Result<()> {
+    fs::create_dir_all(risky_dst)?;
+    fs::create_dir_all(rest_dst)?;
+    split_dir(src, Path::new(""), risky, risky_dst, rest_dst)
+}
+
+fn split_dir(
+    src: &Path,
+    dir: &Path,
+    risky: &[PathBuf],
+    risky_dst: &Path,
+    rest_dst: &Path,
+) -> Result<()> {
+    for entry in fs::read_dir(src.join(dir))? {
+        let entry = entry?;
+        if tree::is_git_dir(&entry.file_name()) {
+            continue;
+        }
+        let path = dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            split_dir(src, &path, risky, risky_dst, rest_dst)?;
+            continue;
+        }
+        let dst = if risky.contains(&path) {
+            risky_dst
+        } else {
+            rest_dst
+        };
+        fs::create_dir_all(dst.join(dir))?;
+        tree::copy_file(&entry.path(), &dst.join(&path))?;
+    }
+    Ok(())
+}
+
+/// Prints a line for each of the `risky` files, saying whether it changed
+/// between `a` and `b`.
+pub(crate) fn print_verdicts(
+    a: &Path,
+    b: &Path,
+    risky: &[(PathBuf, &str)],
+    ignore_whitespace: bool,
+) -> Result<()> {
+    for (path, kind) in risky {
+        let verdict = verdict(a, b, path, ignore_whitespace);
+        println!(" {} ({}): {}", path.display(), kind, verdict);
+    }
+    Ok(())
+}
+
+/// How the file at `path` changed between `a` and `b`: `ADDED`, `REMOVED`,
+/// `unchanged`, `formatting only` or `CHANGED`.
+pub(crate) fn verdict(a: &Path, b: &Path, path: &Path, ignore_whitespace: bool) -> &'static str {
+    let file = ChangedFile {
+        path: path.to_path_buf(),
+        old: fs::read(a.join(path)).ok(),
+        new: fs::read(b.join(path)).ok(),
+        renamed: None,
+    };
+    match (&file.old, &file.new) {
+        (None, _) => "ADDED",
+        (_, None) => "REMOVED",
+        (old, new) if old == new => "unchanged",
+        _ if ignore_whitespace && builtin_diff::is_formatting_only(&file) => "formatting only",
+        _ => "CHANGED",
+    }
+}
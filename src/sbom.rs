@@ -0,0 +1,437 @@
+//! `current --sbom`: a software bill of materials of the packages `current`
+//! selects, as CycloneDX 1.5 or SPDX 2.3 JSON, for compliance pipelines. Each
+//! package comes with its purl, its license expression, the checksum of its
+//! `.crate` file in Cargo.lock, and the packages of the bill it depends on.
+//!
+//! The same Cargo.lock always makes the same document, but for the time of
+//! creation SPDX requires, which is `SOURCE_DATE_EPOCH` if it's set.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    env, fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use cargo_metadata;
+use serde_json;
+use sha2::{Digest, Sha256};
+
+use license;
+use verify::Checksums;
+use {PackageSource, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SbomFormat {
+    CycloneDx,
+    Spdx,
+}
+
+/// A package of the bill.
+struct Component<'a> {
+    pkg: &'a cargo_metadata::Package,
+    sha256: Option<&'a str>,
+    /// The ids of the packages of the bill it depends on.
+    depends_on: Vec<&'a str>,
+}
+
+impl<'a> Component<'a> {
+    fn id(&self) -> &'a str {
+        &self.pkg.id.repr
+    }
+
+    fn purl(&self) -> String {
+        format!("pkg:cargo/{}@{}", self.pkg.name, self.pkg.version)
+    }
+}
+
+/// The bill of the `packages` of `meta`, with the `checksums` of its
+/// Cargo.lock, as `format`, to `path`. The root package is the subject of
+/// the bill, if there's one. Tells where it is unless `quiet`.
+pub(crate) fn write(
+    format: SbomFormat,
+    path: &Path,
+    meta: &cargo_metadata::Metadata,
+    packages: &[&cargo_metadata::Package],
+    checksums: &Checksums,
+    quiet: bool,
+) -> Result<()> {
+    let root = meta
+        .resolve
+        .as_ref()
+        .and_then(|it| it.root.as_ref())
+        .and_then(|id| meta.packages.iter().find(|it| it.id == *id));
+    let packages = packages
+        .iter()
+        .cloned()
+        .filter(|it| root.is_none_or(|root| root.id != it.id));
+    let all = components(meta, root.into_iter().chain(packages), checksums);
+    let (root, components) = match root {
+        Some(_) => (all.first(), &all[1..]),
+        None => (None, &all[..]),
+    };
+    let json = match format {
+        SbomFormat::CycloneDx => serde_json::to_string_pretty(&cyclonedx(root, components))?,
+        SbomFormat::Spdx => serde_json::to_string_pretty(&spdx(root, components)?)?,
+    };
+    fs::write(path, json + "\n")?;
+    note!(quiet, "Wrote the SBOM to {}", path.display());
+    Ok(())
+}
+
+fn components<'a>(
+    meta: &'a cargo_metadata::Metadata,
+    packages: impl Iterator<Item = &'a cargo_metadata::Package>,
+    checksums: &'a Checksums,
+) -> Vec<Component<'a>> {
+    let packages: Vec<&cargo_metadata::Package> = packages.collect();
+    let ids: BTreeSet<&str> = packages.iter().map(|it| it.id.repr.as_str()).collect();
+    let mut dependencies: HashMap<&str, Vec<&str>> = HashMap::new();
+    for node in meta.resolve.iter().flat_map(|it| &it.nodes) {
+        let mut deps: Vec<&str> = node
+            .dependencies
+            .iter()
+            .map(|it| it.repr.as_str())
+            .filter(|it| ids.contains(it))
+            .collect();
+        deps.sort();
+        dependencies.insert(&node.id.repr, deps);
+    }
+    packages
+        .into_iter()
+        .map(|pkg| Component {
+            pkg,
+            sha256: checksums.get(pkg),
+            depends_on: dependencies
+                .remove(pkg.id.repr.as_str())
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Bom<'a> {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    bom_format: &'static str,
+    spec_version: &'static str,
+    version: u32,
+    metadata: BomMetadata<'a>,
+    components: Vec<BomComponent<'a>>,
+    dependencies: Vec<BomDependency<'a>>,
+}
+
+#[derive(Serialize)]
+struct BomMetadata<'a> {
+    tools: Vec<Tool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    component: Option<BomComponent<'a>>,
+}
+
+#[derive(Serialize)]
+struct Tool {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize)]
+struct BomComponent<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(rename = "bom-ref")]
+    bom_ref: &'a str,
+    name: &'a str,
+    version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    licenses: Vec<BomLicense<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    hashes: Vec<BomHash<'a>>,
+    purl: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum BomLicense<'a> {
+    Expression(String),
+    /// A `license` field which isn't an SPDX expression.
+    License {
+        name: &'a str,
+    },
+}
+
+#[derive(Serialize)]
+struct BomHash<'a> {
+    alg: &'static str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BomDependency<'a> {
+    #[serde(rename = "ref")]
+    bom_ref: &'a str,
+    depends_on: &'a [&'a str],
+}
+
+fn cyclonedx<'a>(root: Option<&'a Component<'a>>, components: &'a [Component<'a>]) -> Bom<'a> {
+    let component = |it: &'a Component, kind: &'static str| BomComponent {
+        kind,
+        bom_ref: it.id(),
+        name: &it.pkg.name,
+        version: it.pkg.version.to_string(),
+        description: it.pkg.description.as_deref(),
+        licenses: it
+            .pkg
+            .license
+            .as_deref()
+            .map(|license| match license::spdx_expression(license) {
+                Some(expression) => BomLicense::Expression(expression),
+                None => BomLicense::License { name: license },
+            })
+            .into_iter()
+            .collect(),
+        hashes: it
+            .sha256
+            .map(|content| BomHash {
+                alg: "SHA-256",
+                content,
+            })
+            .into_iter()
+            .collect(),
+        purl: it.purl(),
+    };
+    Bom {
+        schema: "http://cyclonedx.org/schema/bom-1.5.schema.json",
+        bom_format: "CycloneDX",
+        spec_version: "1.5",
+        version: 1,
+        metadata: BomMetadata {
+            tools: vec![Tool {
+                name: env!("CARGO_PKG_NAME"),
+                version: env!("CARGO_PKG_VERSION"),
+            }],
+            component: root.map(|it| component(it, "application")),
+        },
+        components: components
+            .iter()
+            .map(|it| component(it, "library"))
+            .collect(),
+        dependencies: root
+            .into_iter()
+            .chain(components)
+            .map(|it| BomDependency {
+                bom_ref: it.id(),
+                depends_on: &it.depends_on,
+            })
+            .collect(),
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpdxDocument<'a> {
+    spdx_version: &'static str,
+    data_license: &'static str,
+    #[serde(rename = "SPDXID")]
+    spdx_id: &'static str,
+    name: &'a str,
+    document_namespace: String,
+    creation_info: CreationInfo,
+    packages: Vec<SpdxPackage<'a>>,
+    relationships: Vec<Relationship>,
+}
+
+#[derive(Serialize)]
+struct CreationInfo {
+    created: String,
+    creators: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpdxPackage<'a> {
+    #[serde(rename = "SPDXID")]
+    spdx_id: String,
+    name: &'a str,
+    version_info: String,
+    download_location: String,
+    files_analyzed: bool,
+    license_concluded: &'static str,
+    license_declared: String,
+    copyright_text: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    checksums: Vec<SpdxChecksum<'a>>,
+    external_refs: Vec<ExternalRef>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpdxChecksum<'a> {
+    algorithm: &'static str,
+    checksum_value: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExternalRef {
+    reference_category: &'static str,
+    reference_type: &'static str,
+    reference_locator: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Relationship {
+    spdx_element_id: String,
+    relationship_type: &'static str,
+    related_spdx_element: String,
+}
+
+const NOASSERTION: &str = "NOASSERTION";
+
+fn spdx<'a>(
+    root: Option<&'a Component<'a>>,
+    components: &'a [Component<'a>],
+) -> Result<SpdxDocument<'a>> {
+    let all: Vec<&Component> = root.into_iter().chain(components).collect();
+    // SPDX ids only have letters, digits, `.` and `-`, and the index keeps
+    // them unique.
+    let spdx_ids: HashMap<&str, String> = all
+        .iter()
+        .enumerate()
+        .map(|(idx, it)| {
+            let id: String = format!("{}-{}-{}", idx, it.pkg.name, it.pkg.version)
+                .chars()
+                .map(|c| match c {
+                    'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' => c,
+                    _ => '-',
+                })
+                .collect();
+            (it.id(), format!("SPDXRef-Package-{}", id))
+        })
+        .collect();
+    let packages = all
+        .iter()
+        .map(|it| SpdxPackage {
+            spdx_id: spdx_ids[it.id()].clone(),
+            name: &it.pkg.name,
+            version_info: it.pkg.version.to_string(),
+            download_location: match PackageSource::of(it.pkg) {
+                PackageSource::CratesIo => format!(
+                    "https://crates.io/api/v1/crates/{}/{}/download",
+                    it.pkg.name, it.pkg.version
+                ),
+                _ => NOASSERTION.to_string(),
+            },
+            files_analyzed: false,
+            license_concluded: NOASSERTION,
+            license_declared: it
+                .pkg
+                .license
+                .as_deref()
+                .and_then(license::spdx_expression)
+                .unwrap_or_else(|| NOASSERTION.to_string()),
+            copyright_text: NOASSERTION,
+            checksums: it
+                .sha256
+                .map(|checksum_value| SpdxChecksum {
+                    algorithm: "SHA256",
+                    checksum_value,
+                })
+                .into_iter()
+                .collect(),
+            external_refs: vec![ExternalRef {
+                reference_category: "PACKAGE-MANAGER",
+                reference_type: "purl",
+                reference_locator: it.purl(),
+            }],
+        })
+        .collect();
+    // The document describes the root package, or all of them if there's
+    // none, as in a virtual workspace.
+    let described: Vec<&Component> = match root {
+        Some(root) => vec![root],
+        None => all.clone(),
+    };
+    let mut relationships: Vec<Relationship> = described
+        .iter()
+        .map(|it| Relationship {
+            spdx_element_id: "SPDXRef-DOCUMENT".to_string(),
+            relationship_type: "DESCRIBES",
+            related_spdx_element: spdx_ids[it.id()].clone(),
+        })
+        .collect();
+    for it in all.iter() {
+        for dependency in it.depends_on.iter() {
+            relationships.push(Relationship {
+                spdx_element_id: spdx_ids[it.id()].clone(),
+                relationship_type: "DEPENDS_ON",
+                related_spdx_element: spdx_ids[dependency].clone(),
+            });
+        }
+    }
+    let name = root.map_or("dependencies", |it| it.pkg.name.as_str());
+    // The same packages always get the same namespace.
+    let mut hasher = Sha256::new();
+    for it in all.iter() {
+        hasher.update(it.id().as_bytes());
+        hasher.update([0]);
+    }
+    Ok(SpdxDocument {
+        spdx_version: "SPDX-2.3",
+        data_license: "CC0-1.0",
+        spdx_id: "SPDXRef-DOCUMENT",
+        name,
+        document_namespace: format!("https://spdx.org/spdxdocs/{}-{:x}", name, hasher.finalize()),
+        creation_info: CreationInfo {
+            created: timestamp(created()?),
+            creators: vec![format!(
+                "Tool: {}-{}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            )],
+        },
+        packages,
+        relationships,
+    })
+}
+
+/// When the document is made, in seconds since the epoch.
+fn created() -> Result<u64> {
+    if let Some(epoch) = env::var_os("SOURCE_DATE_EPOCH") {
+        let epoch = epoch.to_string_lossy();
+        return epoch
+            .trim()
+            .parse()
+            .map_err(|_| format_err!("SOURCE_DATE_EPOCH isn't a number of seconds: {}", epoch));
+    }
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+/// `secs` since the epoch, like `2024-06-01T12:00:00Z`.
+fn timestamp(secs: u64) -> String {
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    // From the days since 1970-01-01 to the civil date, as in
+    // http://howardhinnant.github.io/date_algorithms.html.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
@@ -0,0 +1,96 @@
+//! `diff --scan`: a quick pass over the added lines, which keeps only the
+//! hunks adding code that runs commands, opens connections, reads the
+//! environment, embeds files or runs before `main`, and what looks like an
+//! encoded blob. It is a heuristic to know where to look first, not a review.
+
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use regex::Regex;
+use similar::{group_diff_ops, DiffTag};
+
+use builtin_diff::{self, DiffOptions};
+use {color, Result};
+
+/// The patterns `--scan` looks for, before those of `--scan-pattern`.
+const DEFAULT_PATTERNS: &[&str] = &[
+    r"std::process|process::Command|Command::new",
+    r"std::net|TcpStream|UdpSocket",
+    r"\b(reqwest|ureq|hyper|curl)\b",
+    r"env::var",
+    r"include_bytes!|include_str!",
+    r"#\[ctor\]|ctor::ctor|link_section",
+    // Base64-looking blobs.
+    r"[A-Za-z0-9+/]{60,}={0,2}",
+];
+
+/// Compiles the default patterns and those of `--scan-pattern`.
+pub(crate) fn patterns(extra: &[String]) -> Result<Vec<Regex>> {
+    let mut res: Vec<Regex> = DEFAULT_PATTERNS
+        .iter()
+        .map(|it| Regex::new(it).expect("the default patterns are valid"))
+        .collect();
+    for pattern in extra {
+        res.push(
+            Regex::new(pattern)
+                .map_err(|err| format_err!("invalid --scan-pattern `{}`: {}", pattern, err))?,
+        );
+    }
+    Ok(res)
+}
+
+/// Prints the hunks of the diff of the `a` and `b` directories whose added
+/// lines match one of `patterns`, with the path of the file and the patterns
+/// which matched above each. Returns whether anything matched.
+pub(crate) fn print_matches(
+    a: &Path,
+    b: &Path,
+    patterns: &[Regex],
+    options: DiffOptions,
+) -> Result<bool> {
+    let color = color::stdout();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let options = DiffOptions {
+        color_words: false,
+        ..options
+    };
+    let mut matched = false;
+    for file in builtin_diff::changed_files(a, b, options.jobs)? {
+        let old = file.old.as_deref().unwrap_or_default();
+        let new = file.new.as_deref().unwrap_or_default();
+        let (old, new) = match (builtin_diff::as_text(old), builtin_diff::as_text(new)) {
+            (Some(old), Some(new)) => (old, new),
+            _ => continue,
+        };
+        let diff = builtin_diff::line_diff(old, new, options.ignore_whitespace);
+        for group in group_diff_ops(diff.ops.clone(), options.context) {
+            let added: Vec<&str> = group
+                .iter()
+                .filter(|op| op.tag() != DiffTag::Equal)
+                .flat_map(|op| diff.new[op.new_range()].iter().cloned())
+                .collect();
+            let hits: Vec<String> = patterns
+                .iter()
+                .filter(|pattern| added.iter().any(|line| pattern.is_match(line)))
+                .map(|pattern| format!("`{}`", pattern.as_str()))
+                .collect();
+            if hits.is_empty() {
+                continue;
+            }
+            if matched {
+                writeln!(out)?;
+            }
+            matched = true;
+            let title = format!("{}: {}", file.path.display(), hits.join(", "));
+            builtin_diff::paint(&mut out, color, builtin_diff::BOLD, &title)?;
+            builtin_diff::print_hunk(&mut out, color, &diff, &group, options)?;
+        }
+    }
+    if !matched {
+        writeln!(out, "No suspicious additions matched")?;
+    }
+    Ok(matched)
+}
@@ -0,0 +1,308 @@
+//! Two-column rendering of the builtin diff, like `diff -y`, with line
+//! numbers on both sides and the changed parts of the lines highlighted.
+//!
+//! Long lines are wrapped at the column width, and tabs are expanded, so the
+//! columns always line up. Without colors, the gutter between the columns
+//! shows the kind of change: `|` for a changed line, `<` for a deleted one and
+//! `>` for an inserted one.
+
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use similar::{group_diff_ops, ChangeTag, DiffOp, DiffTag, TextDiff};
+use terminal_size::Width;
+
+use builtin_diff::{self, ChangedFile, DiffOptions};
+use truncate::Limited;
+use {color, generated, pager, Result};
+
+/// The width used when stdout isn't a terminal, the same as `diff -y`.
+const DEFAULT_WIDTH: usize = 130;
+/// The narrowest output which still leaves room for the text.
+pub(crate) const MIN_WIDTH: usize = 20;
+const NUMBER_WIDTH: usize = 5;
+const TAB_WIDTH: usize = 4;
+
+const BOLD: &str = "1";
+const CYAN: &str = "36";
+const RED: &str = "31";
+const GREEN: &str = "32";
+/// Changed spans are shown in reverse video on top of the line color.
+const EMPHASIS: &str = "7";
+
+/// The width of the terminal on stdout, if there's one, even if it is paged.
+pub(crate) fn detect_width() -> usize {
+    match pager::terminal_size() {
+        Some((Width(width), _)) => width as usize,
+        None => DEFAULT_WIDTH,
+    }
+    .max(MIN_WIDTH)
+}
+
+/// Prints the diff of the `a` and `b` directories in two columns, fitting in
+/// `width` characters.
+pub(crate) fn print_diff(
+    a: &Path,
+    a_label: &str,
+    b: &Path,
+    b_label: &str,
+    options: DiffOptions,
+    width: usize,
+) -> Result<bool> {
+    let color = color::stdout();
+    let stdout = io::stdout();
+    let mut out = Columns {
+        out: Limited {
+            out: stdout.lock(),
+            truncation: options.truncation,
+        },
+        color,
+        // Two numbers, the gutter and the texts.
+        text_width: (width.max(MIN_WIDTH) - 2 * NUMBER_WIDTH - 3) / 2,
+    };
+    let mut differ = false;
+    let files = builtin_diff::changed_files(a, b, options.jobs)?;
+    for (idx, file) in files.iter().enumerate() {
+        if options.truncation.is_full() {
+            let rest = &files[idx..];
+            options.truncation.hide(rest.len());
+            differ |= rest.iter().any(|it| builtin_diff::differs(it, options));
+            break;
+        }
+        let name = |label: &str, contents: &Option<Vec<u8>>| match contents {
+            Some(_) => format!("{}/{}", label, file.path.display()),
+            None => "/dev/null".to_string(),
+        };
+        let old_name = name(a_label, &file.old);
+        let new_name = name(b_label, &file.new);
+        out.header(&old_name, &new_name)?;
+        if options.ignore_whitespace && builtin_diff::is_formatting_only(file) {
+            out.line("", "Formatting-only changes")?;
+            continue;
+        }
+        differ = true;
+        print_file(&mut out, file, options)?;
+    }
+    Ok(differ)
+}
+
+fn print_file(
+    out: &mut Columns<impl Write>,
+    file: &ChangedFile,
+    options: DiffOptions,
+) -> Result<()> {
+    let old = file.old.as_deref().unwrap_or_default();
+    let new = file.new.as_deref().unwrap_or_default();
+    let (old, new) = match (builtin_diff::as_text(old), builtin_diff::as_text(new)) {
+        (Some(_), Some(_))
+            if options.collapse_generated && generated::is_generated(file, options.generated) =>
+        {
+            let name = file.path.display().to_string();
+            return out.line(
+                "",
+                &generated::summary(file, &name, options.ignore_whitespace),
+            );
+        }
+        (Some(old), Some(new)) => (old, new),
+        _ => {
+            let summary = format!("Binary file, {} -> {} bytes", old.len(), new.len());
+            return out.line("", &summary);
+        }
+    };
+    let lines = builtin_diff::line_diff(old, new, options.ignore_whitespace);
+    // The changed spans of lines are only highlighted if whitespace counts.
+    let text_diff = if options.ignore_whitespace {
+        None
+    } else {
+        Some(TextDiff::from_lines(old, new))
+    };
+    let plain = |line: &str| vec![(false, line.to_string())];
+    for group in group_diff_ops(lines.ops.clone(), options.context) {
+        out.line(CYAN, &hunk_header(&group))?;
+        for op in group.iter() {
+            let mut deleted = Vec::new();
+            let mut inserted = Vec::new();
+            if let Some(text_diff) = &text_diff {
+                for change in text_diff.iter_inline_changes(op) {
+                    let number = change.old_index().or(change.new_index()).unwrap_or(0) + 1;
+                    let spans: Vec<(bool, String)> = change
+                        .iter_strings_lossy()
+                        .map(|(emphasized, text)| (emphasized, text.into_owned()))
+                        .collect();
+                    match change.tag() {
+                        ChangeTag::Equal => {
+                            let new_number = change.new_index().unwrap_or(0) + 1;
+                            out.row(Some((number, &spans)), Some((new_number, &spans)), ' ')?;
+                        }
+                        ChangeTag::Delete => deleted.push((number, spans)),
+                        ChangeTag::Insert => inserted.push((number, spans)),
+                    }
+                }
+            } else {
+                let (tag, old_range, new_range) = op.as_tag_tuple();
+                if tag == DiffTag::Equal {
+                    for (i, j) in old_range.zip(new_range) {
+                        let (old, new) = (plain(lines.old[i]), plain(lines.new[j]));
+                        out.row(Some((i + 1, &old)), Some((j + 1, &new)), ' ')?;
+                    }
+                    continue;
+                }
+                deleted.extend(old_range.map(|i| (i + 1, plain(lines.old[i]))));
+                inserted.extend(new_range.map(|j| (j + 1, plain(lines.new[j]))));
+            }
+            // Deleted and inserted lines of a replacement are shown next to
+            // each other.
+            for i in 0..deleted.len().max(inserted.len()) {
+                let old = deleted.get(i).map(|(n, spans)| (*n, spans.as_slice()));
+                let new = inserted.get(i).map(|(n, spans)| (*n, spans.as_slice()));
+                let gutter = match (old, new) {
+                    (Some(_), Some(_)) => '|',
+                    (Some(_), None) => '<',
+                    _ => '>',
+                };
+                out.row(old, new, gutter)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn hunk_header(group: &[DiffOp]) -> String {
+    let (first, last) = (&group[0], &group[group.len() - 1]);
+    let old = first.old_range().start..last.old_range().end;
+    let new = first.new_range().start..last.new_range().end;
+    format!(
+        "@@ -{},{} +{},{} @@",
+        old.start + 1,
+        old.len(),
+        new.start + 1,
+        new.len()
+    )
+}
+
+type Side<'a> = Option<(usize, &'a [(bool, String)])>;
+
+struct Columns<W> {
+    out: W,
+    color: bool,
+    /// The width of the text of each column, without the line number.
+    text_width: usize,
+}
+
+impl<W: Write> Columns<W> {
+    fn header(&mut self, old_name: &str, new_name: &str) -> Result<()> {
+        let old = wrap(
+            &[(false, old_name.to_string())],
+            NUMBER_WIDTH + self.text_width,
+        );
+        let new = wrap(
+            &[(false, new_name.to_string())],
+            NUMBER_WIDTH + self.text_width,
+        );
+        for i in 0..old.len().max(new.len()) {
+            let old = old.get(i).map(Vec::as_slice).unwrap_or_default();
+            let new = new.get(i).map(Vec::as_slice).unwrap_or_default();
+            let width = NUMBER_WIDTH + self.text_width;
+            self.spans(old, BOLD, width)?;
+            write!(self.out, "   ")?;
+            self.spans(new, BOLD, 0)?;
+            writeln!(self.out)?;
+        }
+        Ok(())
+    }
+
+    /// A line spanning both columns.
+    fn line(&mut self, style: &str, text: &str) -> Result<()> {
+        if self.color && !style.is_empty() {
+            writeln!(self.out, "\x1b[{}m{}\x1b[0m", style, text)?;
+        } else {
+            writeln!(self.out, "{}", text)?;
+        }
+        Ok(())
+    }
+
+    /// A line of the old file next to a line of the new one. Long lines take
+    /// several rows.
+    fn row(&mut self, old: Side, new: Side, gutter: char) -> Result<()> {
+        let text_width = self.text_width;
+        let wrapped = |side: Side| {
+            side.map(|(_, spans)| wrap(spans, text_width))
+                .unwrap_or_default()
+        };
+        let old_rows = wrapped(old);
+        let new_rows = wrapped(new);
+        let (old_style, new_style) = if gutter == ' ' {
+            ("", "")
+        } else {
+            (RED, GREEN)
+        };
+        for i in 0..old_rows.len().max(new_rows.len()).max(1) {
+            let number = |side: Side| match side {
+                Some((number, _)) if i == 0 => number.to_string(),
+                _ => String::new(),
+            };
+            let gutter = if i == 0 { gutter } else { ' ' };
+            write!(self.out, "{:>w$} ", number(old), w = NUMBER_WIDTH - 1)?;
+            let text = old_rows.get(i).map(Vec::as_slice).unwrap_or_default();
+            self.spans(text, old_style, self.text_width)?;
+            write!(self.out, " {} ", gutter)?;
+            write!(self.out, "{:>w$} ", number(new), w = NUMBER_WIDTH - 1)?;
+            let text = new_rows.get(i).map(Vec::as_slice).unwrap_or_default();
+            self.spans(text, new_style, 0)?;
+            writeln!(self.out)?;
+        }
+        Ok(())
+    }
+
+    /// Writes styled spans, padded with spaces to `width` characters.
+    fn spans(&mut self, spans: &[(bool, String)], style: &str, width: usize) -> Result<()> {
+        let mut len = 0;
+        for (emphasized, text) in spans {
+            len += text.chars().count();
+            match (self.color, style.is_empty(), emphasized) {
+                (false, _, _) | (true, true, false) => write!(self.out, "{}", text)?,
+                (true, true, true) => write!(self.out, "\x1b[{}m{}\x1b[0m", EMPHASIS, text)?,
+                (true, false, false) => write!(self.out, "\x1b[{}m{}\x1b[0m", style, text)?,
+                (true, false, true) => {
+                    write!(self.out, "\x1b[{};{}m{}\x1b[0m", style, EMPHASIS, text)?
+                }
+            }
+        }
+        if len < width {
+            write!(self.out, "{}", " ".repeat(width - len))?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits a line into rows of at most `width` characters, keeping the
+/// emphasis of the spans. Tabs are expanded and line breaks are dropped.
+fn wrap(spans: &[(bool, String)], width: usize) -> Vec<Vec<(bool, String)>> {
+    let mut rows: Vec<Vec<(bool, String)>> = vec![Vec::new()];
+    let mut row_len = 0;
+    for (emphasized, text) in spans {
+        for c in text.chars() {
+            let expanded = match c {
+                '\n' | '\r' => continue,
+                '\t' => " ".repeat(TAB_WIDTH - row_len % TAB_WIDTH),
+                c if c.is_control() => c.escape_default().to_string(),
+                c => c.to_string(),
+            };
+            for c in expanded.chars() {
+                if row_len == width {
+                    rows.push(Vec::new());
+                    row_len = 0;
+                }
+                let row = rows.last_mut().unwrap();
+                match row.last_mut() {
+                    Some((last_emphasized, last)) if last_emphasized == emphasized => last.push(c),
+                    _ => row.push((*emphasized, c.to_string())),
+                }
+                row_len += 1;
+            }
+        }
+    }
+    rows
+}
@@ -0,0 +1,139 @@
+//! The size of both versions, for reviewers to budget their time: the files,
+//! the bytes and the lines of Rust code. The code in other languages, like a
+//! vendored C library, is counted apart, so that it doesn't pass for Rust.
+
+use std::{fs, path::Path};
+
+use tree;
+use unsafe_report;
+use Result;
+
+/// Extensions of source files in languages other than Rust.
+const OTHER_CODE: &[&str] = &[
+    "c", "h", "cc", "cpp", "cxx", "hh", "hpp", "hxx", "s", "S", "asm", "m", "mm", "go", "py", "js",
+    "ts", "java", "f", "f90", "cu", "zig",
+];
+
+/// The size of a tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct Size {
+    files: usize,
+    bytes: u64,
+    rust_files: usize,
+    /// Lines with code, without the blank lines and the comments.
+    rust_lines: usize,
+    other_code_files: usize,
+    /// Lines which aren't blank.
+    other_code_lines: usize,
+}
+
+/// The sizes of two versions, as in the JSON output.
+#[derive(Debug, Serialize)]
+pub(crate) struct Delta {
+    before: Size,
+    after: Size,
+}
+
+pub(crate) fn compare(a: &Path, b: &Path) -> Result<Delta> {
+    Ok(Delta {
+        before: measure(a)?,
+        after: measure(b)?,
+    })
+}
+
+fn measure(root: &Path) -> Result<Size> {
+    let mut res = Size::default();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if !tree::is_git_dir(&entry.file_name()) {
+                    dirs.push(entry.path());
+                }
+                continue;
+            }
+            res.files += 1;
+            if !file_type.is_file() {
+                continue;
+            }
+            let path = entry.path();
+            res.bytes += entry.metadata()?.len();
+            let extension = path
+                .extension()
+                .map(|it| it.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if extension == "rs" {
+                res.rust_files += 1;
+                if let Ok(text) = fs::read_to_string(&path) {
+                    res.rust_lines += unsafe_report::code_lines(&text);
+                }
+            } else if OTHER_CODE.contains(&extension.as_str()) {
+                res.other_code_files += 1;
+                if let Ok(text) = fs::read_to_string(&path) {
+                    res.other_code_lines += text.lines().filter(|it| !it.trim().is_empty()).count();
+                }
+            }
+        }
+    }
+    Ok(res)
+}
+
+impl Delta {
+    /// Rows of what is measured, with the numbers before and after. The
+    /// code in other languages is only there if there is any.
+    pub(crate) fn rows(&self) -> Vec<(&'static str, u64, u64)> {
+        let (before, after) = (self.before, self.after);
+        let mut res = vec![
+            ("files", before.files as u64, after.files as u64),
+            ("bytes", before.bytes, after.bytes),
+            (
+                "Rust files",
+                before.rust_files as u64,
+                after.rust_files as u64,
+            ),
+            (
+                "lines of Rust",
+                before.rust_lines as u64,
+                after.rust_lines as u64,
+            ),
+        ];
+        if before.other_code_files > 0 || after.other_code_files > 0 {
+            res.push((
+                "files of other code",
+                before.other_code_files as u64,
+                after.other_code_files as u64,
+            ));
+            res.push((
+                "lines of other code",
+                before.other_code_lines as u64,
+                after.other_code_lines as u64,
+            ));
+        }
+        res
+    }
+
+    /// Prints the sizes to stderr, with `title` above them.
+    pub(crate) fn print(&self, title: &str) {
+        eprintln!("{}", title);
+        for (what, before, after) in self.rows() {
+            eprintln!(
+                " {}: {} -> {} ({})",
+                what,
+                before,
+                after,
+                change(before, after)
+            );
+        }
+    }
+}
+
+/// The difference between two sizes, like `+12` or `-3`.
+pub(crate) fn change(before: u64, after: u64) -> String {
+    if after >= before {
+        format!("+{}", after - before)
+    } else {
+        format!("-{}", before - after)
+    }
+}
@@ -0,0 +1,421 @@
+//! What `current` writes next to the packages it checks out: `manifest.json`,
+//! an index of them, and `snapshot.toml`, the build configuration they are
+//! the dependencies of, unless it's the default one.
+//!
+//! Each package of the index comes with the SHA-256 of its tree, so that two
+//! snapshots tell which packages changed without comparing them, and so that
+//! `current` can leave the packages which are checked out already, unchanged,
+//! as they are. The files
+//! are hashed in the order of their paths, each with its path and its size
+//! before its contents, so that renaming a file changes the hash too; a
+//! symlink is hashed as its target.
+//!
+//! Each package also comes with the chains of dependencies which pull it in,
+//! in the index and in a `PROVENANCE` file in its directory, which isn't
+//! part of its tree, unless the package ships one of its own.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use cargo_metadata;
+use serde_json;
+use sha2::{Digest, Sha256};
+use toml;
+
+use verify::Verification;
+use {Features, LinkMode, Result};
+
+/// The index of the packages, in the destination.
+pub(crate) const MANIFEST_FILE: &str = "manifest.json";
+
+/// The features and the targets, in the destination.
+pub(crate) const CONFIG_FILE: &str = "snapshot.toml";
+
+/// Why a package is there, in its directory.
+pub(crate) const PROVENANCE_FILE: &str = "PROVENANCE";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    pub(crate) packages: Vec<Entry>,
+}
+
+/// A package checked out to the destination.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Entry {
+    pub(crate) name: String,
+    pub(crate) version: String,
+    /// Where Cargo got it, like `registry+https://github.com/rust-lang/crates.io-index`.
+    source: Option<String>,
+    pub(crate) license: Option<String>,
+    pub(crate) repository: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) description: Option<String>,
+    /// The manifest of the package it was copied from.
+    manifest_path: String,
+    /// The directory it was copied to, relative to the destination.
+    directory: String,
+    files: usize,
+    pub(crate) bytes: u64,
+    sha256: String,
+    /// Its `.rs` files, and their lines which aren't blank.
+    #[serde(default)]
+    pub(crate) rust_files: usize,
+    #[serde(default)]
+    pub(crate) loc: usize,
+    /// Whether it has code which runs at build time.
+    #[serde(default)]
+    pub(crate) build_script: bool,
+    #[serde(default)]
+    pub(crate) proc_macro: bool,
+    /// How its sources were checked, see `verify`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) verification: Option<Verification>,
+    /// Whether its files are linked to the cache of Cargo, see `LinkMode`.
+    #[serde(default)]
+    pub(crate) link_mode: LinkMode,
+    /// The files `--exclude` and `--max-file-size` left out of it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) skipped_files: Vec<SkippedFile>,
+    /// With `--license-allow`, whether it was left out for its license.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) license_policy: Option<LicensePolicy>,
+    /// The snapshot it's in, relative to this one, if `--since` found it
+    /// there in the same version and didn't copy it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) snapshot: Option<String>,
+    /// Why it couldn't be checked out: what's in its directory, if
+    /// anything, isn't a copy of it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) failed: Option<String>,
+    /// The chains of dependencies which pull it in, like
+    /// `app -> rand -> rand_core`, at most three of them, the shortest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) provenance: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum LicensePolicy {
+    /// Not checked out, `--license-allow` allows its license: it has no
+    /// files.
+    Allowed,
+    /// Checked out for legal to review its license.
+    Review,
+}
+
+/// A file left out of a package checked out, see `filter::Omit`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SkippedFile {
+    /// Its path, relative to the root of the package.
+    path: String,
+    size: u64,
+    reason: String,
+}
+
+impl SkippedFile {
+    pub(crate) fn new(path: &Path, size: u64, reason: String) -> SkippedFile {
+        SkippedFile {
+            path: path.to_string_lossy().replace('\\', "/"),
+            size,
+            reason,
+        }
+    }
+
+    pub(crate) fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// The hash of a tree, from its files in the order of their paths.
+#[derive(Default)]
+pub(crate) struct TreeHash {
+    hasher: Sha256,
+    files: usize,
+    bytes: u64,
+    rust_files: usize,
+    loc: usize,
+}
+
+impl TreeHash {
+    /// Adds the file at `path`, relative to the root, with `contents`, or
+    /// the symlink to the target `contents`.
+    pub(crate) fn add(&mut self, path: &Path, contents: &[u8], is_symlink: bool) {
+        let path = path.to_string_lossy().replace('\\', "/");
+        self.hasher.update(path.as_bytes());
+        self.hasher.update([0]);
+        self.hasher.update((contents.len() as u64).to_le_bytes());
+        self.hasher.update(contents);
+        self.files += 1;
+        if !is_symlink {
+            self.bytes += contents.len() as u64;
+        }
+        if !is_symlink && path.ends_with(".rs") {
+            self.rust_files += 1;
+            let lines = contents.split(|&it| it == b'\n');
+            self.loc += lines.filter(|it| !it.trim_ascii().is_empty()).count();
+        }
+    }
+}
+
+impl Entry {
+    /// The entry of `pkg`, checked out to `dir`.
+    pub(crate) fn new(pkg: &cargo_metadata::Package, dir: &Path) -> Result<Entry> {
+        let mut tree = TreeHash::default();
+        let ships_provenance = ships_provenance(pkg);
+        for path in tree_files(dir)? {
+            if path == Path::new(PROVENANCE_FILE) && !ships_provenance {
+                continue;
+            }
+            let full_path = dir.join(&path);
+            let file_type = fs::symlink_metadata(&full_path)?.file_type();
+            let contents = if file_type.is_symlink() {
+                fs::read_link(&full_path)?
+                    .to_string_lossy()
+                    .into_owned()
+                    .into_bytes()
+            } else {
+                fs::read(&full_path)?
+            };
+            tree.add(&path, &contents, file_type.is_symlink());
+        }
+        let directory = dir
+            .file_name()
+            .map(|it| it.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(Entry::with_tree(pkg, directory, tree))
+    }
+
+    /// The entry of `pkg`, in `directory`, with the files of `tree`.
+    pub(crate) fn with_tree(
+        pkg: &cargo_metadata::Package,
+        directory: String,
+        tree: TreeHash,
+    ) -> Entry {
+        Entry {
+            name: pkg.name.clone(),
+            version: pkg.version.to_string(),
+            source: id_source(&pkg.id.repr).map(String::from),
+            license: pkg.license.clone(),
+            repository: pkg.repository.clone(),
+            description: pkg.description.clone(),
+            manifest_path: pkg.manifest_path.to_string_lossy().into_owned(),
+            directory,
+            files: tree.files,
+            bytes: tree.bytes,
+            sha256: format!("{:x}", tree.hasher.finalize()),
+            rust_files: tree.rust_files,
+            loc: tree.loc,
+            build_script: has_target(pkg, "custom-build"),
+            proc_macro: has_target(pkg, "proc-macro"),
+            verification: None,
+            skipped_files: Vec::new(),
+            link_mode: LinkMode::Copy,
+            license_policy: None,
+            snapshot: None,
+            failed: None,
+            provenance: Vec::new(),
+        }
+    }
+
+    /// The entry of `pkg`, left out of the `directory` for its license.
+    pub(crate) fn allowed(pkg: &cargo_metadata::Package, directory: String) -> Entry {
+        let mut res = Entry::with_tree(pkg, directory, TreeHash::default());
+        res.license_policy = Some(LicensePolicy::Allowed);
+        res
+    }
+
+    /// The entry of `pkg`, which couldn't be checked out to `directory`
+    /// because of `error`.
+    pub(crate) fn failed(pkg: &cargo_metadata::Package, directory: String, error: String) -> Entry {
+        let mut res = Entry::with_tree(pkg, directory, TreeHash::default());
+        res.failed = Some(error);
+        res
+    }
+
+    /// The entry of the package `name` in `version`, of which Cargo couldn't
+    /// tell more, as it couldn't be fetched to `directory` because of
+    /// `error`.
+    pub(crate) fn unfetched(
+        name: String,
+        version: String,
+        directory: String,
+        error: String,
+    ) -> Entry {
+        let tree = TreeHash::default();
+        Entry {
+            name,
+            version,
+            source: None,
+            license: None,
+            repository: None,
+            description: None,
+            manifest_path: String::new(),
+            directory,
+            files: tree.files,
+            bytes: tree.bytes,
+            sha256: format!("{:x}", tree.hasher.finalize()),
+            rust_files: tree.rust_files,
+            loc: tree.loc,
+            build_script: false,
+            proc_macro: false,
+            verification: None,
+            skipped_files: Vec::new(),
+            link_mode: LinkMode::Copy,
+            license_policy: None,
+            snapshot: None,
+            failed: Some(error),
+            provenance: Vec::new(),
+        }
+    }
+}
+
+/// Whether `pkg` has a `PROVENANCE` file of its own.
+pub(crate) fn ships_provenance(pkg: &cargo_metadata::Package) -> bool {
+    pkg.manifest_path
+        .parent()
+        .is_some_and(|it| it.join(PROVENANCE_FILE).exists())
+}
+
+/// Writes the `provenance` of `pkg` to its `PROVENANCE` file in `dir`, one
+/// chain per line, unless it has none or the package ships one.
+pub(crate) fn write_provenance(
+    pkg: &cargo_metadata::Package,
+    dir: &Path,
+    provenance: &[String],
+) -> Result<()> {
+    if provenance.is_empty() || ships_provenance(pkg) {
+        return Ok(());
+    }
+    fs::write(dir.join(PROVENANCE_FILE), provenance.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// Whether `pkg` has a target of the `kind`.
+pub(crate) fn has_target(pkg: &cargo_metadata::Package, kind: &str) -> bool {
+    pkg.targets
+        .iter()
+        .any(|it| it.kind.iter().any(|it| it == kind))
+}
+
+impl Entry {
+    /// The directory of the package, relative to the destination.
+    pub(crate) fn directory(&self) -> &str {
+        &self.directory
+    }
+
+    /// Whether both are the same package, in the very same tree.
+    pub(crate) fn same_tree(&self, other: &Entry) -> bool {
+        self.name == other.name
+            && self.version == other.version
+            && self.source == other.source
+            && self.directory == other.directory
+            && self.sha256 == other.sha256
+    }
+}
+
+impl Manifest {
+    /// The manifest in `dest`, an empty one if there's none yet.
+    pub(crate) fn read(dest: &Path) -> Result<Manifest> {
+        let path = dest.join(MANIFEST_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(it) => it,
+            Err(_) => return Ok(Manifest::default()),
+        };
+        serde_json::from_str(&text)
+            .map_err(|err| format_err!("can't parse {}: {}", path.display(), err))
+    }
+
+    /// The entry of the package checked out to `dir`.
+    pub(crate) fn entry(&self, dir: &Path) -> Option<&Entry> {
+        let name = dir.file_name()?.to_str()?;
+        self.packages.iter().find(|it| it.directory == name)
+    }
+
+    /// The entry of `pkg` with its sources, not left out by
+    /// `--license-allow` nor failed.
+    pub(crate) fn find(&self, pkg: &cargo_metadata::Package) -> Option<&Entry> {
+        let version = pkg.version.to_string();
+        let source = id_source(&pkg.id.repr);
+        self.packages.iter().find(|it| {
+            it.name == pkg.name
+                && it.version == version
+                && it.source.as_deref() == source
+                && it.license_policy != Some(LicensePolicy::Allowed)
+                && it.failed.is_none()
+        })
+    }
+
+    pub(crate) fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub(crate) fn write(&self, dest: &Path) -> Result<()> {
+        fs::write(dest.join(MANIFEST_FILE), self.to_json()? + "\n")?;
+        Ok(())
+    }
+}
+
+/// The source in a package id, either `registry+https://...#name@1.0.0`, or
+/// `name 1.0.0 (registry+https://...)` from older Cargos.
+pub(crate) fn id_source(id: &str) -> Option<&str> {
+    if let (Some(start), true) = (id.find('('), id.ends_with(')')) {
+        return Some(&id[start + 1..id.len() - 1]);
+    }
+    id.rfind('#').map(|idx| &id[..idx])
+}
+
+/// The files and the symlinks under `root`, relative to it, sorted by path.
+fn tree_files(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut res = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(root.join(&dir))? {
+            let entry = entry?;
+            let path = dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else {
+                res.push(path);
+            }
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+/// `snapshot.toml`: the build configuration the packages are the
+/// dependencies of.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Config<'a> {
+    features: &'a [String],
+    all_features: bool,
+    no_default_features: bool,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    targets: &'a [String],
+}
+
+pub(crate) fn write_config(dest: &Path, features: &Features, targets: &[String]) -> Result<()> {
+    fs::write(dest.join(CONFIG_FILE), config_text(features, targets)?)?;
+    Ok(())
+}
+
+/// The contents of `snapshot.toml`.
+pub(crate) fn config_text(features: &Features, targets: &[String]) -> Result<String> {
+    let config = Config {
+        features: &features.features,
+        all_features: features.all_features,
+        no_default_features: features.no_default_features,
+        targets,
+    };
+    Ok(format!(
+        "# The build configuration `cargo review-deps current` checked out the dependencies for.\n{}",
+        toml::to_string(&config)?
+    ))
+}
@@ -0,0 +1,89 @@
+//! Lists of package specifications, for reviewing many crates at once.
+//!
+//! A list has one entry per line. Empty lines are skipped, and `#` starts a
+//! comment if it begins a line or follows a space (git urls contain `#` too).
+
+use std::{fs, path::Path};
+
+use {DiffSpec, PackageId, Result};
+
+/// Reads a list of packages, like `rand:0.6.0`, one per line.
+pub fn read_package_ids(path: &Path) -> Result<Vec<PackageId>> {
+    let mut res: Vec<PackageId> = Vec::new();
+    for (line_no, line) in read_lines(path)? {
+        let pkg_id = line
+            .parse()
+            .map_err(|err| format_err!("{}:{}: {}", path.display(), line_no, err))?;
+        if res.contains(&pkg_id) {
+            eprintln!(
+                "{}:{}: skipping duplicate entry `{}`",
+                path.display(),
+                line_no,
+                line
+            );
+            continue;
+        }
+        res.push(pkg_id);
+    }
+    Ok(res)
+}
+
+/// Reads a list of diffs, with either two packages per line, like
+/// `rand:0.6.0 rand:0.6.1`, or a single range, like `rand:0.6.0..0.6.1`.
+pub fn read_diff_specs(path: &Path) -> Result<Vec<DiffSpec>> {
+    let mut res: Vec<DiffSpec> = Vec::new();
+    for (line_no, line) in read_lines(path)? {
+        let spec = parse_diff_line(&line)
+            .map_err(|err| format_err!("{}:{}: {}", path.display(), line_no, err))?;
+        if res.contains(&spec) {
+            eprintln!(
+                "{}:{}: skipping duplicate entry `{}`",
+                path.display(),
+                line_no,
+                line
+            );
+            continue;
+        }
+        res.push(spec);
+    }
+    Ok(res)
+}
+
+fn parse_diff_line(line: &str) -> Result<DiffSpec> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        [range] => range.parse(),
+        [first, second] => Ok(DiffSpec {
+            first: first.parse()?,
+            second: second.parse()?,
+        }),
+        _ => bail!(
+            "expected two package specifications or a range, got {:?}",
+            line
+        ),
+    }
+}
+
+/// Non-empty lines of `path` without comments, with their 1-based numbers.
+fn read_lines(path: &Path) -> Result<Vec<(usize, String)>> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| format_err!("can't read {}: {}", path.display(), err))?;
+    let res = text
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, strip_comment(line).trim().to_string()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect();
+    Ok(res)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut prev = ' ';
+    for (idx, c) in line.char_indices() {
+        if c == '#' && prev.is_whitespace() {
+            return &line[..idx];
+        }
+        prev = c;
+    }
+    line
+}
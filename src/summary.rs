@@ -0,0 +1,187 @@
+//! The report at the end of `current`: how many packages were checked out,
+//! and why the others weren't, by reason, how large they are, with the
+//! largest ones, and how long it took. `--format json` prints it along with
+//! the manifest, as its `summary`.
+//!
+//! `--quiet` leaves out everything `current` tells on stderr but the errors,
+//! see `note!`.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use cargo_metadata;
+use serde_json;
+
+use snapshot::{Entry, Manifest};
+use {files, packages_word, Result};
+
+/// How many of the largest packages are listed.
+const LARGEST: usize = 10;
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Summary {
+    /// The packages copied, or packed into the archive.
+    pub(crate) copied: usize,
+    pub(crate) unchanged: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) pruned: Option<usize>,
+    pub(crate) failed: usize,
+    /// How many packages were left out, by why.
+    pub(crate) skipped: BTreeMap<String, usize>,
+    /// Of the packages checked out.
+    bytes: u64,
+    loc: usize,
+    /// The files `--exclude` and `--max-file-size` left out of them.
+    skipped_files: usize,
+    skipped_bytes: u64,
+    /// The largest of them, by bytes.
+    largest: Vec<Package>,
+    /// With `--license-allow`, the packages checked out for their license.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    license_review: Vec<LicenseReview>,
+    elapsed_secs: f64,
+    #[serde(skip)]
+    elapsed: Duration,
+    #[serde(skip)]
+    pub(crate) packed: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Package {
+    name: String,
+    version: String,
+    bytes: u64,
+    loc: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct LicenseReview {
+    name: String,
+    version: String,
+    license: Option<String>,
+    license_file: Option<String>,
+}
+
+/// The manifest and the summary, for `--format json`.
+#[derive(Serialize)]
+struct Output<'a> {
+    #[serde(flatten)]
+    manifest: &'a Manifest,
+    summary: &'a Summary,
+}
+
+impl Summary {
+    /// Counts `n` more packages as left out for `why`.
+    pub(crate) fn skip(&mut self, why: &str, n: usize) {
+        if n > 0 {
+            *self.skipped.entry(why.to_string()).or_default() += n;
+        }
+    }
+
+    /// Flags `pkg` as checked out for a review of its license.
+    pub(crate) fn review_license(&mut self, pkg: &cargo_metadata::Package) {
+        self.license_review.push(LicenseReview {
+            name: pkg.name.clone(),
+            version: pkg.version.to_string(),
+            license: pkg.license.clone(),
+            license_file: pkg
+                .license_file
+                .as_ref()
+                .map(|it| it.to_string_lossy().into_owned()),
+        });
+    }
+
+    /// Adds up the `entries` of the packages checked out, after `elapsed`.
+    pub(crate) fn finish(&mut self, entries: &[Entry], elapsed: Duration) {
+        for entry in entries {
+            self.bytes += entry.bytes;
+            self.loc += entry.loc;
+            self.skipped_files += entry.skipped_files.len();
+            self.skipped_bytes += entry.skipped_files.iter().map(|it| it.size()).sum::<u64>();
+        }
+        let mut largest: Vec<&Entry> = entries.iter().collect();
+        largest.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+        self.largest = largest
+            .into_iter()
+            .take(LARGEST)
+            .map(|it| Package {
+                name: it.name.clone(),
+                version: it.version.clone(),
+                bytes: it.bytes,
+                loc: it.loc,
+            })
+            .collect();
+        self.elapsed = elapsed;
+        self.elapsed_secs = elapsed.as_secs_f64();
+    }
+
+    pub(crate) fn to_json(&self, manifest: &Manifest) -> Result<String> {
+        let output = Output {
+            manifest,
+            summary: self,
+        };
+        Ok(serde_json::to_string_pretty(&output)?)
+    }
+
+    /// Prints the summary to stderr, unless `--quiet`.
+    pub(crate) fn print(&self) {
+        if !self.skipped.is_empty() {
+            let total: usize = self.skipped.values().sum();
+            let reasons: Vec<String> = self
+                .skipped
+                .iter()
+                .map(|(why, n)| format!("{}: {}", why, n))
+                .collect();
+            eprintln!("Skipped {}: {}", packages_word(total), reasons.join(", "));
+        }
+        if !self.largest.is_empty() {
+            eprintln!("Largest packages:");
+            for package in self.largest.iter() {
+                eprintln!(
+                    "  {} {}, {} bytes, {} lines of Rust",
+                    package.name, package.version, package.bytes, package.loc
+                );
+            }
+        }
+        if !self.license_review.is_empty() {
+            eprintln!("Licenses to review, not allowed by --license-allow:");
+            for package in self.license_review.iter() {
+                let license = match (&package.license, &package.license_file) {
+                    (Some(license), _) => license.clone(),
+                    (None, Some(file)) => format!("only a license file, {}", file),
+                    (None, None) => "no license".to_string(),
+                };
+                eprintln!("  {} {}: {}", package.name, package.version, license);
+            }
+        }
+        let checked_out = self.copied + self.unchanged;
+        eprintln!(
+            "Total: {}, {} bytes, {} lines of Rust",
+            packages_word(checked_out),
+            self.bytes,
+            self.loc
+        );
+        let mut line = if self.packed {
+            format!("{} packed", packages_word(self.copied))
+        } else {
+            format!(
+                "{} copied, {} unchanged",
+                packages_word(self.copied),
+                self.unchanged
+            )
+        };
+        if let Some(pruned) = self.pruned {
+            line += &format!(", {} pruned", pruned);
+        }
+        if self.failed > 0 {
+            line += &format!(", {} failed", self.failed);
+        }
+        if self.skipped_files > 0 {
+            line += &format!(
+                ", {} left out ({} bytes saved)",
+                files(self.skipped_files),
+                self.skipped_bytes
+            );
+        }
+        eprintln!("{}, in {:.0?}", line, self.elapsed);
+    }
+}
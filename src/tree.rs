@@ -0,0 +1,358 @@
+//! Copying package sources without trusting them: a package may come with
+//! symlinks, say into the workspace it was published from, and badly
+//! packaged crates even with FIFOs or sockets.
+//!
+//! Symlinks are copied as symlinks when they point inside of the package.
+//! Those pointing outside of it, or to an absolute path, are replaced with a
+//! note of their target, so that neither the diff nor `--destination` ever
+//! read files outside of the package. Other special files are left out.
+//!
+//! The files may be linked to the sources instead of copied, see `LinkMode`.
+
+use std::{
+    ffi::OsStr,
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use {warning_label, LinkMode, PackageId, Result};
+
+/// The symlinks and the special files of a tree.
+#[derive(Debug, Default)]
+pub(crate) struct Special {
+    symlinks: Vec<Symlink>,
+    /// The special files left out, with what they are.
+    skipped: Vec<(PathBuf, &'static str)>,
+}
+
+#[derive(Debug)]
+struct Symlink {
+    path: PathBuf,
+    target: PathBuf,
+    /// Whether the target is inside of the package.
+    inside: bool,
+}
+
+impl Special {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.symlinks.is_empty() && self.skipped.is_empty()
+    }
+
+    /// Prints the symlinks and the special files of `pkg_id` to stderr.
+    pub(crate) fn print(&self, pkg_id: &PackageId) {
+        if !self.symlinks.is_empty() {
+            let links: Vec<String> = self
+                .symlinks
+                .iter()
+                .map(|it| {
+                    let note = if it.inside {
+                        ""
+                    } else {
+                        " (outside of the package, replaced with a note)"
+                    };
+                    format!("{} -> {}{}", it.path.display(), it.target.display(), note)
+                })
+                .collect();
+            eprintln!("Symlinks in {}: {}", pkg_id, links.join(", "));
+        }
+        for (path, kind) in self.skipped.iter() {
+            eprintln!(
+                "{} skipped {} of {}, a {}",
+                warning_label(),
+                path.display(),
+                pkg_id,
+                kind
+            );
+        }
+    }
+}
+
+/// What an entry of a tree is copied as.
+pub(crate) enum Item {
+    Dir,
+    File,
+    /// A symlink inside of the package, to its target.
+    Symlink(PathBuf),
+    /// The note a symlink outside of the package is replaced with.
+    Note(String),
+}
+
+/// The symlinks and the special files in `root`.
+pub(crate) fn find(root: &Path) -> Result<Special> {
+    let mut res = Special::default();
+    walk(root, Path::new(""), &mut |_, _| Ok(()), &mut res)?;
+    Ok(res)
+}
+
+/// Copies `src` to `dst`, which may exist already, and returns its symlinks
+/// and special files.
+pub(crate) fn copy_tree(src: &Path, dst: &Path) -> Result<Special> {
+    let mut res = Special::default();
+    fs::create_dir_all(dst)?;
+    walk(src, Path::new(""), &mut copy_to(src, dst), &mut res)?;
+    Ok(res)
+}
+
+/// How many files `link_entries` linked, and how many it copied as they
+/// couldn't be, with why the first one couldn't.
+#[derive(Debug, Default)]
+pub(crate) struct Links {
+    pub(crate) linked: usize,
+    pub(crate) copied: usize,
+    pub(crate) error: Option<io::Error>,
+}
+
+impl Links {
+    /// The mode the files were copied with: `mode` if any of them is
+    /// linked.
+    pub(crate) fn effective(&self, mode: LinkMode) -> LinkMode {
+        if self.linked > 0 {
+            mode
+        } else {
+            LinkMode::Copy
+        }
+    }
+}
+
+/// Copies the entries of `src` but the `skip`ped ones to `dst`, as
+/// `copy_entries`, but links the files with `mode`, or copies those which
+/// can't be linked.
+pub(crate) fn link_entries(src: &Path, dst: &Path, skip: &[&str], mode: LinkMode) -> Result<Links> {
+    let mut res = Links::default();
+    fs::create_dir_all(dst)?;
+    let mut copy = copy_to(src, dst);
+    let mut visit = |path: &Path, item: Item| {
+        if !matches!(item, Item::File) || mode == LinkMode::Copy {
+            return copy(path, item);
+        }
+        match link_file(&src.join(path), &dst.join(path), mode) {
+            Ok(()) => res.linked += 1,
+            Err(err) => {
+                fs::copy(src.join(path), dst.join(path))?;
+                res.copied += 1;
+                res.error.get_or_insert(err);
+            }
+        }
+        Ok(())
+    };
+    let mut names = Vec::new();
+    for entry in fs::read_dir(src)? {
+        names.push(entry?.file_name());
+    }
+    names.sort();
+    for name in names {
+        if !skip.iter().any(|it| name == *it) {
+            walk_entry(src, Path::new(&name), &mut visit, &mut Special::default())?;
+        }
+    }
+    Ok(res)
+}
+
+fn link_file(src: &Path, dst: &Path, mode: LinkMode) -> io::Result<()> {
+    match mode {
+        LinkMode::Copy => fs::copy(src, dst).map(drop),
+        LinkMode::Hardlink => fs::hard_link(src, dst),
+        LinkMode::Reflink => reflink(src, dst),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let from = fs::File::open(src)?;
+    let to = fs::File::create(dst)?;
+    // SAFETY: both descriptors stay open for as long as the call.
+    if unsafe { libc::ioctl(to.as_raw_fd(), libc::FICLONE, from.as_raw_fd()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    to.set_permissions(from.metadata()?.permissions())
+}
+
+#[cfg(target_os = "macos")]
+fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+    let path = |it: &Path| {
+        CString::new(it.as_os_str().as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+    };
+    let (from, to) = (path(src)?, path(dst)?);
+    // SAFETY: both are nul terminated paths.
+    if unsafe { libc::clonefile(from.as_ptr(), to.as_ptr(), 0) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn reflink(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "no copy-on-write clones on this platform",
+    ))
+}
+
+/// Copies the entries of `src` but the `skip`ped ones to `dst`.
+pub(crate) fn copy_entries(src: &Path, dst: &Path, skip: &[&str]) -> Result<Special> {
+    let mut res = Special::default();
+    fs::create_dir_all(dst)?;
+    let mut copy = copy_to(src, dst);
+    for entry in fs::read_dir(src)? {
+        let name = entry?.file_name();
+        if skip.iter().any(|it| name == *it) {
+            continue;
+        }
+        walk_entry(src, Path::new(&name), &mut copy, &mut res)?;
+    }
+    Ok(res)
+}
+
+/// The files of `src` but the `skip`ped entries, sorted, and what they would
+/// be copied as, to pack them without copying them.
+pub(crate) fn list(src: &Path, skip: &[&str]) -> Result<Vec<(PathBuf, Item)>> {
+    let mut res = Vec::new();
+    let mut names = Vec::new();
+    for entry in fs::read_dir(src)? {
+        names.push(entry?.file_name());
+    }
+    names.sort();
+    for name in names {
+        if skip.iter().any(|it| name == *it) {
+            continue;
+        }
+        let mut add = |path: &Path, item: Item| {
+            if !matches!(item, Item::Dir) {
+                res.push((path.to_path_buf(), item));
+            }
+            Ok(())
+        };
+        walk_entry(src, Path::new(&name), &mut add, &mut Special::default())?;
+    }
+    Ok(res)
+}
+
+pub(crate) fn copy_file(src: &Path, dst: &Path) -> Result<()> {
+    if fs::symlink_metadata(src)?.file_type().is_symlink() {
+        symlink(&fs::read_link(src)?, dst)?;
+    } else {
+        fs::copy(src, dst)?;
+    }
+    Ok(())
+}
+
+/// Copies the entries `walk` visits from `src` to `dst`.
+fn copy_to<'a>(src: &'a Path, dst: &'a Path) -> impl FnMut(&Path, Item) -> Result<()> + 'a {
+    move |path, item| {
+        match item {
+            Item::Dir => fs::create_dir_all(dst.join(path))?,
+            Item::File => {
+                fs::copy(src.join(path), dst.join(path))?;
+            }
+            Item::Symlink(target) => symlink(&target, &dst.join(path))?,
+            Item::Note(note) => fs::write(dst.join(path), note)?,
+        }
+        Ok(())
+    }
+}
+
+/// The repository git checkouts come with, which isn't part of the package:
+/// the diffs, the archives and the counts of files and lines leave it out.
+pub(crate) const GIT_DIR: &str = ".git";
+
+/// Whether `name`, of an entry of a package, is its `GIT_DIR`.
+pub(crate) fn is_git_dir(name: &OsStr) -> bool {
+    name == GIT_DIR
+}
+
+type Visit<'a> = dyn FnMut(&Path, Item) -> Result<()> + 'a;
+
+fn walk(root: &Path, dir: &Path, visit: &mut Visit, res: &mut Special) -> Result<()> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(root.join(dir))? {
+        names.push(entry?.file_name());
+    }
+    names.sort();
+    for name in names {
+        walk_entry(root, &dir.join(name), visit, res)?;
+    }
+    Ok(())
+}
+
+fn walk_entry(root: &Path, path: &Path, visit: &mut Visit, res: &mut Special) -> Result<()> {
+    let file_type = fs::symlink_metadata(root.join(path))?.file_type();
+    if file_type.is_dir() {
+        visit(path, Item::Dir)?;
+        walk(root, path, visit, res)?;
+    } else if file_type.is_symlink() {
+        let target = fs::read_link(root.join(path))?;
+        let inside = is_inside(path, &target);
+        if inside {
+            visit(path, Item::Symlink(target.clone()))?;
+        } else {
+            let note = format!(
+                "A symlink to {}, outside of the package, was here.\n",
+                target.display()
+            );
+            visit(path, Item::Note(note))?;
+        }
+        res.symlinks.push(Symlink {
+            path: path.to_path_buf(),
+            target,
+            inside,
+        });
+    } else if file_type.is_file() {
+        visit(path, Item::File)?;
+    } else {
+        res.skipped.push((path.to_path_buf(), kind(&file_type)));
+    }
+    Ok(())
+}
+
+/// Whether the relative `target` of the symlink at `path` stays in the
+/// package, compared without following any symlinks. As the `..` of
+/// `other_link/..` would follow `other_link`, `..` may only come first.
+fn is_inside(path: &Path, target: &Path) -> bool {
+    let mut depth = path.components().count() - 1;
+    let mut down = false;
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => {
+                depth += 1;
+                down = true;
+            }
+            Component::CurDir => (),
+            Component::ParentDir if depth > 0 && !down => depth -= 1,
+            _ => return false,
+        }
+    }
+    true
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+/// Windows needs privileges for symlinks, so they are copied as notes.
+#[cfg(not(unix))]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    fs::write(path, format!("A symlink to {}.\n", target.display()))
+}
+
+#[cfg(unix)]
+fn kind(file_type: &fs::FileType) -> &'static str {
+    use std::os::unix::fs::FileTypeExt;
+    if file_type.is_fifo() {
+        "FIFO"
+    } else if file_type.is_socket() {
+        "socket"
+    } else if file_type.is_block_device() || file_type.is_char_device() {
+        "device"
+    } else {
+        "special file"
+    }
+}
+
+#[cfg(not(unix))]
+fn kind(_file_type: &fs::FileType) -> &'static str {
+    "special file"
+}
@@ -0,0 +1,107 @@
+//! `--max-lines`: the diffs of an invocation stop once they printed that
+//! many lines, all together, so that two releases of a huge crate don't flood a
+//! terminal or a CI log. Only the hunks are cut, the summaries stay complete.
+
+use std::{
+    io::{self, Write},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+/// How many lines the diffs printed, and may.
+#[derive(Debug)]
+pub struct Truncation {
+    /// `0` means no limit.
+    max_lines: usize,
+    printed: AtomicUsize,
+    /// The changed files which weren't shown at all, since the last report.
+    hidden: AtomicUsize,
+    /// Whether anything was cut since the last report.
+    truncated: AtomicBool,
+}
+
+/// For the diffs which are never cut, like that of `run_diff_cmd`.
+pub(crate) static UNLIMITED: Truncation = Truncation::new(0);
+
+impl Truncation {
+    /// The diffs may print `max_lines`, `0` for no limit.
+    pub const fn new(max_lines: usize) -> Truncation {
+        Truncation {
+            max_lines,
+            printed: AtomicUsize::new(0),
+            hidden: AtomicUsize::new(0),
+            truncated: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether there's a limit at all.
+    pub(crate) fn is_limited(&self) -> bool {
+        self.max_lines != 0
+    }
+
+    /// Whether the diffs printed as many lines as they may.
+    pub(crate) fn is_full(&self) -> bool {
+        self.is_limited() && self.printed.load(Ordering::SeqCst) >= self.max_lines
+    }
+
+    /// Counts `files` changed files which aren't shown, as the output is full.
+    pub(crate) fn hide(&self, files: usize) {
+        if files > 0 {
+            self.hidden.fetch_add(files, Ordering::SeqCst);
+            self.truncated.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Tells on stdout, after the diffs, that they were cut, if they were.
+    pub fn report(&self) {
+        if !self.truncated.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let hidden = match self.hidden.swap(0, Ordering::SeqCst) {
+            0 => String::new(),
+            1 => "; 1 more changed file not shown".to_string(),
+            n => format!("; {} more changed files not shown", n),
+        };
+        println!(
+            "\nOutput truncated after {} lines{} — rerun with --destination or --output to get everything, \
+             or with --max-lines 0",
+            self.max_lines, hidden
+        );
+    }
+}
+
+/// Writes to `out` up to the last line the diffs may print, and drops the
+/// rest.
+pub(crate) struct Limited<'a, W> {
+    pub(crate) out: W,
+    pub(crate) truncation: &'a Truncation,
+}
+
+impl<'a, W: Write> Write for Limited<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let max = self.truncation.max_lines;
+        if max == 0 {
+            return self.out.write(buf);
+        }
+        let mut printed = self.truncation.printed.load(Ordering::SeqCst);
+        let mut end = 0;
+        while end < buf.len() && printed < max {
+            match buf[end..].iter().position(|&it| it == b'\n') {
+                Some(idx) => {
+                    end += idx + 1;
+                    printed += 1;
+                }
+                None => end = buf.len(),
+            }
+        }
+        self.out.write_all(&buf[..end])?;
+        self.truncation.printed.store(printed, Ordering::SeqCst);
+        if end < buf.len() {
+            self.truncation.truncated.store(true, Ordering::SeqCst);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
@@ -0,0 +1,404 @@
+//! `diff --unsafe-report`: how much `unsafe` there is in each file of the two
+//! versions, and whether the crate stopped forbidding it.
+//!
+//! The sources are split into tokens, so `unsafe` in comments and in string
+//! literals doesn't count. `unsafe fn`, `unsafe impl` and `unsafe` blocks are
+//! counted, `unsafe extern "C" fn` being a function too.
+
+use std::{
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use toml;
+
+use {tree, Result};
+
+/// The `unsafe` items of a file or of a whole tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct Counts {
+    functions: usize,
+    impls: usize,
+    blocks: usize,
+}
+
+impl Counts {
+    fn total(self) -> usize {
+        self.functions + self.impls + self.blocks
+    }
+
+    fn add(&mut self, other: Counts) {
+        self.functions += other.functions;
+        self.impls += other.impls;
+        self.blocks += other.blocks;
+    }
+}
+
+#[derive(Serialize)]
+struct File {
+    path: String,
+    before: Counts,
+    after: Counts,
+}
+
+/// The `unsafe` code of two versions, as in the JSON output.
+#[derive(Serialize)]
+pub(crate) struct Report {
+    /// The files with `unsafe` code in either version, sorted by path.
+    files: Vec<File>,
+    before: Counts,
+    after: Counts,
+    /// The crate roots which had `#![forbid(unsafe_code)]` in the first
+    /// version, but not in the second.
+    forbid_removed: Vec<String>,
+}
+
+pub(crate) fn report(a: &Path, b: &Path) -> Result<Report> {
+    let mut paths = rust_files(a)?;
+    paths.extend(rust_files(b)?);
+    paths.sort();
+    paths.dedup();
+    let mut res = Report {
+        files: Vec::new(),
+        before: Counts::default(),
+        after: Counts::default(),
+        forbid_removed: Vec::new(),
+    };
+    for path in paths {
+        let before = count(&tokens_of(&a.join(&path)));
+        let after = count(&tokens_of(&b.join(&path)));
+        res.before.add(before);
+        res.after.add(after);
+        if before.total() > 0 || after.total() > 0 {
+            res.files.push(File {
+                path: path.display().to_string(),
+                before,
+                after,
+            });
+        }
+    }
+    for root in crate_roots(a) {
+        if forbids_unsafe(&tokens_of(&a.join(&root))) && !forbids_unsafe(&tokens_of(&b.join(&root)))
+        {
+            res.forbid_removed.push(root.display().to_string());
+        }
+    }
+    Ok(res)
+}
+
+impl Report {
+    /// How many more `unsafe` functions, impls and blocks the second version
+    /// has, `0` if it has fewer.
+    pub(crate) fn added(&self) -> usize {
+        self.after.total().saturating_sub(self.before.total())
+    }
+
+    pub(crate) fn forbid_removed(&self) -> &[String] {
+        &self.forbid_removed
+    }
+
+    /// What needs a closer look: the removed `#![forbid(unsafe_code)]`, and
+    /// more `unsafe` code than before.
+    pub(crate) fn findings(&self) -> Vec<String> {
+        let mut res: Vec<String> = self
+            .forbid_removed
+            .iter()
+            .map(|root| format!("{} no longer has #![forbid(unsafe_code)]", root))
+            .collect();
+        if self.after.total() > self.before.total() {
+            let increased: Vec<&str> = self
+                .files
+                .iter()
+                .filter(|it| it.after.total() > it.before.total())
+                .map(|it| it.path.as_str())
+                .collect();
+            res.push(format!(
+                "more unsafe code: {} -> {} unsafe functions, impls and blocks, more in {}",
+                self.before.total(),
+                self.after.total(),
+                increased.join(", ")
+            ));
+        }
+        res
+    }
+}
+
+/// Prints a table of the `unsafe` code before and after, and the files with
+/// more of it.
+pub(crate) fn print_report(report: &Report) {
+    for root in report.forbid_removed.iter() {
+        println!(
+            "HIGH PRIORITY: {} no longer has #![forbid(unsafe_code)]",
+            root
+        );
+    }
+    if report.files.is_empty() {
+        println!("No unsafe code in either version");
+        return;
+    }
+    let width = report
+        .files
+        .iter()
+        .map(|it| it.path.chars().count())
+        .chain(Some("total".len()))
+        .max()
+        .unwrap_or_default();
+    println!("Unsafe code:");
+    println!(" {:<width$}  before  after", "file", width = width);
+    for file in report.files.iter() {
+        println!(
+            " {:<width$}  {:>6}  {:>5}",
+            file.path,
+            file.before.total(),
+            file.after.total(),
+            width = width
+        );
+    }
+    println!(
+        " {:<width$}  {:>6}  {:>5}",
+        "total",
+        report.before.total(),
+        report.after.total(),
+        width = width
+    );
+    let increased: Vec<&File> = report
+        .files
+        .iter()
+        .filter(|it| it.after.total() > it.before.total())
+        .collect();
+    if !increased.is_empty() {
+        println!("More unsafe code in:");
+        for file in increased {
+            let kinds = [
+                ("unsafe fn", file.before.functions, file.after.functions),
+                ("unsafe impl", file.before.impls, file.after.impls),
+                ("unsafe blocks", file.before.blocks, file.after.blocks),
+            ];
+            let changes: Vec<String> = kinds
+                .iter()
+                .filter(|(_, before, after)| before != after)
+                .map(|(kind, before, after)| format!("{} {} -> {}", kind, before, after))
+                .collect();
+            println!(" {}: {}", file.path, changes.join(", "));
+        }
+    }
+}
+
+/// The `.rs` files of the tree in `root`, relative to it.
+fn rust_files(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut res = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(root.join(&dir))? {
+            let entry = entry?;
+            let path = dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                if !tree::is_git_dir(&entry.file_name()) {
+                    dirs.push(path);
+                }
+            } else if path.extension().is_some_and(|it| it == "rs") {
+                res.push(path);
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// The library and the main binary of the package in `root`.
+fn crate_roots(root: &Path) -> Vec<PathBuf> {
+    let lib = fs::read_to_string(root.join("Cargo.toml"))
+        .ok()
+        .and_then(|it| toml::from_str::<toml::Value>(&it).ok())
+        .and_then(|it| {
+            let path = it.get("lib")?.get("path")?.as_str()?;
+            Some(PathBuf::from(path.trim_start_matches("./")))
+        });
+    vec![
+        lib.unwrap_or_else(|| PathBuf::from("src/lib.rs")),
+        PathBuf::from("src/main.rs"),
+    ]
+}
+
+fn count(tokens: &[String]) -> Counts {
+    let mut res = Counts::default();
+    for (idx, _) in tokens.iter().enumerate().filter(|(_, it)| *it == "unsafe") {
+        let next = |n: usize| tokens.get(idx + n).map(String::as_str);
+        match next(1) {
+            Some("fn") => res.functions += 1,
+            Some("impl") => res.impls += 1,
+            Some("{") => res.blocks += 1,
+            Some("extern") if next(2) == Some("fn") || next(3) == Some("fn") => res.functions += 1,
+            _ => (),
+        }
+    }
+    res
+}
+
+/// Whether the tokens have `#![forbid(..., unsafe_code, ...)]`.
+fn forbids_unsafe(tokens: &[String]) -> bool {
+    tokens.windows(5).enumerate().any(|(idx, window)| {
+        window == ["#", "!", "[", "forbid", "("]
+            && tokens[idx + 5..]
+                .iter()
+                .take_while(|it| *it != ")")
+                .any(|it| it == "unsafe_code")
+    })
+}
+
+/// The tokens of the file at `path`, none if it doesn't exist or isn't text.
+fn tokens_of(path: &Path) -> Vec<String> {
+    match fs::read_to_string(path) {
+        Ok(text) => tokenize(&text),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Splits Rust code into identifiers, keywords and punctuation, one character
+/// each. Comments are dropped, and literals are replaced with `""` so that
+/// their contents can't look like code. It doesn't need to be exact, only to
+/// never mistake text for `unsafe`.
+pub(crate) fn tokenize(text: &str) -> Vec<String> {
+    token_spans(text).into_iter().map(|(it, _)| it).collect()
+}
+
+/// The number of lines of Rust code with any code on them, skipping the
+/// blank lines and the comments.
+pub(crate) fn code_lines(text: &str) -> usize {
+    let mut line_of = Vec::new();
+    let mut line = 0;
+    for c in text.chars() {
+        line_of.push(line);
+        if c == '\n' {
+            line += 1;
+        }
+    }
+    let mut lines: Vec<usize> = Vec::new();
+    for (_, span) in token_spans(text) {
+        let end = span.end.min(line_of.len());
+        if span.start >= end {
+            continue;
+        }
+        let (first, last) = (line_of[span.start], line_of[end - 1]);
+        match lines.last() {
+            Some(&prev) if prev >= first => lines.extend(prev + 1..=last),
+            _ => lines.extend(first..=last),
+        }
+    }
+    lines.len()
+}
+
+/// The tokens of `tokenize`, with the range of characters of each.
+fn token_spans(text: &str) -> Vec<(String, Range<usize>)> {
+    let chars: Vec<char> = text.chars().collect();
+    let at = |idx: usize| chars.get(idx).copied().unwrap_or('\0');
+    let is_ident = |c: char| c == '_' || c.is_alphanumeric();
+    let mut res = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let begin = i;
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && at(i + 1) == '/' {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && at(i + 1) == '*' {
+            // Block comments nest.
+            let mut depth = 0;
+            while i < chars.len() {
+                if chars[i] == '/' && at(i + 1) == '*' {
+                    depth += 1;
+                    i += 2;
+                } else if chars[i] == '*' && at(i + 1) == '/' {
+                    depth -= 1;
+                    i += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    i += 1;
+                }
+            }
+        } else if c == '"' {
+            i = skip_string(&chars, i + 1);
+            res.push(("\"\"".to_string(), begin..i));
+        } else if c == '\'' {
+            if at(i + 1) == '\\' || at(i + 2) == '\'' {
+                // A character, and not a lifetime.
+                i += 2;
+                while i < chars.len() && chars[i] != '\'' {
+                    i += if chars[i] == '\\' { 2 } else { 1 };
+                }
+                i += 1;
+                res.push(("\"\"".to_string(), begin..i));
+            } else {
+                i += 1;
+                while i < chars.len() && is_ident(chars[i]) {
+                    i += 1;
+                }
+            }
+        } else if is_ident(c) {
+            let start = i;
+            while i < chars.len() && is_ident(chars[i]) {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            let raw_string = match at(i) {
+                '"' => true,
+                '#' => {
+                    let hashes = chars[i..].iter().take_while(|it| **it == '#').count();
+                    at(i + hashes) == '"'
+                }
+                _ => false,
+            };
+            if ["r", "br", "cr"].contains(&word.as_str()) && raw_string {
+                let hashes = chars[i..].iter().take_while(|it| **it == '#').count();
+                let end: Vec<char> = Some('"')
+                    .into_iter()
+                    .chain("#".repeat(hashes).chars())
+                    .collect();
+                i += hashes + 1;
+                while i < chars.len() && !chars[i..].starts_with(&end) {
+                    i += 1;
+                }
+                i += end.len();
+                res.push(("\"\"".to_string(), begin..i));
+            } else if word == "r" && at(i) == '#' && is_ident(at(i + 1)) {
+                // A raw identifier like `r#unsafe` is no keyword.
+                let start = i + 1;
+                i += 1;
+                while i < chars.len() && is_ident(chars[i]) {
+                    i += 1;
+                }
+                let name: String = chars[start..i].iter().collect();
+                res.push((format!("r#{}", name), begin..i));
+            } else if (word == "b" || word == "c") && at(i) == '"' {
+                i = skip_string(&chars, i + 1);
+                res.push(("\"\"".to_string(), begin..i));
+            } else if word == "b" && at(i) == '\'' {
+                i += 1;
+                while i < chars.len() && chars[i] != '\'' {
+                    i += if chars[i] == '\\' { 2 } else { 1 };
+                }
+                i += 1;
+                res.push(("\"\"".to_string(), begin..i));
+            } else {
+                res.push((word, begin..i));
+            }
+        } else {
+            i += 1;
+            res.push((c.to_string(), begin..i));
+        }
+    }
+    res
+}
+
+/// The index after the end of the string starting at `i`, after the quote.
+fn skip_string(chars: &[char], mut i: usize) -> usize {
+    while i < chars.len() && chars[i] != '"' {
+        i += if chars[i] == '\\' { 2 } else { 1 };
+    }
+    i + 1
+}
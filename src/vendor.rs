@@ -0,0 +1,320 @@
+//! Checking a published package against its copy in the vendored sources of
+//! the workspace, for `diff --against-vendored`: the differences are what
+//! `cargo vendor` would change.
+//!
+//! The vendored sources are the directory crates.io is replaced with in
+//! `.cargo/config.toml`, following `replace-with` from one source to the
+//! next, as Cargo does. A package is vendored to a directory with its name,
+//! or with its name and version, like `foo-1.2.0`, when several versions of
+//! it are vendored; the version is read from its manifest. The files which
+//! only `cargo vendor` adds, like `.cargo-checksum.json`, don't count, nor do
+//! those it leaves out of the published package.
+//!
+//! The other way around, `current --layout vendor` checks the packages out
+//! as `cargo vendor` does, so that the destination can replace their
+//! sources: each package comes with the `.cargo-checksum.json` Cargo checks
+//! a directory source against.
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use cargo_metadata;
+use semver::{Version, VersionReq};
+use serde_json;
+use sha2::{Digest, Sha256};
+use toml;
+
+use {
+    copy_package, offline, same_name, tree, PackageId, PackageSource, Result, Source, VersionSpec,
+};
+
+/// The checksums `cargo vendor` writes next to the sources.
+const CHECKSUM_FILE: &str = ".cargo-checksum.json";
+
+/// The name of the source `current --layout vendor` replaces the others
+/// with.
+const SOURCE_NAME: &str = "vendored-sources";
+
+/// The files of a published package which `cargo vendor` doesn't copy.
+const NOT_VENDORED: &[&str] = &[".cargo-ok", ".gitattributes", ".gitignore"];
+
+/// The vendored copy of a package and the published package, checked out
+/// to compare them.
+pub(crate) struct Checkout {
+    /// The vendored package, a local one.
+    pub(crate) vendored_id: PackageId,
+    pub(crate) vendored: PathBuf,
+    /// The published package without the files `cargo vendor` leaves out.
+    pub(crate) published: PathBuf,
+}
+
+pub(crate) fn checkout(pkg_id: &PackageId, src: &Path, tmp: &Path) -> Result<Checkout> {
+    let version = match &pkg_id.source {
+        Source::Registry {
+            version: VersionSpec::Exact(version),
+            ..
+        } => version,
+        _ => bail!(
+            "--against-vendored needs a published package, got {}",
+            pkg_id
+        ),
+    };
+    let vendor_dir = vendor_dir()?;
+    let (dir, vendored_version) = find_package(&vendor_dir, &pkg_id.name, version)?;
+    eprintln!(
+        "Comparing with the vendored {} {} in {}",
+        pkg_id.name,
+        vendored_version,
+        dir.display()
+    );
+
+    let vendored_id = PackageId {
+        name: pkg_id.name.clone(),
+        source: Source::Path(dir.clone()),
+    };
+    let vendored = tmp.join("vendored");
+    tree::copy_entries(&dir, &vendored, &[CHECKSUM_FILE])?;
+    let published = tmp.join("published");
+    copy_package(pkg_id, src, &published)?;
+    for name in NOT_VENDORED {
+        let path = published.join(name);
+        if path.is_file() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(Checkout {
+        vendored_id,
+        vendored,
+        published,
+    })
+}
+
+/// The directory which replaces crates.io, from the `.cargo/config.toml` of
+/// the current directory and its parents, and of `CARGO_HOME`.
+fn vendor_dir() -> Result<PathBuf> {
+    let configs = read_configs();
+    let mut name = "crates-io".to_string();
+    let mut seen = Vec::new();
+    while !seen.contains(&name) {
+        seen.push(name.clone());
+        // The closest config which says something about the source decides.
+        if let Some((base, dir)) = source_key(&configs, &name, "directory") {
+            return Ok(base.join(dir));
+        }
+        match source_key(&configs, &name, "replace-with") {
+            Some((_, next)) => name = next,
+            None => break,
+        }
+    }
+    bail!(
+        "there are no vendored sources: crates.io isn't replaced with a directory in .cargo/config.toml; \
+         run `cargo vendor` and add the configuration it prints"
+    )
+}
+
+/// The configs which apply in the current directory, the closest first, with
+/// the directories their relative paths are relative to.
+pub(crate) fn read_configs() -> Vec<(PathBuf, toml::Value)> {
+    let mut dirs: Vec<PathBuf> = match env::current_dir() {
+        Ok(cwd) => cwd.ancestors().map(|it| it.join(".cargo")).collect(),
+        Err(_) => Vec::new(),
+    };
+    if let Some(home) = offline::cargo_home() {
+        if !dirs.contains(&home) {
+            dirs.push(home);
+        }
+    }
+    let mut res = Vec::new();
+    for dir in dirs {
+        for name in ["config.toml", "config"] {
+            let config = fs::read_to_string(dir.join(name))
+                .ok()
+                .and_then(|it| toml::from_str(&it).ok());
+            if let (Some(config), Some(base)) = (config, dir.parent()) {
+                res.push((base.to_path_buf(), config));
+                break;
+            }
+        }
+    }
+    res
+}
+
+/// `source.<name>.<key>` of the closest config which has it, with its base
+/// directory.
+fn source_key<'a>(
+    configs: &'a [(PathBuf, toml::Value)],
+    name: &str,
+    key: &str,
+) -> Option<(&'a Path, String)> {
+    configs.iter().find_map(|(base, config)| {
+        let value = config.get("source")?.get(name)?.get(key)?.as_str()?;
+        Some((base.as_path(), value.to_string()))
+    })
+}
+
+/// The vendored copy of the package `name` in `vendor_dir` to compare with
+/// `version`, and its version: the same version, or else a compatible one, if
+/// several versions are vendored.
+fn find_package(vendor_dir: &Path, name: &str, version: &Version) -> Result<(PathBuf, Version)> {
+    let entries = fs::read_dir(vendor_dir).map_err(|err| {
+        format_err!(
+            "can't read the vendored sources in {}: {}",
+            vendor_dir.display(),
+            err
+        )
+    })?;
+    let mut found = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let dir_name = path
+            .file_name()
+            .map(|it| it.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let prefix = format!("{}-", name.replace('_', "-"));
+        if !same_name(&dir_name, name) && !dir_name.replace('_', "-").starts_with(&prefix) {
+            continue;
+        }
+        if let Some((package, package_version)) = read_package(&path) {
+            if same_name(&package, name) {
+                found.push((path, package_version));
+            }
+        }
+    }
+    found.sort_by(|a, b| a.1.cmp(&b.1));
+    if let Some(idx) = found.iter().position(|(_, it)| it == version) {
+        return Ok(found.swap_remove(idx));
+    }
+    // Either one would be updated to the other, with `cargo update`.
+    let compatible = |a: &Version, b: &Version| {
+        VersionReq::parse(&format!("^{}", a)).is_ok_and(|it| it.matches(b))
+    };
+    let compatible: Vec<usize> = (0..found.len())
+        .filter(|&idx| compatible(&found[idx].1, version) || compatible(version, &found[idx].1))
+        .collect();
+    match (found.len(), compatible.as_slice()) {
+        (0, _) => bail!("`{}` isn't vendored in {}", name, vendor_dir.display()),
+        (1, _) => Ok(found.remove(0)),
+        (_, [idx]) => Ok(found.swap_remove(*idx)),
+        _ => {
+            let versions: Vec<String> = found.iter().map(|(_, it)| it.to_string()).collect();
+            bail!(
+                "several versions of `{}` are vendored, none of them {} or compatible with it: {}",
+                name,
+                version,
+                versions.join(", ")
+            )
+        }
+    }
+}
+
+/// The name and the version in the manifest of the package in `dir`.
+fn read_package(dir: &Path) -> Option<(String, Version)> {
+    let text = fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+    let manifest: toml::Value = toml::from_str(&text).ok()?;
+    let package = manifest.get("package")?;
+    let name = package.get("name")?.as_str()?.to_string();
+    let version = Version::parse(package.get("version")?.as_str()?).ok()?;
+    Some((name, version))
+}
+
+#[derive(Serialize)]
+struct Checksums {
+    /// The SHA-256 of each file, by its path from the root of the package.
+    files: BTreeMap<String, String>,
+    /// The checksum of the `.crate` file in Cargo.lock, none for git
+    /// packages.
+    package: Option<String>,
+}
+
+/// Leaves the files `cargo vendor` doesn't copy out of the package checked
+/// out to `dir`, and writes its `.cargo-checksum.json`, with the checksum of
+/// its `.crate` file, if it has one.
+pub(crate) fn finish_package(dir: &Path, package: Option<&str>) -> Result<()> {
+    for name in NOT_VENDORED {
+        let path = dir.join(name);
+        if path.is_file() {
+            fs::remove_file(path)?;
+        }
+    }
+    let mut files = BTreeMap::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        for entry in fs::read_dir(&current)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let relative = path.strip_prefix(dir)?.to_string_lossy().replace('\\', "/");
+            if relative != CHECKSUM_FILE {
+                let digest = Sha256::digest(&fs::read(&path)?);
+                files.insert(relative, format!("{:x}", digest));
+            }
+        }
+    }
+    let checksums = Checksums {
+        files,
+        package: package.map(String::from),
+    };
+    fs::write(dir.join(CHECKSUM_FILE), serde_json::to_string(&checksums)?)?;
+    Ok(())
+}
+
+/// The `[source]` tables of `.cargo/config.toml` which replace the sources
+/// of `packages` with `dest`.
+pub(crate) fn source_config(packages: &[&cargo_metadata::Package], dest: &Path) -> String {
+    let mut tables = BTreeMap::new();
+    for pkg in packages {
+        let source = match &pkg.source {
+            Some(it) => it.to_string(),
+            None => continue,
+        };
+        let (name, table) = match PackageSource::of(pkg) {
+            PackageSource::CratesIo => ("crates-io".to_string(), String::new()),
+            PackageSource::Git(_) => {
+                // Without the commit the revision was resolved to.
+                let source = source.split('#').next().unwrap_or(&source);
+                let url = source.trim_start_matches("git+");
+                let (url, query) = match url.find('?') {
+                    Some(idx) => (&url[..idx], &url[idx + 1..]),
+                    None => (url, ""),
+                };
+                let mut table = format!("git = {:?}\n", url);
+                for param in query.split('&') {
+                    let mut words = param.splitn(2, '=');
+                    if let (Some(key @ ("branch" | "tag" | "rev")), Some(value)) =
+                        (words.next(), words.next())
+                    {
+                        table += &format!("{} = {:?}\n", key, value);
+                    }
+                }
+                (format!("{:?}", source), table)
+            }
+            _ => {
+                let registry = source.trim_start_matches("registry+");
+                (
+                    format!("{:?}", source),
+                    format!("registry = {:?}\n", registry),
+                )
+            }
+        };
+        tables.insert(name, table);
+    }
+    let mut res = String::new();
+    for (name, table) in tables {
+        res += &format!(
+            "[source.{}]\n{}replace-with = {:?}\n\n",
+            name, table, SOURCE_NAME
+        );
+    }
+    res += &format!(
+        "[source.{}]\ndirectory = {:?}\n",
+        SOURCE_NAME,
+        dest.to_string_lossy()
+    );
+    res
+}
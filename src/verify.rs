@@ -0,0 +1,192 @@
+//! `current` checks the sources of the published packages before it copies
+//! them, unless `--no-verify`: the `.crate` file Cargo downloaded must have
+//! the checksum pinned in Cargo.lock, and the sources Cargo unpacked from it,
+//! which are what gets copied, must be its files, with nothing added but the
+//! `.cargo-ok` of Cargo. A package whose `.crate` file isn't in the cache
+//! any more can't be checked, and is flagged.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+use cargo_metadata;
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use tar::{Archive, EntryType};
+use toml;
+
+use {crate_file, pkg_dir, Result};
+
+/// What Cargo writes next to the sources once it unpacked them.
+const UNPACKED_MARKER: &str = ".cargo-ok";
+
+/// How a package was checked, as recorded in `manifest.json`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Verification {
+    /// Both the checksum and the sources are right.
+    Verified,
+    /// Cargo.lock has no checksum for it, as for git and path packages.
+    NoChecksum,
+    /// Its `.crate` file isn't in the cache of Cargo.
+    NotCached,
+    /// `--no-verify`.
+    Skipped,
+}
+
+/// The checksums in a Cargo.lock, by name, version and source.
+#[derive(Debug, Default)]
+pub(crate) struct Checksums {
+    checksums: HashMap<(String, String, String), String>,
+}
+
+impl Checksums {
+    /// The checksums in the Cargo.lock of the workspace at `root`, none if
+    /// there's no such file.
+    pub(crate) fn read(root: &Path) -> Result<Checksums> {
+        let path = root.join("Cargo.lock");
+        let text = match fs::read_to_string(&path) {
+            Ok(it) => it,
+            Err(_) => return Ok(Checksums::default()),
+        };
+        let lockfile: toml::Value = toml::from_str(&text)
+            .map_err(|err| format_err!("can't parse {}: {}", path.display(), err))?;
+        let mut res = Checksums::default();
+        let packages = lockfile.get("package").and_then(|it| it.as_array());
+        for package in packages.into_iter().flatten() {
+            let field = |key| package.get(key).and_then(|it| it.as_str());
+            if let (Some(name), Some(version), Some(source), Some(checksum)) = (
+                field("name"),
+                field("version"),
+                field("source"),
+                field("checksum"),
+            ) {
+                res.checksums.insert(
+                    (name.to_string(), version.to_string(), source.to_string()),
+                    checksum.to_string(),
+                );
+            }
+        }
+        // Version 1 lockfiles keep them apart, as
+        // `"checksum name version (source)" = "..."`.
+        let metadata = lockfile.get("metadata").and_then(|it| it.as_table());
+        for (key, checksum) in metadata.into_iter().flatten() {
+            let mut words = key.splitn(4, ' ');
+            if let (Some("checksum"), Some(name), Some(version), Some(source), Some(checksum)) = (
+                words.next(),
+                words.next(),
+                words.next(),
+                words.next(),
+                checksum.as_str(),
+            ) {
+                let source = source.trim_start_matches('(').trim_end_matches(')');
+                res.checksums.insert(
+                    (name.to_string(), version.to_string(), source.to_string()),
+                    checksum.to_string(),
+                );
+            }
+        }
+        Ok(res)
+    }
+
+    /// The names and the versions of the packages it has the checksums of.
+    pub(crate) fn packages(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.checksums
+            .keys()
+            .map(|(name, version, _)| (name.as_str(), version.as_str()))
+    }
+
+    /// The checksum of the `.crate` file of `pkg`.
+    pub(crate) fn get(&self, pkg: &cargo_metadata::Package) -> Option<&str> {
+        let source = pkg.source.as_ref()?;
+        let key = (
+            pkg.name.clone(),
+            pkg.version.to_string(),
+            source.to_string(),
+        );
+        self.checksums.get(&key).map(String::as_str)
+    }
+}
+
+/// Checks the sources of `pkg` against `checksums`. Fails if they don't
+/// match.
+pub(crate) fn verify(pkg: &cargo_metadata::Package, checksums: &Checksums) -> Result<Verification> {
+    let expected = match checksums.get(pkg) {
+        Some(it) => it,
+        None => return Ok(Verification::NoChecksum),
+    };
+    let src = pkg_dir(pkg)?;
+    let crate_file = crate_file(&src)?;
+    let bytes = match fs::read(&crate_file) {
+        Ok(it) => it,
+        Err(_) => return Ok(Verification::NotCached),
+    };
+    let actual = format!("{:x}", Sha256::digest(&bytes));
+    if actual != expected {
+        bail!(
+            "checksum mismatch for {} {}: Cargo.lock has sha256={}, {} has sha256={}\n\
+             The sources might have been tampered with!",
+            pkg.name,
+            pkg.version,
+            expected,
+            crate_file.display(),
+            actual
+        );
+    }
+    if let Some(path) = changed_file(&bytes, &src)? {
+        bail!(
+            "{} of {} {} isn't the one of its .crate file, in {}\n\
+             The sources might have been tampered with!",
+            path.display(),
+            pkg.name,
+            pkg.version,
+            src.display()
+        );
+    }
+    Ok(Verification::Verified)
+}
+
+/// A file of `src` which isn't in the `.crate` file `crate_bytes`, or
+/// differs from it, or one of the `.crate` which isn't in `src`.
+fn changed_file(crate_bytes: &[u8], src: &Path) -> Result<Option<PathBuf>> {
+    let mut archive = Archive::new(GzDecoder::new(crate_bytes));
+    let mut packed = HashSet::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type() != EntryType::Regular {
+            continue;
+        }
+        // The files are under a `name-version` directory.
+        let path: PathBuf = entry.path()?.components().skip(1).collect();
+        if path.components().any(|it| it == Component::ParentDir) {
+            bail!("{} has a file outside of its directory", src.display());
+        }
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        let mut unpacked = Vec::new();
+        let same = File::open(src.join(&path))
+            .and_then(|mut it| it.read_to_end(&mut unpacked))
+            .is_ok()
+            && unpacked == contents;
+        if !same {
+            return Ok(Some(path));
+        }
+        packed.insert(path);
+    }
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(src.join(&dir))? {
+            let entry = entry?;
+            let path = dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else if !packed.contains(&path) && path != Path::new(UNPACKED_MARKER) {
+                return Ok(Some(path));
+            }
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,79 @@
+//! The yanked versions among the packages of `current`: Cargo keeps using a
+//! yanked version as long as Cargo.lock pins it, without a word. The index of
+//! crates.io tells which ones are, and their newest version which isn't.
+//!
+//! Each crate is looked up once, however many versions of it there are, and
+//! the lookups run in parallel. Nothing is looked up with `--offline`.
+
+use std::collections::BTreeSet;
+
+use semver::Version;
+
+use index::{self, IndexEntry};
+use {enumerate, packages_word, warning_label, Result};
+
+#[derive(Debug)]
+pub(crate) struct Yanked {
+    name: String,
+    version: Version,
+    /// The newest version which isn't yanked, if there's one.
+    newest: Option<Version>,
+}
+
+/// The yanked versions among the `packages` of crates.io, as names and
+/// versions, looked up on `jobs` threads.
+pub(crate) fn audit<'a>(
+    packages: impl IntoIterator<Item = (&'a str, &'a Version)>,
+    jobs: usize,
+) -> Result<Vec<Yanked>> {
+    let packages: BTreeSet<(&str, &Version)> = packages.into_iter().collect();
+    let names: BTreeSet<&str> = packages.iter().map(|it| it.0).collect();
+    let names: Vec<String> = names.into_iter().map(String::from).collect();
+    let versions = index::versions_of(&names, jobs)?;
+    let mut res = Vec::new();
+    for (name, version) in packages {
+        let entries: &[IndexEntry] = match versions.get(name) {
+            Some(it) => it,
+            None => continue,
+        };
+        if entries.iter().any(|it| it.version == *version && it.yanked) {
+            res.push(Yanked {
+                name: name.to_string(),
+                version: version.clone(),
+                newest: index::latest(entries, false).map(|it| it.version.clone()),
+            });
+        }
+    }
+    Ok(res)
+}
+
+/// Warns about the `yanked` versions, unless `quiet`, or fails with `deny`.
+pub(crate) fn report(yanked: &[Yanked], deny: bool, quiet: bool) -> Result<()> {
+    if yanked.is_empty() {
+        return Ok(());
+    }
+    let names: Vec<String> = yanked
+        .iter()
+        .map(|it| format!("{} {}", it.name, it.version))
+        .collect();
+    if deny {
+        bail!(
+            "{} yanked from crates.io: {}",
+            packages_word(yanked.len()),
+            enumerate(&names)
+        );
+    }
+    note!(
+        quiet,
+        "{} {} yanked from crates.io, review them with extra care:",
+        warning_label(),
+        packages_word(yanked.len())
+    );
+    for (it, name) in yanked.iter().zip(names) {
+        match &it.newest {
+            Some(newest) => note!(quiet, "  {}, the newest version is {}", name, newest),
+            None => note!(quiet, "  {}, all its versions are yanked", name),
+        }
+    }
+    Ok(())
+}
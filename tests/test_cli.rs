@@ -1,9 +1,19 @@
 extern crate assert_cli;
+extern crate flate2;
+extern crate tar;
 extern crate tempdir;
+extern crate ureq;
 
-use std::{env, fs, path::PathBuf, process::Command};
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+};
 
-use assert_cli::Assert;
+use assert_cli::{Assert, Environment};
 
 fn cmd_diff() -> Assert {
     base_cmd().with_args(&["diff"])
@@ -20,7 +30,7 @@ fn cmd_update_diff() -> Assert {
 #[test]
 fn diff_shows_diff() {
     match std::process::Command::new("diff")
-        .args(&["--color=auto", "-", "-"])
+        .args(["--color=auto", "-", "-"])
         .status()
     {
         Ok(s) if s.success() => (),
@@ -32,6 +42,7 @@ fn diff_shows_diff() {
 
     cmd_diff()
         .with_args(&["rand:0.6.0", "rand:0.6.1"])
+        .fails_with(1)
         .stdout()
         .contains("< version = \"0.6.0\"")
         .unwrap();
@@ -43,28 +54,141 @@ fn diff_reports_error_for_invalid_package_id() {
         .with_args(&["rand:0.6.0", "rand-0.6.1"])
         .fails_with(101)
         .stderr()
-        .contains("error: invalid package specification: \"rand-0.6.1\"; expected \"name:x.y.z\"")
+        .contains("error: invalid package specification: \"rand-0.6.1\"; expected \"name\", \"name:x.y.z\", \"name@x.y.z\", \"name:x.y.z@registry\" or \"name:git+URL#REV\"")
         .unwrap();
 }
 
 #[test]
-fn diff_copies_sources_to_dest() {
+fn diff_accepts_cargo_spec_syntax() {
     let dir = tempdir::TempDir::new("diff-tests").unwrap();
     cmd_diff()
-        .with_args(&["rand:0.6.0", "rand:0.6.1", "--destination"])
+        .with_args(&["rand@0.6.0", "rand@0.6.1", "--destination"])
         .with_args(&[dir.path()])
-        .stdout()
-        .is("")
         .unwrap();
-    assert!(dir.path().join("rand:0.6.0").exists());
-    assert!(dir.path().join("rand:0.6.1").exists());
+    assert!(dir.path().join("old/rand-0.6.0").exists());
+    assert!(dir.path().join("new/rand-0.6.1").exists());
 }
 
 #[test]
-fn current_reports_deps() -> std::io::Result<()> {
+fn diff_resolves_bare_name_to_latest_version() {
+    let dir = tempdir::TempDir::new("diff-tests").unwrap();
+    // `void` has not seen a release since 2016.
+    cmd_diff()
+        .with_args(&["void:1.0.1", "void", "--destination"])
+        .with_args(&[dir.path()])
+        .stderr()
+        .contains("Resolved `void:latest` to void:1.0.2")
+        .unwrap();
+    assert!(dir.path().join("old/void-1.0.1").exists());
+    assert!(dir.path().join("new/void-1.0.2").exists());
+}
+
+#[test]
+fn diff_resolves_prev_relative_to_other_side() {
+    let dir = tempdir::TempDir::new("diff-tests").unwrap();
+    cmd_diff()
+        .with_args(&["void:prev", "void:1.0.2", "--destination"])
+        .with_args(&[dir.path()])
+        .stderr()
+        .contains("Resolved `void:prev` to void:1.0.1")
+        .unwrap();
+    assert!(dir.path().join("old/void-1.0.1").exists());
+    assert!(dir.path().join("new/void-1.0.2").exists());
+}
+
+#[test]
+fn diff_accepts_minus_one_for_prev() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    cmd_diff()
+        .with_args(&["void:-1", "void:1.0.2", "--destination"])
+        .with_args(&[dir.path()])
+        .stderr()
+        .contains("Resolved `void:prev` to void:1.0.1")
+        .unwrap();
+    assert!(dir.path().join("old/void-1.0.1").exists());
+    Ok(())
+}
+
+#[test]
+fn diff_compares_a_single_crate_with_the_locked_version() -> std::io::Result<()> {
     let project_dir = tempdir::TempDir::new("temp-project")?;
-    let dest = project_dir.path().join("dest");
+    fs::write(
+        project_dir.path().join("Cargo.toml"),
+        r#"
+        [package]
+        name = "test-pkg"
+        version = "0.0.0"
+
+        [dependencies]
+        void = "=1.0.1"
+
+        [lib]
+        path = "./Cargo.toml"
+    "#,
+    )?;
+    cmd_diff()
+        .current_dir(project_dir.path())
+        .with_args(&["void:1.0.2", "--brief"])
+        .fails_with(1)
+        .stderr()
+        .contains("Resolved `void:locked` to void:1.0.1")
+        .stdout()
+        .contains("M src/lib.rs")
+        .unwrap();
+    cmd_diff()
+        .current_dir(project_dir.path())
+        .with_args(&["rand:0.6.1"])
+        .fails_with(101)
+        .stderr()
+        .contains(
+            "error: `rand` is not a dependency of the current workspace; \
+             pass both crates to diff, like `rand:OLD rand:NEW`",
+        )
+        .unwrap();
+
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    cmd_diff()
+        .current_dir(dir.path())
+        .with_args(&["void:1.0.2"])
+        .fails_with(101)
+        .stderr()
+        .contains("Outside of one, pass both crates to diff, like `void:OLD void:NEW`.")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_only_takes_the_locked_version_of_crates_io_packages() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    // Nothing but its source tells a path dependency from crates.io.
+    let pdep = dir.path().join("crates.io-index/void");
+    fs::create_dir_all(pdep.join("src"))?;
+    fs::write(pdep.join("src/lib.rs"), "")?;
+    fs::write(
+        pdep.join("Cargo.toml"),
+        "[package]\nname = \"void\"\nversion = \"1.0.1\"\n",
+    )?;
+    let project = dir.path().join("project");
+    fs::create_dir_all(project.join("src"))?;
+    fs::write(project.join("src/lib.rs"), "")?;
+    fs::write(
+        project.join("Cargo.toml"),
+        "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+         [dependencies]\nvoid = { path = \"../crates.io-index/void\" }\n",
+    )?;
+    cmd_diff()
+        .current_dir(&project)
+        .with_args(&["void:1.0.2"])
+        .fails_with(101)
+        .stderr()
+        .contains("error: `void` is not a dependency of the current workspace")
+        .unwrap();
+    Ok(())
+}
 
+#[test]
+fn diff_resolves_prev_relative_to_locked_version() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
     fs::write(
         project_dir.path().join("Cargo.toml"),
         r#"
@@ -73,28 +197,30 @@ fn current_reports_deps() -> std::io::Result<()> {
         version = "0.0.0"
 
         [dependencies]
-        thread_local = "=0.3.6"
+        void = "=1.0.1"
 
         [lib]
         path = "./Cargo.toml"
     "#,
     )?;
-    cmd_current()
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    // The local package has no published version to count from.
+    cmd_diff()
         .current_dir(project_dir.path())
+        .with_args(&["void:prev", "path:.", "--allow-different-names"])
         .with_args(&["--destination"])
-        .with_args(&[&dest.as_path()])
+        .with_args(&[dir.path()])
         .stderr()
-        .contains("Skipping package `test-pkg`")
+        .contains("Resolved `void:prev` to void:1.0.0")
         .unwrap();
-    assert!(dest.join("thread_local:0.3.6").exists());
+    assert!(dir.path().join("old/void-1.0.0").exists());
+    assert!(dir.path().join("new/test-pkg-local").exists());
     Ok(())
 }
 
 #[test]
-fn update_diff_dumps_changed_crates() -> std::io::Result<()> {
+fn diff_resolves_bare_first_name_to_locked_version() -> std::io::Result<()> {
     let project_dir = tempdir::TempDir::new("temp-project")?;
-    let dest = project_dir.path().join("dest");
-
     fs::write(
         project_dir.path().join("Cargo.toml"),
         r#"
@@ -103,60 +229,5746 @@ fn update_diff_dumps_changed_crates() -> std::io::Result<()> {
         version = "0.0.0"
 
         [dependencies]
-        thread_local = "0.3"
+        void = "=1.0.1"
 
         [lib]
         path = "./Cargo.toml"
     "#,
     )?;
-    let run_cargo = |args: &[&str]| -> std::io::Result<()> {
-        let status = Command::new("cargo")
-            .current_dir(project_dir.path())
-            .args(args)
-            .status()?;
-        assert!(status.success());
-        Ok(())
-    };
-
-    run_cargo(&["generate-lockfile"])?;
-    run_cargo(&["update", "--package", "thread_local", "--precise", "0.3.3"])?;
-    let lockfile = fs::read_to_string(project_dir.path().join("Cargo.lock"))?;
-    cmd_update_diff()
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    cmd_diff()
         .current_dir(project_dir.path())
-        .with_args(&["--destination"])
-        .with_args(&[&dest.as_path()])
-        .with_args(&["--", "--package", "thread_local", "--precise", "0.3.4"])
+        .with_args(&["void", "void", "--destination"])
+        .with_args(&[dir.path()])
         .stderr()
-        .contains("thread_local v0.3.3 -> v0.3.4")
+        .contains("Resolved `void:locked` to void:1.0.1")
+        .stderr()
+        .contains("Resolved `void:latest` to void:1.0.2")
         .unwrap();
-    assert_eq!(
-        lockfile,
-        fs::read_to_string(project_dir.path().join("Cargo.lock"))?,
-    );
-    assert!(dest.join("before/thread_local:0.3").exists());
-    assert!(dest.join("after/thread_local:0.3").exists());
+    assert!(dir.path().join("old/void-1.0.1").exists());
+    assert!(dir.path().join("new/void-1.0.2").exists());
     Ok(())
 }
 
-// Adapted from
-// https://github.com/rust-lang/cargo/blob/485670b3983b52289a2f353d589c57fae2f60f82/tests/testsuite/support/mod.rs#L507
-fn target_dir() -> PathBuf {
-    env::current_exe()
-        .ok()
-        .map(|mut path| {
-            path.pop();
-            if path.ends_with("deps") {
-                path.pop();
-            }
-            path
-        })
-        .unwrap()
+#[test]
+fn diff_reports_error_for_locked_version_outside_of_project() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    cmd_diff()
+        .current_dir(dir.path())
+        .with_args(&["void:locked", "void:1.0.2"])
+        .fails_with(101)
+        .stderr()
+        .contains("error: can't find the locked version of `void`, are you inside a Cargo project?")
+        .unwrap();
+    Ok(())
 }
 
-fn cargo_review_deps_exe() -> PathBuf {
-    target_dir().join(format!("cargo-review-deps{}", env::consts::EXE_SUFFIX))
+#[test]
+fn diff_normalizes_dashes_and_underscores_in_names() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    cmd_diff()
+        .with_args(&["thread-local:1.0.0", "thread-local:1.0.1", "--destination"])
+        .with_args(&[dir.path()])
+        .stderr()
+        .contains("Resolved `thread-local:1.0.0` to thread_local:1.0.0")
+        .unwrap();
+    assert!(dir.path().join("old/thread_local-1.0.0").exists());
+    assert!(dir.path().join("new/thread_local-1.0.1").exists());
+    Ok(())
 }
 
-fn base_cmd() -> Assert {
-    Assert::command(&[&cargo_review_deps_exe()]).with_args(&["review-deps"])
+#[test]
+fn diff_verifies_checksums() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    cmd_diff()
+        .with_args(&[
+            "void:1.0.1",
+            "void:1.0.2:sha256=6a02e4885ed3bc0f2de90ea6dd45ebcbb66dacffe03547fadbb0eeae2770887d",
+            "--destination",
+        ])
+        .with_args(&[dir.path()])
+        .stderr()
+        .contains(
+            "Checksum of void:1.0.1: sha256=bf1a31caf5a37d644b05946ac69108e2b39917aa5c203fd33eaf5b99adf8d2d2",
+        )
+        .stderr()
+        .contains("Verified checksum of void:1.0.2")
+        .unwrap();
+    assert!(dir.path().join("new/void-1.0.2").exists());
+    Ok(())
+}
+
+#[test]
+fn diff_reports_error_for_checksum_mismatch() {
+    cmd_diff()
+        .with_args(&[
+            "void:1.0.1:sha256=6a02e4885ed3bc0f2de90ea6dd45ebcbb66dacffe03547fadbb0eeae2770887d",
+            "void:1.0.2",
+        ])
+        .fails_with(101)
+        .stderr()
+        .contains("error: checksum mismatch for void:1.0.1")
+        .stderr()
+        .contains("The sources might have been tampered with!")
+        .unwrap();
+}
+
+#[test]
+fn diff_accepts_version_range() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    cmd_diff()
+        .with_args(&["void:1.0.1..1.0.2", "--destination"])
+        .with_args(&[dir.path()])
+        .unwrap();
+    assert!(dir.path().join("old/void-1.0.1").exists());
+    assert!(dir.path().join("new/void-1.0.2").exists());
+    Ok(())
+}
+
+#[test]
+fn diff_reports_error_for_range_across_crates() {
+    cmd_diff()
+        .with_args(&["rand:0.6.0..rand_core:0.6.1"])
+        .fails_with(101)
+        .stderr()
+        .contains("error: invalid diff specification: \"rand:0.6.0..rand_core:0.6.1\"; both sides must be the same crate, got `rand` and `rand_core`")
+        .unwrap();
+}
+
+#[test]
+fn diff_accepts_registry_package_id_urls() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    cmd_diff()
+        .with_args(&[
+            "registry+https://github.com/rust-lang/crates.io-index#void@1.0.1",
+            "sparse+https://index.crates.io/#void@1.0.2",
+            "--destination",
+        ])
+        .with_args(&[dir.path()])
+        .unwrap();
+    assert!(dir.path().join("old/void-1.0.1").exists());
+    assert!(dir.path().join("new/void-1.0.2").exists());
+    Ok(())
+}
+
+#[test]
+fn diff_reports_error_for_unknown_registry_url() {
+    cmd_diff()
+        .with_args(&["registry+https://example.com/index#void@1.0.1", "void:1.0.2"])
+        .fails_with(101)
+        .stderr()
+        .contains("only crates.io registry urls are known, use \"name:x.y.z@registry\" for other registries")
+        .unwrap();
+}
+
+#[test]
+fn diff_reads_pairs_from_file() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    let pairs = dir.path().join("pairs.txt");
+    fs::write(&pairs, "void:1.0.0 void:1.0.1 # first\nvoid:1.0.1..1.0.2\n")?;
+    let dest = dir.path().join("dest");
+    cmd_diff()
+        .with_args(&["--specs-from"])
+        .with_args(&[&pairs])
+        .with_args(&["--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    assert!(dest.join("old/void-1.0.0").exists());
+    assert!(dest.join("old/void-1.0.1").exists());
+    assert!(dest.join("new/void-1.0.1").exists());
+    assert!(dest.join("new/void-1.0.2").exists());
+    Ok(())
+}
+
+#[test]
+fn diff_enables_features() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    cmd_diff()
+        .with_args(&["void:1.0.1", "void:1.0.2", "--no-default-features"])
+        .with_args(&["--features", "std", "--destination"])
+        .with_args(&[dir.path()])
+        .unwrap();
+    assert!(dir.path().join("new/void-1.0.2").exists());
+    Ok(())
+}
+
+#[test]
+fn diff_reports_error_for_unknown_feature() {
+    cmd_diff()
+        .with_args(&[
+            "void:1.0.1",
+            "void:1.0.2",
+            "--features",
+            "std,no-such-feature",
+        ])
+        .fails_with(101)
+        .stderr()
+        .contains(
+            "error: void:1.0.1 has no feature `no-such-feature`; available features: default, std",
+        )
+        .unwrap();
+}
+
+#[test]
+fn diff_runs_custom_tool() {
+    cmd_diff()
+        .with_args(&[
+            "void:1.0.1",
+            "void:1.0.2",
+            "--tool",
+            "echo",
+            "--",
+            "--custom-arg",
+        ])
+        .stdout()
+        .contains("--custom-arg ")
+        .stdout()
+        .contains("void-1.0.2")
+        .unwrap();
+}
+
+#[test]
+fn diff_reports_error_for_failed_tool() {
+    // Exit code 1 means that there are differences.
+    cmd_diff()
+        .with_args(&["void:1.0.1", "void:1.0.2", "--tool", "false"])
+        .fails_with(1)
+        .unwrap();
+    cmd_diff()
+        .with_args(&["void:1.0.1", "void:1.0.2", "--tool", "sh"])
+        .with_args(&["--", "-c", "exit 2", "sh"])
+        .fails_with(101)
+        .stderr()
+        .contains("error: `sh` failed: exit status: 2")
+        .unwrap();
+}
+
+#[test]
+fn diff_suggests_similar_crate_names() {
+    cmd_diff()
+        .with_args(&["serde_jsno:1.0.0", "serde_json:1.0.1"])
+        .fails_with(101)
+        .stderr()
+        .contains("error: crate `serde_jsno` not found on crates.io; did you mean `serde_json`?")
+        .unwrap();
+}
+
+#[test]
+fn diff_resolves_version_requirements() {
+    let dir = tempdir::TempDir::new("diff-tests").unwrap();
+    cmd_diff()
+        .with_args(&["void:<1.0.2", "void:1", "--destination"])
+        .with_args(&[dir.path()])
+        .stderr()
+        .contains("Resolved `void:< 1.0.2` to void:1.0.1")
+        .stderr()
+        .contains("Resolved `void:^1` to void:1.0.2")
+        .unwrap();
+    assert!(dir.path().join("old/void-1.0.1").exists());
+    assert!(dir.path().join("new/void-1.0.2").exists());
+}
+
+#[test]
+fn diff_reports_error_for_unsatisfiable_requirement() {
+    cmd_diff()
+        .with_args(&["void:1.0.1", "void:^9"])
+        .fails_with(101)
+        .stderr()
+        .contains("error: no version of `void` matches `^9`")
+        .unwrap();
+}
+
+#[test]
+fn diff_reports_error_for_unknown_crate() {
+    cmd_diff()
+        .with_args(&["rand:0.6.0", "no-such-crate-cargo-review-deps"])
+        .fails_with(101)
+        .stderr()
+        .contains("error: crate `no-such-crate-cargo-review-deps` not found on crates.io")
+        .unwrap();
+}
+
+#[test]
+fn diff_reports_error_for_unknown_crate_version() {
+    cmd_diff()
+        .with_args(&["void:1.0.0", "void:9.9.9"])
+        .fails_with(101)
+        .stderr()
+        .contains("error: crate `void` has no version 9.9.9 (latest is 1.0.2)")
+        .unwrap();
+}
+
+#[test]
+fn diff_reports_error_for_unknown_crate_with_exact_version() {
+    cmd_diff()
+        .with_args(&["no-such-crate-cargo-review-deps:1.0.0", "void:1.0.2"])
+        .fails_with(101)
+        .stderr()
+        .contains("error: crate `no-such-crate-cargo-review-deps` not found on crates.io")
+        .unwrap();
+}
+
+#[test]
+fn diff_reports_error_for_empty_registry() {
+    cmd_diff()
+        .with_args(&["rand:0.6.0@", "rand:0.6.1"])
+        .fails_with(101)
+        .stderr()
+        .contains("error: invalid package specification: \"rand:0.6.0@\"; registry name is empty")
+        .unwrap();
+}
+
+#[test]
+fn diff_passes_registry_to_cargo() {
+    cmd_diff()
+        .with_args(&["rand:0.6.0@no-such-registry", "rand:0.6.1"])
+        .fails_with(101)
+        .stderr()
+        .contains("registry index was not found in any configuration: `no-such-registry`")
+        .unwrap();
+}
+
+#[test]
+fn diff_copies_sources_from_alternative_registry() {
+    let dir = tempdir::TempDir::new("diff-tests").unwrap();
+    cmd_diff()
+        .with_env(mirror_registry_env())
+        .with_args(&["rand:0.6.0@mirror", "rand:0.6.1", "--destination"])
+        .with_args(&[dir.path()])
+        .unwrap();
+    assert!(dir.path().join("old/rand-0.6.0_mirror").exists());
+    assert!(dir.path().join("new/rand-0.6.1").exists());
+}
+
+#[test]
+fn diff_reports_error_for_invalid_git_source() {
+    for spec in &[
+        "serde:git+https://github.com/serde-rs/serde",
+        "serde:git+https://github.com/serde-rs/serde#",
+        "serde:git+github.com/serde-rs/serde#a1b2c3d",
+    ] {
+        cmd_diff()
+            .with_args(&["serde:1.0.80", spec])
+            .fails_with(101)
+            .stderr()
+            .contains(
+                format!(
+                    "error: invalid git source: {:?}; expected \"name:git+URL#REV\"",
+                    spec
+                )
+                .as_str(),
+            )
+            .unwrap();
+    }
+}
+
+/// A git repository of package `foo` with two commits, `old` and `new`.
+/// Returns the revisions of both.
+fn git_repo_with_two_revisions(repo_dir: &tempdir::TempDir) -> std::io::Result<(String, String)> {
+    let git = |args: &[&str]| -> std::io::Result<String> {
+        let output = Command::new("git")
+            .current_dir(repo_dir.path())
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()?;
+        assert!(output.status.success());
+        Ok(String::from_utf8(output.stdout).unwrap().trim().to_string())
+    };
+    fs::create_dir(repo_dir.path().join("src"))?;
+    fs::write(
+        repo_dir.path().join("Cargo.toml"),
+        "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+    )?;
+    fs::write(repo_dir.path().join("src/lib.rs"), "pub fn old() {}\n")?;
+    git(&["init", "--quiet"])?;
+    git(&["symbolic-ref", "HEAD", "refs/heads/master"])?;
+    git(&["add", "."])?;
+    git(&["commit", "--quiet", "-m", "old"])?;
+    let old_rev = git(&["rev-parse", "HEAD"])?;
+    fs::write(repo_dir.path().join("src/lib.rs"), "pub fn new() {}\n")?;
+    git(&["commit", "--quiet", "-am", "new"])?;
+    let new_rev = git(&["rev-parse", "HEAD"])?;
+    Ok((old_rev, new_rev))
+}
+
+#[test]
+fn diff_shows_diff_of_git_revisions() -> std::io::Result<()> {
+    let repo_dir = tempdir::TempDir::new("git-repo")?;
+    let (old_rev, new_rev) = git_repo_with_two_revisions(&repo_dir)?;
+    let url = format!("file://{}", repo_dir.path().display());
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    cmd_diff()
+        .with_args(&[
+            format!("foo:git+{}#{}", url, old_rev),
+            format!("foo:git+{}#{}", url, new_rev),
+        ])
+        .with_args(&["--destination"])
+        .with_args(&[dir.path()])
+        .unwrap();
+    let old_src = dir
+        .path()
+        .join(format!("old/foo-git.{}", old_rev))
+        .join("src/lib.rs");
+    let new_src = dir
+        .path()
+        .join(format!("new/foo-git.{}", new_rev))
+        .join("src/lib.rs");
+    assert_eq!(fs::read_to_string(old_src)?, "pub fn old() {}\n");
+    assert_eq!(fs::read_to_string(new_src)?, "pub fn new() {}\n");
+    Ok(())
+}
+
+#[test]
+fn diff_accepts_git_package_id_urls() -> std::io::Result<()> {
+    let repo_dir = tempdir::TempDir::new("git-repo")?;
+    let (old_rev, _) = git_repo_with_two_revisions(&repo_dir)?;
+    let url = format!("file://{}", repo_dir.path().display());
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    cmd_diff()
+        .with_args(&[
+            format!("git+{}?rev={}#foo@0.1.0", url, old_rev),
+            format!("git+{}?branch=master#foo@0.1.0", url),
+        ])
+        .with_args(&["--destination"])
+        .with_args(&[dir.path()])
+        .unwrap();
+    let old_src = dir
+        .path()
+        .join(format!("old/foo-git.{}", old_rev))
+        .join("src/lib.rs");
+    let new_src = dir.path().join("new/foo-git.refs_heads_master/src/lib.rs");
+    assert_eq!(fs::read_to_string(old_src)?, "pub fn old() {}\n");
+    assert_eq!(fs::read_to_string(new_src)?, "pub fn new() {}\n");
+    Ok(())
+}
+
+#[test]
+fn diff_copies_local_package_without_build_artifacts() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    fs::write(
+        project_dir.path().join("Cargo.toml"),
+        "[package]\nname = \"void\"\nversion = \"1.0.3\"\n",
+    )?;
+    fs::create_dir_all(project_dir.path().join("src"))?;
+    fs::write(project_dir.path().join("src/lib.rs"), "")?;
+    fs::create_dir_all(project_dir.path().join("target/debug"))?;
+
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    cmd_diff()
+        .with_args(&["void:1.0.2"])
+        .with_args(&[format!("path:{}", project_dir.path().display())])
+        .with_args(&["--destination"])
+        .with_args(&[dir.path()])
+        .unwrap();
+    assert!(dir.path().join("old/void-1.0.2").exists());
+    assert!(dir.path().join("new/void-local/src/lib.rs").exists());
+    assert!(!dir.path().join("new/void-local/target").exists());
+    Ok(())
+}
+
+#[test]
+fn diff_shows_builtin_diff() {
+    cmd_diff()
+        .with_args(&["rand:0.6.0", "rand:0.6.1", "--builtin"])
+        .fails_with(1)
+        .stdout()
+        .contains("--- rand-0.6.0/Cargo.toml\n+++ rand-0.6.1/Cargo.toml\n")
+        .stdout()
+        .contains(
+            "-version = \"0.6.0\" # NB: When modifying, also modify html_root_url in lib.rs\n\
+             +version = \"0.6.1\"\n",
+        )
+        .unwrap();
+}
+
+#[test]
+fn diff_shows_builtin_diff_of_added_removed_and_binary_files() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::create_dir(dir.path().join("src"))?;
+    }
+    fs::write(old_dir.path().join("src/lib.rs"), "1\n2\n3\n4\n5\n")?;
+    fs::write(new_dir.path().join("src/lib.rs"), "1\n2\n3\n4\nfive\n")?;
+    fs::write(old_dir.path().join("src/removed.rs"), "gone\n")?;
+    fs::write(new_dir.path().join("src/added.rs"), "new\n")?;
+    fs::write(old_dir.path().join("blob.bin"), b"\0old")?;
+    fs::write(new_dir.path().join("blob.bin"), b"\0new")?;
+
+    cmd_diff()
+        .with_args(&[
+            format!("path:{}", old_dir.path().display()),
+            format!("path:{}", new_dir.path().display()),
+        ])
+        .with_args(&["--builtin", "--context", "1"])
+        .fails_with(1)
+        .stdout()
+        .contains("Binary files foo-local/blob.bin and foo-local/blob.bin differ")
+        .stdout()
+        .contains("--- /dev/null\n+++ foo-local/src/added.rs\n@@ -0,0 +1 @@\n+new\n")
+        .stdout()
+        .contains("@@ -4,2 +4,2 @@\n 4\n-5\n+five\n")
+        .stdout()
+        .contains("--- foo-local/src/removed.rs\n+++ /dev/null\n")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_ignores_whitespace() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+    }
+    fs::write(
+        old_dir.path().join("fmt.rs"),
+        "fn main() {\n  if x {\n    y();\n  }\n}\n",
+    )?;
+    fs::write(
+        new_dir.path().join("fmt.rs"),
+        "fn main() {\n    if x {\n        y();\n    }\n}\n",
+    )?;
+    fs::write(old_dir.path().join("lib.rs"), "fn a() {\n  1\n}\n")?;
+    fs::write(new_dir.path().join("lib.rs"), "fn a() {\n    2\n}\n")?;
+    let diff = |args: &[&str]| {
+        cmd_diff()
+            .with_args(&[
+                format!("path:{}", old_dir.path().display()),
+                format!("path:{}", new_dir.path().display()),
+            ])
+            .with_args(args)
+            .fails_with(1)
+    };
+
+    diff(&["--stat", "--ignore-whitespace"])
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n \
+             lib.rs | 2 +-\n \
+             1 file changed, 1 insertion(+), 1 deletion(-)\n \
+             Formatting-only changes:\n  \
+             fmt.rs")
+        .unwrap();
+    diff(&["--builtin", "--ignore-whitespace", "-U", "0"])
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n\
+             Formatting-only changes in foo-local/fmt.rs and foo-local/fmt.rs\n\
+             --- foo-local/lib.rs\n\
+             +++ foo-local/lib.rs\n\
+             @@ -2 +2 @@\n\
+             -  1\n\
+             +    2")
+        .unwrap();
+    diff(&["--format", "json", "--ignore-whitespace"])
+        .stdout()
+        .contains("\"formatting_only\": [\n    \"fmt.rs\"\n  ]")
+        .unwrap();
+    // Without the option, formatting is a change like any other.
+    diff(&["--stat"])
+        .stdout()
+        .contains(" 2 files changed, 4 insertions(+), 4 deletions(-)")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_shows_side_by_side_diff() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+    }
+    fs::write(
+        old_dir.path().join("main.rs"),
+        "fn main() {\n    println!(\"hello\");\n}\n",
+    )?;
+    fs::write(
+        new_dir.path().join("main.rs"),
+        "fn main() {\n    println!(\"hello, world, this line is long\");\n    run();\n}\n",
+    )?;
+
+    cmd_diff()
+        .with_args(&[
+            format!("path:{}", old_dir.path().display()),
+            format!("path:{}", new_dir.path().display()),
+        ])
+        .with_args(&["--side-by-side", "--width", "60"])
+        .fails_with(1)
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n\
+             foo-local/main.rs              foo-local/main.rs\n\
+             @@ -1,3 +1,4 @@\n   \
+             1 fn main() {                  1 fn main() {\n   \
+             2     println!(\"hello\");  |    2     println!(\"hello, wo\n                                    \
+             rld, this line is long\"\n                                    \
+             );\n                             \
+             >    3     run();\n   \
+             3 }                            4 }")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_rejects_too_narrow_side_by_side_diff() {
+    cmd_diff()
+        .with_args(&["void:1.0.1", "void:1.0.2", "-y", "--width", "10"])
+        .fails()
+        .stderr()
+        .contains("--width must be at least 20")
+        .unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn diff_shows_stat_without_diff_command() -> std::io::Result<()> {
+    // Only Cargo is on the PATH.
+    let bin_dir = tempdir::TempDir::new("bin")?;
+    let cargo = PathBuf::from(env::var_os("CARGO").unwrap());
+    std::os::unix::fs::symlink(&cargo, bin_dir.path().join("cargo"))?;
+    cmd_diff()
+        .with_env(
+            Environment::inherit()
+                .insert("PATH", bin_dir.path())
+                .insert("RUSTC", cargo.with_file_name("rustc")),
+        )
+        .with_args(&["void:1.0.1", "void:1.0.2", "--stat"])
+        .fails_with(1)
+        .stdout()
+        .contains(" Cargo.toml  |  2 +-\n")
+        .stdout()
+        .contains(" 3 files changed, 18 insertions(+), 5 deletions(-)")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_shows_stat_of_added_removed_and_binary_files() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+    }
+    fs::write(old_dir.path().join("removed.rs"), "gone\n")?;
+    fs::write(new_dir.path().join("added.rs"), "new\nnew\n")?;
+    fs::write(old_dir.path().join("blob.bin"), b"\0old")?;
+    fs::write(new_dir.path().join("blob.bin"), b"\0newer")?;
+
+    cmd_diff()
+        .with_args(&[
+            format!("path:{}", old_dir.path().display()),
+            format!("path:{}", new_dir.path().display()),
+        ])
+        .with_args(&["--stat"])
+        .fails_with(1)
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n \
+             added.rs (added)     | 2 ++\n \
+              blob.bin             | Bin 4 -> 6 bytes\n \
+              removed.rs (deleted) | 1 -\n \
+             3 files changed, 2 insertions(+), 1 deletion(-)")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_excludes_files_matching_globs() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::create_dir_all(dir.path().join("src/snapshots"))?;
+        fs::create_dir_all(dir.path().join("tests/fixtures"))?;
+    }
+    fs::write(old_dir.path().join("src/lib.rs"), "old\n")?;
+    fs::write(new_dir.path().join("src/lib.rs"), "new\n")?;
+    fs::write(old_dir.path().join("src/snapshots/lib.snap"), "old\n")?;
+    fs::write(new_dir.path().join("src/snapshots/lib.snap"), "new\n")?;
+    fs::write(old_dir.path().join("tests/fixtures/data.txt"), "old\n")?;
+    fs::write(new_dir.path().join("tests/fixtures/data.txt"), "new\n")?;
+    fs::write(new_dir.path().join("tests/it.rs"), "new\n")?;
+
+    cmd_diff()
+        .with_args(&[
+            format!("path:{}", old_dir.path().display()),
+            format!("path:{}", new_dir.path().display()),
+        ])
+        .with_args(&["--stat", "--exclude", "tests/**", "--exclude", "*.snap"])
+        .fails_with(1)
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n \
+             src/lib.rs | 2 +-\n \
+             1 file changed, 1 insertion(+), 1 deletion(-)")
+        .stderr()
+        .contains("Filtered out 2 files of foo:local and 3 files of foo:local")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_includes_build_files_unless_strict() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for (dir, version) in &[(&old_dir, "0.1.0"), (&new_dir, "0.2.0")] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            format!(
+                "[package]\nname = \"foo\"\nversion = \"{}\"\nbuild = \"gen.rs\"\n\n\
+                 [lib]\nproc-macro = true\npath = \"macros.rs\"\n",
+                version
+            ),
+        )?;
+        fs::create_dir_all(dir.path().join("src/inner"))?;
+        for file in &["gen.rs", "macros.rs", "src/inner/a.rs", "README.md"] {
+            fs::write(dir.path().join(file), version)?;
+        }
+    }
+    let diff = |args: &[&str]| {
+        cmd_diff()
+            .with_args(&[
+                format!("path:{}", old_dir.path().display()),
+                format!("path:{}", new_dir.path().display()),
+            ])
+            .with_args(args)
+            .fails_with(1)
+    };
+
+    diff(&["--stat", "--include", "src/**/*.rs"])
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n \
+             Cargo.toml     | 2 +-\n \
+             gen.rs         | 2 +-\n \
+             macros.rs      | 2 +-\n \
+             src/inner/a.rs | 2 +-\n \
+             4 files changed, 4 insertions(+), 4 deletions(-)")
+        .stderr()
+        .contains("Filtered out 1 file of foo:local and 1 file of foo:local")
+        .unwrap();
+    diff(&["--stat", "--include", "src/**/*.rs", "--include-strict"])
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n \
+             src/inner/a.rs | 2 +-\n \
+             1 file changed, 1 insertion(+), 1 deletion(-)")
+        .unwrap();
+    // Excludes win over includes, even the implicit ones.
+    diff(&[
+        "--format",
+        "json",
+        "--include",
+        "*.rs",
+        "--exclude",
+        "gen.rs",
+    ])
+    .stdout()
+    .contains("\"path\": \"macros.rs\"")
+    .stdout()
+    .doesnt_contain("gen.rs")
+    .stdout()
+    .doesnt_contain("README.md")
+    .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_rejects_invalid_exclude_globs() {
+    cmd_diff()
+        .with_args(&["void:1.0.1", "void:1.0.2", "--exclude", "[z-a"])
+        .fails()
+        .stderr()
+        .contains("invalid --exclude pattern `[z-a`")
+        .unwrap();
+}
+
+#[test]
+fn diff_leaves_out_tests_benches_and_examples() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for (dir, version) in &[(&old_dir, "old"), (&new_dir, "new")] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        for sub in &["src/util", "tests", "benches", "examples"] {
+            fs::create_dir_all(dir.path().join(sub))?;
+        }
+        fs::write(
+            dir.path().join("src/lib.rs"),
+            format!(
+                "// {}\nmod helpers;\nmod util;\n#[cfg(test)]\npub(crate) mod tests;\n",
+                version
+            ),
+        )?;
+        fs::write(
+            dir.path().join("src/util.rs"),
+            format!("// {}\n#[cfg(test)]\nmod fixtures;\n", version),
+        )?;
+        fs::write(
+            dir.path().join("src/helpers.rs"),
+            format!("#![allow(dead_code)]\n#![cfg(test)]\n// {}\n", version),
+        )?;
+        fs::write(dir.path().join("src/util/fixtures.rs"), version)?;
+        fs::write(dir.path().join("src/tests.rs"), version)?;
+        fs::write(dir.path().join("tests/it.rs"), version)?;
+        fs::write(dir.path().join("benches/bench.rs"), "same")?;
+    }
+    fs::write(new_dir.path().join("examples/demo.rs"), "new")?;
+    let diff = |args: &[&str]| {
+        cmd_diff()
+            .with_args(&[
+                format!("path:{}", old_dir.path().display()),
+                format!("path:{}", new_dir.path().display()),
+                "--brief".to_string(),
+            ])
+            .with_args(args)
+            .fails_with(1)
+    };
+
+    diff(&["--no-tests"])
+        .stdout()
+        .is("--- old: foo:local\n+++ new: foo:local\nM src/lib.rs\nM src/util.rs")
+        .stderr()
+        .contains(
+            "Skipped 5 changed files under tests/, benches/ and examples/, \
+             and in test modules (use --all to include them)",
+        )
+        .unwrap();
+    diff(&["--no-tests", "--exclude", "util.rs"])
+        .stdout()
+        .is("--- old: foo:local\n+++ new: foo:local\nM src/lib.rs")
+        .unwrap();
+    diff(&["--no-tests", "--all"])
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n\
+             A examples/demo.rs\nM src/helpers.rs\nM src/lib.rs\nM src/tests.rs\n\
+             M src/util/fixtures.rs\nM src/util.rs\nM tests/it.rs")
+        .stderr()
+        .doesnt_contain("Skipped")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_scans_added_lines_for_suspicious_code() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::create_dir(dir.path().join("src"))?;
+    }
+    fs::write(
+        old_dir.path().join("src/lib.rs"),
+        "pub fn a() {}\n\npub fn b() {}\n\n\n\n\npub fn c() {}\n",
+    )?;
+    fs::write(
+        new_dir.path().join("src/lib.rs"),
+        "pub fn a() {\n    std::process::Command::new(\"sh\");\n}\n\npub fn b() {}\n\n\n\n\n\
+         pub fn c() {}\npub fn d() {}\n",
+    )?;
+    let diff = |args: &[&str]| {
+        cmd_diff()
+            .with_args(&[
+                format!("path:{}", old_dir.path().display()),
+                format!("path:{}", new_dir.path().display()),
+                "--scan".to_string(),
+                "--context=1".to_string(),
+            ])
+            .with_args(args)
+    };
+
+    diff(&[])
+        .fails_with(1)
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n\
+             src/lib.rs: `std::process|process::Command|Command::new`\n\
+             @@ -1,2 +1,4 @@\n\
+             -pub fn a() {}\n\
+             +pub fn a() {\n\
+             +    std::process::Command::new(\"sh\");\n\
+             +}\n \n")
+        .unwrap();
+    diff(&["--scan-pattern", "fn d"])
+        .fails_with(1)
+        .stdout()
+        .contains("\n\nsrc/lib.rs: `fn d`\n@@ -8 +10,2 @@\n pub fn c() {}\n+pub fn d() {}\n")
+        .unwrap();
+    diff(&["--exclude", "lib.rs"])
+        .stdout()
+        .is("--- old: foo:local\n+++ new: foo:local\nNo suspicious additions matched")
+        .unwrap();
+    diff(&["--scan-pattern", "("])
+        .fails_with(101)
+        .stderr()
+        .contains("invalid --scan-pattern `(`")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_colors_only_as_asked() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for (dir, name) in &[(&old_dir, "foo"), (&new_dir, "bar")] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\n", name),
+        )?;
+        fs::write(dir.path().join("lib.rs"), format!("fn {}() {{}}\n", name))?;
+    }
+    let out_dir = tempdir::TempDir::new("out")?;
+    let patch = out_dir.path().join("changes.patch");
+    let diff = |args: &[&str]| {
+        cmd_diff()
+            .with_args(&[
+                format!("path:{}", old_dir.path().display()),
+                format!("path:{}", new_dir.path().display()),
+                "--allow-different-names".to_string(),
+            ])
+            .with_args(args)
+            .fails_with(1)
+    };
+
+    diff(&["--builtin", "--color=always"])
+        .with_env(Environment::inherit().insert("NO_COLOR", "1"))
+        .stdout()
+        .contains("\x1b[31m-fn foo() {}\x1b[0m")
+        .stderr()
+        .contains("\x1b[1;33mwarning\x1b[0m: comparing DIFFERENT crates")
+        .unwrap();
+    for args in &[&["--builtin"][..], &[], &["--stat"], &["--side-by-side"]] {
+        diff(args)
+            .with_args(&["--color=never"])
+            .stdout()
+            .doesnt_contain("\x1b")
+            .stderr()
+            .doesnt_contain("\x1b")
+            .unwrap();
+    }
+    base_cmd()
+        .with_args(&["--color=always", "diff"])
+        .with_args(&[
+            format!("path:{}", old_dir.path().display()),
+            format!("path:{}", new_dir.path().display()),
+            "--allow-different-names".to_string(),
+            "--output".to_string(),
+            patch.display().to_string(),
+        ])
+        .fails_with(1)
+        .unwrap();
+    assert!(fs::read_to_string(&patch)?.contains("-fn foo() {}\n+fn bar() {}\n"));
+    assert!(!fs::read_to_string(&patch)?.contains('\x1b'));
+    Ok(())
+}
+
+#[test]
+fn diff_reviews_files_interactively() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    let dest = tempdir::TempDir::new("dest")?;
+    for (dir, name) in &[(&old_dir, "foo"), (&new_dir, "bar")] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\n", name),
+        )?;
+        fs::write(dir.path().join("a.rs"), name)?;
+        fs::write(dir.path().join("b.rs"), name)?;
+    }
+    fs::write(new_dir.path().join("build.rs"), "fn main() {}\n")?;
+    let diff = || {
+        cmd_diff().with_args(&[
+            format!("path:{}", old_dir.path().display()),
+            format!("path:{}", new_dir.path().display()),
+            "--allow-different-names".to_string(),
+            "--interactive".to_string(),
+        ])
+    };
+
+    // The high-risk files come first, and quitting keeps the answers.
+    diff()
+        .with_args(&["--destination", &dest.path().display().to_string()])
+        .stdin("a\nmaybe\nf\nnew build script\nq\n")
+        .fails_with(1)
+        .stdout()
+        .contains("[1/4] Cargo.toml, high-risk: manifest\n")
+        .stdout()
+        .contains("[2/4] build.rs, high-risk: build script\n")
+        .stdout()
+        .contains("[3/4] a.rs\n")
+        .stdout()
+        .doesnt_contain("[4/4]")
+        .stderr()
+        .contains("Approved 1, skipped 0 and flagged 1 of 4 files")
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(dest.path().join("review-notes.json"))?,
+        r#"{
+  "first": "foo:local",
+  "second": "bar:local",
+  "files": [
+    {
+      "file": "Cargo.toml",
+      "decision": "approve"
+    },
+    {
+      "file": "build.rs",
+      "decision": "flag",
+      "note": "new build script"
+    }
+  ]
+}
+"#
+    );
+
+    let notes = dest.path().join("notes.json");
+    diff()
+        .with_args(&["--notes", &notes.display().to_string()])
+        .stdin("s\ns\n")
+        .fails_with(1)
+        .stderr()
+        .contains("Approved 0, skipped 2 and flagged 0 of 4 files")
+        .unwrap();
+    assert!(fs::read_to_string(&notes)?.contains("\"decision\": \"skip\""));
+
+    diff()
+        .fails_with(101)
+        .stderr()
+        .contains("--interactive needs --destination or --notes")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_prints_files_in_the_same_order_every_time() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    let out_dir = tempdir::TempDir::new("patches")?;
+    for (dir, text) in [(&old_dir, "old\n"), (&new_dir, "new\n")] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        // Created out of order, so that the directory isn't sorted.
+        for path in ["src/z.rs", "src/util.rs", "src/util/b.rs", "src/a.rs"] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, text)?;
+        }
+    }
+    let pkgs = [
+        format!("path:{}", old_dir.path().display()),
+        format!("path:{}", new_dir.path().display()),
+    ];
+    let mut patches = Vec::new();
+    for name in ["first.patch", "second.patch"] {
+        let path = out_dir.path().join(name);
+        cmd_diff()
+            .with_args(&pkgs)
+            .with_args(&["--output"])
+            .with_args(&[&path])
+            .fails_with(1)
+            .unwrap();
+        patches.push(fs::read(path)?);
+    }
+    assert_eq!(patches[0], patches[1]);
+    let patch = String::from_utf8_lossy(&patches[0]);
+    let position = |path: &str| patch.find(&format!("--- a/{}\n", path)).unwrap();
+    assert!(position("src/a.rs") < position("src/util/b.rs"));
+    assert!(position("src/util/b.rs") < position("src/util.rs"));
+    assert!(position("src/util.rs") < position("src/z.rs"));
+
+    if Command::new("diff").arg("--version").output().is_ok() {
+        let diff = || {
+            Command::new(cargo_review_deps_exe())
+                .args(["review-deps", "diff"])
+                .args(&pkgs)
+                .output()
+        };
+        let (first, second) = (diff()?, diff()?);
+        assert_eq!(first.stdout, second.stdout);
+        let stdout = String::from_utf8_lossy(&first.stdout);
+        assert!(stdout.contains("a/foo-local/src/a.rs b/foo-local/src/a.rs\n"));
+    }
+    Ok(())
+}
+
+#[test]
+fn diff_gives_the_same_output_with_any_number_of_jobs() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for (dir, text) in [(&old_dir, "old"), (&new_dir, "new")] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::create_dir(dir.path().join("src"))?;
+        for idx in 0..20 {
+            fs::write(
+                dir.path().join(format!("src/m{}.rs", idx)),
+                format!("fn f() {{}}\n// {} {}\n", text, idx % 3),
+            )?;
+        }
+    }
+    let diff = |args: &[&str]| {
+        Command::new(cargo_review_deps_exe())
+            .args(["review-deps", "diff"])
+            .arg(format!("path:{}", old_dir.path().display()))
+            .arg(format!("path:{}", new_dir.path().display()))
+            .args(args)
+            .output()
+    };
+    for args in [&["--builtin"][..], &["--stat"], &["--format", "json"]] {
+        let one = diff(&[args, &["--jobs", "1"]].concat())?;
+        let many = diff(&[args, &["--jobs", "4", "--verbose"]].concat())?;
+        assert_eq!(one.stdout, many.stdout);
+        assert!(String::from_utf8_lossy(&many.stderr).contains(" with 4 jobs\n"));
+    }
+    Ok(())
+}
+
+#[test]
+fn diff_truncates_enormous_diffs() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+    }
+    for idx in 1..=4 {
+        fs::write(old_dir.path().join(format!("f{}.rs", idx)), "old\n")?;
+        fs::write(new_dir.path().join(format!("f{}.rs", idx)), "new\n")?;
+    }
+    let diff = |args: &[&str]| {
+        cmd_diff()
+            .with_args(&[
+                format!("path:{}", old_dir.path().display()),
+                format!("path:{}", new_dir.path().display()),
+            ])
+            .with_args(args)
+            .fails_with(1)
+    };
+
+    // The cap is on the whole output, not on each file.
+    diff(&["--builtin", "--max-lines", "7"])
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n\
+             --- foo-local/f1.rs\n\
+             +++ foo-local/f1.rs\n\
+             @@ -1 +1 @@\n\
+             -old\n\
+             +new\n\
+             --- foo-local/f2.rs\n\
+             +++ foo-local/f2.rs\n\
+             \n\
+             Output truncated after 7 lines; 2 more changed files not shown \
+             — rerun with --destination or --output to get everything, or with --max-lines 0")
+        .unwrap();
+    diff(&["--max-lines", "3"])
+        .stdout()
+        .contains("Output truncated after 3 lines — rerun")
+        .unwrap();
+    diff(&["--builtin", "--max-lines", "0"])
+        .stdout()
+        .contains("+++ foo-local/f4.rs")
+        .stdout()
+        .doesnt_contain("truncated")
+        .unwrap();
+    diff(&["--stat", "--max-lines", "1"])
+        .stdout()
+        .contains(" 4 files changed, 4 insertions(+), 4 deletions(-)")
+        .stdout()
+        .doesnt_contain("truncated")
+        .unwrap();
+
+    // The cap is on all of the pairs of --specs-from together, and the
+    // output tells once that it's cut.
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    let pairs = dir.path().join("pairs.txt");
+    let (old, new) = (old_dir.path().display(), new_dir.path().display());
+    fs::write(
+        &pairs,
+        format!("path:{} path:{}\npath:{} path:{}\n", old, new, new, old),
+    )?;
+    let output = Command::new(cargo_review_deps_exe())
+        .args([
+            "review-deps",
+            "diff",
+            "--builtin",
+            "--max-lines",
+            "7",
+            "--specs-from",
+        ])
+        .arg(&pairs)
+        .output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.matches("+++ foo-local/").count(), 2, "{}", stdout);
+    assert_eq!(stdout.matches("Output truncated").count(), 1, "{}", stdout);
+    assert!(
+        stdout.ends_with(
+            "\nOutput truncated after 7 lines; 6 more changed files not shown \
+             — rerun with --destination or --output to get everything, or with --max-lines 0\n"
+        ),
+        "{}",
+        stdout
+    );
+    Ok(())
+}
+
+#[test]
+fn diff_leaves_out_the_files_in_reviewignore() -> std::io::Result<()> {
+    let workspace = tempdir::TempDir::new("workspace")?;
+    let config = tempdir::TempDir::new("config")?;
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for (dir, text) in [(&old_dir, "old\n"), (&new_dir, "new\n")] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        for path in [
+            "build.rs",
+            "src/lib.rs",
+            "src/big_tables.rs",
+            "data/a",
+            "data/b",
+        ] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, text)?;
+        }
+    }
+    fs::write(workspace.path().join("Cargo.lock"), "")?;
+    fs::write(
+        workspace.path().join(".reviewignore"),
+        "# Noise\n*_tables.rs\nfoo/data/\nbar/src/lib.rs\nbuild.rs\n!lib.rs\n",
+    )?;
+    fs::create_dir(config.path().join("cargo-review-deps"))?;
+    fs::write(config.path().join("cargo-review-deps/ignore"), "lib.rs\n")?;
+    let diff = |args: &[&str]| {
+        cmd_diff()
+            .current_dir(workspace.path())
+            .with_env(Environment::inherit().insert("XDG_CONFIG_HOME", config.path()))
+            .with_args(&[
+                format!("path:{}", old_dir.path().display()),
+                format!("path:{}", new_dir.path().display()),
+                "--brief".to_string(),
+            ])
+            .with_args(args)
+            .fails_with(1)
+    };
+
+    diff(&["--verbose"])
+        .stdout()
+        .is("--- old: foo:local\n+++ new: foo:local\nM build.rs\nM src/lib.rs")
+        .stderr()
+        .contains(
+            "Left out 6 files listed in .reviewignore (use --no-reviewignore to include them)\n \
+             *_tables.rs (",
+        )
+        .stderr()
+        .contains(".reviewignore:2): 2 files\n foo/data/ (")
+        .unwrap();
+    diff(&["--no-reviewignore"])
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n\
+             M build.rs\nM data/a\nM data/b\nM src/big_tables.rs\nM src/lib.rs")
+        .stderr()
+        .doesnt_contain(".reviewignore")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_calls_out_license_changes() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    let manifest = |license: &str| {
+        format!(
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\nlicense = \"{}\"\n",
+            license
+        )
+    };
+    fs::write(old_dir.path().join("LICENSE-MIT"), "MIT\n")?;
+    fs::write(new_dir.path().join("COPYING"), "GPL\n")?;
+    fs::write(old_dir.path().join("README.md"), "old\n")?;
+    fs::write(new_dir.path().join("README.md"), "new\n")?;
+    let diff = |old: &str, new: &str, args: &[&str]| {
+        fs::write(old_dir.path().join("Cargo.toml"), manifest(old)).unwrap();
+        fs::write(new_dir.path().join("Cargo.toml"), manifest(new)).unwrap();
+        cmd_diff()
+            .with_args(&[
+                format!("path:{}", old_dir.path().display()),
+                format!("path:{}", new_dir.path().display()),
+            ])
+            .with_args(args)
+            .fails_with(1)
+    };
+
+    diff("MIT", "GPL-3.0-only", &["--builtin"])
+        .stdout()
+        .contains(
+            "License changes:\n \
+             license: MIT -> GPL-3.0-only\n \
+             added COPYING\n \
+             removed LICENSE-MIT\n\
+             \n\
+             Changed READMEs: README.md (modified)\n\
+             \n\
+             --- /dev/null\n",
+        )
+        .unwrap();
+    diff("MIT/Apache-2.0", "Apache-2.0 OR MIT", &["--brief"])
+        .stdout()
+        .contains(" license: MIT/Apache-2.0 -> Apache-2.0 OR MIT, the same licenses\n")
+        .stdout()
+        .doesnt_contain("Changed READMEs")
+        .unwrap();
+    diff("MIT", "MIT OR Apache-2.0", &["--format", "json"])
+        .stdout()
+        .contains(
+            "\"license\": {\n    \
+             \"expression_changed\": true,\n    \
+             \"expression\": {\n      \
+             \"old\": \"MIT\",\n      \
+             \"new\": \"MIT OR Apache-2.0\"\n    },",
+        )
+        .unwrap();
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn diff_copies_symlinks_safely_and_skips_special_files() -> std::io::Result<()> {
+    use std::os::unix::fs::symlink;
+
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    let dest = tempdir::TempDir::new("dest")?;
+    for (dir, name) in [(&old_dir, "old"), (&new_dir, "new")] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\n", name),
+        )?;
+        fs::create_dir(dir.path().join("src"))?;
+        fs::write(dir.path().join("src/lib.rs"), name)?;
+    }
+    symlink("lib.rs", new_dir.path().join("src/alias.rs"))?;
+    symlink("../../secret", new_dir.path().join("src/secret"))?;
+    let fifo = new_dir.path().join("pipe");
+    assert!(Command::new("mkfifo").arg(&fifo).status()?.success());
+
+    cmd_diff()
+        .with_args(&[
+            format!("path:{}", old_dir.path().display()),
+            format!("path:{}", new_dir.path().display()),
+        ])
+        .with_args(&["--allow-different-names", "--destination"])
+        .with_args(&[dest.path()])
+        .stderr()
+        .contains(
+            "Symlinks in new:local: src/alias.rs -> lib.rs, \
+             src/secret -> ../../secret (outside of the package, replaced with a note)\n\
+             warning: skipped pipe of new:local, a FIFO\n",
+        )
+        .unwrap();
+    let copy = dest.path().join("new/new-local");
+    assert_eq!(
+        fs::read_link(copy.join("src/alias.rs"))?,
+        PathBuf::from("lib.rs")
+    );
+    assert_eq!(
+        fs::read_to_string(copy.join("src/secret"))?,
+        "A symlink to ../../secret, outside of the package, was here.\n"
+    );
+    assert!(!copy.join("pipe").exists());
+    Ok(())
+}
+
+#[test]
+fn diff_lists_changed_paths_briefly() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::create_dir(dir.path().join("src"))?;
+    }
+    fs::write(old_dir.path().join("src/lib.rs"), "old\n")?;
+    fs::write(new_dir.path().join("src/lib.rs"), "new\n")?;
+    fs::write(old_dir.path().join("removed.rs"), "gone\n")?;
+    fs::write(new_dir.path().join("src/added.rs"), "new\n")?;
+    let diff = |args: &[&str]| {
+        cmd_diff()
+            .with_args(&[
+                format!("path:{}", old_dir.path().display()),
+                format!("path:{}", new_dir.path().display()),
+            ])
+            .with_args(args)
+            .fails_with(1)
+    };
+
+    diff(&["--brief"])
+        .stdout()
+        .is("--- old: foo:local\n+++ new: foo:local\nD removed.rs\nA src/added.rs\nM src/lib.rs")
+        .unwrap();
+    diff(&["--brief", "--format", "json"])
+        .stdout()
+        .contains(
+            "\"path\": \"src/lib.rs\",\n      \"status\": \"modified\",\n      \"binary\": false\n",
+        )
+        .stdout()
+        .contains("\"totals\": {\n    \"files\": 3\n  }")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_exit_status_tells_whether_sources_differ() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::write(dir.path().join("lib.rs"), "fn a() {}\n")?;
+    }
+    let diff = |args: &[&str]| {
+        cmd_diff()
+            .with_args(&[
+                format!("path:{}", old_dir.path().display()),
+                format!("path:{}", new_dir.path().display()),
+            ])
+            .with_args(args)
+    };
+
+    diff(&["--builtin"])
+        .stdout()
+        .is("--- old: foo:local\n+++ new: foo:local")
+        .unwrap();
+    diff(&["--tool", "true"]).unwrap();
+    fs::write(new_dir.path().join("README.md"), "Foo\n")?;
+    diff(&["--builtin"]).fails_with(1).unwrap();
+    diff(&["--stat"]).fails_with(1).unwrap();
+    diff(&["--format", "json"]).fails_with(1).unwrap();
+    // Changes in the filtered out files don't count.
+    diff(&["--builtin", "--exclude", "*.md"]).unwrap();
+    // Like with `diff`, exit code 2 means trouble.
+    diff(&["--tool", "sh", "--", "-c", "exit 2", "sh"])
+        .fails_with(101)
+        .unwrap();
+    cmd_diff()
+        .with_args(&["path:/no/such/dir", "path:/no/such/dir"])
+        .fails_with(101)
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_prints_json_report() {
+    cmd_diff()
+        .with_args(&["void:1.0.1", "void:1.0.2", "--format", "json"])
+        .fails_with(1)
+        .stdout()
+        .contains("\"schema_version\": 2")
+        .stdout()
+        .contains("\"version\": \"1.0.2\",\n    \"source\": \"crates.io\"")
+        .stdout()
+        .contains(
+            "\"path\": \"Cargo.toml\",\n      \"status\": \"modified\",\n      \
+             \"binary\": false,\n      \"insertions\": 1,\n      \"deletions\": 1\n",
+        )
+        .stdout()
+        .contains(
+            "\"totals\": {\n    \"files\": 3,\n    \"insertions\": 18,\n    \"deletions\": 5\n",
+        )
+        .unwrap();
+}
+
+#[test]
+fn diff_reports_renamed_files_and_patches_in_json() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+    }
+    fs::write(old_dir.path().join("old.rs"), "moved\n")?;
+    fs::write(new_dir.path().join("new.rs"), "moved\n")?;
+    fs::write(old_dir.path().join("lib.rs"), "1\n2\n")?;
+    fs::write(new_dir.path().join("lib.rs"), "1\ntwo\n")?;
+    fs::write(new_dir.path().join("blob.bin"), b"\0new")?;
+
+    cmd_diff()
+        .with_args(&[
+            format!("path:{}", old_dir.path().display()),
+            format!("path:{}", new_dir.path().display()),
+        ])
+        .with_args(&["--format", "json", "--include-patches", "-U", "0"])
+        .fails_with(1)
+        .stdout()
+        .contains("\"path\": \"blob.bin\",\n      \"status\": \"added\",\n      \"binary\": true\n")
+        .stdout()
+        .contains("\"patch\": \"@@ -2 +2 @@\\n-2\\n+two\\n\"")
+        .stdout()
+        .contains(
+            "\"path\": \"new.rs\",\n      \"old_path\": \"old.rs\",\n      \
+             \"status\": \"renamed\",",
+        )
+        .stdout()
+        .doesnt_contain("\"status\": \"removed\"")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_shows_moved_files_as_renames() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::create_dir_all(dir.path().join("src/foo"))?;
+    }
+    let lines: String = (1..=9).map(|it| format!("line {}\n", it)).collect();
+    fs::write(old_dir.path().join("src/foo.rs"), &lines)?;
+    fs::write(
+        new_dir.path().join("src/foo/mod.rs"),
+        lines.replace("line 9", "line nine"),
+    )?;
+    let diff = |args: &[&str]| {
+        cmd_diff()
+            .with_args(&[
+                format!("path:{}", old_dir.path().display()),
+                format!("path:{}", new_dir.path().display()),
+            ])
+            .with_args(args)
+            .fails_with(1)
+    };
+
+    diff(&["--builtin"])
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n\
+             R src/foo.rs -> src/foo/mod.rs (88% similar)\n\
+             --- foo-local/src/foo.rs\n\
+             +++ foo-local/src/foo/mod.rs\n\
+             @@ -6,4 +6,4 @@\n \
+             line 6\n \
+             line 7\n \
+             line 8\n\
+             -line 9\n\
+             +line nine")
+        .unwrap();
+    diff(&["--brief"])
+        .stdout()
+        .is("--- old: foo:local\n+++ new: foo:local\nR src/foo.rs -> src/foo/mod.rs (88% similar)")
+        .unwrap();
+    diff(&["--brief", "--rename-threshold", "90"])
+        .stdout()
+        .is("--- old: foo:local\n+++ new: foo:local\nA src/foo/mod.rs\nD src/foo.rs")
+        .unwrap();
+    diff(&["--format", "json"])
+        .stdout()
+        .contains(
+            "\"path\": \"src/foo/mod.rs\",\n      \
+             \"old_path\": \"src/foo.rs\",\n      \
+             \"status\": \"renamed\",\n      \
+             \"similarity\": 88,\n      \
+             \"binary\": false,\n      \
+             \"insertions\": 1,\n      \
+             \"deletions\": 1\n",
+        )
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_reverses_and_labels_the_old_and_new_sides() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+    }
+    fs::write(old_dir.path().join("lib.rs"), "fn a() {}\n")?;
+    fs::write(new_dir.path().join("added.rs"), "fn b() {}\n")?;
+    fs::write(new_dir.path().join("lib.rs"), "fn a() {}\n")?;
+    let diff = |args: &[&str]| {
+        cmd_diff()
+            .with_args(&[
+                format!("path:{}", old_dir.path().display()),
+                format!("path:{}", new_dir.path().display()),
+            ])
+            .with_args(args)
+    };
+
+    diff(&["--brief"])
+        .fails_with(1)
+        .stdout()
+        .is("--- old: foo:local\n+++ new: foo:local\nA added.rs")
+        .unwrap();
+    diff(&["--brief", "--reverse"])
+        .fails_with(1)
+        .stdout()
+        .is("--- old: foo:local\n+++ new: foo:local\nD added.rs")
+        .unwrap();
+    diff(&["--format", "json", "--reverse"])
+        .fails_with(1)
+        .stdout()
+        .contains("\"schema_version\": 2,\n  \"old\": {\n    \"name\": \"foo\"")
+        .stdout()
+        .contains("\"new\": {")
+        .stdout()
+        .contains("\"status\": \"removed\"")
+        .unwrap();
+
+    // Both sides have the same name, and still don't clash.
+    let dest = tempdir::TempDir::new("dest")?;
+    diff(&["--reverse", "--destination"])
+        .with_args(&[dest.path()])
+        .unwrap();
+    assert!(dest.path().join("old/foo-local/added.rs").exists());
+    assert!(dest.path().join("new/foo-local/lib.rs").exists());
+    assert!(!dest.path().join("new/foo-local/added.rs").exists());
+    Ok(())
+}
+
+#[test]
+fn diff_rejects_json_format_with_stat() {
+    cmd_diff()
+        .with_args(&["void:1.0.1", "void:1.0.2", "--format", "json", "--stat"])
+        .fails()
+        .stderr()
+        .contains("--format json can't be used with --stat")
+        .unwrap();
+}
+
+#[test]
+fn diff_writes_html_report() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    let out_dir = tempdir::TempDir::new("report")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::create_dir(dir.path().join("src"))?;
+    }
+    fs::write(old_dir.path().join("src/lib.rs"), "fn a() {}\n")?;
+    fs::write(new_dir.path().join("src/lib.rs"), "fn a() -> Vec<u8> {}\n")?;
+    fs::write(new_dir.path().join("src/added.rs"), "new\n")?;
+    fs::write(old_dir.path().join("blob.bin"), b"\0old")?;
+    let report = out_dir.path().join("report.html");
+
+    cmd_diff()
+        .with_args(&[
+            format!("path:{}", old_dir.path().display()),
+            format!("path:{}", new_dir.path().display()),
+        ])
+        .with_args(&["--format", "html", "--output"])
+        .with_args(&[&report])
+        .fails_with(1)
+        .unwrap();
+    let html = fs::read_to_string(&report)?;
+    assert!(html.contains("<li>src/<ul>"));
+    assert!(html.contains("<a class=\"added\" href=\"#file-1\">added.rs</a>"));
+    assert!(html.contains("<a class=\"modified\" href=\"#file-2\">lib.rs</a>"));
+    assert!(html.contains("<td class=\"ins\">fn a() -&gt; Vec&lt;u8&gt; {}</td>"));
+    // sha256 of "\0old".
+    assert!(html.contains(
+        "Binary file, sha256 2a6013f83555ed1c5f4c16e2eba14a1cf64b3bf6be8268fe291e2a000378193d → (none)"
+    ));
+    Ok(())
+}
+
+#[test]
+fn diff_writes_patch_which_git_applies() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    let out_dir = tempdir::TempDir::new("patch")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+    }
+    fs::write(old_dir.path().join("lib.rs"), "1\n2\n3")?;
+    fs::write(new_dir.path().join("lib.rs"), "1\ntwo\n3")?;
+    fs::write(old_dir.path().join("removed.rs"), "gone\n")?;
+    fs::write(new_dir.path().join("added.rs"), "new\n")?;
+    let patch = out_dir.path().join("changes.patch");
+
+    cmd_diff()
+        .with_args(&[
+            format!("path:{}", old_dir.path().display()),
+            format!("path:{}", new_dir.path().display()),
+        ])
+        .with_args(&["--output"])
+        .with_args(&[&patch])
+        .fails_with(1)
+        .stdout()
+        .is("")
+        .unwrap();
+    let text = fs::read_to_string(&patch)?;
+    assert_eq!(
+        text,
+        "--- /dev/null\n+++ b/added.rs\n@@ -0,0 +1 @@\n+new\n\
+         --- a/lib.rs\n+++ b/lib.rs\n@@ -1,3 +1,3 @@\n 1\n-2\n+two\n 3\n\\ No newline at end of file\n\
+         --- a/removed.rs\n+++ /dev/null\n@@ -1 +0,0 @@\n-gone\n"
+    );
+    let status = Command::new("git")
+        .current_dir(old_dir.path())
+        .args(["apply", "--check"])
+        .arg(&patch)
+        .status()?;
+    assert!(status.success());
+    Ok(())
+}
+
+#[test]
+fn diff_reviews_chain_of_versions() -> std::io::Result<()> {
+    let dirs = [
+        tempdir::TempDir::new("v1")?,
+        tempdir::TempDir::new("v2")?,
+        tempdir::TempDir::new("v3")?,
+    ];
+    for (dir, contents) in dirs.iter().zip(["1\n", "1\n2\n", "2\n"]) {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::write(dir.path().join("lib.rs"), contents)?;
+    }
+    let specs: Vec<String> = dirs
+        .iter()
+        .map(|it| format!("path:{}", it.path().display()))
+        .collect();
+
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--builtin"])
+        .fails_with(1)
+        .stdout()
+        .is(
+            "Step 1 of 2: foo:local -> foo:local\n--- old: foo:local\n+++ new: foo:local\n\
+             --- foo-local/lib.rs\n\
+             +++ foo-local/lib.rs\n\
+             @@ -1 +1,2 @@\n \
+             1\n\
+             +2\n\
+             \n\
+             Step 2 of 2: foo:local -> foo:local\n--- old: foo:local\n+++ new: foo:local\n\
+             --- foo-local/lib.rs\n\
+             +++ foo-local/lib.rs\n\
+             @@ -1,2 +1 @@\n\
+             -1\n \
+             2\n\
+             \n\
+             Summary:\n \
+             foo:local -> foo:local: 1 file changed, 1 insertion(+), 0 deletions(-)\n \
+             foo:local -> foo:local: 1 file changed, 0 insertions(+), 1 deletion(-)",
+        )
+        .unwrap();
+    let dest = tempdir::TempDir::new("dest")?;
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--destination"])
+        .with_args(&[dest.path()])
+        .unwrap();
+    assert!(dest
+        .path()
+        .join("1-foo-local..foo-local/old/foo-local")
+        .exists());
+    assert!(dest
+        .path()
+        .join("2-foo-local..foo-local/new/foo-local")
+        .exists());
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--format", "html"])
+        .fails_with(101)
+        .stderr()
+        .contains("error: --format html can't be used with more than two versions")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_reviews_all_intermediate_releases() {
+    cmd_diff()
+        .with_args(&["void:1.0.0", "void:1.0.2", "--all-intermediate", "--brief"])
+        .fails_with(1)
+        .stdout()
+        .contains(
+            "Step 1 of 2: void:1.0.0 -> void:1.0.1\n\
+             --- old: void:1.0.0\n\
+             +++ new: void:1.0.1\n\
+             M Cargo.toml\n",
+        )
+        .stdout()
+        .contains("\nStep 2 of 2: void:1.0.1 -> void:1.0.2\n")
+        .stdout()
+        .contains(
+            "Summary:\n \
+             void:1.0.0 -> void:1.0.1: 3 files changed, 4 insertions(+), 3 deletions(-)\n \
+             void:1.0.1 -> void:1.0.2: 3 files changed, 18 insertions(+), 5 deletions(-)",
+        )
+        .unwrap();
+    // JSON reports are nested, in the order of the steps.
+    cmd_diff()
+        .with_args(&["void:1.0.2", "void:1.0.0", "--all-intermediate"])
+        .with_args(&["--format", "json"])
+        .fails_with(1)
+        .stdout()
+        .contains(
+            "\"steps\": [\n    {\n      \"old\": {\n        \"name\": \"void\",\n        \
+             \"version\": \"1.0.2\"",
+        )
+        .stdout()
+        .contains("\"new\": {\n        \"name\": \"void\",\n        \"version\": \"1.0.0\"")
+        .unwrap();
+    cmd_diff()
+        .with_args(&["void:1.0.0", "void:1.0.2", "--all-intermediate"])
+        .with_args(&["--max-steps", "1"])
+        .fails_with(101)
+        .stderr()
+        .contains(
+            "error: reviewing every release from void:1.0.0 to void:1.0.2 takes 2 steps, \
+             more than --max-steps 1",
+        )
+        .unwrap();
+}
+
+#[test]
+fn diff_compares_published_package_with_repository() -> std::io::Result<()> {
+    let repo_dir = tempdir::TempDir::new("repo")?;
+    let published_dir = tempdir::TempDir::new("published")?;
+    let (repo, published) = (repo_dir.path(), published_dir.path());
+    let url = format!("file://{}", repo.display());
+    let manifest = format!(
+        "[package]\nname = \"foo\"\nversion = \"0.1.0\"\nrepository = \"{}\"\n",
+        url
+    );
+    // A workspace, with the package in a subdirectory.
+    fs::create_dir_all(repo.join("foo/src"))?;
+    fs::write(
+        repo.join("Cargo.toml"),
+        "[workspace]\nmembers = [\"foo\"]\n",
+    )?;
+    fs::write(repo.join("foo/Cargo.toml"), &manifest)?;
+    fs::write(repo.join("foo/src/lib.rs"), "fn a() {}\n")?;
+    fs::write(repo.join("foo/TODO.md"), "Publish\n")?;
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .current_dir(repo)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .status()?;
+        assert!(status.success());
+        Ok::<_, std::io::Error>(())
+    };
+    git(&["init", "--quiet"])?;
+    git(&["add", "."])?;
+    git(&["commit", "--quiet", "-m", "Release"])?;
+    git(&["tag", "v0.0.9"])?;
+    git(&["tag", "v0.2.0"])?;
+
+    fs::create_dir(published.join("src"))?;
+    fs::write(
+        published.join("Cargo.toml"),
+        format!("# Normalized\n{}", manifest),
+    )?;
+    fs::write(published.join("Cargo.toml.orig"), &manifest)?;
+    fs::write(
+        published.join(".cargo_vcs_info.json"),
+        "{\"path_in_vcs\": \"foo\"}",
+    )?;
+    fs::write(published.join("src/lib.rs"), "fn a() {}\nfn b() {}\n")?;
+    let diff = || {
+        cmd_diff()
+            .with_args(&[
+                format!("path:{}", published.display()),
+                "--against-repo".into(),
+            ])
+            .with_args(&["--builtin"])
+    };
+
+    diff()
+        .fails_with(101)
+        .stderr()
+        .contains(
+            format!(
+                "error: no tag for version 0.1.0 in {} (tried v0.1.0, 0.1.0, foo-v0.1.0, foo-0.1.0); \
+                 nearby tags: v0.0.9, v0.2.0",
+                url
+            )
+            .as_str(),
+        )
+        .unwrap();
+    git(&["tag", "foo-v0.1.0"])?;
+    diff()
+        .fails_with(1)
+        .stdout()
+        .is("--- old: foo:git.foo-v0.1.0\n\
+             +++ new: foo:local\n\
+             --- foo-git.foo-v0.1.0/src/lib.rs\n\
+             +++ foo-local/src/lib.rs\n\
+             @@ -1 +1,2 @@\n \
+             fn a() {}\n\
+             +fn b() {}")
+        .stderr()
+        .contains("Left out 1 file of the repository, not in the published package")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_compares_published_package_with_vendored_copy() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    let project = project_dir.path();
+    fs::create_dir(project.join(".cargo"))?;
+    fs::write(
+        project.join(".cargo/config.toml"),
+        "[source.crates-io]\nreplace-with = \"vendored-sources\"\n\n\
+         [source.vendored-sources]\ndirectory = \"third-party\"\n",
+    )?;
+    let diff = |args: &[&str]| {
+        cmd_diff()
+            .current_dir(project)
+            .with_args(&["void:1.0.2", "--against-vendored"])
+            .with_args(args)
+    };
+    diff(&[])
+        .fails_with(101)
+        .stderr()
+        .contains("error: can't read the vendored sources in ")
+        .unwrap();
+
+    // A copy of the published package, as `cargo vendor` makes it, which
+    // was patched in place.
+    let checkout = project.join("checkout");
+    cmd_diff()
+        .with_args(&["void:1.0.1", "void:1.0.2", "--destination"])
+        .with_args(&[&checkout])
+        .unwrap();
+    let vendored = project.join("third-party/void");
+    fs::create_dir(project.join("third-party"))?;
+    fs::rename(checkout.join("new/void-1.0.2"), &vendored)?;
+    for name in [".cargo-ok", ".gitignore"] {
+        let _ = fs::remove_file(vendored.join(name));
+    }
+    fs::write(vendored.join(".cargo-checksum.json"), "{}")?;
+    fs::write(vendored.join("src/lib.rs"), "// patched\n")?;
+    // Another version, vendored next to it.
+    fs::rename(
+        checkout.join("old/void-1.0.1"),
+        project.join("third-party/void-1.0.1"),
+    )?;
+
+    diff(&["--brief"])
+        .fails_with(1)
+        .stdout()
+        .is("--- old: void:local\n+++ new: void:1.0.2\nM src/lib.rs")
+        .stderr()
+        .contains(
+            format!(
+                "Comparing with the vendored void 1.0.2 in {}",
+                vendored.display()
+            )
+            .as_str(),
+        )
+        .unwrap();
+
+    fs::remove_dir_all(&vendored)?;
+    fs::create_dir(project.join("third-party/other"))?;
+    diff(&["--brief"])
+        .fails_with(1)
+        .stderr()
+        .contains("Comparing with the vendored void 1.0.1 in ")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_writes_patch_and_copies_sources_to_dest() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    let patch = dir.path().join("changes.patch");
+    cmd_diff()
+        .with_args(&["void:1.0.1", "void:1.0.2", "--destination"])
+        .with_args(&[dir.path()])
+        .with_args(&["--output"])
+        .with_args(&[&patch])
+        .fails_with(1)
+        .unwrap();
+    assert!(dir.path().join("old/void-1.0.1").exists());
+    assert!(dir.path().join("new/void-1.0.2").exists());
+    let text = fs::read_to_string(&patch)?;
+    assert!(text.contains("--- a/Cargo.toml\n+++ b/Cargo.toml\n"));
+    assert!(text.contains("-version = \"1.0.1\"\n+version = \"1.0.2\"\n"));
+    assert!(!text.contains('\x1b'));
+    Ok(())
+}
+
+#[test]
+fn diff_copies_sources_to_dest() {
+    let dir = tempdir::TempDir::new("diff-tests").unwrap();
+    cmd_diff()
+        .with_args(&["rand:0.6.0", "rand:0.6.1", "--destination"])
+        .with_args(&[dir.path()])
+        .stdout()
+        .is("")
+        .unwrap();
+    assert!(dir.path().join("old/rand-0.6.0").exists());
+    assert!(dir.path().join("new/rand-0.6.1").exists());
+}
+
+#[test]
+fn diff_handles_pre_release_versions() {
+    let dir = tempdir::TempDir::new("diff-tests").unwrap();
+    cmd_diff()
+        .with_args(&["semver:1.0.0-rc.1", "semver:1.0.0-rc.2", "--destination"])
+        .with_args(&[dir.path()])
+        .unwrap();
+    assert!(dir.path().join("old/semver-1.0.0-rc.1").exists());
+    assert!(dir.path().join("new/semver-1.0.0-rc.2").exists());
+}
+
+#[test]
+fn diff_uses_directory_names_valid_on_windows() {
+    let dir = tempdir::TempDir::new("diff-tests").unwrap();
+    cmd_diff()
+        .with_env(mirror_registry_env())
+        .with_args(&["wasi:0.10.1@mirror", "wasi:0.10.2+wasi-snapshot-preview1"])
+        .with_args(&["--destination"])
+        .with_args(&[dir.path()])
+        .unwrap();
+    let names: Vec<String> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|it| it.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(names.len(), 2);
+    for name in names {
+        assert!(
+            !name.contains(|c| "<>:\"/\\|?*+@".contains(c)),
+            "not a portable directory name: {:?}",
+            name
+        );
+    }
+}
+
+#[test]
+fn diff_handles_build_metadata() {
+    let dir = tempdir::TempDir::new("diff-tests").unwrap();
+    cmd_diff()
+        .with_args(&["wasi:0.10.1", "wasi:0.10.2+wasi-snapshot-preview1"])
+        .with_args(&["--destination"])
+        .with_args(&[dir.path()])
+        .unwrap();
+    assert!(dir.path().join("old/wasi-0.10.1").exists());
+    assert!(dir
+        .path()
+        .join("new/wasi-0.10.2_wasi-snapshot-preview1")
+        .exists());
+}
+
+#[test]
+fn diff_warns_about_yanked_versions() {
+    let dir = tempdir::TempDir::new("diff-tests").unwrap();
+    cmd_diff()
+        .with_args(&["rand:0.7.0", "rand:0.7.1", "--destination"])
+        .with_args(&[dir.path()])
+        .stderr()
+        .contains("warning: rand:0.7.1 is YANKED from crates.io")
+        .unwrap();
+    assert!(dir.path().join("new/rand-0.7.1").exists());
+}
+
+#[test]
+fn diff_denies_yanked_versions() {
+    cmd_diff()
+        .with_args(&["rand:0.7.0", "rand:0.7.1", "--deny-yanked"])
+        .fails_with(101)
+        .stderr()
+        .contains("error: rand:0.7.1 is yanked from crates.io")
+        .unwrap();
+}
+
+#[test]
+fn current_reports_deps() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    let dest = project_dir.path().join("dest");
+
+    fs::write(
+        project_dir.path().join("Cargo.toml"),
+        r#"
+        [package]
+        name = "test-pkg"
+        version = "0.0.0"
+
+        [dependencies]
+        thread_local = "=0.3.6"
+
+        [lib]
+        path = "./Cargo.toml"
+    "#,
+    )?;
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["--destination"])
+        .with_args(&[&dest.as_path()])
+        .stderr()
+        .contains("Skipping package `test-pkg`")
+        .unwrap();
+    assert!(dest.join("thread_local-0.3.6").exists());
+    Ok(())
+}
+
+#[test]
+fn current_reads_the_manifest_given_with_manifest_path() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    fs::write(
+        project_dir.path().join("Cargo.toml"),
+        r#"
+        [package]
+        name = "test-pkg"
+        version = "0.0.0"
+
+        [dependencies]
+        void = "=1.0.1"
+
+        [lib]
+        path = "./Cargo.toml"
+    "#,
+    )?;
+    let elsewhere = tempdir::TempDir::new("current-tests")?;
+    let dest = elsewhere.path().join("dest");
+    cmd_current()
+        .current_dir(elsewhere.path())
+        .with_args(&["--manifest-path"])
+        .with_args(&[project_dir.path().join("Cargo.toml")])
+        .with_args(&["--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    assert!(dest.join("void-1.0.1").exists());
+
+    // Bare names are the locked versions of that workspace too.
+    let list = elsewhere.path().join("review-list.txt");
+    fs::write(&list, "void\n")?;
+    let dest = elsewhere.path().join("listed");
+    cmd_current()
+        .current_dir(elsewhere.path())
+        .with_args(&["--manifest-path"])
+        .with_args(&[project_dir.path().join("Cargo.toml")])
+        .with_args(&["--packages-from"])
+        .with_args(&[&list])
+        .with_args(&["--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    assert!(dest.join("void-1.0.1").exists());
+    Ok(())
+}
+
+#[test]
+fn current_checks_out_only_the_given_packages() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    fs::write(
+        project_dir.path().join("Cargo.toml"),
+        r#"
+        [package]
+        name = "test-pkg"
+        version = "0.0.0"
+
+        [dependencies]
+        void = "=1.0.1"
+        void_old = { package = "void", version = "=0.0.5" }
+        thread_local = "=0.3.6"
+        lazy_static = "=1.4.0"
+
+        [lib]
+        path = "./Cargo.toml"
+    "#,
+    )?;
+    let dest = project_dir.path().join("dest");
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["-p", "void", "--package", "lazy_static", "--destination"])
+        .with_args(&[&dest])
+        .stderr()
+        .contains("Checking out the 2 versions of `void` in the dependencies, 0.0.5 and 1.0.1")
+        .unwrap();
+    let mut names: Vec<String> = fs::read_dir(&dest)?
+        .map(|it| Ok(it?.file_name().to_string_lossy().into_owned()))
+        .collect::<std::io::Result<_>>()?;
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "REVIEW.md",
+            "lazy_static-1.4.0",
+            "manifest.json",
+            "void-0.0.5",
+            "void-1.0.1"
+        ]
+    );
+
+    let dest = project_dir.path().join("versioned");
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["-p", "void:1.0.1", "--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    assert_eq!(fs::read_dir(&dest)?.count(), 3);
+    assert!(dest.join("void-1.0.1").exists());
+
+    // Nothing is copied if one of the names is wrong.
+    let dest = project_dir.path().join("failed");
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["-p", "void", "-p", "thread_locl", "--destination"])
+        .with_args(&[&dest])
+        .fails_with(101)
+        .stderr()
+        .contains(
+            "error: `thread_locl` is not a dependency of the workspace; did you mean `thread_local`?",
+        )
+        .unwrap();
+    assert!(!dest.exists());
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["-p", "void:1.0.3", "--destination"])
+        .with_args(&[&dest])
+        .fails_with(101)
+        .stderr()
+        .contains("error: the workspace depends on `void` 0.0.5 and 1.0.1, not 1.0.3")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn current_checks_out_git_and_path_dependencies_with_all_sources() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("current-tests")?;
+    let repo = dir.path().join("gdep");
+    fs::create_dir_all(repo.join("src"))?;
+    fs::write(
+        repo.join("Cargo.toml"),
+        "[package]\nname = \"gdep\"\nversion = \"0.3.0\"\n",
+    )?;
+    fs::write(repo.join("src/lib.rs"), "")?;
+    let git = |args: &[&str]| -> std::io::Result<String> {
+        let output = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(&repo)
+            .output()?;
+        assert!(output.status.success(), "git {:?} failed", args);
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    git(&["init", "-q"])?;
+    git(&["add", "-A"])?;
+    git(&["commit", "-q", "-m", "init"])?;
+    let rev = git(&["rev-parse", "--short=7", "HEAD"])?;
+
+    let project = dir.path().join("project");
+    fs::create_dir_all(project.join("src"))?;
+    fs::create_dir_all(dir.path().join("pdep/src"))?;
+    fs::write(project.join("src/lib.rs"), "")?;
+    fs::write(dir.path().join("pdep/src/lib.rs"), "")?;
+    fs::write(
+        dir.path().join("pdep/Cargo.toml"),
+        "[package]\nname = \"pdep\"\nversion = \"0.1.0\"\n",
+    )?;
+    fs::write(
+        project.join("Cargo.toml"),
+        format!(
+            "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+             [dependencies]\ngdep = {{ git = \"file://{}\" }}\npdep = {{ path = \"../pdep\" }}\n\
+             void = \"=1.0.1\"\n",
+            repo.display()
+        ),
+    )?;
+    let names = |dest: &Path| -> std::io::Result<Vec<String>> {
+        let mut res: Vec<String> = fs::read_dir(dest)?
+            .map(|it| Ok(it?.file_name().to_string_lossy().into_owned()))
+            .collect::<std::io::Result<_>>()?;
+        res.sort();
+        Ok(res)
+    };
+
+    let dest = dir.path().join("registry");
+    cmd_current()
+        .current_dir(&project)
+        .with_args(&["--destination"])
+        .with_args(&[&dest])
+        .stderr()
+        .contains("Skipping package `gdep`: a git dependency, pass --include-git to check it out")
+        .stderr()
+        .contains("Skipping package `pdep`: a path dependency, pass --include-path to check it out")
+        .unwrap();
+    assert_eq!(names(&dest)?, ["REVIEW.md", "manifest.json", "void-1.0.1"]);
+
+    let dest = dir.path().join("all");
+    cmd_current()
+        .current_dir(&project)
+        .with_args(&["--all-sources", "--destination"])
+        .with_args(&[&dest])
+        .stderr()
+        .contains(
+            "Skipping package `test-pkg`: a member of the workspace, \
+             pass --include-workspace to check it out",
+        )
+        .unwrap();
+    let git_dir = format!("gdep-0.3.0_git.{}", rev);
+    assert_eq!(
+        names(&dest)?,
+        [
+            "REVIEW.md",
+            git_dir.as_str(),
+            "manifest.json",
+            "pdep-0.1.0_local",
+            "void-1.0.1"
+        ]
+    );
+    assert!(dest.join(&git_dir).join("src/lib.rs").exists());
+    assert!(!dest.join(&git_dir).join(".git").exists());
+
+    let dest = dir.path().join("workspace");
+    cmd_current()
+        .current_dir(&project)
+        .with_args(&["--include-workspace", "--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    assert_eq!(
+        names(&dest)?,
+        [
+            "REVIEW.md",
+            "manifest.json",
+            "test-pkg-0.0.0_workspace",
+            "void-1.0.1"
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn current_reports_the_crates_in_several_versions() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    fs::create_dir_all(dir.path().join("src"))?;
+    fs::write(dir.path().join("src/lib.rs"), "")?;
+    fs::write(
+        dir.path().join("Cargo.toml"),
+        "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+         [dependencies]\nunreachable = \"=1.0.0\"\nvoid = \"=0.0.5\"\n",
+    )?;
+    let dest = dir.path().join("dest");
+    cmd_current()
+        .current_dir(dir.path())
+        .with_args(&["--destination"])
+        .with_args(&[&dest])
+        .stderr()
+        .contains("Duplicates (1 crate in several versions, ")
+        .stderr()
+        .contains(
+            "  void 0.0.5, 1.0.2\n    \
+             0.0.5, pulled in by void 0.0.5\n    \
+             1.0.2, pulled in by unreachable 1.0.0\n",
+        )
+        .unwrap();
+
+    cmd_current()
+        .current_dir(dir.path())
+        .with_args(&["--deny-duplicates", "nothing", "--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    cmd_current()
+        .current_dir(dir.path())
+        .with_args(&["--deny-duplicates", "void", "--destination"])
+        .with_args(&[&dest])
+        .fails_with(101)
+        .stderr()
+        .contains(
+            "--deny-duplicates denies `void` (0.0.5 and 1.0.2), checked out in several versions",
+        )
+        .unwrap();
+    // It's checked out all the same.
+    assert!(dest.join("void-1.0.2").is_dir());
+    Ok(())
+}
+
+#[test]
+fn current_checks_out_the_members_as_cargo_package_would() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    let root = dir.path().join("workspace");
+    for (path, contents) in [
+        (
+            "Cargo.toml",
+            "[package]\nname = \"app\"\nversion = \"0.4.0\"\n\
+             exclude = [\"notes/\", \"/secret.txt\", \"*.log\"]\n\n\
+             [dependencies]\ninner = { path = \"crates/inner\" }\n\n\
+             [workspace]\nmembers = [\"crates/inner\"]\n",
+        ),
+        ("src/lib.rs", ""),
+        ("src/secret.txt", ""),
+        ("secret.txt", ""),
+        ("notes/todo.md", ""),
+        ("src/debug.log", ""),
+        ("target/debug/app", ""),
+        (".git/HEAD", ""),
+        (
+            "crates/inner/Cargo.toml",
+            "[package]\nname = \"inner\"\nversion = \"0.1.0\"\n\
+             include = [\"src/**/*.rs\"]\n",
+        ),
+        ("crates/inner/src/lib.rs", ""),
+        ("crates/inner/src/data.bin", ""),
+        ("crates/inner/README.md", ""),
+    ] {
+        fs::create_dir_all(root.join(path).parent().unwrap())?;
+        fs::write(root.join(path), contents)?;
+    }
+    let files = |dir: &Path| -> std::io::Result<Vec<String>> {
+        let mut res = Vec::new();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(current) = dirs.pop() {
+            for entry in fs::read_dir(&current)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    let relative = path.strip_prefix(dir).unwrap();
+                    res.push(relative.display().to_string());
+                }
+            }
+        }
+        res.sort();
+        Ok(res)
+    };
+
+    let dest = dir.path().join("default");
+    cmd_current()
+        .current_dir(&root)
+        .with_args(&["--destination"])
+        .with_args(&[&dest])
+        .stderr()
+        .contains("Skipping package `app`: a member of the workspace, pass --include-workspace to check it out")
+        .stderr()
+        .contains("Skipping package `inner`: a member of the workspace, pass --include-workspace to check it out")
+        .unwrap();
+    assert_eq!(files(&dest)?, ["REVIEW.md", "manifest.json"]);
+
+    let dest = dir.path().join("members");
+    cmd_current()
+        .current_dir(&root)
+        .with_args(&["--include-workspace", "--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    assert_eq!(
+        files(&dest.join("app-0.4.0_workspace"))?,
+        ["Cargo.lock", "Cargo.toml", "src/lib.rs", "src/secret.txt"]
+    );
+    assert_eq!(
+        files(&dest.join("inner-0.1.0_workspace"))?,
+        ["Cargo.toml", "src/lib.rs"]
+    );
+    Ok(())
+}
+
+#[test]
+fn current_checks_out_only_the_direct_dependencies() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    fs::write(
+        project_dir.path().join("Cargo.toml"),
+        r#"
+        [package]
+        name = "test-pkg"
+        version = "0.0.0"
+
+        [dependencies]
+        void = "=1.0.1"
+        thread_local = "=0.3.6"
+
+        [lib]
+        path = "./Cargo.toml"
+    "#,
+    )?;
+    let dest = project_dir.path().join("dest");
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["--direct-only", "--destination"])
+        .with_args(&[&dest])
+        .stderr()
+        .contains("Direct dependencies: 2, transitive ones left out: 1")
+        .unwrap();
+    assert!(dest.join("thread_local-0.3.6").exists());
+    assert!(dest.join("void-1.0.1").exists());
+    assert_eq!(fs::read_dir(&dest)?.count(), 4);
+
+    let dest = project_dir.path().join("selected");
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["--direct-only", "-p", "void", "--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    assert_eq!(fs::read_dir(&dest)?.count(), 3);
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["--direct-only", "-p", "lazy_static", "--destination"])
+        .with_args(&[&dest])
+        .fails_with(101)
+        .stderr()
+        .contains(
+            "error: `lazy_static` is only a transitive dependency, \
+             leave out --direct-only to check it out",
+        )
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn current_leaves_out_dev_and_build_dependencies() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    fs::write(
+        project_dir.path().join("Cargo.toml"),
+        r#"
+        [package]
+        name = "test-pkg"
+        version = "0.0.0"
+
+        [dependencies]
+        void = "=1.0.1"
+
+        [dev-dependencies]
+        void = "=1.0.1"
+        thread_local = "=0.3.6"
+
+        [build-dependencies]
+        lazy_static = "=1.4.0"
+
+        [lib]
+        path = "./Cargo.toml"
+    "#,
+    )?;
+    let dest = project_dir.path().join("no-dev");
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["--no-dev-deps", "--destination"])
+        .with_args(&[&dest])
+        .stderr()
+        .contains("Left out 1 package only pulled in by dev-dependencies")
+        .unwrap();
+    assert!(dest.join("lazy_static-1.4.0").exists());
+    assert!(dest.join("void-1.0.1").exists());
+    assert_eq!(fs::read_dir(&dest)?.count(), 4);
+
+    let dest = project_dir.path().join("neither");
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["--no-dev-deps", "--no-build-deps", "--destination"])
+        .with_args(&[&dest])
+        .stderr()
+        .contains("Left out 2 packages only pulled in by dev-dependencies and build-dependencies")
+        .unwrap();
+    assert!(dest.join("void-1.0.1").exists());
+    assert_eq!(fs::read_dir(&dest)?.count(), 3);
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["--no-dev-deps", "-p", "thread_local", "--destination"])
+        .with_args(&[&dest])
+        .fails_with(101)
+        .stderr()
+        .contains(
+            "error: `thread_local` is only pulled in by dev-dependencies, \
+             leave out --no-dev-deps to check it out",
+        )
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn current_tells_the_versions_of_a_crate_apart_by_dependency_kind() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    fs::write(
+        project_dir.path().join("Cargo.toml"),
+        r#"
+        [package]
+        name = "test-pkg"
+        version = "0.0.0"
+
+        [dependencies]
+        itoa = "=0.4.8"
+
+        [dev-dependencies]
+        itoa1 = { package = "itoa", version = "=1.0.1" }
+
+        [lib]
+        path = "./Cargo.toml"
+    "#,
+    )?;
+    let output = Command::new(cargo_review_deps_exe())
+        .args(["review-deps", "current", "--list", "--no-dev-deps"])
+        .current_dir(project_dir.path())
+        .output()?;
+    assert!(output.status.success());
+    let listed: Vec<Vec<String>> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().take(2).map(String::from).collect())
+        .collect();
+    assert_eq!(listed, vec![vec!["itoa".to_string(), "0.4.8".to_string()]]);
+    Ok(())
+}
+
+#[test]
+fn current_checks_out_only_what_the_members_pull_in() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    let root = project_dir.path();
+    for (path, contents) in &[
+        ("Cargo.toml", "[workspace]\nmembers = [\"app\", \"tool\"]\n"),
+        (
+            "app/Cargo.toml",
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\nvoid = \"=1.0.1\"\nitoa = \"=0.4.8\"\n\n\
+             [dev-dependencies]\nlazy_static = \"=1.4.0\"\n\
+             itoa1 = { package = \"itoa\", version = \"=1.0.1\" }\n",
+        ),
+        ("app/src/lib.rs", ""),
+        (
+            "tool/Cargo.toml",
+            "[package]\nname = \"tool\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\nthread_local = \"=0.3.6\"\n",
+        ),
+        ("tool/src/lib.rs", ""),
+    ] {
+        fs::create_dir_all(root.join(path).parent().unwrap())?;
+        fs::write(root.join(path), contents)?;
+    }
+    let dest = root.join("app-only");
+    cmd_current()
+        .current_dir(root)
+        .with_args(&["--member", "app", "--no-dev-deps", "--destination"])
+        .with_args(&[&dest])
+        .stderr()
+        .contains("not pulled in by --member app: 2")
+        .unwrap();
+    // Only the version `app` builds with.
+    assert!(dest.join("void-1.0.1").exists());
+    assert!(dest.join("itoa-0.4.8").exists());
+    assert_eq!(fs::read_dir(&dest)?.count(), 4);
+
+    let dest = root.join("both");
+    cmd_current()
+        .current_dir(root)
+        .with_args(&["--member", "app", "--member", "tool", "--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    assert!(dest.join("lazy_static-1.4.0").exists());
+    assert!(dest.join("thread_local-0.3.6").exists());
+    assert!(dest.join("itoa-1.0.1").exists());
+    cmd_current()
+        .current_dir(root)
+        .with_args(&["--member", "server", "--destination"])
+        .with_args(&[&root.join("unknown")])
+        .fails_with(101)
+        .stderr()
+        .contains(
+            "error: --member server: `server` is not a member of the workspace, \
+             its members are app and tool",
+        )
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn current_tells_why_each_package_is_there() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    let root = project_dir.path();
+    for (path, contents) in &[
+        ("Cargo.toml", "[workspace]\nmembers = [\"app\", \"tool\"]\n"),
+        (
+            "app/Cargo.toml",
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\nthread_local = \"=0.3.6\"\n",
+        ),
+        ("app/src/lib.rs", ""),
+        (
+            "tool/Cargo.toml",
+            "[package]\nname = \"tool\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\nlazy_static = \"=1.4.0\"\n",
+        ),
+        ("tool/src/lib.rs", ""),
+    ] {
+        fs::create_dir_all(root.join(path).parent().unwrap())?;
+        fs::write(root.join(path), contents)?;
+    }
+    let dest = root.join("dest");
+    cmd_current()
+        .current_dir(root)
+        .with_args(&["--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(dest.join("lazy_static-1.4.0/PROVENANCE"))?,
+        "tool -> lazy_static\napp -> thread_local -> lazy_static\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dest.join("thread_local-0.3.6/PROVENANCE"))?,
+        "app -> thread_local\n"
+    );
+    let manifest = fs::read_to_string(dest.join("manifest.json"))?;
+    let json: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+    let lazy_static = json["packages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|it| it["name"] == "lazy_static")
+        .unwrap();
+    assert_eq!(
+        lazy_static["provenance"],
+        serde_json::json!(["tool -> lazy_static", "app -> thread_local -> lazy_static"])
+    );
+    // It isn't part of the tree of the package, which is left as it is.
+    cmd_current()
+        .current_dir(root)
+        .with_args(&["--destination"])
+        .with_args(&[&dest])
+        .stderr()
+        .contains("0 packages copied, 2 unchanged")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn current_checks_out_only_the_packages_built_for_the_targets() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    fs::write(
+        project_dir.path().join("Cargo.toml"),
+        r#"
+        [package]
+        name = "test-pkg"
+        version = "0.0.0"
+
+        [target.'cfg(windows)'.dependencies]
+        void = "=1.0.1"
+
+        [target.'cfg(unix)'.dependencies]
+        lazy_static = "=1.4.0"
+
+        [lib]
+        path = "./Cargo.toml"
+    "#,
+    )?;
+    let dest = project_dir.path().join("windows");
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["--target", "x86_64-pc-windows-msvc", "--destination"])
+        .with_args(&[&dest])
+        .stderr()
+        .contains("Skipped (not built for x86_64-pc-windows-msvc):\n  lazy_static 1.4.0")
+        .unwrap();
+    assert!(dest.join("void-1.0.1").exists());
+    assert!(fs::read_to_string(dest.join("snapshot.toml"))?
+        .contains("targets = [\"x86_64-pc-windows-msvc\"]"));
+    assert_eq!(fs::read_dir(&dest)?.count(), 4);
+
+    let dest = project_dir.path().join("host");
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["--filter-platform-host", "--destination"])
+        .with_args(&[&dest])
+        .stderr()
+        .contains("  void 1.0.1")
+        .unwrap();
+    assert!(dest.join("lazy_static-1.4.0").exists());
+    assert_eq!(fs::read_dir(&dest)?.count(), 4);
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["--filter-platform-host", "-p", "void", "--destination"])
+        .with_args(&[&dest])
+        .fails_with(101)
+        .stderr()
+        .contains("error: `void` is not built for")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn current_resolves_the_dependencies_with_the_given_features() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    fs::write(
+        project_dir.path().join("Cargo.toml"),
+        r#"
+        [package]
+        name = "test-pkg"
+        version = "0.0.0"
+
+        [features]
+        default = ["full"]
+        full = ["void"]
+
+        [dependencies]
+        void = { version = "=1.0.1", optional = true }
+        lazy_static = "=1.4.0"
+
+        [lib]
+        path = "./Cargo.toml"
+    "#,
+    )?;
+    let dest = project_dir.path().join("default");
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    assert!(dest.join("void-1.0.1").exists());
+    assert!(!dest.join("snapshot.toml").exists());
+
+    let dest = project_dir.path().join("minimal");
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["--no-default-features", "--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    assert!(dest.join("lazy_static-1.4.0").exists());
+    assert!(!dest.join("void-1.0.1").exists());
+    assert_eq!(
+        fs::read_to_string(dest.join("snapshot.toml"))?,
+        "# The build configuration `cargo review-deps current` checked out the dependencies for.\n\
+         features = []\n\
+         all-features = false\n\
+         no-default-features = true\n"
+    );
+
+    let dest = project_dir.path().join("full");
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&[
+            "--no-default-features",
+            "--features",
+            "full",
+            "--destination",
+        ])
+        .with_args(&[&dest])
+        .unwrap();
+    assert!(dest.join("void-1.0.1").exists());
+    assert!(fs::read_to_string(dest.join("snapshot.toml"))?.contains("features = [\"full\"]"));
+    Ok(())
+}
+
+#[test]
+fn current_writes_a_manifest_of_the_packages() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    fs::write(
+        project_dir.path().join("Cargo.toml"),
+        r#"
+        [package]
+        name = "test-pkg"
+        version = "0.0.0"
+
+        [dependencies]
+        void = "=1.0.1"
+
+        [lib]
+        path = "./Cargo.toml"
+    "#,
+    )?;
+    let entry = "\"name\": \"void\",\n      \
+                 \"version\": \"1.0.1\",\n      \
+                 \"source\": \"registry+https://github.com/rust-lang/crates.io-index\",\n      \
+                 \"license\": \"MIT\",\n      \
+                 \"repository\": \"https://github.com/reem/rust-void.git\",\n";
+    let tree = "\"directory\": \"void-1.0.1\",\n      \
+                \"files\": 5,\n      \
+                \"bytes\": 4683,\n      \
+                \"sha256\": \"243a2e578f09689b0eeadba8d4b4d4b633e773add53db517edb2dc9068533d00\"";
+    let dest = project_dir.path().join("dest");
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_args(&["--format", "json", "--destination"])
+        .with_args(&[&dest])
+        .stdout()
+        .contains(entry)
+        .stdout()
+        .contains(tree)
+        .unwrap();
+    let manifest = fs::read_to_string(dest.join("manifest.json"))?;
+    assert!(manifest.contains(entry) && manifest.contains(tree));
+    assert!(manifest.contains("\"verification\": \"verified\""));
+
+    // The same package is the same tree, however it's checked out.
+    let list = project_dir.path().join("review-list.txt");
+    fs::write(&list, "void:1.0.1\n")?;
+    let dest = project_dir.path().join("from-list");
+    cmd_current()
+        .with_args(&["--packages-from"])
+        .with_args(&[&list])
+        .with_args(&["--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    let manifest = fs::read_to_string(dest.join("manifest.json"))?;
+    assert!(manifest.contains(entry) && manifest.contains(tree));
+    Ok(())
+}
+
+#[test]
+fn current_verifies_the_sources_against_the_lockfile() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    fs::write(
+        project_dir.path().join("Cargo.toml"),
+        r#"
+        [package]
+        name = "test-pkg"
+        version = "0.0.0"
+
+        [dependencies]
+        void = "=1.0.1"
+
+        [lib]
+        path = "./Cargo.toml"
+    "#,
+    )?;
+    // A registry of its own, to tamper with.
+    let cargo_home = project_dir.path().join("cargo-home");
+    let env = || Environment::inherit().insert("CARGO_HOME", &cargo_home);
+    let dest = project_dir.path().join("dest");
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_env(env())
+        .with_args(&["--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    assert!(
+        fs::read_to_string(dest.join("manifest.json"))?.contains("\"verification\": \"verified\"")
+    );
+
+    let index = fs::read_dir(cargo_home.join("registry/src"))?
+        .next()
+        .unwrap()?
+        .path();
+    fs::write(index.join("void-1.0.1/src/lib.rs"), "// Nothing to see.\n")?;
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_env(env())
+        .with_args(&["--refresh", "--destination"])
+        .with_args(&[&dest])
+        .fails_with(101)
+        .stderr()
+        .contains("error: src/lib.rs of void 1.0.1 isn't the one of its .crate file")
+        .stderr()
+        .contains("The sources might have been tampered with!")
+        .unwrap();
+    cmd_current()
+        .current_dir(project_dir.path())
+        .with_env(env())
+        .with_args(&["--refresh", "--no-verify", "--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    assert!(
+        fs::read_to_string(dest.join("manifest.json"))?.contains("\"verification\": \"skipped\"")
+    );
+    Ok(())
+}
+
+#[test]
+fn current_leaves_the_unchanged_packages_as_they_are() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    let manifest = |dependencies: &str| {
+        fs::write(
+            project_dir.path().join("Cargo.toml"),
+            format!(
+                "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+                 [dependencies]\n{}\n[lib]\npath = \"./Cargo.toml\"\n",
+                dependencies
+            ),
+        )
+    };
+    manifest("void = \"=1.0.1\"\nlazy_static = \"=1.4.0\"\n")?;
+    let dest = project_dir.path().join("dest");
+    let current = |args: &[&str]| {
+        cmd_current()
+            .current_dir(project_dir.path())
+            .with_args(args)
+            .with_args(&["--destination"])
+            .with_args(&[&dest])
+    };
+    current(&["--jobs", "2"])
+        .stderr()
+        .contains("2 packages copied, 0 unchanged, in ")
+        .unwrap();
+    current(&[])
+        .stderr()
+        .contains("0 packages copied, 2 unchanged, in ")
+        .unwrap();
+
+    // A package changed since is only replaced with --force.
+    fs::write(dest.join("void-1.0.1/notes.txt"), "Looks fine.\n")?;
+    current(&[])
+        .fails_with(101)
+        .stderr()
+        .contains("void-1.0.1 exists already, pass --force to replace it")
+        .unwrap();
+    current(&["--force"])
+        .stderr()
+        .contains("1 package copied, 1 unchanged")
+        .unwrap();
+    assert!(!dest.join("void-1.0.1/notes.txt").exists());
+
+    manifest("void = \"=1.0.1\"\n")?;
+    current(&[])
+        .stderr()
+        .contains("0 packages copied, 1 unchanged")
+        .unwrap();
+    assert!(dest.join("lazy_static-1.4.0").exists());
+    current(&["--prune"])
+        .stderr()
+        .contains("0 packages copied, 1 unchanged, 1 pruned")
+        .unwrap();
+    assert!(!dest.join("lazy_static-1.4.0").exists());
+    current(&["--refresh"])
+        .stderr()
+        .contains("1 package copied, 0 unchanged")
+        .unwrap();
+    Ok(())
+}
+
+// As in `current_checks_out_the_other_packages_when_one_fails`, a file can
+// be too deep to be copied.
+#[cfg(target_os = "linux")]
+#[test]
+fn current_reports_its_progress_and_all_the_failures_at_the_end() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    let project = dir.path().join("project");
+    let names: Vec<String> = (0..11)
+        .map(|it| format!("local{}", it))
+        .chain(vec!["deep1".to_string(), "deep2".to_string()])
+        .collect();
+    let dependencies: String = names
+        .iter()
+        .map(|it| format!("{0} = {{ path = \"../{0}\" }}\n", it))
+        .collect();
+    fs::create_dir_all(project.join("src"))?;
+    fs::write(
+        project.join("Cargo.toml"),
+        format!(
+            "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n[dependencies]\n{}",
+            dependencies
+        ),
+    )?;
+    fs::write(project.join("src/lib.rs"), "")?;
+    for name in names.iter() {
+        fs::create_dir_all(dir.path().join(name).join("src"))?;
+        fs::write(
+            dir.path().join(name).join("Cargo.toml"),
+            format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\n", name),
+        )?;
+        fs::write(dir.path().join(name).join("src/lib.rs"), "")?;
+    }
+    for name in ["deep1", "deep2"] {
+        let mut deep = dir.path().join(name);
+        while deep.as_os_str().len() < 4080 {
+            let len = (4085 - deep.as_os_str().len() - 1).min(200);
+            deep.push("d".repeat(len));
+        }
+        fs::create_dir_all(&deep)?;
+        fs::write(deep.join("f"), "")?;
+    }
+
+    let dest = dir.path().join("dest");
+    let output = Command::new(cargo_review_deps_exe())
+        .args(["review-deps", "current", "--include-path", "--jobs", "4"])
+        .arg("--destination")
+        .arg(&dest)
+        .current_dir(&project)
+        .output()?;
+    assert_eq!(output.status.code(), Some(101));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // Not a terminal: a line every tenth of the way, at least every ten.
+    assert!(stderr.contains("\nCopied 10/13 packages\n"), "{}", stderr);
+    assert!(!stderr.contains('\r'), "{}", stderr);
+    // The failures don't stop the others, they are all told at the end.
+    assert!(
+        stderr.contains("11 packages copied, 0 unchanged, 2 failed, in ")
+            && stderr.contains("Failed packages:\n  deep1 0.1.0: ")
+            && stderr.contains("\n  deep2 0.1.0: ")
+            && stderr.ends_with(
+                "\nerror: 2 of 13 packages couldn't be checked out, \
+                 marked as failed in manifest.json\n"
+            ),
+        "{}",
+        stderr
+    );
+    for name in names.iter().take(11) {
+        assert!(dest
+            .join(format!("{}-0.1.0_local/src/lib.rs", name))
+            .is_file());
+    }
+    Ok(())
+}
+
+#[test]
+fn current_checks_out_the_same_version_from_two_registries_apart() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    fs::write(
+        project_dir.path().join("Cargo.toml"),
+        r#"
+        [package]
+        name = "test-pkg"
+        version = "0.0.0"
+
+        [dependencies]
+        void = "=1.0.1"
+        void_mirror = { package = "void", version = "=1.0.1", registry = "mirror" }
+
+        [lib]
+        path = "./Cargo.toml"
+    "#,
+    )?;
+    let dest = project_dir.path().join("dest");
+    let current = || {
+        cmd_current()
+            .current_dir(project_dir.path())
+            .with_env(mirror_registry_env())
+            .with_args(&["--destination"])
+            .with_args(&[&dest])
+    };
+    current()
+        .stderr()
+        .contains("2 packages copied, 0 unchanged")
+        .unwrap();
+    let mut names: Vec<String> = fs::read_dir(&dest)?
+        .map(|it| Ok(it?.file_name().to_string_lossy().into_owned()))
+        .collect::<std::io::Result<_>>()?;
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "REVIEW.md",
+            "manifest.json",
+            "void-1.0.1_65f52ed8",
+            "void-1.0.1_c19b7c6f"
+        ]
+    );
+    current()
+        .stderr()
+        .contains("0 packages copied, 2 unchanged")
+        .unwrap();
+
+    for name in &names[2..] {
+        fs::write(dest.join(name).join("notes.txt"), "")?;
+    }
+    current()
+        .fails_with(101)
+        .stderr()
+        .contains(
+            format!(
+                "{} and {} exist already, pass --force to replace them",
+                dest.join(&names[3]).display(),
+                dest.join(&names[2]).display()
+            )
+            .as_str(),
+        )
+        .unwrap();
+    assert!(dest.join(&names[2]).join("notes.txt").exists());
+    Ok(())
+}
+
+#[test]
+fn current_packs_the_packages_into_a_reproducible_archive() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    fs::create_dir_all(dir.path().join("src"))?;
+    fs::write(dir.path().join("src/lib.rs"), "")?;
+    fs::write(
+        dir.path().join("Cargo.toml"),
+        "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+         [dependencies]\nthread_local = \"=0.3.6\"\nvoid = \"=1.0.1\"\n",
+    )?;
+    let archive = |name: &str| -> std::io::Result<Vec<u8>> {
+        let path = dir.path().join(name);
+        cmd_current()
+            .current_dir(dir.path())
+            .with_args(&["--direct-only", "-p", "void", "--archive"])
+            .with_args(&[&path])
+            .stderr()
+            .contains("Wrote the archive to ")
+            .stderr()
+            .contains("1 package packed, in ")
+            .unwrap();
+        fs::read(path)
+    };
+    let first = archive("first.tar.gz")?;
+    assert_eq!(first, archive("second.tar.gz")?);
+    assert_eq!(archive("first.zip")?, archive("second.zip")?);
+
+    let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(first.as_slice()));
+    let mut entries = Vec::new();
+    let mut manifest = String::new();
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        assert_eq!(entry.header().mtime()?, 0);
+        let path = entry.path()?.display().to_string();
+        if path == "manifest.json" {
+            entry.read_to_string(&mut manifest)?;
+        }
+        entries.push(path);
+    }
+    assert_eq!(entries.first().map(String::as_str), Some("void-1.0.1/"));
+    assert_eq!(entries.last().map(String::as_str), Some("manifest.json"));
+    assert!(entries.contains(&"void-1.0.1/src/lib.rs".to_string()));
+    assert!(!entries.iter().any(|it| it.ends_with(".cargo-ok")));
+
+    // The same manifest as for a checkout.
+    let dest = dir.path().join("dest");
+    cmd_current()
+        .current_dir(dir.path())
+        .with_args(&["-p", "void", "--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    assert_eq!(manifest, fs::read_to_string(dest.join("manifest.json"))?);
+    Ok(())
+}
+
+#[test]
+fn current_leaves_out_the_excluded_and_the_large_files() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    fs::create_dir_all(dir.path().join("src"))?;
+    fs::write(dir.path().join("src/lib.rs"), "")?;
+    fs::write(
+        dir.path().join("Cargo.toml"),
+        "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+         [dependencies]\nvoid = \"=1.0.1\"\n",
+    )?;
+    let dest = dir.path().join("dest");
+    let filters = [
+        "-x",
+        ".travis.yml",
+        "-x",
+        "Cargo.toml",
+        "--max-file-size",
+        "500",
+    ];
+    cmd_current()
+        .current_dir(dir.path())
+        .with_args(&filters)
+        .with_args(&["--destination"])
+        .with_args(&[&dest])
+        .stderr()
+        .contains("2 files left out (1480 bytes saved)")
+        .unwrap();
+    let void = dest.join("void-1.0.1");
+    assert!(!void.join(".travis.yml").exists());
+    assert!(!void.join("README.md").exists());
+    // Never the manifest, nor the sources, whatever their size.
+    assert!(void.join("Cargo.toml").exists());
+    assert!(void.join("src/lib.rs").exists());
+    assert!(void.join(".gitignore").exists());
+
+    let manifest = fs::read_to_string(dest.join("manifest.json"))?;
+    let json: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+    assert_eq!(
+        json["packages"][0]["skipped_files"],
+        serde_json::json!([
+            {"path": ".travis.yml", "size": 573, "reason": "excluded by `.travis.yml`"},
+            {"path": "README.md", "size": 907, "reason": "larger than 500 bytes"},
+        ])
+    );
+
+    let archive = dir.path().join("void.tar.gz");
+    cmd_current()
+        .current_dir(dir.path())
+        .with_args(&filters)
+        .with_args(&["--archive"])
+        .with_args(&[&archive])
+        .stderr()
+        .contains("1 package packed, 2 files left out (1480 bytes saved), in ")
+        .unwrap();
+    let bytes = fs::read(archive)?;
+    let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(bytes.as_slice()));
+    let mut packed = String::new();
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.display().to_string() == "manifest.json" {
+            entry.read_to_string(&mut packed)?;
+        }
+    }
+    assert_eq!(packed, manifest);
+    Ok(())
+}
+
+#[test]
+fn current_writes_a_review_index_the_riskiest_first() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    let project = dir.path().join("project");
+    for (name, manifest, files) in [
+        (
+            "project",
+            "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+             [dependencies]\nbuilder = { path = \"../builder\" }\n\
+             macros = { path = \"../macros\" }\nvoid = \"=1.0.1\"\n",
+            &[("src/lib.rs", "")][..],
+        ),
+        (
+            "builder",
+            "[package]\nname = \"builder\"\nversion = \"0.1.0\"\n",
+            &[
+                ("build.rs", "fn main() {}\n"),
+                ("src/lib.rs", "\npub fn f() {}\n"),
+            ][..],
+        ),
+        (
+            "macros",
+            "[package]\nname = \"macros\"\nversion = \"0.2.0\"\n\
+             description = \"Some | macros\"\n\n[lib]\nproc-macro = true\n",
+            &[("src/lib.rs", "")][..],
+        ),
+    ] {
+        let root = dir.path().join(name);
+        fs::create_dir_all(root.join("src"))?;
+        fs::write(root.join("Cargo.toml"), manifest)?;
+        for (path, contents) in files {
+            fs::write(root.join(path), contents)?;
+        }
+    }
+    let dest = dir.path().join("dest");
+    let output = Command::new(cargo_review_deps_exe())
+        .args([
+            "review-deps",
+            "current",
+            "--include-path",
+            "--format",
+            "json",
+        ])
+        .arg("--destination")
+        .arg(&dest)
+        .current_dir(&project)
+        .output()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let review = fs::read_to_string(dest.join("REVIEW.md"))?;
+    let rows: Vec<&str> = review.lines().filter(|it| it.starts_with("| [")).collect();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(
+        rows[0],
+        "| [builder](builder-0.1.0_local/) | 0.1.0 |  | yes |  | 2 | 2 |  |  |"
+    );
+    assert_eq!(
+        rows[1],
+        "| [macros](macros-0.2.0_local/) | 0.2.0 |  |  | yes | 1 | 0 |  | Some \\| macros |"
+    );
+    assert!(rows[2].starts_with("| [void](void-1.0.1/) | 1.0.1 | MIT |  |  | 1 | "));
+    assert!(review.contains("3 packages, the riskiest first"));
+
+    // The same data is in the manifest printed to stdout.
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let packages = json["packages"].as_array().unwrap();
+    let builder = packages.iter().find(|it| it["name"] == "builder").unwrap();
+    assert_eq!(builder["build_script"], true);
+    assert_eq!(builder["rust_files"], 2);
+    assert_eq!(builder["loc"], 2);
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn current_links_the_files_to_the_cache_of_cargo() -> std::io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempdir::TempDir::new("temp-project")?;
+    fs::create_dir_all(dir.path().join("src"))?;
+    fs::write(dir.path().join("src/lib.rs"), "")?;
+    fs::write(
+        dir.path().join("Cargo.toml"),
+        "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+         [dependencies]\nvoid = \"=1.0.1\"\n",
+    )?;
+    for mode in ["hardlink", "reflink"] {
+        let dest = dir.path().join(mode);
+        let output = Command::new(cargo_review_deps_exe())
+            .args([
+                "review-deps",
+                "current",
+                "--link-mode",
+                mode,
+                "--destination",
+            ])
+            .arg(&dest)
+            .current_dir(dir.path())
+            .output()?;
+        assert!(output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let manifest = fs::read_to_string(dest.join("manifest.json"))?;
+        let json: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+        let package = &json["packages"][0];
+        let src = Path::new(package["manifest_path"].as_str().unwrap())
+            .parent()
+            .unwrap()
+            .join("src/lib.rs");
+        let dst = dest.join("void-1.0.1/src/lib.rs");
+        assert_eq!(fs::read(&src)?, fs::read(&dst)?);
+        // Where the files can't be linked, they are copied, and it's said.
+        if package["link_mode"] == "copy" {
+            assert!(
+                stderr.contains("warning: can't link 6 files of void 1.0.1, copied them instead: ")
+            );
+        } else {
+            assert_eq!(package["link_mode"], mode);
+            let shared = fs::metadata(&src)?.ino() == fs::metadata(&dst)?.ino();
+            assert_eq!(shared, mode == "hardlink");
+        }
+        // Leaving the artifacts out doesn't change the cache.
+        assert!(!dest.join("void-1.0.1/.cargo-ok").exists());
+        assert!(src
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .join(".cargo-ok")
+            .exists());
+    }
+    Ok(())
+}
+
+#[test]
+fn current_checks_out_a_directory_cargo_builds_with() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    fs::create_dir_all(dir.path().join("src"))?;
+    fs::write(
+        dir.path().join("src/lib.rs"),
+        "extern crate void;\npub use void::Void;\n",
+    )?;
+    fs::write(
+        dir.path().join("Cargo.toml"),
+        "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+         [dependencies]\nvoid = \"=1.0.1\"\n",
+    )?;
+    let output = Command::new(cargo_review_deps_exe())
+        .current_dir(dir.path())
+        .args([
+            "review-deps",
+            "current",
+            "--layout",
+            "vendor",
+            "-d",
+            "vendor",
+        ])
+        .output()?;
+    assert!(output.status.success());
+    let config = String::from_utf8(output.stdout).unwrap();
+    assert!(config.contains("[source.crates-io]\nreplace-with = \"vendored-sources\"\n"));
+
+    let package = dir.path().join("vendor/void-1.0.1");
+    let checksums = fs::read_to_string(package.join(".cargo-checksum.json"))?;
+    let lockfile = fs::read_to_string(dir.path().join("Cargo.lock"))?;
+    let checksum = lockfile
+        .lines()
+        .find_map(|it| it.strip_prefix("checksum = "))
+        .unwrap();
+    assert!(checksums.contains(&format!("\"package\":{}", checksum)));
+    assert!(checksums.contains("\"Cargo.toml\":"));
+    assert!(!package.join(".cargo-ok").exists());
+
+    fs::create_dir_all(dir.path().join(".cargo"))?;
+    fs::write(dir.path().join(".cargo/config.toml"), config)?;
+    let build = Command::new("cargo")
+        .current_dir(dir.path())
+        .args(["build", "--offline", "--quiet"])
+        .output()?;
+    assert!(
+        build.status.success(),
+        "{}",
+        String::from_utf8_lossy(&build.stderr)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn current_checks_out_packages_from_file() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("current-tests")?;
+    let list = dir.path().join("review-list.txt");
+    fs::write(
+        &list,
+        "# Quarterly review\nvoid:1.0.0\n\nvoid:1.0.1  # the current one\nvoid:1.0.0\n",
+    )?;
+    let dest = dir.path().join("dest");
+    cmd_current()
+        .with_args(&["--packages-from"])
+        .with_args(&[&list])
+        .with_args(&["--destination"])
+        .with_args(&[&dest])
+        .stderr()
+        .contains("review-list.txt:5: skipping duplicate entry `void:1.0.0`")
+        .unwrap();
+    assert!(dest.join("void-1.0.0").exists());
+    assert!(dest.join("void-1.0.1").exists());
+    Ok(())
+}
+
+#[test]
+fn current_reports_line_of_invalid_package_in_file() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("current-tests")?;
+    let list = dir.path().join("review-list.txt");
+    fs::write(&list, "void:1.0.0\nvoid:1.0.0:1\n")?;
+    cmd_current()
+        .with_args(&["--packages-from"])
+        .with_args(&[&list])
+        .with_args(&["--destination"])
+        .with_args(&[dir.path().join("dest")])
+        .fails_with(101)
+        .stderr()
+        .contains("review-list.txt:2: invalid version or requirement")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn current_reports_deps_from_alternative_registry() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    let dest = project_dir.path().join("dest");
+
+    fs::write(
+        project_dir.path().join("Cargo.toml"),
+        r#"
+        [package]
+        name = "test-pkg"
+        version = "0.0.0"
+
+        [dependencies]
+        thread_local = { version = "=0.3.6", registry = "mirror" }
+
+        [lib]
+        path = "./Cargo.toml"
+    "#,
+    )?;
+    cmd_current()
+        .with_env(mirror_registry_env())
+        .current_dir(project_dir.path())
+        .with_args(&["--destination"])
+        .with_args(&[&dest.as_path()])
+        .unwrap();
+    assert!(dest.join("thread_local-0.3.6").exists());
+    Ok(())
+}
+
+#[test]
+fn update_diff_dumps_changed_crates() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    let dest = project_dir.path().join("dest");
+
+    fs::write(
+        project_dir.path().join("Cargo.toml"),
+        r#"
+        [package]
+        name = "test-pkg"
+        version = "0.0.0"
+
+        [dependencies]
+        thread_local = "0.3"
+
+        [lib]
+        path = "./Cargo.toml"
+    "#,
+    )?;
+    let run_cargo = |args: &[&str]| -> std::io::Result<()> {
+        let status = Command::new("cargo")
+            .current_dir(project_dir.path())
+            .args(args)
+            .status()?;
+        assert!(status.success());
+        Ok(())
+    };
+
+    run_cargo(&["generate-lockfile"])?;
+    run_cargo(&["update", "--package", "thread_local", "--precise", "0.3.3"])?;
+    let lockfile = fs::read_to_string(project_dir.path().join("Cargo.lock"))?;
+    cmd_update_diff()
+        .current_dir(project_dir.path())
+        .with_args(&["--destination"])
+        .with_args(&[&dest.as_path()])
+        .with_args(&["--", "--package", "thread_local", "--precise", "0.3.4"])
+        .stderr()
+        .contains("thread_local v0.3.3 -> v0.3.4")
+        .unwrap();
+    assert_eq!(
+        lockfile,
+        fs::read_to_string(project_dir.path().join("Cargo.lock"))?,
+    );
+    assert!(dest.join("before/thread_local-0.3").exists());
+    assert!(dest.join("after/thread_local-0.3").exists());
+    Ok(())
+}
+
+#[test]
+fn update_diff_tells_the_sources_of_a_crate_apart() -> std::io::Result<()> {
+    let project_dir = tempdir::TempDir::new("temp-project")?;
+    let dest = project_dir.path().join("dest");
+    let manifest = |requirement: &str| {
+        format!(
+            r#"
+            [package]
+            name = "test-pkg"
+            version = "0.0.0"
+
+            [dependencies]
+            thread_local = "{0}"
+            thread_local_mirror = {{ package = "thread_local", version = "{0}", registry = "mirror" }}
+
+            [lib]
+            path = "./Cargo.toml"
+            "#,
+            requirement
+        )
+    };
+    fs::write(project_dir.path().join("Cargo.toml"), manifest("=0.3.3"))?;
+    let status = Command::new("cargo")
+        .current_dir(project_dir.path())
+        .env(
+            "CARGO_REGISTRIES_MIRROR_INDEX",
+            "sparse+https://index.crates.io/",
+        )
+        .args(["generate-lockfile"])
+        .status()?;
+    assert!(status.success());
+    fs::write(project_dir.path().join("Cargo.toml"), manifest("0.3"))?;
+    cmd_update_diff()
+        .current_dir(project_dir.path())
+        .with_env(mirror_registry_env())
+        .with_args(&["--destination"])
+        .with_args(&[&dest.as_path()])
+        .unwrap();
+    for side in ["before", "after"] {
+        let mut dirs: Vec<String> = fs::read_dir(dest.join(side))?
+            .map(|it| it.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|it| it.starts_with("thread_local-0.3"))
+            .collect();
+        dirs.sort();
+        assert_eq!(dirs.len(), 2, "{:?}", dirs);
+        assert!(dirs
+            .iter()
+            .all(|it| it.len() == "thread_local-0.3_".len() + 8));
+    }
+    Ok(())
+}
+
+#[test]
+fn diff_prioritizes_risky_files() -> std::io::Result<()> {
+    let dirs = [tempdir::TempDir::new("v1")?, tempdir::TempDir::new("v2")?];
+    for (dir, build) in dirs.iter().zip(["fn main() {}\n", "fn main() { run() }\n"]) {
+        fs::create_dir_all(dir.path().join("src"))?;
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n\n[lib]\nproc-macro = true\n",
+        )?;
+        fs::write(dir.path().join("build.rs"), build)?;
+        fs::write(dir.path().join("src/lib.rs"), "")?;
+        fs::write(dir.path().join("README.md"), "foo\n")?;
+    }
+    let specs: Vec<String> = dirs
+        .iter()
+        .map(|it| format!("path:{}", it.path().display()))
+        .collect();
+
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--builtin", "--prioritize-risky"])
+        .fails_with(1)
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n\
+             High-risk changes:\n \
+             Cargo.toml (manifest): unchanged\n \
+             build.rs (build script): CHANGED\n \
+             src/lib.rs (proc-macro): unchanged\n\
+             --- foo-local/build.rs\n\
+             +++ foo-local/build.rs\n\
+             @@ -1 +1 @@\n\
+             -fn main() {}\n\
+             +fn main() { run() }\n\
+             \n\
+             Other changes:")
+        .unwrap();
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--prioritize-risky", "--format", "json"])
+        .fails_with(101)
+        .stderr()
+        .contains("error: --format json can't be used with --prioritize-risky")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_reports_unsafe_code() -> std::io::Result<()> {
+    let dirs = [tempdir::TempDir::new("v1")?, tempdir::TempDir::new("v2")?];
+    let libs = [
+        "#![forbid(unsafe_code)]\n\
+         // unsafe { in a comment }\n\
+         fn f() -> &'static str { \"unsafe fn\" }\n",
+        "/* unsafe { /* nested */ } */\n\
+         pub unsafe fn f() -> &'static str { r#\"unsafe {\"# }\n\
+         unsafe impl Send for Foo {}\n\
+         fn g() { unsafe { f() }; }\n",
+    ];
+    for (dir, lib) in dirs.iter().zip(libs) {
+        fs::create_dir_all(dir.path().join("src"))?;
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::write(dir.path().join("src/lib.rs"), lib)?;
+        fs::write(
+            dir.path().join("src/raw.rs"),
+            "unsafe extern \"C\" fn raw() {}\n",
+        )?;
+    }
+    let specs: Vec<String> = dirs
+        .iter()
+        .map(|it| format!("path:{}", it.path().display()))
+        .collect();
+
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--brief", "--unsafe-report"])
+        .fails_with(1)
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n\
+             M src/lib.rs\n\
+             \n\
+             HIGH PRIORITY: src/lib.rs no longer has #![forbid(unsafe_code)]\n\
+             Unsafe code:\n \
+             file        before  after\n \
+             src/lib.rs       0      3\n \
+             src/raw.rs       1      1\n \
+             total            1      4\n\
+             More unsafe code in:\n \
+             src/lib.rs: unsafe fn 0 -> 1, unsafe impl 0 -> 1, unsafe blocks 0 -> 1")
+        .unwrap();
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--format", "json", "--unsafe-report"])
+        .fails_with(1)
+        .stdout()
+        .contains("\"forbid_removed\": [\n      \"src/lib.rs\"\n    ]")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_calls_out_opaque_files() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+    }
+    fs::write(old_dir.path().join("blob.bin"), b"\0old")?;
+    fs::write(new_dir.path().join("blob.bin"), b"\0newer")?;
+    fs::write(new_dir.path().join("libfoo.so"), b"\x7fELF\0")?;
+    fs::write(new_dir.path().join("data.rs"), "x".repeat(1001))?;
+    let specs = [
+        format!("path:{}", old_dir.path().display()),
+        format!("path:{}", new_dir.path().display()),
+    ];
+
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--brief"])
+        .fails_with(1)
+        .stderr()
+        .is("Opaque files:\n \
+             changed binary: blob.bin (4 -> 6 bytes, sha256 2a6013f83555ed1c -> 30b61cfde8aae47e)\n \
+             long line: data.rs (the longest line has 1001 characters)\n \
+             added binary: libfoo.so (5 bytes, sha256 8d70ec3a2f3f8395)\n\
+             Size of foo:local -> foo:local:\n \
+             files: 2 -> 4 (+2)\n \
+             bytes: 45 -> 1053 (+1008)\n \
+             Rust files: 0 -> 1 (+1)\n \
+             lines of Rust: 0 -> 1 (+1)")
+        .unwrap();
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--brief", "--deny-new-binaries"])
+        .fails_with(101)
+        .stderr()
+        .contains("error: new binary files: libfoo.so of foo:local")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_highlights_changed_words() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+    }
+    fs::write(
+        old_dir.path().join("lib.rs"),
+        "fn main() {\n    let foo_bar = compute(1);\n}\n",
+    )?;
+    fs::write(
+        new_dir.path().join("lib.rs"),
+        "fn main() {\n    let foo_baz = compute(1, 2);\n}\n",
+    )?;
+    let specs = [
+        format!("path:{}", old_dir.path().display()),
+        format!("path:{}", new_dir.path().display()),
+    ];
+
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--color-words"])
+        .fails_with(1)
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n\
+             --- foo-local/lib.rs\n\
+             +++ foo-local/lib.rs\n\
+             @@ -1,3 +1,3 @@\n \
+             fn main() {\n\
+             ~    let [-foo_bar-]{+foo_baz+} = compute(1{+, 2+});\n \
+             }")
+        .unwrap();
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--color-words", "--format", "html"])
+        .fails_with(1)
+        .stdout()
+        .contains(
+            "<td class=\"ins\">    let <span>foo_baz</span> = compute(1<span>, 2</span>);</td>",
+        )
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_pages_only_to_terminal() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for (dir, lib) in [(&old_dir, "1\n"), (&new_dir, "2\n")] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::write(dir.path().join("lib.rs"), lib)?;
+    }
+    cmd_diff()
+        .with_env(Environment::inherit().insert("PAGER", "sed s/^/paged:/"))
+        .with_args(&[
+            format!("path:{}", old_dir.path().display()),
+            format!("path:{}", new_dir.path().display()),
+        ])
+        .with_args(&["--builtin"])
+        .fails_with(1)
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n\
+             --- foo-local/lib.rs\n+++ foo-local/lib.rs\n@@ -1 +1 @@\n-1\n+2")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_summarizes_manifest_changes() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    fs::write(
+        old_dir.path().join("Cargo.toml"),
+        "[package]\nname = \"foo\"\nversion = \"0.1.0\"\nedition = \"2018\"\n\n\
+         [dependencies]\nlibc = \"0.2\"\nrand_core = { version = \"0.5\", package = \"rand_core\" }\n\n\
+         [features]\ndefault = [\"std\"]\nstd = []\n",
+    )?;
+    fs::write(
+        new_dir.path().join("Cargo.toml"),
+        "[package]\nname = \"foo\"\nversion = \"0.1.1\"\nedition = \"2021\"\n\n\
+         [dependencies]\nrc = { version = \"0.6\", package = \"rand_core\", features = [\"std\"] }\n\
+         memchr = { version = \"2\", optional = true }\n\n\
+         [build-dependencies]\ncc = \"1.0\"\n\n\
+         [features]\ndefault = [\"std\", \"memchr\"]\nstd = []\n",
+    )?;
+    let specs = [
+        format!("path:{}", old_dir.path().display()),
+        format!("path:{}", new_dir.path().display()),
+    ];
+
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--brief"])
+        .fails_with(1)
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n\
+             Changes in Cargo.toml:\n \
+             edition: 2018 -> 2021\n \
+             added dependency: memchr 2, optional\n \
+             added build-dependency: cc 1.0\n \
+             removed dependency: libc 0.2\n \
+             changed dependency: rand_core: renamed rand_core -> rc, 0.5 -> 0.6, features +std\n \
+             changed feature: default: [std] -> [std, memchr]\n\
+             \n\
+             M Cargo.toml")
+        .unwrap();
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--format", "json"])
+        .fails_with(1)
+        .stdout()
+        .contains(
+            "\"change\": \"removed\",\n        \"kind\": \"normal\",\n        \"package\": \"libc\",",
+        )
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_strips_packaging_artifacts() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for (dir, sha1) in [(&old_dir, "aaa"), (&new_dir, "bbb")] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            format!("# normalized {}\n[package]\nname = \"foo\"\n", sha1),
+        )?;
+        fs::write(
+            dir.path().join("Cargo.toml.orig"),
+            "[package]\nname = \"foo\"\n",
+        )?;
+        fs::write(
+            dir.path().join(".cargo_vcs_info.json"),
+            format!("{{\"git\": {{\"sha1\": \"{}\"}}}}\n", sha1),
+        )?;
+        fs::write(dir.path().join(".cargo-ok"), sha1)?;
+    }
+    let specs = [
+        format!("path:{}", old_dir.path().display()),
+        format!("path:{}", new_dir.path().display()),
+    ];
+
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--brief"])
+        .stdout()
+        .is("--- old: foo:local\n+++ new: foo:local")
+        .unwrap();
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--brief", "--no-strip-artifacts"])
+        .fails_with(1)
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n\
+             M .cargo-ok\nM .cargo_vcs_info.json\nM Cargo.toml")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_leaves_out_unchanged_files_for_tool() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for (dir, body) in [(&old_dir, "fn old() {}\n"), (&new_dir, "fn new() {}\n")] {
+        fs::create_dir_all(dir.path().join("src"))?;
+        fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"foo\"\n")?;
+        fs::write(dir.path().join("src/lib.rs"), "mod changed;\n")?;
+        fs::write(dir.path().join("src/changed.rs"), body)?;
+    }
+    let specs = [
+        format!("path:{}", old_dir.path().display()),
+        format!("path:{}", new_dir.path().display()),
+    ];
+
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--verbose", "--tool", "sh", "--"])
+        .with_args(&["-c", "cd \"$1\" && find . -type f; exit 1", "sh"])
+        .fails_with(1)
+        .stdout()
+        .is("--- old: foo:local\n+++ new: foo:local\n./src/changed.rs")
+        .stderr()
+        .contains("Left out 2 files which are the same on both sides (36 bytes) in ")
+        .stderr()
+        .contains(", 1 file to diff")
+        .unwrap();
+
+    // The tool doesn't run at all without differences.
+    fs::write(new_dir.path().join("src/changed.rs"), "fn old() {}\n")?;
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--tool", "false"])
+        .stdout()
+        .is("--- old: foo:local\n+++ new: foo:local")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_requires_confirmation_for_different_crates() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for (dir, name) in [(&old_dir, "foo"), (&new_dir, "bar")] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            format!("[package]\nname = \"{}\"\n", name),
+        )?;
+    }
+    let specs = [
+        format!("path:{}", old_dir.path().display()),
+        format!("path:{}", new_dir.path().display()),
+    ];
+
+    cmd_diff()
+        .with_args(&specs)
+        .fails_with(101)
+        .stderr()
+        .contains(
+            "error: foo:local and bar:local are different crates; \
+             pass --allow-different-names to compare them anyway",
+        )
+        .unwrap();
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--allow-different-names", "--brief"])
+        .fails_with(1)
+        .stdout()
+        .is("--- old: foo:local\n+++ new: bar:local\nM Cargo.toml")
+        .stderr()
+        .contains("warning: comparing DIFFERENT crates, foo:local and bar:local; all of their files may differ")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_opens_sources_with_gui_tool() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for (dir, name) in [(&old_dir, "foo"), (&new_dir, "bar")] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            format!("[package]\nname = \"{}\"\n", name),
+        )?;
+    }
+    let specs = [
+        format!("path:{}", old_dir.path().display()),
+        format!("path:{}", new_dir.path().display()),
+        "--allow-different-names".to_string(),
+        "--open".to_string(),
+    ];
+
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--tool", "echo", "--", "--wait"])
+        .stdout()
+        .contains("--wait ")
+        .stdout()
+        .contains("foo-local ")
+        .stdout()
+        .contains("bar-local")
+        .unwrap();
+
+    let dir = tempdir::TempDir::new("diff-tests")?;
+    let paths = |sep: &str| {
+        format!(
+            "{}{}{}",
+            dir.path().join("old/foo-local").display(),
+            sep,
+            dir.path().join("new/bar-local").display()
+        )
+    };
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--destination"])
+        .with_args(&[dir.path()])
+        .with_env(Environment::inherit().insert("CARGO_REVIEW_DEPS_TOOL", "echo"))
+        .stdout()
+        .is(paths(" ").as_str())
+        .unwrap();
+    // Without a tool, the paths are printed instead.
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--force", "--destination"])
+        .with_args(&[dir.path()])
+        .with_env(
+            Environment::inherit()
+                .insert("CARGO_REVIEW_DEPS_TOOL", "")
+                .insert("PATH", "/nonexistent"),
+        )
+        .stdout()
+        .is(paths("\n").as_str())
+        .stderr()
+        .contains("warning: found none of meld, kdiff3 and bcompare")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_writes_reproducible_archive() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for (dir, name) in [(&old_dir, "foo"), (&new_dir, "bar")] {
+        fs::create_dir_all(dir.path().join("src"))?;
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            format!("[package]\nname = \"{}\"\n", name),
+        )?;
+        fs::write(
+            dir.path().join("src/lib.rs"),
+            format!("fn {}() {{}}\n", name),
+        )?;
+    }
+    let specs = [
+        format!("path:{}", old_dir.path().display()),
+        format!("path:{}", new_dir.path().display()),
+        "--allow-different-names".to_string(),
+    ];
+    let out_dir = tempdir::TempDir::new("diff-tests")?;
+    let archive = |name: &str| -> std::io::Result<Vec<u8>> {
+        let path = out_dir.path().join(name);
+        cmd_diff()
+            .with_args(&specs)
+            .with_args(&["--archive"])
+            .with_args(&[&path])
+            .fails_with(1)
+            .stderr()
+            .contains("Wrote the archive to ")
+            .unwrap();
+        fs::read(path)
+    };
+
+    let first = archive("first.tar.gz")?;
+    // Newer timestamps don't change the archive.
+    fs::write(old_dir.path().join("src/lib.rs"), "fn foo() {}\n")?;
+    assert_eq!(first, archive("second.tar.gz")?);
+    assert_eq!(archive("first.zip")?, archive("second.zip")?);
+
+    let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(first.as_slice()));
+    let mut entries = Vec::new();
+    for entry in tar.entries()? {
+        let entry = entry?;
+        assert_eq!(entry.header().mtime()?, 0);
+        entries.push(entry.path()?.display().to_string());
+    }
+    assert_eq!(
+        entries,
+        [
+            "CHANGES.patch",
+            "new/",
+            "new/bar-local/",
+            "new/bar-local/Cargo.toml",
+            "new/bar-local/src/",
+            "new/bar-local/src/lib.rs",
+            "old/",
+            "old/foo-local/",
+            "old/foo-local/Cargo.toml",
+            "old/foo-local/src/",
+            "old/foo-local/src/lib.rs",
+            "summary.json",
+        ]
+    );
+
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--archive", "out.rar"])
+        .fails_with(101)
+        .stderr()
+        .contains("error: unsupported archive out.rar; expected a .tar.gz, .tgz or .zip file")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_writes_markdown_report() {
+    cmd_diff()
+        .with_args(&["void:1.0.1", "void:1.0.2", "--format", "markdown"])
+        .with_args(&["--max-patch-size", "100"])
+        .fails_with(1)
+        .stdout()
+        .is(r#"
+## `void:1.0.1` → `void:1.0.2`
+
+- Old: `void:1.0.1` ([crates.io](https://crates.io/crates/void/1.0.1), [docs.rs](https://docs.rs/void/1.0.1))
+- New: `void:1.0.2` ([crates.io](https://crates.io/crates/void/1.0.2), [docs.rs](https://docs.rs/void/1.0.2))
+- Risk: LOW (23 lines changed)
+
+### Changed files
+
+| File | Change | Added | Removed |
+| --- | --- | ---: | ---: |
+| `.travis.yml` | modified | 7 | 0 |
+| `Cargo.toml` | modified | 1 | 1 |
+| `src/lib.rs` | modified | 10 | 4 |
+| **3 files changed** | | **18** | **5** |
+
+### Size
+
+| | Old | New | Change |
+| --- | ---: | ---: | ---: |
+| files | 5 | 5 | +0 |
+| bytes | 4683 | 4931 | +248 |
+| Rust files | 1 | 1 | +0 |
+| lines of Rust | 72 | 77 | +5 |
+
+### Changes in Cargo.toml
+
+None.
+
+### High-risk findings
+
+- Cargo.toml (manifest): CHANGED
+
+### Patches
+
+<details>
+<summary><code>.travis.yml</code></summary>
+
+```diff
+@@ -1,9 +1,16 @@
+ language: rust
+ sudo: false
+ 
++rust:
++    - 1.0.0
++    - stable
++    - beta
+```
+
+The patch is cut off after 94 of 295 bytes.
+
+</details>
+
+<details>
+<summary><code>Cargo.toml</code></summary>
+
+```diff
+@@ -1,7 +1,7 @@
+ [package]
+ 
+ name = "void"
+-version = "1.0.1"
++version = "1.0.2"
+```
+
+The patch is cut off after 82 of 274 bytes.
+
+</details>
+
+<details>
+<summary><code>src/lib.rs</code></summary>
+
+```diff
+@@ -11,12 +11,18 @@
+ //! extra functionality to `Result<T, Void>` and `Result<Void, E>`.
+ //!
+ 
+```
+
+The patch is cut off after 96 of 468 bytes.
+
+</details>
+"#)
+        .unwrap();
+    cmd_diff()
+        .with_args(&[
+            "void:1.0.1",
+            "void:1.0.2",
+            "--format",
+            "markdown",
+            "--brief",
+        ])
+        .fails_with(101)
+        .stderr()
+        .contains("error: --format markdown can't be used with --brief")
+        .unwrap();
+}
+
+#[test]
+fn diff_reports_size_delta() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::create_dir_all(dir.path().join("src"))?;
+        fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"foo\"\n")?;
+        // Comments and blank lines aren't code, strings are.
+        fs::write(
+            dir.path().join("src/lib.rs"),
+            "// fn no() {}\n\nfn yes() {}\n/* fn no() {}\n */\nconst S: &str = \"\n// yes\";\n",
+        )?;
+    }
+    fs::create_dir_all(new_dir.path().join("vendor"))?;
+    fs::write(new_dir.path().join("vendor/lib.c"), "int x;\n\nint y;\n")?;
+    let specs = [
+        format!("path:{}", old_dir.path().display()),
+        format!("path:{}", new_dir.path().display()),
+    ];
+
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--brief"])
+        .fails_with(1)
+        .stderr()
+        .contains(
+            "Size of foo:local -> foo:local:\n \
+             files: 2 -> 3 (+1)\n \
+             bytes: 95 -> 110 (+15)\n \
+             Rust files: 1 -> 1 (+0)\n \
+             lines of Rust: 3 -> 3 (+0)\n \
+             files of other code: 0 -> 1 (+1)\n \
+             lines of other code: 0 -> 2 (+2)\n",
+        )
+        .unwrap();
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--format", "json"])
+        .fails_with(1)
+        .stdout()
+        .contains("\"after\": {\n      \"files\": 3,\n      \"bytes\": 110,\n      \"rust_files\": 1,\n      \"rust_lines\": 3,\n      \"other_code_files\": 1,\n      \"other_code_lines\": 2\n    }")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_normalizes_rust_files_with_rustfmt() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for dir in &[&old_dir, &new_dir] {
+        fs::create_dir(dir.path().join("src"))?;
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        )?;
+        // Neither their style nor their edition apply.
+        fs::write(
+            dir.path().join("rustfmt.toml"),
+            "edition = \"2015\"\nhard_tabs = true\n",
+        )?;
+    }
+    // Only parses with the edition of the manifest.
+    fs::write(
+        old_dir.path().join("src/lib.rs"),
+        "async fn run(){let x=1;println!(\"{}\",x);}\n",
+    )?;
+    fs::write(
+        new_dir.path().join("src/lib.rs"),
+        "async fn run() {\n    let x = 1;\n    println!(\"{}\", x);\n}\n",
+    )?;
+    fs::write(old_dir.path().join("src/one.rs"), "pub fn one()->u32{1}\n")?;
+    fs::write(
+        new_dir.path().join("src/one.rs"),
+        "pub fn one() -> u32 {\n    2\n}\n",
+    )?;
+    fs::write(old_dir.path().join("src/broken.rs"), "fn broken( {\n")?;
+    fs::write(new_dir.path().join("src/broken.rs"), "fn broken(  {\n")?;
+    let diff = || {
+        cmd_diff().with_args(&[
+            format!("path:{}", old_dir.path().display()),
+            format!("path:{}", new_dir.path().display()),
+        ])
+    };
+
+    diff()
+        .with_args(&["--normalize", "rustfmt", "--builtin"])
+        .fails_with(1)
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n\
+             --- foo-local/src/broken.rs\n\
+             +++ foo-local/src/broken.rs\n\
+             @@ -1 +1 @@\n\
+             -fn broken( {\n\
+             +fn broken(  {\n\
+             --- foo-local/src/one.rs\n\
+             +++ foo-local/src/one.rs\n\
+             @@ -1,3 +1,3 @@\n \
+             pub fn one() -> u32 {\n\
+             -    1\n\
+             +    2\n \
+             }")
+        .stderr()
+        .contains("warning: rustfmt can't parse src/broken.rs of foo:local, it is compared as it is")
+        .stderr()
+        .contains("Normalized 2 files of foo:local and 2 files of foo:local with rustfmt, formatting-only changes don't show")
+        .unwrap();
+    // The cache and the local packages are left alone.
+    assert_eq!(
+        fs::read_to_string(old_dir.path().join("src/one.rs"))?,
+        "pub fn one()->u32{1}\n"
+    );
+    diff()
+        .with_args(&["--normalize", "rustfmt", "--format", "json"])
+        .fails_with(1)
+        .stdout()
+        .contains("\"normalized\": \"rustfmt\"")
+        .unwrap();
+    diff()
+        .with_args(&["--builtin"])
+        .fails_with(1)
+        .stdout()
+        .contains("+++ foo-local/src/lib.rs")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_lists_the_changes_of_the_dependency_graph() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("dep-graph")?;
+    let package = |name: &str, version: &str, extra: &str| -> std::io::Result<()> {
+        let root = dir.path().join(format!("{}-{}", name, version));
+        fs::create_dir_all(root.join("src"))?;
+        fs::write(root.join("src/lib.rs"), "")?;
+        fs::write(
+            root.join("Cargo.toml"),
+            format!(
+                "[package]\nname = \"{}\"\nversion = \"{}\"\n{}",
+                name, version, extra
+            ),
+        )
+    };
+    package("bar", "0.1.0", "")?;
+    package("bar", "0.2.0", "")?;
+    package("gone", "0.3.0", "")?;
+    package("derive", "1.0.0", "[lib]\nproc-macro = true\n")?;
+    package("cc", "1.0.0", "")?;
+    // Indirectly, through bar.
+    package(
+        "bar",
+        "0.2.1",
+        "[dependencies]\nleaf = { path = \"../leaf-0.1.0\" }\n",
+    )?;
+    package("leaf", "0.1.0", "")?;
+    package(
+        "foo",
+        "1.0.0",
+        "[dependencies]\nbar = { path = \"../bar-0.1.0\" }\ngone = { path = \"../gone-0.3.0\" }\n",
+    )?;
+    package(
+        "foo",
+        "1.1.0",
+        "[dependencies]\nbar = { path = \"../bar-0.2.0\" }\nderive = { path = \"../derive-1.0.0\" }\n\
+         [build-dependencies]\ncc = { path = \"../cc-1.0.0\" }\n\
+         [dev-dependencies]\ngone = { path = \"../gone-0.3.0\" }\n",
+    )?;
+    package(
+        "foo",
+        "1.1.1",
+        "[dependencies]\nbar = { path = \"../bar-0.2.1\" }\n",
+    )?;
+    let diff = |old: &str, new: &str| {
+        cmd_diff().with_args(&[
+            format!("path:{}", dir.path().join(old).display()),
+            format!("path:{}", dir.path().join(new).display()),
+            "--brief".to_string(),
+        ])
+    };
+
+    diff("foo-1.0.0", "foo-1.1.0")
+        .fails_with(1)
+        .stdout()
+        .contains(
+            "Changes in the dependency graph:\n \
+             added cc 1.0.0 (build-dependency)\n \
+             added derive 1.0.0 (proc-macro)\n \
+             removed gone 0.3.0\n \
+             changed bar 0.1.0 -> 0.2.0\n\
+             \n\
+             Changes in Cargo.toml:",
+        )
+        .unwrap();
+    diff("foo-1.1.0", "foo-1.1.1")
+        .with_args(&["--deny-new-deps"])
+        .fails_with(101)
+        .stdout()
+        .contains(
+            "Changes in the dependency graph:\n \
+             added leaf 0.1.0\n \
+             removed cc 1.0.0 (build-dependency)\n \
+             removed derive 1.0.0 (proc-macro)\n \
+             changed bar 0.2.0 -> 0.2.1\n",
+        )
+        .stderr()
+        .contains("error: new dependencies in foo:local: leaf")
+        .unwrap();
+    // The dev-dependencies don't count.
+    diff("foo-1.1.0", "foo-1.0.0")
+        .with_args(&["--deny-new-deps", "--format", "json"])
+        .fails_with(101)
+        .stdout()
+        .contains(
+            "\"dependency_graph\": {\n    \"added\": [\n      {\n        \"name\": \"gone\",\n        \
+             \"new\": [\n          \"0.3.0\"\n        ],\n        \"proc_macro\": false,\n        \
+             \"build\": false\n      }\n    ],\n",
+        )
+        .stderr()
+        .contains("error: new dependencies in foo:local: gone")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_keeps_the_sources_and_prints_where_they_are() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for (dir, text) in [(&old_dir, "old"), (&new_dir, "new")] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::write(dir.path().join("lib.rs"), text)?;
+    }
+    let output = Command::new(cargo_review_deps_exe())
+        .args(["review-deps", "diff", "--keep"])
+        .arg(format!("path:{}", old_dir.path().display()))
+        .arg(format!("path:{}", new_dir.path().display()))
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Kept the sources of foo:local and foo:local, one directory per line, the old ones first:\n"
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let dirs: Vec<&Path> = stdout.lines().map(Path::new).collect();
+    assert_eq!(dirs.len(), 2);
+    for (dir, text) in dirs.iter().zip(["old", "new"]) {
+        assert!(dir.is_absolute());
+        assert_eq!(fs::read_to_string(dir.join("lib.rs"))?, text);
+    }
+    fs::remove_dir_all(dirs[0].parent().unwrap())?;
+    Ok(())
+}
+
+#[test]
+fn diff_collapses_generated_files() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for (dir, text) in [(&old_dir, "old"), (&new_dir, "new")] {
+        fs::create_dir_all(dir.path().join("src/tables"))?;
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::write(dir.path().join("src/lib.rs"), format!("// {}\n", text))?;
+        fs::write(
+            dir.path().join("src/api.rs"),
+            format!("// @generated by a build step\nfn {}() {{}}\n", text),
+        )?;
+        fs::write(
+            dir.path().join("src/tables/unicode.rs"),
+            format!("{}\n", text),
+        )?;
+    }
+    fs::write(old_dir.path().join("Cargo.lock"), "a\nb\n")?;
+    fs::write(new_dir.path().join("Cargo.lock"), "a\nc\nd\n")?;
+    let diff = |args: &[&str]| {
+        cmd_diff()
+            .with_args(&[
+                format!("path:{}", old_dir.path().display()),
+                format!("path:{}", new_dir.path().display()),
+                "--generated".to_string(),
+                "src/tables/*.rs".to_string(),
+            ])
+            .with_args(args)
+            .fails_with(1)
+    };
+
+    diff(&["--builtin"])
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n\
+             Generated file changed: foo-local/Cargo.lock, +2/-1 lines\n\
+             Generated file changed: foo-local/src/api.rs, +1/-1 lines\n\
+             --- foo-local/src/lib.rs\n\
+             +++ foo-local/src/lib.rs\n\
+             @@ -1 +1 @@\n\
+             -// old\n\
+             +// new\n\
+             Generated file changed: foo-local/src/tables/unicode.rs, +1/-1 lines")
+        .unwrap();
+    // `diff` only gets the other files.
+    diff(&[])
+        .stdout()
+        .is("--- old: foo:local\n\
+             +++ new: foo:local\n\
+             Generated file changed: b/foo-local/Cargo.lock, +2/-1 lines\n\
+             Generated file changed: b/foo-local/src/api.rs, +1/-1 lines\n\
+             Generated file changed: b/foo-local/src/tables/unicode.rs, +1/-1 lines\n\
+             diff '--color=never' -r '--exclude=.git' a/foo-local/src/lib.rs b/foo-local/src/lib.rs\n\
+             1c1\n\
+             < // old\n\
+             ---\n\
+             > // new")
+        .unwrap();
+    diff(&["--stat"])
+        .stdout()
+        .contains(
+            " 4 files changed, 5 insertions(+), 4 deletions(-)\n \
+             Of which generated: 3 files changed, 4 insertions(+), 3 deletions(-)",
+        )
+        .unwrap();
+    diff(&["--builtin", "--show-generated"])
+        .stdout()
+        .contains(
+            "--- foo-local/Cargo.lock\n+++ foo-local/Cargo.lock\n@@ -1,2 +1,3 @@\n a\n-b\n+c\n+d\n",
+        )
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn diff_refuses_to_overwrite_the_destination_without_force() -> std::io::Result<()> {
+    let old_dir = tempdir::TempDir::new("old-pkg")?;
+    let new_dir = tempdir::TempDir::new("new-pkg")?;
+    for (dir, text) in [(&old_dir, "old"), (&new_dir, "new")] {
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )?;
+        fs::write(dir.path().join("lib.rs"), text)?;
+        fs::write(dir.path().join("gone.rs"), text)?;
+    }
+    let dest = tempdir::TempDir::new("diff-tests")?;
+    let old = format!("path:{}", old_dir.path().display());
+    let new = format!("path:{}", new_dir.path().display());
+    cmd_diff()
+        .with_args(&[&old, &new, "--destination"])
+        .with_args(&[dest.path()])
+        .unwrap();
+    let checkout = fs::read_dir(dest.path().join("new"))?
+        .next()
+        .unwrap()?
+        .path();
+    assert!(checkout.join("gone.rs").exists());
+
+    fs::remove_file(new_dir.path().join("gone.rs"))?;
+    cmd_diff()
+        .with_args(&[&old, &new, "--destination"])
+        .with_args(&[dest.path()])
+        .fails_with(101)
+        .stderr()
+        .contains("exist already, pass --force to replace them")
+        .unwrap();
+    assert!(checkout.join("gone.rs").exists());
+
+    cmd_diff()
+        .with_args(&[&old, &new, "--force", "--destination"])
+        .with_args(&[dest.path()])
+        .unwrap();
+    assert!(!checkout.join("gone.rs").exists());
+    assert_eq!(fs::read_to_string(checkout.join("lib.rs"))?, "new");
+    assert_eq!(fs::read_dir(dest.path().join("new"))?.count(), 1);
+    Ok(())
+}
+
+#[test]
+fn current_refuses_to_overwrite_the_destination_without_force() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("current-tests")?;
+    let packages = dir.path().join("packages.txt");
+    fs::write(&packages, "void:1.0.2\n")?;
+    let dest = dir.path().join("dest");
+    cmd_current()
+        .with_args(&["--packages-from"])
+        .with_args(&[&packages])
+        .with_args(&["--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    let checkout = dest.join("void-1.0.2");
+    fs::write(checkout.join("stale.rs"), "")?;
+    cmd_current()
+        .with_args(&["--packages-from"])
+        .with_args(&[&packages])
+        .with_args(&["--destination"])
+        .with_args(&[&dest])
+        .fails_with(101)
+        .stderr()
+        .contains(
+            format!(
+                "{} exists already, pass --force to replace it",
+                checkout.display()
+            )
+            .as_str(),
+        )
+        .unwrap();
+    cmd_current()
+        .with_args(&["--packages-from"])
+        .with_args(&[&packages])
+        .with_args(&["--force", "--destination"])
+        .with_args(&[&dest])
+        .unwrap();
+    assert!(!checkout.join("stale.rs").exists());
+    assert!(checkout.join("Cargo.toml").exists());
+    Ok(())
+}
+
+#[test]
+fn diff_scores_the_risk_of_the_changes() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("risk")?;
+    let package = |name: &str, manifest: &str, lib: &str| -> std::io::Result<()> {
+        let root = dir.path().join(name);
+        fs::create_dir_all(root.join("src"))?;
+        fs::write(root.join("src/lib.rs"), lib)?;
+        fs::write(root.join("Cargo.toml"), manifest)
+    };
+    let manifest = "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n";
+    package("old", manifest, "pub fn foo() {}\n")?;
+    package(
+        "new",
+        &format!(
+            "{}[dependencies]\nbar = {{ path = \"../bar\" }}\n",
+            manifest
+        ),
+        "pub fn foo() {\n    unsafe {}\n}\n",
+    )?;
+    fs::write(dir.path().join("new/build.rs"), "fn main() {}\n")?;
+    package(
+        "bar",
+        "[package]\nname = \"bar\"\nversion = \"0.1.0\"\n",
+        "",
+    )?;
+    let specs = [
+        format!("path:{}", dir.path().join("old").display()),
+        format!("path:{}", dir.path().join("new").display()),
+    ];
+    let line = "foo:local -> foo:local: risk MEDIUM \
+                (build.rs added, +1 unsafe, 1 new dependency, 7 lines changed)";
+
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--brief", "--risk"])
+        .fails_with(1)
+        .stdout()
+        .contains(line)
+        .unwrap();
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--format", "json"])
+        .fails_with(1)
+        .stdout()
+        .contains(
+            "\"risk\": {\n    \"score\": 45,\n    \"level\": \"medium\",\n    \"reasons\": [\n      \
+             \"build.rs added\",",
+        )
+        .unwrap();
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--format", "markdown"])
+        .fails_with(1)
+        .stdout()
+        .contains("- Risk: MEDIUM (build.rs added, +1 unsafe, 1 new dependency, 7 lines changed)\n")
+        .unwrap();
+
+    // The threshold decides the exit status.
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--brief", "--risk-threshold", "high"])
+        .fails_with(1)
+        .stdout()
+        .contains(line)
+        .unwrap();
+    cmd_diff()
+        .with_args(&specs)
+        .with_args(&["--brief", "--risk-threshold", "medium"])
+        .fails_with(101)
+        .stderr()
+        .contains(
+            "error: the risk of foo:local -> foo:local is MEDIUM, at or above --risk-threshold medium",
+        )
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn current_sums_up_what_it_checked_out() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    let project = dir.path().join("project");
+    for (path, contents) in [
+        (
+            "Cargo.toml",
+            "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+             [dependencies]\nvoid = \"=1.0.1\"\nhelper = { path = \"../helper\" }\n",
+        ),
+        ("src/lib.rs", ""),
+        (
+            "../helper/Cargo.toml",
+            "[package]\nname = \"helper\"\nversion = \"0.1.0\"\n",
+        ),
+        ("../helper/src/lib.rs", ""),
+    ] {
+        let path = project.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, contents)?;
+    }
+    let dest = dir.path().join("dest");
+    cmd_current()
+        .current_dir(&project)
+        .with_args(&["--destination"])
+        .with_args(&[&dest])
+        .stderr()
+        .contains(
+            "Skipped 2 packages: members of the workspace: 1, path dependencies: 1\n\
+             Largest packages:\n  \
+             void 1.0.1, 4683 bytes, 98 lines of Rust\n",
+        )
+        .stderr()
+        .contains("Total: 1 package, 4683 bytes, 98 lines of Rust\n")
+        .stderr()
+        .contains("1 package copied, 0 unchanged, in ")
+        .unwrap();
+
+    let output = Command::new(cargo_review_deps_exe())
+        .args([
+            "review-deps",
+            "current",
+            "--format",
+            "json",
+            "--destination",
+        ])
+        .arg(&dest)
+        .current_dir(&project)
+        .output()?;
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["packages"][0]["name"], "void");
+    let summary = &json["summary"];
+    assert_eq!(summary["copied"], 0);
+    assert_eq!(summary["unchanged"], 1);
+    assert_eq!(summary["skipped"]["path dependencies"], 1);
+    assert_eq!(summary["largest"][0]["bytes"], 4683);
+    // manifest.json stays the same from a run to the next.
+    let manifest = fs::read_to_string(dest.join("manifest.json"))?;
+    assert!(!manifest.contains("summary"));
+
+    let output = Command::new(cargo_review_deps_exe())
+        .args(["review-deps", "current", "--quiet", "--refresh", "--force"])
+        .arg("--destination")
+        .arg(&dest)
+        .current_dir(&project)
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    Ok(())
+}
+
+#[test]
+fn current_lists_the_packages_without_copying_them() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    let project = dir.path().join("project");
+    for (path, contents) in [
+        (
+            "Cargo.toml",
+            "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+             [dependencies]\nvoid = \"=1.0.1\"\nbuilder = { path = \"../builder\" }\n",
+        ),
+        ("src/lib.rs", ""),
+        (
+            "../builder/Cargo.toml",
+            "[package]\nname = \"builder\"\nversion = \"0.1.0\"\nlicense = \"MIT\"\n",
+        ),
+        ("../builder/src/lib.rs", ""),
+        ("../builder/build.rs", "fn main() {}\n"),
+    ] {
+        let path = project.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, contents)?;
+    }
+    cmd_current()
+        .current_dir(&project)
+        .with_args(&["--list", "--include-path"])
+        .stdout()
+        .is(
+            "Package  Version  Source     License  build.rs  proc-macro\n\
+             builder  0.1.0    path       MIT      yes\n\
+             void     1.0.1    crates.io  MIT",
+        )
+        .unwrap();
+
+    let output = Command::new(cargo_review_deps_exe())
+        .args(["review-deps", "current", "--list", "--format", "json"])
+        .current_dir(&project)
+        .output()?;
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!([{
+            "name": "void",
+            "version": "1.0.1",
+            "source": "crates.io",
+            "license": "MIT",
+            "repository": "https://github.com/reem/rust-void.git",
+            "build_script": false,
+            "proc_macro": false,
+            "directory": "void-1.0.1",
+        }])
+    );
+    // Nothing is written.
+    assert_eq!(fs::read_dir(dir.path())?.count(), 2);
+    Ok(())
+}
+
+#[test]
+fn current_leaves_out_the_packages_with_allowed_licenses() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    let project = dir.path().join("project");
+    let package = |name: &str, license: &str| {
+        format!(
+            "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n{}\n",
+            name, license
+        )
+    };
+    for (path, contents) in [
+        (
+            "Cargo.toml".to_string(),
+            "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+             [dependencies]\nvoid = \"=1.0.1\"\n\
+             mixed = { path = \"../mixed\" }\n\
+             copyleft = { path = \"../copyleft\" }\n\
+             own = { path = \"../own\" }\n"
+                .to_string(),
+        ),
+        ("src/lib.rs".to_string(), String::new()),
+        (
+            "../mixed/Cargo.toml".to_string(),
+            package(
+                "mixed",
+                "license = \"MIT AND (Apache-2.0 WITH LLVM-exception)\"",
+            ),
+        ),
+        (
+            "../copyleft/Cargo.toml".to_string(),
+            package("copyleft", "license = \"GPL-3.0-or-later OR Apache-2.0\""),
+        ),
+        (
+            "../own/Cargo.toml".to_string(),
+            package("own", "license-file = \"LICENSE\""),
+        ),
+    ] {
+        let path = project.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, contents)?;
+    }
+    for name in ["mixed", "copyleft", "own"] {
+        fs::create_dir_all(dir.path().join(name).join("src"))?;
+        fs::write(dir.path().join(name).join("src/lib.rs"), "")?;
+    }
+    let dest = dir.path().join("dest");
+    cmd_current()
+        .current_dir(&project)
+        .with_args(&[
+            "--include-path",
+            "--license-allow",
+            "mit",
+            "--license-allow",
+            "Apache-2.0 WITH LLVM-exception",
+            "--destination",
+        ])
+        .with_args(&[&dest])
+        .stderr()
+        .contains("Skipped 3 packages: allowed by --license-allow: 2, ")
+        .stderr()
+        .contains(
+            "Licenses to review, not allowed by --license-allow:\n  \
+             copyleft 0.1.0: GPL-3.0-or-later OR Apache-2.0\n  \
+             own 0.1.0: only a license file, LICENSE\n",
+        )
+        .unwrap();
+    let mut checked_out: Vec<String> = fs::read_dir(&dest)?
+        .map(|it| it.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    checked_out.sort();
+    assert_eq!(
+        checked_out,
+        [
+            "REVIEW.md",
+            "copyleft-0.1.0_local",
+            "manifest.json",
+            "own-0.1.0_local"
+        ]
+    );
+
+    let manifest = fs::read_to_string(dest.join("manifest.json"))?;
+    let json: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+    let policies: Vec<(&str, &str)> = json["packages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|it| {
+            let policy = it["license_policy"].as_str().unwrap();
+            (it["name"].as_str().unwrap(), policy)
+        })
+        .collect();
+    assert_eq!(
+        policies,
+        [
+            ("copyleft", "review"),
+            ("own", "review"),
+            ("mixed", "allowed"),
+            ("void", "allowed")
+        ]
+    );
+    let review = fs::read_to_string(dest.join("REVIEW.md"))?;
+    assert!(review.contains("2 packages, the riskiest first"));
+
+    cmd_current()
+        .current_dir(&project)
+        .with_args(&["--license-allow", "MIT OR (", "--destination"])
+        .with_args(&[&dest])
+        .fails_with(101)
+        .stderr()
+        .contains("error: --license-allow can't parse `MIT OR (`: the expression ends too early")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn current_references_the_packages_of_the_snapshot_since() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    let project = dir.path().join("project");
+    fs::create_dir_all(project.join("src"))?;
+    fs::write(project.join("src/lib.rs"), "")?;
+    let manifest = |deps: &str| {
+        format!(
+            "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n[dependencies]\n{}",
+            deps
+        )
+    };
+    fs::write(
+        project.join("Cargo.toml"),
+        manifest("void = \"=1.0.1\"\nitoa = \"=0.4.3\"\ncfg-if = \"=0.1.6\"\n"),
+    )?;
+    cmd_current()
+        .current_dir(dir.path())
+        .with_args(&["--manifest-path", "project/Cargo.toml", "-d", "old"])
+        .unwrap();
+
+    fs::write(
+        project.join("Cargo.toml"),
+        manifest("void = \"=1.0.1\"\nitoa = \"=1.0.18\"\nglob = \"=0.3.4\"\n"),
+    )?;
+    cmd_current()
+        .current_dir(dir.path())
+        .with_args(&["--manifest-path", "project/Cargo.toml", "-d", "new"])
+        .with_args(&["--since", "old"])
+        .stderr()
+        .contains("Since old: 1 package added, 1 removed, 1 upgraded\n")
+        .stderr()
+        .contains("Skipped 2 packages: members of the workspace: 1, the same in --since: 1\n")
+        .stderr()
+        .contains("2 packages copied, 0 unchanged, in ")
+        .unwrap();
+    let new = dir.path().join("new");
+    assert!(!new.join("void-1.0.1").exists());
+    assert!(new.join("itoa-1.0.18").is_dir());
+    assert_eq!(
+        fs::read_to_string(new.join("delta.md"))?,
+        "# Delta since old\n\n\
+         1 package added, 1 removed, 1 upgraded.\n\n\
+         ## Added\n\n\
+         - `glob` 0.3.4\n\n\
+         ## Removed\n\n\
+         - `cfg-if` 0.1.6\n\n\
+         ## Upgraded\n\n\
+         - `itoa` 0.4.3 -> 1.0.18\n"
+    );
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(new.join("manifest.json"))?).unwrap();
+    let void = json["packages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|it| it["name"] == "void")
+        .unwrap();
+    assert_eq!(void["snapshot"], "../old");
+    assert_eq!(void["directory"], "void-1.0.1");
+    let review = fs::read_to_string(new.join("REVIEW.md"))?;
+    assert!(review.contains("2 packages, the riskiest first"));
+
+    cmd_current()
+        .current_dir(dir.path())
+        .with_args(&["--manifest-path", "project/Cargo.toml", "-d", "newer"])
+        .with_args(&["--since", "project"])
+        .fails_with(101)
+        .stderr()
+        .contains("error: project has no manifest.json, it's not a snapshot of `current`")
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn current_offline_lists_the_sources_which_arent_downloaded() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    let project = dir.path().join("project");
+    fs::create_dir_all(project.join("src"))?;
+    fs::write(project.join("src/lib.rs"), "")?;
+    fs::write(
+        project.join("Cargo.toml"),
+        "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+         [dependencies]\nvoid = \"=1.0.1\"\nitoa = \"=0.4.3\"\n",
+    )?;
+    let registry = "registry+https://github.com/rust-lang/crates.io-index";
+    fs::write(
+        project.join("Cargo.lock"),
+        format!(
+            "version = 3\n\n\
+             [[package]]\nname = \"itoa\"\nversion = \"0.4.3\"\nsource = \"{0}\"\n\
+             checksum = \"1306f3464951f30e30d12373d31c79fbd52d236e5e896fd92f96ec7babbbe60b\"\n\n\
+             [[package]]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\
+             dependencies = [\n \"itoa\",\n \"void\",\n]\n\n\
+             [[package]]\nname = \"void\"\nversion = \"1.0.1\"\nsource = \"{0}\"\n\
+             checksum = \"bf1a31caf5a37d644b05946ac69108e2b39917aa5c203fd33eaf5b99adf8d2d2\"\n",
+            registry
+        ),
+    )?;
+    // A cache of Cargo with nothing in it.
+    let home = dir.path().join("cargo-home");
+    fs::create_dir_all(&home)?;
+    let output = Command::new(cargo_review_deps_exe())
+        .args(["review-deps", "current", "--offline", "--destination"])
+        .arg(dir.path().join("dest"))
+        .current_dir(&project)
+        .env("CARGO_HOME", &home)
+        .output()?;
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "error: the sources of 2 packages aren't downloaded: itoa 0.4.3 and void 1.0.1\n\
+         run `cargo fetch --locked` on a machine with network access\n"
+    );
+    assert!(!dir.path().join("dest").exists());
+    Ok(())
+}
+
+#[test]
+fn current_writes_a_bill_of_materials() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    let project = dir.path().join("project");
+    for (path, contents) in [
+        (
+            "Cargo.toml",
+            "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+             [dependencies]\nbuilder = { path = \"../builder\" }\n",
+        ),
+        ("src/lib.rs", ""),
+        (
+            "../builder/Cargo.toml",
+            "[package]\nname = \"builder\"\nversion = \"0.1.0\"\nlicense = \"MIT/Apache-2.0\"\n\n\
+             [dependencies]\nvoid = \"=1.0.1\"\n",
+        ),
+        ("../builder/src/lib.rs", ""),
+    ] {
+        let path = project.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, contents)?;
+    }
+    let bom = dir.path().join("bom.json");
+    let output = Command::new(cargo_review_deps_exe())
+        .args([
+            "review-deps",
+            "current",
+            "--include-path",
+            "--sbom",
+            "cyclonedx",
+        ])
+        .arg("--sbom-output")
+        .arg(&bom)
+        .arg("--destination")
+        .arg(dir.path().join("dest"))
+        .current_dir(&project)
+        .output()?;
+    assert!(output.status.success());
+    assert!(dir.path().join("dest/void-1.0.1").is_dir());
+    let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&bom)?).unwrap();
+
+    let schemas = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/schema");
+    let schema = |name: &str| -> serde_json::Value {
+        serde_json::from_str(&fs::read_to_string(schemas.join(name)).unwrap()).unwrap()
+    };
+    let mut options = jsonschema::options();
+    for name in ["spdx.schema.json", "jsf-0.82.schema.json"] {
+        let resource = jsonschema::Resource::from_contents(schema(name)).unwrap();
+        options = options.with_resource(format!("http://cyclonedx.org/schema/{}", name), resource);
+    }
+    let validator = options.build(&schema("bom-1.5.schema.json")).unwrap();
+    let errors: Vec<String> = validator
+        .iter_errors(&json)
+        .map(|it| it.to_string())
+        .collect();
+    assert!(errors.is_empty(), "{:#?}", errors);
+
+    assert_eq!(json["metadata"]["component"]["name"], "test-pkg");
+    let components = json["components"].as_array().unwrap();
+    let names: Vec<&str> = components
+        .iter()
+        .map(|it| it["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["builder", "void"]);
+    assert_eq!(
+        components[0]["licenses"],
+        serde_json::json!([{ "expression": "MIT OR Apache-2.0" }])
+    );
+    assert_eq!(components[1]["purl"], "pkg:cargo/void@1.0.1");
+    assert_eq!(
+        components[1]["hashes"],
+        serde_json::json!([{
+            "alg": "SHA-256",
+            "content": "bf1a31caf5a37d644b05946ac69108e2b39917aa5c203fd33eaf5b99adf8d2d2",
+        }])
+    );
+    let refs: Vec<&serde_json::Value> = [
+        &json["metadata"]["component"],
+        &components[0],
+        &components[1],
+    ]
+    .iter()
+    .map(|it| &it["bom-ref"])
+    .collect();
+    assert_eq!(
+        json["dependencies"],
+        serde_json::json!([
+            { "ref": refs[0], "dependsOn": [refs[1]] },
+            { "ref": refs[1], "dependsOn": [refs[2]] },
+            { "ref": refs[2], "dependsOn": [] },
+        ])
+    );
+
+    // Without copying anything.
+    let spdx = dir.path().join("spdx.json");
+    let output = Command::new(cargo_review_deps_exe())
+        .args([
+            "review-deps",
+            "current",
+            "--list",
+            "--sbom",
+            "spdx",
+            "--sbom-output",
+        ])
+        .arg(&spdx)
+        .current_dir(&project)
+        .env("SOURCE_DATE_EPOCH", "1717243200")
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Package  Version  Source     License  build.rs  proc-macro\n\
+         void     1.0.1    crates.io  MIT\n"
+    );
+    let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&spdx)?).unwrap();
+    assert_eq!(json["spdxVersion"], "SPDX-2.3");
+    assert_eq!(json["creationInfo"]["created"], "2024-06-01T12:00:00Z");
+    let packages = json["packages"].as_array().unwrap();
+    let names: Vec<&str> = packages
+        .iter()
+        .map(|it| it["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["test-pkg", "void"]);
+    assert_eq!(
+        packages[1]["downloadLocation"],
+        "https://crates.io/api/v1/crates/void/1.0.1/download"
+    );
+    assert_eq!(packages[1]["licenseDeclared"], "MIT");
+    assert_eq!(
+        packages[1]["externalRefs"][0]["referenceLocator"],
+        "pkg:cargo/void@1.0.1"
+    );
+    let relationships: Vec<(&str, &str, &str)> = json["relationships"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|it| {
+            (
+                it["spdxElementId"].as_str().unwrap(),
+                it["relationshipType"].as_str().unwrap(),
+                it["relatedSpdxElement"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        relationships,
+        [(
+            "SPDXRef-DOCUMENT",
+            "DESCRIBES",
+            "SPDXRef-Package-0-test-pkg-0.0.0"
+        )]
+    );
+    Ok(())
+}
+
+#[test]
+fn current_warns_about_yanked_versions() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    let project = dir.path().join("project");
+    fs::create_dir_all(project.join("src"))?;
+    fs::write(project.join("src/lib.rs"), "")?;
+    fs::write(
+        project.join("Cargo.toml"),
+        "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+         [dependencies]\ncfg-if = \"1.0.0\"\nvoid = \"=1.0.1\"\n",
+    )?;
+    // Cargo keeps the yanked cfg-if 1.0.2 as long as Cargo.lock pins it.
+    let registry = "registry+https://github.com/rust-lang/crates.io-index";
+    fs::write(
+        project.join("Cargo.lock"),
+        format!(
+            "version = 3\n\n\
+             [[package]]\nname = \"cfg-if\"\nversion = \"1.0.2\"\nsource = \"{0}\"\n\
+             checksum = \"9aeec81361cbe5564f44e79538fc112002f70a448966f9160591c2bd5706dfad\"\n\n\
+             [[package]]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\
+             dependencies = [\n \"cfg-if\",\n \"void\",\n]\n\n\
+             [[package]]\nname = \"void\"\nversion = \"1.0.1\"\nsource = \"{0}\"\n\
+             checksum = \"bf1a31caf5a37d644b05946ac69108e2b39917aa5c203fd33eaf5b99adf8d2d2\"\n",
+            registry
+        ),
+    )?;
+    let output = Command::new(cargo_review_deps_exe())
+        .args(["review-deps", "current", "--list"])
+        .current_dir(&project)
+        .output()?;
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "warning: 1 package yanked from crates.io, review them with extra care:\n  \
+             cfg-if 1.0.2, the newest version is 1."
+        ),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("void"), "{}", stderr);
+
+    let dest = dir.path().join("dest");
+    let output = Command::new(cargo_review_deps_exe())
+        .args(["review-deps", "current", "--deny-yanked", "--destination"])
+        .arg(&dest)
+        .current_dir(&project)
+        .output()?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.ends_with("\nerror: 1 package yanked from crates.io: cfg-if 1.0.2\n"),
+        "{}",
+        stderr
+    );
+    assert!(!dest.exists());
+
+    // Nothing is looked up offline.
+    let output = Command::new(cargo_review_deps_exe())
+        .args([
+            "review-deps",
+            "current",
+            "--list",
+            "--offline",
+            "--deny-yanked",
+        ])
+        .current_dir(&project)
+        .output()?;
+    assert!(output.status.success());
+    Ok(())
+}
+
+#[test]
+fn current_denies_the_sources_of_the_policy() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    let project = dir.path().join("project");
+    for (path, contents) in [
+        (
+            "Cargo.toml",
+            "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+             [dependencies]\nvoid = \"=1.0.1\"\nbuilder = { path = \"../builder\" }\n",
+        ),
+        ("src/lib.rs", ""),
+        (
+            "../builder/Cargo.toml",
+            "[package]\nname = \"builder\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\ninner = { path = \"../inner\" }\n",
+        ),
+        ("../builder/src/lib.rs", ""),
+        (
+            "../inner/Cargo.toml",
+            "[package]\nname = \"inner\"\nversion = \"0.2.0\"\n",
+        ),
+        ("../inner/src/lib.rs", ""),
+    ] {
+        let path = project.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, contents)?;
+    }
+    let dest = dir.path().join("dest");
+    let output = Command::new(cargo_review_deps_exe())
+        .args([
+            "review-deps",
+            "current",
+            "--deny-source",
+            "path",
+            "--destination",
+        ])
+        .arg(&dest)
+        .current_dir(&project)
+        .output()?;
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "error: 2 packages come from sources the policy denies:\n  \
+         builder 0.1.0: a path dependency, via test-pkg 0.0.0 -> builder 0.1.0\n  \
+         inner 0.2.0: a path dependency, via test-pkg 0.0.0 -> builder 0.1.0 -> inner 0.2.0\n"
+    );
+    assert!(!dest.exists());
+
+    cmd_current()
+        .current_dir(&project)
+        .with_args(&["--list", "--deny-source", "git"])
+        .stdout()
+        .contains("void")
+        .unwrap();
+
+    // Those of --packages-from, rather than the dependencies of the
+    // workspace.
+    let list = dir.path().join("review-list.txt");
+    fs::write(
+        &list,
+        format!(
+            "void:1.0.1\npath:{}\nthread_local:0.3.6@mirror\n",
+            project.join("../inner").display()
+        ),
+    )?;
+    let output = Command::new(cargo_review_deps_exe())
+        .args(["review-deps", "current", "--deny-source", "path"])
+        .args(["--allow-registry", "https://example.com/index"])
+        .arg("--packages-from")
+        .arg(&list)
+        .arg("--destination")
+        .arg(&dest)
+        .env(
+            "CARGO_REGISTRIES_MIRROR_INDEX",
+            "sparse+https://index.crates.io/",
+        )
+        .output()?;
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "error: 2 packages come from sources the policy denies:\n  \
+         inner:local: a path dependency\n  \
+         thread_local:0.3.6@mirror: from the registry mirror, which --allow-registry doesn't allow\n"
+    );
+    assert!(!dest.exists());
+
+    let output = Command::new(cargo_review_deps_exe())
+        .args([
+            "review-deps",
+            "current",
+            "--list",
+            "--allow-registry",
+            "internal",
+        ])
+        .current_dir(&project)
+        .env("CARGO_HOME", dir.path().join("cargo-home"))
+        .output()?;
+    assert_eq!(output.status.code(), Some(101));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "error: --allow-registry internal: there's no registry `internal` in .cargo/config.toml\n"
+    );
+
+    // A sparse registry, allowed by the URL of its index, however it's
+    // spelled.
+    let index = serve_sparse_index()?;
+    let project = dir.path().join("sparse");
+    fs::create_dir_all(project.join("src"))?;
+    fs::write(
+        project.join("Cargo.toml"),
+        "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+         [dependencies]\nvoid = { version = \"=1.0.1\", registry = \"local\" }\n",
+    )?;
+    fs::write(project.join("src/lib.rs"), "")?;
+    let current = |allowed: &str| {
+        Command::new(cargo_review_deps_exe())
+            .args(["review-deps", "current", "--list", "--allow-registry"])
+            .arg(allowed)
+            .current_dir(&project)
+            .env("CARGO_REGISTRIES_LOCAL_INDEX", format!("sparse+{}/", index))
+            .output()
+    };
+    let output = current(&index)?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("void"));
+    assert!(current(&format!("sparse+{}/", index))?.status.success());
+    assert_eq!(current("https://example.com/index")?.status.code(), Some(2));
+    Ok(())
+}
+
+// Linux paths are at most 4096 bytes long.
+#[cfg(target_os = "linux")]
+#[test]
+fn current_checks_out_the_other_packages_when_one_fails() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("temp-project")?;
+    let project = dir.path().join("project");
+    for (path, contents) in [
+        (
+            "Cargo.toml",
+            "[package]\nname = \"test-pkg\"\nversion = \"0.0.0\"\n\n\
+             [dependencies]\nvoid = \"=1.0.1\"\ndeep = { path = \"../deep\" }\n",
+        ),
+        ("src/lib.rs", ""),
+        (
+            "../deep/Cargo.toml",
+            "[package]\nname = \"deep\"\nversion = \"0.1.0\"\n",
+        ),
+        ("../deep/src/lib.rs", ""),
+    ] {
+        let path = project.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, contents)?;
+    }
+    // A file whose path fits, but not once in the longer destination.
+    let mut deep = dir.path().join("deep");
+    while deep.as_os_str().len() < 4080 {
+        let len = (4085 - deep.as_os_str().len() - 1).min(200);
+        deep.push("d".repeat(len));
+    }
+    fs::create_dir_all(&deep)?;
+    fs::write(deep.join("f"), "")?;
+
+    let dest = dir.path().join("dest");
+    let output = Command::new(cargo_review_deps_exe())
+        .args(["review-deps", "current", "--include-path", "--destination"])
+        .arg(&dest)
+        .current_dir(&project)
+        .output()?;
+    assert_eq!(output.status.code(), Some(101));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("1 package copied, 0 unchanged, 1 failed, in ")
+            && stderr.contains("Failed packages:\n  deep 0.1.0: ")
+            && stderr.ends_with(
+                "\nerror: 1 of 2 packages couldn't be checked out, \
+                 marked as failed in manifest.json\n"
+            ),
+        "{}",
+        stderr
+    );
+    assert!(dest.join("void-1.0.1/src/lib.rs").is_file());
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(dest.join("manifest.json"))?).unwrap();
+    let packages = manifest["packages"].as_array().unwrap();
+    assert_eq!(packages.len(), 2);
+    assert_eq!(packages[0]["name"], "void");
+    assert!(packages[0].get("failed").is_none());
+    assert_eq!(packages[1]["name"], "deep");
+    assert!(packages[1]["failed"].is_string());
+    assert!(!fs::read_to_string(dest.join("REVIEW.md"))?.contains("deep"));
+
+    // What's left of it is tried again, without --force.
+    let output = Command::new(cargo_review_deps_exe())
+        .args(["review-deps", "current", "--include-path", "--destination"])
+        .arg(&dest)
+        .current_dir(&project)
+        .output()?;
+    assert!(String::from_utf8_lossy(&output.stderr).ends_with(
+        "\nerror: 1 of 1 package couldn't be checked out, marked as failed in manifest.json\n"
+    ));
+
+    let dest = dir.path().join("fail-fast");
+    let output = Command::new(cargo_review_deps_exe())
+        .args([
+            "review-deps",
+            "current",
+            "--include-path",
+            "--fail-fast",
+            "--jobs",
+            "1",
+        ])
+        .arg("--destination")
+        .arg(&dest)
+        .current_dir(&project)
+        .output()?;
+    assert_eq!(output.status.code(), Some(101));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("\nerror: can't copy deep 0.1.0: "));
+    assert!(!dest.join("manifest.json").exists());
+    Ok(())
+}
+
+#[test]
+fn current_marks_the_packages_from_file_it_cant_fetch_as_failed() -> std::io::Result<()> {
+    let dir = tempdir::TempDir::new("current-tests")?;
+    let list = dir.path().join("review-list.txt");
+    let gone = dir.path().join("gone");
+    fs::write(
+        &list,
+        format!("void:1.0.0\ngone:git+file://{}#0123abc\n", gone.display()),
+    )?;
+
+    let dest = dir.path().join("dest");
+    let output = Command::new(cargo_review_deps_exe())
+        .args(["review-deps", "current", "--packages-from"])
+        .arg(&list)
+        .arg("--destination")
+        .arg(&dest)
+        .output()?;
+    assert_eq!(output.status.code(), Some(101));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("1 package copied, 0 unchanged, 1 failed, in ")
+            && stderr.contains("Failed packages:\n  gone:git.0123abc: ")
+            && stderr.ends_with(
+                "\nerror: 1 of 2 packages couldn't be checked out, \
+                 marked as failed in manifest.json\n"
+            ),
+        "{}",
+        stderr
+    );
+    assert!(dest.join("void-1.0.0/src/lib.rs").is_file());
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(dest.join("manifest.json"))?).unwrap();
+    let packages = manifest["packages"].as_array().unwrap();
+    assert_eq!(packages.len(), 2);
+    assert_eq!(packages[0]["name"], "void");
+    assert!(packages[0].get("failed").is_none());
+    assert_eq!(packages[1]["name"], "gone");
+    assert_eq!(packages[1]["directory"], "gone-git.0123abc");
+    assert!(packages[1]["failed"].is_string());
+
+    let dest = dir.path().join("fail-fast");
+    let output = Command::new(cargo_review_deps_exe())
+        .args([
+            "review-deps",
+            "current",
+            "--fail-fast",
+            "--jobs",
+            "1",
+            "--packages-from",
+        ])
+        .arg(&list)
+        .arg("--destination")
+        .arg(&dest)
+        .output()?;
+    assert_eq!(output.status.code(), Some(101));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("error: can't copy gone:git.0123abc: ")
+    );
+    assert!(!dest.join("manifest.json").exists());
+    Ok(())
+}
+
+/// Configures crates.io sparse index as an alternative registry named `mirror`.
+fn mirror_registry_env() -> Environment {
+    Environment::inherit().insert(
+        "CARGO_REGISTRIES_MIRROR_INDEX",
+        "sparse+https://index.crates.io/",
+    )
+}
+
+// Adapted from
+// https://github.com/rust-lang/cargo/blob/485670b3983b52289a2f353d589c57fae2f60f82/tests/testsuite/support/mod.rs#L507
+fn target_dir() -> PathBuf {
+    env::current_exe()
+        .ok()
+        .map(|mut path| {
+            path.pop();
+            if path.ends_with("deps") {
+                path.pop();
+            }
+            path
+        })
+        .unwrap()
+}
+
+fn cargo_review_deps_exe() -> PathBuf {
+    target_dir().join(format!("cargo-review-deps{}", env::consts::EXE_SUFFIX))
+}
+
+fn base_cmd() -> Assert {
+    Assert::command(&[&cargo_review_deps_exe()]).with_args(&["review-deps"])
+}
+
+/// Serves the index of crates.io as a sparse registry on localhost, with the
+/// `.crate` files downloaded from crates.io. Returns the URL of the index.
+fn serve_sparse_index() -> std::io::Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}/index", listener.local_addr()?);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(it) => it,
+                Err(_) => continue,
+            };
+            let mut request = String::new();
+            let mut reader = BufReader::new(&stream);
+            if reader.read_line(&mut request).is_err() {
+                continue;
+            }
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+                line.clear();
+            }
+            let path = request
+                .split_whitespace()
+                .nth(1)
+                .unwrap_or_default()
+                .trim_start_matches("/index");
+            let body = if path == "/config.json" {
+                Some(r#"{"dl": "https://static.crates.io/crates"}"#.to_string())
+            } else {
+                ureq::get(&format!("https://index.crates.io{}", path))
+                    .call()
+                    .ok()
+                    .and_then(|it| it.into_string().ok())
+            };
+            let response = match body {
+                Some(body) => format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                ),
+                None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string(),
+            };
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    Ok(url)
 }